@@ -11,6 +11,7 @@ use std::ops::{Add, Div, Mul, Sub};
 
 const FLOAT_PRECISION: f64 = 1_000_000_000.0;
 const TINYTEXT_WIDTH: usize = 15;
+const MAX_DECIMAL_SCALE: u8 = 18;
 
 /// The main type used for user data throughout the codebase.
 ///
@@ -25,6 +26,8 @@ const TINYTEXT_WIDTH: usize = 15;
 pub enum DataType {
     /// An empty value.
     None,
+    /// A boolean value.
+    Bool(bool),
     /// A signed 32-bit numeric value.
     Int(i32),
     /// An unsigned 32-bit numeric value.
@@ -36,6 +39,9 @@ pub enum DataType {
     /// A fixed point real value. The first field is the integer part, while the second is the
     /// fractional and must be between -999999999 and 999999999.
     Real(i64, i32),
+    /// An exact fixed point value. The first field is the value multiplied by ten to the power of
+    /// the second, which is the number of digits after the decimal point and at most 18.
+    Decimal(i64, u8),
     /// A reference-counted string-like value.
     Text(ArcCStr),
     /// A tiny string that fits in a pointer
@@ -48,6 +54,7 @@ impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             DataType::None => write!(f, "NULL"),
+            DataType::Bool(b) => write!(f, "{}", if b { "TRUE" } else { "FALSE" }),
             DataType::Text(..) | DataType::TinyText(..) => {
                 let text: &str = self.into();
                 // TODO: do we really want to produce quoted strings?
@@ -65,6 +72,22 @@ impl fmt::Display for DataType {
                     write!(f, "{}.{:09}", i, frac.abs())
                 }
             }
+            DataType::Decimal(m, scale) => {
+                if scale == 0 {
+                    return write!(f, "{}", m);
+                }
+                let n = i128::from(m).abs();
+                let p = pow10(scale);
+                let sign = if m < 0 { "-" } else { "" };
+                write!(
+                    f,
+                    "{}{}.{:0width$}",
+                    sign,
+                    n / p,
+                    n % p,
+                    width = usize::from(scale)
+                )
+            }
             DataType::Timestamp(ts) => write!(f, "{}", ts.format("%c")),
         }
     }
@@ -74,6 +97,7 @@ impl fmt::Debug for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             DataType::None => write!(f, "None"),
+            DataType::Bool(b) => write!(f, "Bool({})", b),
             DataType::Text(..) => {
                 let text: &str = self.into();
                 write!(f, "Text({:?})", text)
@@ -84,6 +108,7 @@ impl fmt::Debug for DataType {
             }
             DataType::Timestamp(ts) => write!(f, "Timestamp({:?})", ts),
             DataType::Real(..) => write!(f, "Real({})", self),
+            DataType::Decimal(..) => write!(f, "Decimal({})", self),
             DataType::Int(n) => write!(f, "Int({})", n),
             DataType::UnsignedInt(n) => write!(f, "UnsignedInt({})", n),
             DataType::BigInt(n) => write!(f, "BigInt({})", n),
@@ -112,6 +137,14 @@ impl DataType {
         }
     }
 
+    /// Checks if this value is of a boolean data type.
+    pub fn is_bool(&self) -> bool {
+        match *self {
+            DataType::Bool(_) => true,
+            _ => false,
+        }
+    }
+
     /// Checks if this value is of an integral data type (i.e., can be converted into integral types).
     pub fn is_integer(&self) -> bool {
         match *self {
@@ -128,6 +161,68 @@ impl DataType {
         }
     }
 
+    /// Checks if this value is of an exact decimal data type.
+    pub fn is_decimal(&self) -> bool {
+        match *self {
+            DataType::Decimal(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Construct a decimal value equal to `mantissa` divided by ten to the power of `scale`.
+    ///
+    /// Values with more than 18 digits after the decimal point are rounded half away from zero,
+    /// and trailing zeros after the decimal point are dropped if that is what it takes for the
+    /// mantissa to fit in 64 bits. Values that still don't fit are out of range, and give
+    /// `DataType::None`.
+    pub fn decimal(mantissa: i128, scale: u8) -> Self {
+        let (mut m, mut scale) = if scale > MAX_DECIMAL_SCALE {
+            (
+                round_decimal(mantissa, scale, MAX_DECIMAL_SCALE),
+                MAX_DECIMAL_SCALE,
+            )
+        } else {
+            (mantissa, scale)
+        };
+        while scale > 0 && m % 10 == 0 && i64::try_from(m).is_err() {
+            m /= 10;
+            scale -= 1;
+        }
+        match i64::try_from(m) {
+            Ok(m) => DataType::Decimal(m, scale),
+            Err(_) => DataType::None,
+        }
+    }
+
+    /// This value as a mantissa and scale, if it is an integer, a decimal, or a real.
+    fn exact(&self) -> Option<(i128, u8)> {
+        match *self {
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => Some((self.into(), 0)),
+            DataType::Decimal(m, scale) => Some((i128::from(m), scale)),
+            DataType::Real(i, f) => Some((i128::from(i) * 1_000_000_000 + i128::from(f), 9)),
+            _ => None,
+        }
+    }
+
+    /// The mantissa and scale of a decimal value with no trailing zeros after the decimal point.
+    ///
+    /// Decimals that are equal have the same normalized form, regardless of their scale.
+    pub(crate) fn normalized_decimal(&self) -> Option<(i64, u8)> {
+        match *self {
+            DataType::Decimal(mut m, mut scale) => {
+                while scale > 0 && m % 10 == 0 {
+                    m /= 10;
+                    scale -= 1;
+                }
+                Some((m, scale))
+            }
+            _ => None,
+        }
+    }
+
     /// Checks if this value is of a string data type (i.e., can be converted into `String` and
     /// `&str`).
     pub fn is_string(&self) -> bool {
@@ -190,7 +285,11 @@ impl PartialEq for DataType {
                 a == b
             }
             (&DataType::Real(ai, af), &DataType::Real(bi, bf)) => ai == bi && af == bf,
+            (&DataType::Decimal(..), &DataType::Decimal(..)) => {
+                cmp_exact(self.exact().unwrap(), other.exact().unwrap()) == Ordering::Equal
+            }
             (&DataType::Timestamp(tsa), &DataType::Timestamp(tsb)) => tsa == tsb,
+            (&DataType::Bool(a), &DataType::Bool(b)) => a == b,
             (&DataType::None, &DataType::None) => true,
 
             _ => false,
@@ -239,18 +338,58 @@ impl Ord for DataType {
             (&DataType::Real(ai, af), &DataType::Real(ref bi, ref bf)) => {
                 ai.cmp(bi).then_with(|| af.cmp(bf))
             }
+            (&DataType::Int(..), &DataType::Real(bi, bf))
+            | (&DataType::UnsignedInt(..), &DataType::Real(bi, bf))
+            | (&DataType::BigInt(..), &DataType::Real(bi, bf))
+            | (&DataType::UnsignedBigInt(..), &DataType::Real(bi, bf)) => {
+                // an integer sorts before a real of the same value, so that the ordering agrees
+                // with equality
+                let a: i128 = self.into();
+                a.cmp(&i128::from(bi))
+                    .then_with(|| 0.cmp(&bf))
+                    .then(Ordering::Less)
+            }
+            (&DataType::Real(..), &DataType::Int(..))
+            | (&DataType::Real(..), &DataType::UnsignedInt(..))
+            | (&DataType::Real(..), &DataType::BigInt(..))
+            | (&DataType::Real(..), &DataType::UnsignedBigInt(..)) => other.cmp(self).reverse(),
+            (&DataType::Decimal(..), _) | (_, &DataType::Decimal(..))
+                if self.type_rank() == other.type_rank() =>
+            {
+                // at equal value, integers sort before decimals and decimals before reals, so that
+                // the ordering agrees with equality
+                let kind = |d: &DataType| match *d {
+                    DataType::Decimal(..) => 1,
+                    DataType::Real(..) => 2,
+                    _ => 0,
+                };
+                cmp_exact(self.exact().unwrap(), other.exact().unwrap())
+                    .then_with(|| kind(self).cmp(&kind(other)))
+            }
             (&DataType::Timestamp(tsa), &DataType::Timestamp(ref tsb)) => tsa.cmp(tsb),
+            (&DataType::Bool(a), &DataType::Bool(ref b)) => a.cmp(b),
             (&DataType::None, &DataType::None) => Ordering::Equal,
 
-            // order Ints, Reals, Text, Timestamps, None
-            (&DataType::Int(..), _)
-            | (&DataType::UnsignedInt(..), _)
-            | (&DataType::BigInt(..), _)
-            | (&DataType::UnsignedBigInt(..), _) => Ordering::Greater,
-            (&DataType::Real(..), _) => Ordering::Greater,
-            (&DataType::Text(..), _) | (&DataType::TinyText(..), _) => Ordering::Greater,
-            (&DataType::Timestamp(..), _) => Ordering::Greater,
-            (&DataType::None, _) => Ordering::Greater,
+            // order None, booleans, numbers, Text, Timestamps
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+}
+
+impl DataType {
+    /// The position of this value's type in the ordering between values of different types.
+    fn type_rank(&self) -> u8 {
+        match *self {
+            DataType::None => 0,
+            DataType::Bool(..) => 1,
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..)
+            | DataType::Real(..)
+            | DataType::Decimal(..) => 2,
+            DataType::Text(..) | DataType::TinyText(..) => 3,
+            DataType::Timestamp(..) => 4,
         }
     }
 }
@@ -262,6 +401,7 @@ impl Hash for DataType {
         // collisions, but the decreased overhead is worth it.
         match *self {
             DataType::None => {}
+            DataType::Bool(b) => b.hash(state),
             DataType::Int(..) | DataType::BigInt(..) => {
                 let n: i64 = self.into();
                 n.hash(state)
@@ -274,6 +414,10 @@ impl Hash for DataType {
                 i.hash(state);
                 f.hash(state);
             }
+            DataType::Decimal(..) => {
+                // equal decimals may have different scales
+                self.normalized_decimal().unwrap().hash(state)
+            }
             DataType::Text(..) | DataType::TinyText(..) => {
                 let t: &str = self.into();
                 t.hash(state)
@@ -305,6 +449,12 @@ impl From<i128> for DataType {
     }
 }
 
+impl From<bool> for DataType {
+    fn from(b: bool) -> Self {
+        DataType::Bool(b)
+    }
+}
+
 impl From<i64> for DataType {
     fn from(s: i64) -> Self {
         DataType::BigInt(s)
@@ -507,6 +657,16 @@ impl From<&'_ DataType> for u32 {
     }
 }
 
+impl From<&'_ DataType> for bool {
+    fn from(data: &'_ DataType) -> Self {
+        if let DataType::Bool(b) = *data {
+            b
+        } else {
+            panic!("attempted to convert a {:?} to a bool", data)
+        }
+    }
+}
+
 impl From<DataType> for f64 {
     fn from(data: DataType) -> Self {
         (&data).into()
@@ -517,6 +677,7 @@ impl From<&'_ DataType> for f64 {
     fn from(data: &'_ DataType) -> Self {
         match *data {
             DataType::Real(i, f) => i as f64 + f64::from(f) / FLOAT_PRECISION,
+            DataType::Decimal(m, scale) => m as f64 / 10f64.powi(i32::from(scale)),
             DataType::Int(i) => f64::from(i),
             DataType::BigInt(i) => i as f64,
             _ => panic!("attempted to convert a {:?} to an f64", data),
@@ -586,6 +747,66 @@ impl TryFrom<mysql_common::value::Value> for DataType {
     }
 }
 
+fn pow10(exp: u8) -> i128 {
+    10i128.pow(u32::from(exp))
+}
+
+// Rounds a mantissa with scale `from` half away from zero to the smaller scale `to`.
+fn round_decimal(m: i128, from: u8, to: u8) -> i128 {
+    let d = pow10(from - to);
+    let (q, r) = (m / d, m % d);
+    if r.abs() * 2 >= d {
+        q + m.signum()
+    } else {
+        q
+    }
+}
+
+// Compares two (mantissa, scale) pairs by value.
+fn cmp_exact((a, sa): (i128, u8), (b, sb): (i128, u8)) -> Ordering {
+    let scale = sa.max(sb);
+    (a * pow10(scale - sa)).cmp(&(b * pow10(scale - sb)))
+}
+
+// Performs an arithmetic operation on a decimal and another decimal or integer without going
+// through floating point. Quotients keep four more digits after the decimal point than the
+// dividend, like MySQL does. Like in MySQL, results that are out of range and quotients of a
+// division by zero are NULL.
+fn decimal_operation(op: &str, first: &DataType, second: &DataType) -> DataType {
+    let ((a, sa), (b, sb)) = match (first.exact(), second.exact()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => panic!("can't {} a {:?} and {:?}", op, first, second),
+    };
+
+    let m = match op {
+        "+" | "-" => {
+            let scale = sa.max(sb);
+            let a = a * pow10(scale - sa);
+            let b = b * pow10(scale - sb);
+            let m = if op == "+" {
+                a.checked_add(b)
+            } else {
+                a.checked_sub(b)
+            };
+            m.map(|m| (m, scale))
+        }
+        "*" => a.checked_mul(b).map(|m| (m, sa + sb)),
+        "/" if b == 0 => None,
+        "/" => {
+            let scale = (sa + 4).min(MAX_DECIMAL_SCALE);
+            // compute one extra digit so that the quotient can be rounded
+            pow10(scale + 1 + sb - sa)
+                .checked_mul(a)
+                .map(|m| (round_decimal(m / b, scale + 1, scale), scale))
+        }
+        _ => unreachable!(),
+    };
+    match m {
+        Some((m, scale)) => DataType::decimal(m, scale),
+        None => DataType::None,
+    }
+}
+
 // Performs an arithmetic operation on two numeric DataTypes,
 // returning a new DataType as the result.
 macro_rules! arithmetic_operation (
@@ -614,11 +835,17 @@ macro_rules! arithmetic_operation (
             (first @ &DataType::Real(..), second @ &DataType::BigInt(..)) |
             (first @ &DataType::Real(..), second @ &DataType::UnsignedInt(..)) |
             (first @ &DataType::Real(..), second @ &DataType::UnsignedBigInt(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::Real(..)) => {
+            (first @ &DataType::Real(..), second @ &DataType::Real(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::Real(..)) |
+            (first @ &DataType::Real(..), second @ &DataType::Decimal(..)) => {
                 let a: f64 = first.into();
                 let b: f64 = second.into();
                 (a $op b).into()
             }
+            (first @ &DataType::Decimal(..), second) |
+            (first, second @ &DataType::Decimal(..)) => {
+                decimal_operation(stringify!($op), first, second)
+            }
             (first, second) => panic!(
                 format!(
                     "can't {} a {:?} and {:?}",
@@ -740,6 +967,147 @@ impl From<Vec<DataType>> for TableOperation {
 mod tests {
     use super::*;
 
+    #[test]
+    fn it_orders_across_types() {
+        let ints: DataType = 2.into();
+        let big: DataType = DataType::BigInt(3);
+        let real: DataType = 2.5.into();
+        let negative: DataType = (-0.5).into();
+        let text: DataType = "a".into();
+
+        assert!(negative < ints);
+        assert!(ints < real && real > ints);
+        assert!(real < big && big > real);
+        assert!(DataType::from(2) < DataType::from(2.0));
+        assert!(DataType::None < negative && negative > DataType::None);
+        assert!(big < text && text > big);
+
+        let mut all = vec![
+            text.clone(),
+            real.clone(),
+            DataType::None,
+            big.clone(),
+            ints.clone(),
+        ];
+        all.sort();
+        assert_eq!(all, vec![DataType::None, ints, real, big, text]);
+    }
+
+    #[test]
+    fn bools_compare_and_print() {
+        let t = DataType::from(true);
+        let f = DataType::from(false);
+        assert!(f < t);
+        assert_ne!(t, DataType::from(1));
+        assert!(DataType::None < f && t < DataType::from(0));
+        assert!(bool::from(&t));
+        assert_eq!(t.to_string(), "TRUE");
+        assert_eq!(format!("{:?}", f), "Bool(false)");
+    }
+
+    #[test]
+    fn timestamps_compare_and_hash() {
+        use std::collections::HashMap;
+
+        let earlier = DataType::Timestamp(NaiveDate::from_ymd(2020, 3, 1).and_hms(12, 0, 0));
+        let later = DataType::Timestamp(NaiveDate::from_ymd(2020, 3, 1).and_hms_nano(12, 0, 0, 1));
+        assert!(earlier < later && later > earlier);
+        assert_ne!(earlier, later);
+        assert_eq!(
+            earlier,
+            DataType::from(NaiveDate::from_ymd(2020, 3, 1).and_hms(12, 0, 0))
+        );
+
+        // timestamps sort after every other type
+        assert!(DataType::from("z") < earlier);
+
+        let mut counts = HashMap::new();
+        *counts.entry(earlier.clone()).or_insert(0) += 1;
+        *counts.entry(later.clone()).or_insert(0) += 1;
+        *counts.entry(earlier.deep_clone()).or_insert(0) += 1;
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&earlier], 2);
+        assert_eq!(counts[&later], 1);
+    }
+
+    #[test]
+    fn decimals_compare_by_value() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |d: &DataType| {
+            let mut h = DefaultHasher::new();
+            d.hash(&mut h);
+            h.finish()
+        };
+
+        let a = DataType::Decimal(150, 2);
+        let b = DataType::Decimal(15, 1);
+        assert_eq!(a, b);
+        assert_eq!(a.cmp(&b), Ordering::Equal);
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(a, DataType::Decimal(151, 2));
+
+        // like reals, decimals sort among the other numbers
+        let mut all = vec![
+            DataType::from(1.5),
+            DataType::Decimal(2, 0),
+            DataType::Decimal(-1, 3),
+            DataType::from(2),
+            a.clone(),
+        ];
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                DataType::Decimal(-1, 3),
+                a,
+                DataType::from(1.5),
+                DataType::from(2),
+                DataType::Decimal(2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn decimal_to_string() {
+        assert_eq!(DataType::Decimal(250, 2).to_string(), "2.50");
+        assert_eq!(DataType::Decimal(-5, 2).to_string(), "-0.05");
+        assert_eq!(DataType::Decimal(-12, 0).to_string(), "-12");
+        assert_eq!(format!("{:?}", DataType::Decimal(1, 3)), "Decimal(0.001)");
+    }
+
+    #[test]
+    fn decimal_arithmetic_is_exact() {
+        let tenth = DataType::Decimal(1, 1);
+        let sum = (0..10).fold(DataType::Decimal(0, 1), |sum, _| &sum + &tenth);
+        assert_eq!(sum, DataType::Decimal(1, 0));
+        assert_eq!(sum.to_string(), "1.0");
+
+        let a = DataType::Decimal(125, 2);
+        assert_eq!(&a - &DataType::from(2), DataType::Decimal(-75, 2));
+        assert_eq!(&a * &DataType::Decimal(2, 1), DataType::Decimal(25, 2));
+        assert_eq!(&a / &DataType::from(3), DataType::Decimal(416_667, 6));
+        assert_eq!(&a + &DataType::from(0.5), DataType::from(1.75));
+    }
+
+    #[test]
+    fn decimal_overflow_is_null() {
+        let max = DataType::Decimal(i64::max_value(), 0);
+        assert_eq!(&max + &DataType::Decimal(1, 0), DataType::None);
+        assert_eq!(
+            &max * &DataType::Decimal(i64::max_value(), 0),
+            DataType::None
+        );
+        assert_eq!(&max / &DataType::Decimal(1, 10), DataType::None);
+        assert_eq!(&max / &DataType::Decimal(0, 2), DataType::None);
+        assert_eq!(
+            DataType::decimal(i128::from(i64::max_value()) * 10, 0),
+            DataType::None
+        );
+
+        // trailing zeros are dropped to keep values that do fit
+        assert_eq!(DataType::decimal(i128::from(i64::max_value()) * 10, 1), max);
+    }
+
     #[test]
     fn mysql_value_to_datatype() {
         use assert_approx_eq::assert_approx_eq;
@@ -3,6 +3,7 @@ use crate::MaterializationStatus;
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::time;
 
 type DomainMap = HashMap<(DomainIndex, usize), (DomainStats, HashMap<NodeIndex, NodeStats>)>;
 
@@ -40,6 +41,75 @@ pub struct NodeStats {
     pub materialized: MaterializationStatus,
     /// The value returned from Ingredient::probe.
     pub probe_result: HashMap<String, String>,
+    /// The value returned from Ingredient::metrics.
+    pub metrics: Option<OpMetrics>,
+}
+
+/// Counters an operator keeps about the partial replays passing through it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpMetrics {
+    /// The number of replay keys for which some, but not all, pieces have arrived.
+    pub in_flight_keys: u64,
+    /// The total number of records buffered for in-flight keys.
+    pub buffered_records: u64,
+    /// The number of replay keys released once all their pieces had arrived.
+    pub releases: u64,
+}
+
+/// A coarse histogram of durations, with one bucket per power of two nanoseconds.
+///
+/// Recording is a couple of arithmetic operations and an increment, so it is cheap enough to do
+/// once per processed batch.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    samples: u64,
+}
+
+impl Histogram {
+    /// Record a single duration.
+    pub fn record(&mut self, duration: time::Duration) {
+        let ns = duration.as_nanos().min(u128::from(u64::max_value())) as u64;
+        let bucket = (64 - ns.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.samples += 1;
+    }
+
+    /// The number of samples recorded.
+    pub fn len(&self) -> u64 {
+        self.samples
+    }
+
+    /// Whether no samples have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0
+    }
+
+    /// An upper bound on the `q`th quantile (between 0 and 1) of the recorded durations.
+    pub fn quantile(&self, q: f64) -> Option<time::Duration> {
+        if self.samples == 0 {
+            return None;
+        }
+
+        let target = ((self.samples as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                // bucket i holds durations that need exactly i bits
+                let max = if bucket == 0 {
+                    0
+                } else {
+                    u64::max_value() >> (64 - bucket)
+                };
+                return Some(time::Duration::from_nanos(max));
+            }
+        }
+        unreachable!("quantile target exceeds number of samples");
+    }
 }
 
 /// Statistics about the Soup data-flow.
@@ -79,3 +149,22 @@ fn deserialize_domainmap<'de, D: Deserializer<'de>>(d: D) -> Result<DomainMap, D
     }
     Ok(map)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_by_power_of_two() {
+        let mut h = Histogram::default();
+        h.record(time::Duration::from_nanos(0));
+        h.record(time::Duration::from_nanos(5));
+        h.record(time::Duration::from_nanos(6));
+        h.record(time::Duration::from_nanos(1000));
+        assert_eq!(h.len(), 4);
+        assert_eq!(h.quantile(0.25), Some(time::Duration::from_nanos(0)));
+        assert_eq!(h.quantile(0.5), Some(time::Duration::from_nanos(7)));
+        assert_eq!(h.quantile(0.75), Some(time::Duration::from_nanos(7)));
+        assert_eq!(h.quantile(1.0), Some(time::Duration::from_nanos(1023)));
+    }
+}
@@ -298,7 +298,7 @@ impl<T> From<T> for Tagged<T> {
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
 pub use crate::data::{DataType, Modification, Operation, TableOperation};
 pub use crate::table::Table;
-pub use crate::view::View;
+pub use crate::view::{Pagination, Subscription, View};
 
 #[doc(hidden)]
 pub use crate::table::Input;
@@ -332,6 +332,7 @@ pub struct ActivationResult {
 #[inline]
 pub fn shard_by(dt: &DataType, shards: usize) -> usize {
     match *dt {
+        DataType::Bool(b) => b as usize % shards,
         DataType::Int(n) => n as usize % shards,
         DataType::UnsignedInt(n) => n as usize % shards,
         DataType::BigInt(n) => n as usize % shards,
@@ -343,10 +344,85 @@ pub fn shard_by(dt: &DataType, shards: usize) -> usize {
             hasher.write(s.as_bytes());
             hasher.finish() as usize % shards
         }
+        DataType::Decimal(..) => {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+            dt.normalized_decimal().unwrap().hash(&mut hasher);
+            hasher.finish() as usize % shards
+        }
+        DataType::Timestamp(ts) => {
+            use std::hash::Hasher;
+            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+            hasher.write_i64(ts.timestamp());
+            hasher.write_u32(ts.timestamp_subsec_nanos());
+            hasher.finish() as usize % shards
+        }
+        DataType::Real(i, f) => {
+            use std::hash::Hasher;
+            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+            hasher.write_i64(i);
+            hasher.write_i32(f);
+            hasher.finish() as usize % shards
+        }
         // a bit hacky: send all NULL values to the first shard
         DataType::None => 0,
-        ref x => {
-            unimplemented!("asked to shard on value {:?}", x);
+    }
+}
+
+/// Like `shard_by`, but for a key of one or more columns.
+///
+/// A single-column key goes to the same shard as `shard_by` would send its value to. A composite
+/// key goes to a shard chosen by hashing all of its values together, so that records with the same
+/// composite key always land on the same shard.
+#[doc(hidden)]
+#[inline]
+pub fn shard_by_key(key: &[DataType], shards: usize) -> usize {
+    if let [ref dt] = *key {
+        return shard_by(dt, shards);
+    }
+
+    use std::hash::Hasher;
+    let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+    for dt in key {
+        // prefix each value with its kind, and strings with their length, so that different
+        // tuples don't hash the same bytes
+        match *dt {
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => {
+                let n: i128 = dt.into();
+                hasher.write_u8(1);
+                hasher.write_i128(n);
+            }
+            DataType::Text(..) | DataType::TinyText(..) => {
+                let s: &str = dt.into();
+                hasher.write_u8(2);
+                hasher.write_usize(s.len());
+                hasher.write(s.as_bytes());
+            }
+            DataType::Decimal(..) => {
+                let (m, scale) = dt.normalized_decimal().unwrap();
+                hasher.write_u8(3);
+                hasher.write_i64(m);
+                hasher.write_u8(scale);
+            }
+            DataType::Timestamp(ts) => {
+                hasher.write_u8(4);
+                hasher.write_i64(ts.timestamp());
+                hasher.write_u32(ts.timestamp_subsec_nanos());
+            }
+            DataType::None => hasher.write_u8(0),
+            DataType::Bool(b) => {
+                hasher.write_u8(5);
+                hasher.write_u8(b as u8);
+            }
+            DataType::Real(i, f) => {
+                hasher.write_u8(6);
+                hasher.write_i64(i);
+                hasher.write_i32(f);
+            }
         }
     }
+    hasher.finish() as usize % shards
 }
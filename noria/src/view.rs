@@ -5,13 +5,14 @@ use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
     stream::StreamExt, stream::TryStreamExt,
 };
-use nom_sql::ColumnSpecification;
+use nom_sql::{ColumnSpecification, OrderType};
 use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+use std::ops::Bound;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use tokio_tower::multiplex;
@@ -104,6 +105,9 @@ pub enum ViewError {
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
+    /// The subscription is no longer registered with the view.
+    #[fail(display = "the subscription has ended")]
+    SubscriptionEnded,
 }
 
 impl From<Box<dyn std::error::Error + Send + Sync>> for ViewError {
@@ -123,12 +127,31 @@ pub enum ReadQuery {
         keys: Vec<Vec<DataType>>,
         /// Whether to block if a partial replay is triggered
         block: bool,
+        /// Which slice of the rows for each key to return, if not all of them
+        page: Option<Pagination>,
     },
     /// Read the size of a leaf view
     Size {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Subscribe to changes to a range of keys in a leaf view
+    Subscribe {
+        /// Where to subscribe
+        target: (NodeIndex, usize),
+        /// Keys to receive changes for
+        range: (Bound<Vec<DataType>>, Bound<Vec<DataType>>),
+    },
+    /// Wait for changes on a subscription
+    Poll {
+        /// The subscription to wait on
+        subscription: u64,
+    },
+    /// End a subscription
+    Unsubscribe {
+        /// The subscription to end
+        subscription: u64,
+    },
 }
 
 #[doc(hidden)]
@@ -138,6 +161,16 @@ pub enum ReadReply<D = ReadReplyBatch> {
     Normal(Result<Vec<D>, ()>),
     /// Read size of view
     Size(usize),
+    /// Slices of the rows for each key, along with the total number of rows for that key.
+    /// Errors if view isn't ready yet.
+    Paged(Result<Vec<(D, usize)>, ()>),
+    /// Identifier of a new subscription
+    Subscribed(u64),
+    /// Changes seen by a subscription since it was last polled.
+    /// Errors if the subscription is not registered.
+    Deltas(Result<Vec<(Vec<DataType>, bool)>, ()>),
+    /// The subscription has ended
+    Unsubscribed,
 }
 
 #[doc(hidden)]
@@ -270,6 +303,7 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                 target: (self.node, 0),
                 keys,
                 block,
+                page: None,
             });
 
             let _guard = span.as_ref().map(tracing::Span::enter);
@@ -295,10 +329,9 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
         if let Some(ref span) = span {
             span.in_scope(|| tracing::trace!("shard request"));
         }
-        assert!(keys.iter().all(|k| k.len() == 1));
         let mut shard_queries = vec![Vec::new(); self.shards.len()];
         for key in keys {
-            let shard = crate::shard_by(&key[0], self.shards.len());
+            let shard = crate::shard_by_key(&key[..], self.shards.len());
             shard_queries[shard].push(key);
         }
 
@@ -324,6 +357,7 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                         target: (node, shardi),
                         keys: shard_queries,
                         block,
+                        page: None,
                     });
 
                     let _guard = span.as_ref().map(tracing::Span::enter);
@@ -400,6 +434,64 @@ impl View {
         Ok(nrows)
     }
 
+    /// Subscribe to changes to the rows for the given parameter value.
+    ///
+    /// Changes made after this method returns are delivered by [`Subscription::next`].
+    pub async fn subscribe(&mut self, key: &[DataType]) -> Result<Subscription, ViewError> {
+        let shard = self.shard_for(key);
+        let range = (
+            Bound::Included(Vec::from(key)),
+            Bound::Included(Vec::from(key)),
+        );
+        self.subscribe_shards(vec![shard], range).await
+    }
+
+    /// Subscribe to changes to the rows for all parameter values in the given range.
+    ///
+    /// Changes made after this method returns are delivered by [`Subscription::next`].
+    pub async fn subscribe_range(
+        &mut self,
+        range: (Bound<Vec<DataType>>, Bound<Vec<DataType>>),
+    ) -> Result<Subscription, ViewError> {
+        let shards = (0..self.shards.len()).collect();
+        self.subscribe_shards(shards, range).await
+    }
+
+    fn shard_for(&self, key: &[DataType]) -> usize {
+        if self.shards.len() == 1 {
+            0
+        } else {
+            crate::shard_by_key(key, self.shards.len())
+        }
+    }
+
+    async fn subscribe_shards(
+        &mut self,
+        shards: Vec<usize>,
+        range: (Bound<Vec<DataType>>, Bound<Vec<DataType>>),
+    ) -> Result<Subscription, ViewError> {
+        let mut subscription = Subscription {
+            shards: Vec::with_capacity(shards.len()),
+        };
+        for shardi in shards {
+            // use a fresh handle so we don't hold on to the slots that poll_ready reserves on
+            // shards we aren't talking to
+            let mut shard = self.shards[shardi].clone();
+            future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+            let reply = shard
+                .call(Tagged::from(ReadQuery::Subscribe {
+                    target: (self.node, shardi),
+                    range: range.clone(),
+                }))
+                .await?;
+            match reply.v {
+                ReadReply::Subscribed(id) => subscription.shards.push((shard, id)),
+                _ => unreachable!(),
+            }
+        }
+        Ok(subscription)
+    }
+
     /// Retrieve the query results for the given parameter values.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
@@ -423,6 +515,42 @@ impl View {
         Ok(rs.into_iter().next().unwrap())
     }
 
+    /// Retrieve a slice of the query results for the given parameter value.
+    ///
+    /// Along with the rows in the slice, this returns the total number of rows for the key in the
+    /// snapshot that was read, which tells the caller when it has reached the last page. Rows
+    /// written between two calls may shift the rows that fall on later pages.
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    pub async fn lookup_page(
+        &mut self,
+        key: &[DataType],
+        page: Pagination,
+        block: bool,
+    ) -> Result<(Results, usize), ViewError> {
+        let shardi = self.shard_for(key);
+        let mut shard = self.shards[shardi].clone();
+        future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+        let reply = shard
+            .call(Tagged::from(ReadQuery::Normal {
+                target: (self.node, shardi),
+                keys: vec![Vec::from(key)],
+                block,
+                page: Some(page),
+            }))
+            .await?;
+
+        match reply.v {
+            ReadReply::Paged(Ok(pages)) => {
+                let (rows, total) = pages.into_iter().next().unwrap();
+                let columns = Arc::from(&self.columns[..]);
+                Ok((Results::new(rows.into(), columns), total))
+            }
+            ReadReply::Paged(Err(())) => Err(ViewError::NotYetAvailable),
+            _ => unreachable!(),
+        }
+    }
+
     /// Retrieve the first query result for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
@@ -437,6 +565,121 @@ impl View {
     }
 }
 
+/// Which slice of the rows for a key a paginated lookup should return.
+///
+/// See [`View::lookup_page`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pagination {
+    /// The column to order rows by, and in which direction.
+    ///
+    /// Rows are also ordered by their full contents, both to break ties and so that pages are
+    /// consistent even when no ordering column is given.
+    pub order: Option<(usize, OrderType)>,
+    /// The number of rows to skip.
+    pub offset: usize,
+    /// The maximum number of rows to return.
+    pub limit: usize,
+}
+
+impl Pagination {
+    /// Order `rows`, and return the ones that fall on this page along with how many there were.
+    #[doc(hidden)]
+    pub fn slice<'a, I>(&self, rows: I) -> (Vec<&'a Vec<DataType>>, usize)
+    where
+        I: IntoIterator<Item = &'a Vec<DataType>>,
+    {
+        let mut rows: Vec<_> = rows.into_iter().collect();
+        let total = rows.len();
+        match self.order {
+            Some((c, OrderType::OrderAscending)) => {
+                rows.sort_unstable_by(|a, b| a[c].cmp(&b[c]).then_with(|| a.cmp(b)))
+            }
+            Some((c, OrderType::OrderDescending)) => {
+                rows.sort_unstable_by(|a, b| b[c].cmp(&a[c]).then_with(|| a.cmp(b)))
+            }
+            None => rows.sort_unstable(),
+        }
+
+        let rows = rows
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit)
+            .collect();
+        (rows, total)
+    }
+}
+
+/// A stream of changes to some of the keys of a [`View`].
+///
+/// Created by [`View::subscribe`] and [`View::subscribe_range`].
+pub struct Subscription {
+    shards: Vec<(ViewRpc, u64)>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("ids", &self.shards.iter().map(|s| s.1).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Subscription {
+    /// Wait for the next batch of changes to the subscribed keys.
+    ///
+    /// Each change is a row along with whether it was added (`true`) or removed (`false`).
+    pub async fn next(&mut self) -> Result<Vec<(Vec<DataType>, bool)>, ViewError> {
+        loop {
+            // every shard replies within a bounded time, even if nothing changed, so we can wait
+            // for all of them without losing changes that arrive on one while another is waiting
+            let mut polls = self
+                .shards
+                .iter_mut()
+                .map(|(shard, id)| {
+                    let subscription = *id;
+                    async move {
+                        future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+                        shard
+                            .call(Tagged::from(ReadQuery::Poll { subscription }))
+                            .await
+                            .map_err(ViewError::from)
+                    }
+                })
+                .collect::<FuturesUnordered<_>>();
+
+            let mut deltas = Vec::new();
+            let mut ended = false;
+            while let Some(reply) = polls.next().await.transpose()? {
+                match reply.v {
+                    ReadReply::Deltas(Ok(ds)) => deltas.extend(ds),
+                    ReadReply::Deltas(Err(())) => ended = true,
+                    _ => unreachable!(),
+                }
+            }
+
+            if !deltas.is_empty() {
+                return Ok(deltas);
+            }
+            if ended {
+                return Err(ViewError::SubscriptionEnded);
+            }
+        }
+    }
+
+    /// Stop receiving changes.
+    pub async fn unsubscribe(mut self) -> Result<(), ViewError> {
+        for (shard, subscription) in &mut self.shards {
+            future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+            shard
+                .call(Tagged::from(ReadQuery::Unsubscribe {
+                    subscription: *subscription,
+                }))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default)]
 #[doc(hidden)]
 #[repr(transparent)]
@@ -530,3 +773,56 @@ impl std::ops::DerefMut for ReadReplyBatch {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Vec<DataType>> {
+        // ten rows for the same key, with ties in the second column
+        (0..10)
+            .map(|i| vec![1.into(), (i / 2).into(), i.into()])
+            .collect()
+    }
+
+    fn pages(order: Option<(usize, OrderType)>) -> Vec<Vec<DataType>> {
+        let rows = rows();
+        let mut seen = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = Pagination {
+                order: order.clone(),
+                offset,
+                limit: 3,
+            };
+            // the order in which the reader hands us rows must not matter
+            let (slice, total) = page.slice(rows.iter().rev());
+            assert_eq!(total, 10);
+            assert!(slice.len() <= 3);
+            if slice.is_empty() {
+                break;
+            }
+            seen.extend(slice.into_iter().cloned());
+            offset += 3;
+        }
+        assert_eq!(offset, 12);
+        seen
+    }
+
+    #[test]
+    fn it_pages_in_order() {
+        let seen = pages(Some((2, OrderType::OrderAscending)));
+        assert_eq!(seen, rows());
+
+        let seen = pages(Some((1, OrderType::OrderDescending)));
+        let mut expected = rows();
+        expected.sort_by(|a, b| b[1].cmp(&a[1]).then_with(|| a.cmp(b)));
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn it_pages_unordered_rows_without_gaps() {
+        // without an ordering column, rows come back ordered by their contents
+        assert_eq!(pages(None), rows());
+    }
+}
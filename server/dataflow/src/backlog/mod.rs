@@ -3,7 +3,23 @@ use ahash::RandomState;
 use common::SizeOf;
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// A range of reader keys, as given to [`SingleReadHandle::subscribe`].
+pub type KeyRange = (Bound<Vec<DataType>>, Bound<Vec<DataType>>);
+
+/// The most batches of changes that may wait for a subscriber to receive them. A subscriber that
+/// falls further behind than this is dropped, which ends its subscription.
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+struct Subscriber {
+    range: KeyRange,
+    tx: mpsc::Sender<Vec<Record>>,
+}
+
+type Subscribers = Arc<Mutex<Vec<Subscriber>>>;
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
@@ -63,6 +79,7 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    let subscribers = Subscribers::default();
     let w = WriteHandle {
         partial: trigger.is_some(),
         handle: w,
@@ -70,11 +87,13 @@ fn new_inner(
         cols,
         contiguous,
         mem_size: 0,
+        subscribers: Arc::clone(&subscribers),
     };
     let r = SingleReadHandle {
         handle: r,
         trigger,
         key: Vec::from(key),
+        subscribers,
     };
 
     (r, w)
@@ -111,6 +130,7 @@ pub(crate) struct WriteHandle {
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+    subscribers: Subscribers,
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -254,6 +274,39 @@ impl WriteHandle {
         self.partial
     }
 
+    /// Send each subscriber the records in `rs` whose key falls in its range.
+    ///
+    /// Subscribers whose receiving end has gone away, or that have fallen too far behind, are
+    /// dropped.
+    pub(crate) fn notify(&self, rs: &Records) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() || rs.is_empty() {
+            return;
+        }
+
+        let keys: Vec<Vec<DataType>> = rs
+            .iter()
+            .map(|r| self.key.iter().map(|&k| r[k].clone()).collect())
+            .collect();
+        let kept = subscribers
+            .drain(..)
+            .filter_map(|mut s| {
+                let deltas: Vec<Record> = rs
+                    .iter()
+                    .zip(&keys)
+                    .filter(|&(_, key)| s.range.contains(key))
+                    .map(|(r, _)| r.clone())
+                    .collect();
+                if deltas.is_empty() || s.tx.try_send(deltas).is_ok() {
+                    Some(s)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        *subscribers = kept;
+    }
+
     /// Evict `count` randomly selected keys from state and return them along with the number of
     /// bytes that will be freed once the underlying `evmap` applies the operation.
     pub(crate) fn evict_random_keys(&mut self, rng: &mut ThreadRng, mut n: usize) -> u64 {
@@ -300,6 +353,7 @@ pub struct SingleReadHandle {
     handle: multir::Handle,
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
     key: Vec<usize>,
+    subscribers: Subscribers,
 }
 
 impl std::fmt::Debug for SingleReadHandle {
@@ -308,6 +362,7 @@ impl std::fmt::Debug for SingleReadHandle {
             .field("handle", &self.handle)
             .field("has_trigger", &self.trigger.is_some())
             .field("key", &self.key)
+            .field("subscribers", &self.subscribers.lock().unwrap().len())
             .finish()
     }
 }
@@ -352,6 +407,33 @@ impl SingleReadHandle {
             })
     }
 
+    /// Register interest in changes to the keys in `range`.
+    ///
+    /// Every batch of updates that reaches the reader is narrowed down to the records whose key
+    /// falls in `range`, and any that remain are sent on the returned channel. The subscription
+    /// ends when the receiver is dropped, or when it falls so far behind that the channel fills
+    /// up, in which case the channel is closed once the receiver has drained it.
+    ///
+    /// Updates only reach a partially materialized reader for keys whose state is present
+    /// upstream, so subscribing to a single key that is missing triggers a replay of it. Keys
+    /// that are later evicted stop producing updates until they are read again.
+    pub fn subscribe(&self, range: KeyRange) -> mpsc::Receiver<Vec<Record>> {
+        if let (Bound::Included(ref lo), Bound::Included(ref hi)) = range {
+            if lo == hi && self.trigger.is_some() {
+                if let Ok((None, _)) = self.try_find_and(lo, |_| ()) {
+                    self.trigger(std::iter::once(&lo[..]));
+                }
+            }
+        }
+
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BACKLOG);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber { range, tx });
+        rx
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
@@ -564,4 +646,58 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn subscribers_only_see_their_keys() {
+        use std::ops::Bound::*;
+
+        let a = vec![1.into(), "a".into()];
+        let b = vec![2.into(), "b".into()];
+        let c = vec![3.into(), "c".into()];
+
+        let (r, w) = new(2, &[0]);
+        let mut one = r.subscribe((Included(vec![1.into()]), Included(vec![1.into()])));
+        let mut tail = r.subscribe((Excluded(vec![1.into()]), Unbounded));
+
+        w.notify(&vec![(a.clone(), true), (b.clone(), true), (c.clone(), false)].into());
+        assert_eq!(one.try_recv().unwrap(), vec![Record::Positive(a.clone())]);
+        assert!(one.try_recv().is_err());
+        assert_eq!(
+            tail.try_recv().unwrap(),
+            vec![Record::Positive(b.clone()), Record::Negative(c.clone())]
+        );
+
+        // nothing is sent for a batch that doesn't touch the key
+        w.notify(&vec![(b.clone(), false)].into());
+        assert!(one.try_recv().is_err());
+
+        // dropped receivers are unregistered
+        drop(tail);
+        w.notify(&vec![(b.clone(), true)].into());
+        assert_eq!(r.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn lagging_subscribers_are_dropped() {
+        use std::ops::Bound::*;
+
+        let a = vec![1.into(), "a".into()];
+        let (r, w) = new(2, &[0]);
+        let mut rx = r.subscribe((Unbounded, Unbounded));
+
+        for _ in 0..SUBSCRIBER_BACKLOG {
+            w.notify(&vec![a.clone()].into());
+        }
+        assert_eq!(r.subscribers.lock().unwrap().len(), 1);
+
+        // one more batch than fits, and the subscriber is gone
+        w.notify(&vec![a.clone()].into());
+        assert!(r.subscribers.lock().unwrap().is_empty());
+
+        // but what was sent before still arrives
+        for _ in 0..SUBSCRIBER_BACKLOG {
+            assert_eq!(rx.try_recv().unwrap(), vec![Record::Positive(a.clone())]);
+        }
+        assert!(rx.try_recv().is_err());
+    }
 }
@@ -384,11 +384,11 @@ impl Domain {
     fn send_partial_replay_request(&mut self, tag: Tag, keys: Vec<Vec<DataType>>) {
         debug_assert!(self.concurrent_replays < self.max_concurrent_replays);
         if let TriggerEndpoint::End {
-            source,
+            ref source,
             ref mut options,
         } = self.replay_paths.get_mut(&tag).unwrap().trigger
         {
-            let ask_shard_by_key_i = match source {
+            let ask_shard_by_key_i = match *source {
                 SourceSelection::AllShards(_) => None,
                 SourceSelection::SameShard => {
                     // note that we "ask all" here because we're not indexing the vector by the
@@ -396,7 +396,9 @@ impl Domain {
                     // options.len() == 1.
                     None
                 }
-                SourceSelection::KeyShard { key_i_to_shard, .. } => Some(key_i_to_shard),
+                SourceSelection::KeyShard {
+                    ref key_i_to_shard, ..
+                } => Some(key_i_to_shard.clone()),
             };
 
             if ask_shard_by_key_i.is_none() && options.len() != 1 {
@@ -449,7 +451,8 @@ impl Domain {
             } else if let Some(key_shard_i) = ask_shard_by_key_i {
                 let mut shards = HashMap::new();
                 for key in keys {
-                    let shard = crate::shard_by(&key[key_shard_i], options.len());
+                    let shard_key: Vec<_> = key_shard_i.iter().map(|&i| key[i].clone()).collect();
+                    let shard = crate::shard_by_key(&shard_key[..], options.len());
                     shards.entry(shard).or_insert_with(Vec::new).push(key);
                 }
                 for (shard, keys) in shards {
@@ -855,6 +858,12 @@ impl Domain {
                             s.add_sharded_child(new_txs.0, new_txs.1);
                         });
                     }
+                    Packet::UpdateSamplingRate { node, from, rate } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.get_union_mut()
+                            .expect("told to update sampling rate of non-union node")
+                            .set_sampling_rate(from, rate);
+                    }
                     Packet::StateSizeProbe { node } => {
                         let row_count = self.state.get(node).map(|r| r.rows()).unwrap_or(0);
                         let mem_size = self.state.get(node).map(|s| s.deep_size_of()).unwrap_or(0);
@@ -1424,6 +1433,7 @@ impl Domain {
                                 } else {
                                     Default::default()
                                 };
+                                let metrics = if n.is_internal() { n.metrics() } else { None };
 
                                 if time.is_some() && ptime.is_some() {
                                     Some((
@@ -1435,6 +1445,7 @@ impl Domain {
                                             mem_size,
                                             materialized: mat_state,
                                             probe_result,
+                                            metrics,
                                         },
                                     ))
                                 } else {
@@ -37,12 +37,14 @@ pub type DomainConfig = domain::Config;
 pub use crate::domain::{Domain, DomainBuilder, Index, PollEvent, ProcessResult};
 pub use crate::payload::Packet;
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Sharding {
     None,
     ForcedNone,
     Random(usize),
     ByColumn(usize, usize),
+    /// Sharded by the combined value of several columns (see `noria::shard_by_key`).
+    ByColumns(Vec<usize>, usize),
 }
 
 impl Sharding {
@@ -56,7 +58,18 @@ impl Sharding {
     pub fn shards(&self) -> Option<usize> {
         match *self {
             Sharding::None | Sharding::ForcedNone => None,
-            Sharding::Random(shards) | Sharding::ByColumn(_, shards) => Some(shards),
+            Sharding::Random(shards)
+            | Sharding::ByColumn(_, shards)
+            | Sharding::ByColumns(_, shards) => Some(shards),
+        }
+    }
+
+    /// Sharding by the given key columns, using `ByColumn` if there is only one of them.
+    pub fn by_columns(cols: &[usize], shards: usize) -> Sharding {
+        if let [c] = *cols {
+            Sharding::ByColumn(c, shards)
+        } else {
+            Sharding::ByColumns(cols.to_vec(), shards)
         }
     }
 }
@@ -128,4 +141,4 @@ impl PersistenceParameters {
     }
 }
 
-pub use noria::shard_by;
+pub use noria::{shard_by, shard_by_key};
@@ -9,7 +9,7 @@ impl fmt::Debug for Node {
             NodeType::Source => write!(f, "source node"),
             NodeType::Ingress => write!(f, "ingress node"),
             NodeType::Egress { .. } => write!(f, "egress node"),
-            NodeType::Sharder(ref s) => write!(f, "sharder [{}] node", s.sharded_by()),
+            NodeType::Sharder(ref s) => write!(f, "sharder {:?} node", s.sharded_by_columns()),
             NodeType::Reader(..) => write!(f, "reader node"),
             NodeType::Base(..) => write!(f, "B"),
             NodeType::Internal(ref i) => write!(f, "internal {} node", i.description(true)),
@@ -26,7 +26,9 @@ impl Node {
     ) -> String {
         let mut s = String::new();
         let border = match self.sharded_by {
-            Sharding::ByColumn(_, _) | Sharding::Random(_) => "filled,dashed",
+            Sharding::ByColumn(..) | Sharding::ByColumns(..) | Sharding::Random(_) => {
+                "filled,dashed"
+            }
             _ => {
                 if Self::is_security(self.name()) {
                     "filled,rounded"
@@ -53,7 +55,14 @@ impl Node {
                 NodeType::Sharder(ref sharder) => {
                     s.push_str(&format!(
                         "[style=bold, shape=Msquare, label=\"shard by {}\"]\n",
-                        Self::escape(&self.fields[sharder.sharded_by()]),
+                        Self::escape(
+                            &sharder
+                                .sharded_by_columns()
+                                .iter()
+                                .map(|&c| &self.fields[c][..])
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
                     ));
                 }
                 NodeType::Reader(_) => {
@@ -135,6 +144,14 @@ impl Node {
 
             let sharding = match self.sharded_by {
                 Sharding::ByColumn(k, w) => format!("shard ⚷: {} / {}-way", self.fields[k], w),
+                Sharding::ByColumns(ref ks, w) => format!(
+                    "shard ⚷: {} / {}-way",
+                    ks.iter()
+                        .map(|&k| self.fields[k].as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    w
+                ),
                 Sharding::Random(_) => "shard randomly".to_owned(),
                 Sharding::None => "unsharded".to_owned(),
                 Sharding::ForcedNone => "desharded to avoid SS".to_owned(),
@@ -175,7 +192,12 @@ impl Node {
                 NodeType::Sharder(ref sharder) => s.push_str(&format!(
                     "{{ {} | shard by {} | {} }}",
                     addr,
-                    self.fields[sharder.sharded_by()],
+                    sharder
+                        .sharded_by_columns()
+                        .iter()
+                        .map(|&c| &self.fields[c][..])
+                        .collect::<Vec<_>>()
+                        .join(", "),
                     sharding
                 )),
                 NodeType::Reader(ref r) => {
@@ -98,8 +98,8 @@ impl Node {
     /// Called when a node is first connected to the graph.
     ///
     /// All its ancestors are present, but this node and its children may not have been connected
-    /// yet.
-    pub fn on_connected(&mut self, graph: &Graph) {
+    /// yet. An error means the node cannot work with its ancestors as they are.
+    pub fn on_connected(&mut self, graph: &Graph) -> Result<(), String> {
         Ingredient::on_connected(&mut **self, graph)
     }
 
@@ -163,6 +163,8 @@ impl Node {
     ///    ⋈    |  Join
     ///    ⋉    |  Left join
     ///    ⋃    |  Union
+    ///    ⩁    |  Distinct union
+    ///    ⊍    |  Shard merger
     pub fn description(&self, detailed: bool) -> String {
         Ingredient::description(&**self, detailed)
     }
@@ -179,7 +181,7 @@ impl Node {
     }
 
     pub fn sharded_by(&self) -> Sharding {
-        self.sharded_by
+        self.sharded_by.clone()
     }
 
     /// Set this node's sharding property.
@@ -381,6 +383,14 @@ impl Node {
         }
     }
 
+    pub fn get_union_mut(&mut self) -> Option<&mut ops::union::Union> {
+        if let NodeType::Internal(NodeOperator::Union(ref mut u)) = self.inner {
+            Some(u)
+        } else {
+            None
+        }
+    }
+
     pub fn add_to(&mut self, domain: domain::Index) {
         assert_eq!(self.domain, None);
         assert!(!self.is_dropped());
@@ -115,6 +115,11 @@ impl Reader {
     pub(in crate::node) fn process(&mut self, m: &mut Option<Box<Packet>>, swap: bool) {
         if let Some(ref mut state) = self.writer {
             let m = m.as_mut().unwrap();
+            // subscribers see every update, including those to keys that are holes here
+            if m.is_regular() {
+                m.map_data(|data| state.notify(data));
+            }
+
             // make sure we don't fill a partial materialization
             // hole with incomplete (i.e., non-replay) state.
             if m.is_regular() && state.is_partial() {
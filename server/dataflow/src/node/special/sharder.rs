@@ -6,7 +6,7 @@ use vec_map::VecMap;
 pub struct Sharder {
     txs: Vec<(LocalNodeIndex, ReplicaAddr)>,
     sharded: VecMap<Box<Packet>>,
-    shard_by: usize,
+    shard_by: Vec<usize>,
 }
 
 impl Clone for Sharder {
@@ -16,13 +16,20 @@ impl Clone for Sharder {
         Sharder {
             txs: Vec::new(),
             sharded: Default::default(),
-            shard_by: self.shard_by,
+            shard_by: self.shard_by.clone(),
         }
     }
 }
 
 impl Sharder {
     pub fn new(by: usize) -> Self {
+        Self::new_composite(vec![by])
+    }
+
+    /// Construct a sharder that shards by the combined value of several columns, so that records
+    /// with the same values in all of `by` go to the same shard.
+    pub fn new_composite(by: Vec<usize>) -> Self {
+        assert!(!by.is_empty());
         Self {
             txs: Default::default(),
             shard_by: by,
@@ -36,7 +43,7 @@ impl Sharder {
         Self {
             txs,
             sharded: VecMap::default(),
-            shard_by: self.shard_by,
+            shard_by: self.shard_by.clone(),
         }
     }
 
@@ -48,18 +55,36 @@ impl Sharder {
         }
     }
 
+    /// The column this sharder shards by.
+    ///
+    /// Panics if the sharder shards by a composite key; use `sharded_by_columns` for those.
     pub fn sharded_by(&self) -> usize {
-        self.shard_by
+        assert_eq!(
+            self.shard_by.len(),
+            1,
+            "sharder shards by a composite key {:?}",
+            self.shard_by
+        );
+        self.shard_by[0]
+    }
+
+    /// The columns this sharder shards by.
+    pub fn sharded_by_columns(&self) -> &[usize] {
+        &self.shard_by[..]
     }
 
     #[inline]
     fn to_shard(&self, r: &Record) -> usize {
-        self.shard(&r[self.shard_by])
+        if let [c] = self.shard_by[..] {
+            return crate::shard_by(&r[c], self.txs.len());
+        }
+        let key: Vec<_> = self.shard_by.iter().map(|&c| r[c].clone()).collect();
+        self.shard(&key[..])
     }
 
     #[inline]
-    fn shard(&self, dt: &DataType) -> usize {
-        crate::shard_by(dt, self.txs.len())
+    fn shard(&self, key: &[DataType]) -> usize {
+        crate::shard_by_key(key, self.txs.len())
     }
 
     pub fn process(
@@ -171,10 +196,10 @@ impl Sharder {
     ) {
         assert!(!is_sharded);
 
-        if key_columns.len() == 1 && key_columns[0] == self.shard_by {
+        if key_columns == &self.shard_by[..] {
             // Send only to the shards that must evict something.
             for key in keys {
-                let shard = self.shard(&key[0]);
+                let shard = self.shard(&key[..]);
                 let dst = self.txs[shard].0;
                 let p = self.sharded.entry(shard).or_insert_with(|| {
                     Box::new(Packet::EvictKeys {
@@ -196,7 +221,7 @@ impl Sharder {
             }
         } else {
             assert_eq!(!key_columns.len(), 0);
-            assert!(!key_columns.contains(&self.shard_by));
+            assert!(self.shard_by.len() > 1 || !key_columns.contains(&self.shard_by[0]));
 
             // send to all shards
             for &mut (dst, addr) in self.txs.iter_mut() {
@@ -212,3 +237,85 @@ impl Sharder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Collect(Vec<(ReplicaAddr, Box<Packet>)>);
+
+    impl Executor for Collect {
+        fn ack(&mut self, _: SourceChannelIdentifier) {}
+        fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+        fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
+            self.0.push((dest, m));
+        }
+    }
+
+    fn sharder(by: Vec<usize>, shards: usize) -> Sharder {
+        let mut s = Sharder::new_composite(by);
+        let dst = unsafe { LocalNodeIndex::make(1) };
+        s.add_sharded_child(
+            dst,
+            (0..shards).map(|i| (DomainIndex::from(0), i)).collect(),
+        );
+        s
+    }
+
+    fn send(s: &mut Sharder, rs: Vec<Vec<DataType>>) -> Vec<(usize, Vec<Record>)> {
+        let src = unsafe { LocalNodeIndex::make(0) };
+        let mut m = Some(Box::new(Packet::Message {
+            link: Link::new(src, src),
+            data: rs.into(),
+        }));
+        let mut out = Collect::default();
+        s.process(&mut m, src, false, None, &mut out);
+        out.0
+            .into_iter()
+            .map(|((_, shard), mut p)| (shard, p.take_data().into()))
+            .collect()
+    }
+
+    #[test]
+    fn it_shards_by_one_column() {
+        let mut s = sharder(vec![1], 4);
+        assert_eq!(s.sharded_by(), 1);
+        let rs = vec![vec![1.into(), 7.into()], vec![2.into(), 7.into()]];
+        let out = send(&mut s, rs);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].0, crate::shard_by(&7.into(), 4));
+        assert_eq!(out[0].1.len(), 2);
+    }
+
+    #[test]
+    fn it_shards_by_composite_key() {
+        let mut s = sharder(vec![0, 2], 4);
+        assert_eq!(s.sharded_by_columns(), &[0, 2]);
+
+        let rs: Vec<Vec<DataType>> = (0..32)
+            .map(|i| vec![(i % 5).into(), i.into(), (i % 3).into()])
+            .collect();
+        let out = send(&mut s, rs.clone());
+        assert_eq!(out.iter().map(|(_, rs)| rs.len()).sum::<usize>(), rs.len());
+        for (shard, rs) in out {
+            for r in rs {
+                // every record goes to the shard the client would ask for its key
+                let key = vec![r[0].clone(), r[2].clone()];
+                assert_eq!(shard, crate::shard_by_key(&key[..], 4));
+            }
+        }
+    }
+
+    #[test]
+    fn it_sends_composite_evictions_to_owning_shard() {
+        let mut s = sharder(vec![0, 1], 4);
+        let src = unsafe { LocalNodeIndex::make(0) };
+        let key: Vec<DataType> = vec![1.into(), "a".into()];
+        let mut out = Collect::default();
+        s.process_eviction(&[0, 1], Tag::new(0), &[key.clone()], src, false, &mut out);
+        assert_eq!(out.0.len(), 1);
+        assert_eq!((out.0[0].0).1, crate::shard_by_key(&key[..], 4));
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ops::project::KeyExpression;
+use crate::prelude::*;
+
+/// AsOfJoin joins every left record with the most recent right record for the same key whose
+/// timestamp is not after the left record's timestamp.
+///
+/// Both sides are kept in operator-internal state. The right side is stored ordered by timestamp
+/// for every key so that the latest-not-after lookup is a single range query. The left side is
+/// kept so that a right-side change that alters which row a left record matched can revoke the old
+/// output row and emit the new one.
+///
+/// Since both sides exist only in the operator, its output is always fully materialized, and
+/// replays are served from that materialization rather than traced through to either ancestor.
+///
+/// Left records without a prior right record for their key produce no output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AsOfJoin {
+    left: IndexPair,
+    right: IndexPair,
+
+    // Key column in the left and right parents respectively
+    on: (usize, usize),
+
+    // Expressions computing the keys in the left and right parents, in place of the key columns
+    keys: Option<(KeyExpression, KeyExpression)>,
+
+    // Timestamp column in the left and right parents respectively
+    ts: (usize, usize),
+
+    // Which columns to emit. True means the column is from the left parent, false means from the
+    // right
+    emit: Vec<(bool, usize)>,
+
+    left_rows: HashMap<DataType, Vec<Vec<DataType>>>,
+    right_rows: HashMap<DataType, BTreeMap<DataType, Vec<Vec<DataType>>>>,
+}
+
+impl AsOfJoin {
+    /// Construct a new as-of join operator.
+    ///
+    /// `on` gives the key columns in the left and right parents, and `ts` gives the timestamp
+    /// columns in the left and right parents. `emit` dictates for each output column which source
+    /// and column should be used (true means left parent, and false means right parent).
+    pub fn new(
+        left: NodeIndex,
+        right: NodeIndex,
+        on: (usize, usize),
+        ts: (usize, usize),
+        emit: Vec<(bool, usize)>,
+    ) -> Self {
+        AsOfJoin {
+            left: left.into(),
+            right: right.into(),
+            on,
+            keys: None,
+            ts,
+            emit,
+            left_rows: HashMap::new(),
+            right_rows: HashMap::new(),
+        }
+    }
+
+    /// Match records on keys computed by the given expressions rather than on the `on` columns.
+    ///
+    /// `left` computes the key of records from the left parent, and `right` that of records from
+    /// the right parent. Both are computed the same way for every record, whether it is a new
+    /// record or one that is being matched against, so that, for example, `Lower` on both sides
+    /// joins records whose keys differ only in casing.
+    pub fn with_key_expressions(mut self, left: KeyExpression, right: KeyExpression) -> Self {
+        self.keys = Some((left, right));
+        self
+    }
+
+    fn left_key(&self, r: &[DataType]) -> DataType {
+        match self.keys {
+            Some((ref e, _)) => e.eval(r),
+            None => r[self.on.0].clone(),
+        }
+    }
+
+    fn right_key(&self, r: &[DataType]) -> DataType {
+        match self.keys {
+            Some((_, ref e)) => e.eval(r),
+            None => r[self.on.1].clone(),
+        }
+    }
+
+    /// Find the right row that a left row with the given key and timestamp currently matches.
+    fn matching(&self, key: &DataType, ts: &DataType) -> Option<&Vec<DataType>> {
+        self.right_rows
+            .get(key)
+            .and_then(|rows| rows.range(..=ts.clone()).next_back())
+            .and_then(|(_, rs)| rs.last())
+    }
+
+    fn generate_row(&self, left: &[DataType], right: &[DataType]) -> Vec<DataType> {
+        self.emit
+            .iter()
+            .map(|&(from_left, col)| {
+                if from_left {
+                    left[col].clone()
+                } else {
+                    right[col].clone()
+                }
+            })
+            .collect()
+    }
+
+    fn on_left(&mut self, r: Record, out: &mut Vec<Record>) {
+        let (r, positive) = r.extract();
+        let key = self.left_key(&r);
+
+        if let Some(m) = self.matching(&key, &r[self.ts.0]) {
+            out.push((self.generate_row(&r, m), positive).into());
+        }
+
+        let rows = self.left_rows.entry(key.clone()).or_insert_with(Vec::new);
+        if positive {
+            rows.push(r);
+        } else {
+            if let Some(i) = rows.iter().position(|l| l == &r) {
+                rows.swap_remove(i);
+            }
+            if rows.is_empty() {
+                self.left_rows.remove(&key);
+            }
+        }
+    }
+
+    fn on_right(&mut self, r: Record, out: &mut Vec<Record>) {
+        let (r, positive) = r.extract();
+        let key = self.right_key(&r);
+        let ts = r[self.ts.1].clone();
+
+        // only left rows at or after the changed timestamp can change what they match
+        let affected: Vec<_> = self
+            .left_rows
+            .get(&key)
+            .map(|ls| {
+                ls.iter()
+                    .filter(|l| l[self.ts.0] >= ts)
+                    .map(|l| (l.clone(), self.matching(&key, &l[self.ts.0]).cloned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let rows = self
+            .right_rows
+            .entry(key.clone())
+            .or_insert_with(BTreeMap::new);
+        if positive {
+            rows.entry(ts).or_insert_with(Vec::new).push(r);
+        } else {
+            if let Some(at) = rows.get_mut(&ts) {
+                if let Some(i) = at.iter().position(|rr| rr == &r) {
+                    at.remove(i);
+                }
+                if at.is_empty() {
+                    rows.remove(&ts);
+                }
+            }
+            if rows.is_empty() {
+                self.right_rows.remove(&key);
+            }
+        }
+
+        for (l, before) in affected {
+            let after = self.matching(&key, &l[self.ts.0]);
+            if before.as_ref() == after {
+                continue;
+            }
+            if let Some(ref before) = before {
+                out.push(Record::Negative(self.generate_row(&l, before)));
+            }
+            if let Some(after) = after {
+                out.push(Record::Positive(self.generate_row(&l, after)));
+            }
+        }
+    }
+}
+
+impl Ingredient for AsOfJoin {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.left.as_global(), self.right.as_global()]
+    }
+
+    fn on_connected(&mut self, _: &Graph) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.left.remap(remap);
+        self.right.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        let mut out = Vec::with_capacity(rs.len());
+        if from == *self.left {
+            for r in rs {
+                self.on_left(r, &mut out);
+            }
+        } else {
+            debug_assert_eq!(from, *self.right);
+            for r in rs {
+                self.on_right(r, &mut out);
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // all lookups go to internal state, so our output must be materialized for replays to be
+        // served from it rather than applied to that state again
+        let all = (0..self.emit.len()).collect();
+        Some((this, all)).into_iter().collect()
+    }
+
+    fn resolve(&self, _: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        // which right row a left row matches depends on state that only we hold
+        None
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("⋈≤");
+        }
+
+        let emit = self
+            .emit
+            .iter()
+            .map(|&(from_left, col)| {
+                let src = if from_left { self.left } else { self.right };
+                format!("{}:{}", src.as_global().index(), col)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let (lkey, rkey) = match self.keys {
+            Some((ref l, ref r)) => (l.to_string(), r.to_string()),
+            None => (self.on.0.to_string(), self.on.1.to_string()),
+        };
+        format!(
+            "[{}] {}:({}, {}) ⋈≤ {}:({}, {})",
+            emit,
+            self.left.as_global().index(),
+            lkey,
+            self.ts.0,
+            self.right.as_global().index(),
+            rkey,
+            self.ts.1
+        )
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        let e = self.emit[col];
+        if e.0 {
+            vec![(self.left.as_global(), Some(e.1))]
+        } else {
+            vec![(self.right.as_global(), Some(e.1))]
+        }
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["sym", "ts", "qty"]);
+        let r = g.add_base("right", &["sym", "ts", "price"]);
+
+        let j = AsOfJoin::new(
+            l.as_global(),
+            r.as_global(),
+            (0, 0),
+            (1, 1),
+            vec![(true, 0), (true, 1), (true, 2), (false, 2)],
+        );
+        g.set_op("asof", &["sym", "ts", "qty", "price"], j, false);
+        (g, l, r)
+    }
+
+    #[test]
+    fn it_matches_latest_prior() {
+        let (mut j, l, r) = setup();
+
+        j.one_row(r, vec![1.into(), 10.into(), "a".into()], false);
+        j.one_row(r, vec![1.into(), 20.into(), "b".into()], false);
+        j.one_row(r, vec![1.into(), 30.into(), "c".into()], false);
+
+        // a left row at 25 should pick up the right row at 20
+        let rs = j.one_row(l, vec![1.into(), 25.into(), 5.into()], false);
+        assert_eq!(
+            rs,
+            vec![vec![1.into(), 25.into(), 5.into(), "b".into()]].into()
+        );
+
+        // a left row before any right row produces nothing
+        let rs = j.one_row(l, vec![1.into(), 5.into(), 5.into()], false);
+        assert!(rs.is_empty());
+    }
+
+    #[test]
+    fn it_rematches() {
+        let (mut j, l, r) = setup();
+
+        j.one_row(r, vec![1.into(), 10.into(), "a".into()], false);
+        let rs = j.one_row(l, vec![1.into(), 25.into(), 5.into()], false);
+        assert_eq!(
+            rs,
+            vec![vec![1.into(), 25.into(), 5.into(), "a".into()]].into()
+        );
+
+        // an intervening right row should move the match
+        let rs = j.one_row(r, vec![1.into(), 20.into(), "b".into()], false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 25.into(), 5.into(), "a".into()], false),
+                (vec![1.into(), 25.into(), 5.into(), "b".into()], true),
+            ]
+            .into()
+        );
+
+        // a right row after the left row changes nothing
+        let rs = j.one_row(r, vec![1.into(), 30.into(), "c".into()], false);
+        assert!(rs.is_empty());
+
+        // and removing the intervening row moves the match back
+        let rs = j.one_row(r, (vec![1.into(), 20.into(), "b".into()], false), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 25.into(), 5.into(), "b".into()], false),
+                (vec![1.into(), 25.into(), 5.into(), "a".into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_matches_computed_keys() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["sym", "ts", "qty"]);
+        let r = g.add_base("right", &["sym", "ts", "price"]);
+
+        let lower = |c| KeyExpression::Lower(Box::new(KeyExpression::Column(c)));
+        let j = AsOfJoin::new(
+            l.as_global(),
+            r.as_global(),
+            (0, 0),
+            (1, 1),
+            vec![(true, 0), (true, 1), (true, 2), (false, 0), (false, 2)],
+        )
+        .with_key_expressions(lower(0), lower(0));
+        g.set_op("asof", &["sym", "ts", "qty", "rsym", "price"], j, false);
+
+        g.one_row(r, vec!["abc".into(), 10.into(), "a".into()], false);
+        g.one_row(r, vec!["xyz".into(), 10.into(), "x".into()], false);
+
+        // the keys differ in casing, but match once case-folded
+        let rs = g.one_row(l, vec!["ABC".into(), 25.into(), 5.into()], false);
+        assert_eq!(
+            rs,
+            vec![vec![
+                "ABC".into(),
+                25.into(),
+                5.into(),
+                "abc".into(),
+                "a".into()
+            ]]
+            .into()
+        );
+
+        // and right-side changes find the left rows they affect the same way
+        let rs = g.one_row(r, vec!["aBc".into(), 20.into(), "b".into()], false);
+        assert_eq!(rs.len(), 2);
+        let matched: Vec<DataType> =
+            vec!["ABC".into(), 25.into(), 5.into(), "aBc".into(), "b".into()];
+        assert!(rs.has_positive(&matched[..]));
+    }
+
+    #[test]
+    fn it_materializes_itself() {
+        let (j, _, _) = setup();
+        let me = 3.into();
+        let idx = j.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![0, 1, 2, 3]);
+        assert_eq!(j.node().resolve(0), None);
+        assert_eq!(j.node().resolve(3), None);
+    }
+}
@@ -3,6 +3,90 @@ use std::collections::HashMap;
 
 use crate::prelude::*;
 
+/// How values in a column are compared when deciding whether two records are the same.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Equality {
+    /// Values must be identical.
+    Exact,
+    /// Text values are equal if they only differ in case.
+    CaseInsensitive,
+    /// Real values are equal if they round to the same multiple of the given epsilon.
+    Epsilon(f64),
+}
+
+impl Equality {
+    /// Map `v` to the representative of all the values it is equal to under this policy.
+    pub fn canonicalize(&self, v: &DataType) -> DataType {
+        match (self, v) {
+            (Equality::CaseInsensitive, DataType::Text(..))
+            | (Equality::CaseInsensitive, DataType::TinyText(..)) => {
+                let s: &str = v.into();
+                s.to_lowercase().into()
+            }
+            (&Equality::Epsilon(eps), DataType::Real(..)) => {
+                let f: f64 = v.into();
+                ((f / eps).round() * eps).into()
+            }
+            _ => v.clone(),
+        }
+    }
+}
+
+/// A counting Bloom filter over groups.
+///
+/// Plain Bloom filters cannot forget keys, but Distinct must forget groups when their last record
+/// is retracted. Each bit is therefore replaced by a small counter that is incremented when a key
+/// that hashes to it is added, and decremented when such a key is removed. A counter that
+/// overflows sticks at its maximum and is never decremented again. This may cause false positives,
+/// but never false negatives.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CountingBloom {
+    counters: Vec<u8>,
+    hashes: usize,
+}
+
+impl CountingBloom {
+    fn new(counters: usize, hashes: usize) -> Self {
+        assert!(counters > 0 && hashes > 0);
+        CountingBloom {
+            counters: vec![0; counters],
+            hashes,
+        }
+    }
+
+    fn slots<'a>(&'a self, key: &'a [DataType]) -> impl Iterator<Item = usize> + 'a {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        (0..self.hashes).map(move |i| {
+            let mut hasher = DefaultHasher::new();
+            i.hash(&mut hasher);
+            key.hash(&mut hasher);
+            hasher.finish() as usize % self.counters.len()
+        })
+    }
+
+    fn insert(&mut self, key: &[DataType]) {
+        for slot in self.slots(key).collect::<Vec<_>>() {
+            let c = &mut self.counters[slot];
+            *c = c.saturating_add(1);
+        }
+    }
+
+    fn remove(&mut self, key: &[DataType]) {
+        for slot in self.slots(key).collect::<Vec<_>>() {
+            let c = &mut self.counters[slot];
+            if *c != u8::max_value() {
+                *c = c.saturating_sub(1);
+            }
+        }
+    }
+
+    fn may_contain(&self, key: &[DataType]) -> bool {
+        self.slots(key).all(|slot| self.counters[slot] > 0)
+    }
+}
+
 /// This will get distinct records from a set of records compared over a given set of columns
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Distinct {
@@ -12,6 +96,15 @@ pub struct Distinct {
     us: Option<IndexPair>,
 
     group_by: Vec<usize>,
+
+    // Columns compared with something other than exact equality. Records are emitted with these
+    // columns canonicalized, so that our own state is keyed by the canonical values.
+    equality: Vec<(usize, Equality)>,
+
+    // Groups we have emitted, used to skip state lookups for groups we definitely haven't.
+    bloom: Option<CountingBloom>,
+    lookups: usize,
+    prescreened: usize,
 }
 
 impl Distinct {
@@ -22,8 +115,55 @@ impl Distinct {
             src: src.into(),
             us: None,
             group_by,
+            equality: Vec::new(),
+            bloom: None,
+            lookups: 0,
+            prescreened: 0,
         }
     }
+
+    /// Estimate how many records this operator holds given an estimate for its ancestor.
+    ///
+    /// `distinctness` is the expected fraction of the ancestor's records that are distinct in the
+    /// compared columns. At least one record remains if the ancestor is non-empty.
+    pub fn estimated_cardinality(
+        &self,
+        parent_estimates: &HashMap<NodeIndex, usize>,
+        distinctness: f64,
+    ) -> usize {
+        assert!((0.0..=1.0).contains(&distinctness));
+        match parent_estimates.get(&self.src.as_global()) {
+            Some(&0) | None => 0,
+            Some(&n) => ((n as f64 * distinctness).round() as usize).max(1),
+        }
+    }
+
+    /// Compare values in column `col` using `equality` rather than exact equality.
+    pub fn with_equality(mut self, col: usize, equality: Equality) -> Self {
+        assert!(
+            self.group_by.contains(&col),
+            "equality policy given for column that is not compared"
+        );
+        self.equality.retain(|&(c, _)| c != col);
+        if equality != Equality::Exact {
+            self.equality.push((col, equality));
+        }
+        self
+    }
+
+    /// Keep a counting Bloom filter with `counters` counters and `hashes` hash functions over the
+    /// groups this operator has emitted.
+    ///
+    /// Records whose group the filter has definitely not seen are handled without looking up the
+    /// group in this operator's state.
+    pub fn with_bloom_filter(mut self, counters: usize, hashes: usize) -> Self {
+        self.bloom = Some(CountingBloom::new(counters, hashes));
+        self
+    }
+
+    fn group_of(&self, r: &[DataType]) -> Vec<DataType> {
+        self.group_by.iter().map(|&c| r[c].clone()).collect()
+    }
 }
 
 impl Ingredient for Distinct {
@@ -62,7 +202,19 @@ impl Ingredient for Distinct {
 
         let pos_comp = |a: &Record, b: &Record| a.is_positive().cmp(&b.is_positive());
 
-        let mut rs: Vec<_> = rs.into();
+        let mut rs: Vec<_> = if self.equality.is_empty() {
+            rs.into()
+        } else {
+            rs.into_iter()
+                .map(|r| {
+                    let (mut r, positive) = r.extract();
+                    for &(col, ref equality) in &self.equality {
+                        r[col] = equality.canonicalize(&r[col]);
+                    }
+                    Record::from((r, positive))
+                })
+                .collect()
+        };
         // Sort by positive or negative
         rs.sort_by(&pos_comp);
 
@@ -112,6 +264,17 @@ impl Ingredient for Distinct {
             prev_pos = rec.is_positive();
 
             let positive = rec.is_positive();
+            if let Some(ref bloom) = self.bloom {
+                if !bloom.may_contain(&group) {
+                    // we've never emitted this group, so there's nothing to look up
+                    self.prescreened += 1;
+                    if positive {
+                        output.push(rec.clone());
+                    }
+                    continue;
+                }
+            }
+            self.lookups += 1;
             match db.lookup(group_by, &KeyType::from(&group[..])) {
                 LookupResult::Some(rr) => {
                     if positive {
@@ -127,6 +290,22 @@ impl Ingredient for Distinct {
             }
         }
 
+        // only update the filter now, so that it agrees with our state during the lookups above
+        if self.bloom.is_some() {
+            let groups: Vec<_> = output
+                .iter()
+                .map(|rec| (self.group_of(rec), rec.is_positive()))
+                .collect();
+            let bloom = self.bloom.as_mut().unwrap();
+            for (group, positive) in groups {
+                if positive {
+                    bloom.insert(&group);
+                } else {
+                    bloom.remove(&group);
+                }
+            }
+        }
+
         ProcessingResult {
             results: output.into(),
             ..Default::default()
@@ -137,7 +316,16 @@ impl Ingredient for Distinct {
         "Distinct".into()
     }
 
-    fn on_connected(&mut self, _: &Graph) {}
+    fn probe(&self) -> HashMap<String, String> {
+        let mut hm = HashMap::new();
+        hm.insert("lookups".into(), format!("{}", self.lookups));
+        hm.insert("prescreened".into(), format!("{}", self.prescreened));
+        hm
+    }
+
+    fn on_connected(&mut self, _: &Graph) -> Result<(), String> {
+        Ok(())
+    }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.src.remap(remap);
@@ -226,6 +414,102 @@ mod tests {
         assert_eq!(a, vec![r1.clone()].into());
     }
 
+    #[test]
+    fn distinct_case_insensitive() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "distinct",
+            &["x", "y", "z"],
+            Distinct::new(s.as_global(), vec![1, 2]).with_equality(1, Equality::CaseInsensitive),
+            true,
+        );
+
+        let r1: Vec<DataType> = vec![1.into(), "ABC".into(), 1.into()];
+        let r2: Vec<DataType> = vec![1.into(), "abc".into(), 1.into()];
+        let canonical = r2.clone();
+
+        let a = g.narrow_one_row(r1.clone(), true);
+        assert_eq!(a, vec![canonical.clone()].into());
+
+        let a = g.narrow_one_row(r2.clone(), true);
+        assert_eq!(a.len(), 0);
+
+        let a = g.narrow_one_row((r1.clone(), false), true);
+        assert_eq!(a, vec![(canonical, false)].into());
+    }
+
+    #[test]
+    fn distinct_estimates_cardinality() {
+        let g = setup(true);
+        let src = g.narrow_base_id().as_global();
+        let estimate = |estimates: &HashMap<_, _>, distinctness| match **g.node() {
+            NodeOperator::Distinct(ref d) => d.estimated_cardinality(estimates, distinctness),
+            _ => unreachable!(),
+        };
+
+        let mut estimates = HashMap::new();
+        assert_eq!(estimate(&estimates, 0.5), 0);
+        estimates.insert(src, 1000);
+        assert_eq!(estimate(&estimates, 0.25), 250);
+        assert_eq!(estimate(&estimates, 1.0), 1000);
+        assert_eq!(estimate(&estimates, 0.0), 1);
+    }
+
+    #[test]
+    fn equality_policies() {
+        let abc: DataType = "aBc".into();
+        assert_eq!(Equality::Exact.canonicalize(&abc), abc);
+        assert_eq!(Equality::CaseInsensitive.canonicalize(&abc), "abc".into());
+        assert_eq!(
+            Equality::Epsilon(0.1).canonicalize(&DataType::from(1.02)),
+            Equality::Epsilon(0.1).canonicalize(&DataType::from(0.98))
+        );
+        assert_eq!(
+            Equality::CaseInsensitive.canonicalize(&DataType::from(1)),
+            DataType::from(1)
+        );
+    }
+
+    #[test]
+    fn distinct_bloom_prescreen() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "distinct",
+            &["x", "y", "z"],
+            Distinct::new(s.as_global(), vec![1, 2]).with_bloom_filter(1024, 3),
+            true,
+        );
+        let probe = |g: &ops::test::MockGraph, what: &str| -> usize {
+            g.node().probe()[what].parse().unwrap()
+        };
+
+        let r1: Vec<DataType> = vec![1.into(), "z".into(), 1.into()];
+        let r2: Vec<DataType> = vec![2.into(), "z".into(), 1.into()];
+        let r3: Vec<DataType> = vec![3.into(), "c".into(), 2.into()];
+
+        // new groups are emitted without looking at our state
+        assert_eq!(g.narrow_one_row(r1.clone(), true), vec![r1.clone()].into());
+        assert_eq!(g.narrow_one_row(r3.clone(), true), vec![r3.clone()].into());
+        assert_eq!(probe(&g, "prescreened"), 2);
+        assert_eq!(probe(&g, "lookups"), 0);
+
+        // but a group we've emitted has to be looked up
+        assert_eq!(g.narrow_one_row(r2.clone(), true).len(), 0);
+        assert_eq!(probe(&g, "lookups"), 1);
+
+        // once a group is retracted, it's forgotten again
+        assert_eq!(
+            g.narrow_one_row((r3.clone(), false), true),
+            vec![(r3.clone(), false)].into()
+        );
+        assert_eq!(probe(&g, "lookups"), 2);
+        assert_eq!(g.narrow_one_row(r3.clone(), true), vec![r3].into());
+        assert_eq!(probe(&g, "lookups"), 2);
+        assert_eq!(probe(&g, "prescreened"), 3);
+    }
+
     #[test]
     fn multiple_records_distinct() {
         let mut g = setup(true);
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+/// Except emits every distinct record that its positive ancestor holds more copies of than its
+/// negative ancestor.
+///
+/// Like a union, the operator selects a set of columns from each ancestor, and compares records on
+/// only those columns. It counts how many times each record has been received from either side,
+/// and emits a record while its count on the positive side exceeds its count on the negative side.
+/// Deletes on either side may move a record across that boundary in either direction, in which
+/// case the record is emitted or revoked.
+///
+/// The counts exist only in the operator, so its output is always fully materialized, and
+/// replays are served from that materialization rather than traced through to either ancestor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Except {
+    positive: (IndexPair, Vec<usize>),
+    negative: (IndexPair, Vec<usize>),
+
+    // the number of times each record has been received from the positive and negative side
+    counts: HashMap<Vec<DataType>, (i64, i64)>,
+}
+
+impl Except {
+    /// Construct a new except operator.
+    ///
+    /// Each side is given as an ancestor and the columns selected from it. Records from the
+    /// `negative` side are subtracted from those of the `positive` side, so both sides must
+    /// select the same number of columns.
+    pub fn new(positive: (NodeIndex, Vec<usize>), negative: (NodeIndex, Vec<usize>)) -> Self {
+        assert_ne!(
+            positive.0, negative.0,
+            "cannot subtract an ancestor from itself"
+        );
+        assert_eq!(
+            positive.1.len(),
+            negative.1.len(),
+            "both sides of an except must emit the same number of columns"
+        );
+
+        Except {
+            positive: (positive.0.into(), positive.1),
+            negative: (negative.0.into(), negative.1),
+            counts: HashMap::new(),
+        }
+    }
+
+    fn contains(counts: (i64, i64)) -> bool {
+        counts.0 > counts.1
+    }
+}
+
+impl Ingredient for Except {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.positive.0.as_global(), self.negative.0.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
+        for (n, emit) in &[&self.positive, &self.negative] {
+            let cols = g[n.as_global()].fields().len();
+            if let Some(c) = emit.iter().find(|&&c| c >= cols) {
+                return Err(format!(
+                    "cannot subtract non-existing column {} of ancestor with {} columns",
+                    c, cols
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.positive.0.remap(remap);
+        self.negative.0.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        let (is_positive_side, emit) = if from == *self.positive.0 {
+            (true, self.positive.1.clone())
+        } else {
+            assert_eq!(
+                from, *self.negative.0,
+                "except received records from unknown ancestor"
+            );
+            (false, self.negative.1.clone())
+        };
+
+        // apply all records first, so that each record changes at most once per batch
+        let mut before = HashMap::new();
+        for r in rs {
+            let (r, positive) = r.extract();
+            let row: Vec<_> = emit.iter().map(|&c| r[c].clone()).collect();
+            let counts = self.counts.entry(row.clone()).or_insert((0, 0));
+            before.entry(row).or_insert_with(|| Self::contains(*counts));
+
+            let delta = if positive { 1 } else { -1 };
+            if is_positive_side {
+                counts.0 += delta;
+            } else {
+                counts.1 += delta;
+            }
+        }
+
+        let mut out = Vec::new();
+        for (row, was) in before {
+            let counts = self.counts[&row];
+            if counts == (0, 0) {
+                self.counts.remove(&row);
+            }
+            match (was, Self::contains(counts)) {
+                (false, true) => out.push(Record::Positive(row)),
+                (true, false) => out.push(Record::Negative(row)),
+                _ => {}
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // counts are kept in internal state, so our output must be materialized for replays to
+        // be served from it rather than counted again
+        let all = (0..self.positive.1.len()).collect();
+        Some((this, all)).into_iter().collect()
+    }
+
+    fn resolve(&self, _: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        // whether a record is emitted depends on counts that only we hold
+        None
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("∖");
+        }
+
+        let side = |(n, emit): &(IndexPair, Vec<usize>)| {
+            let cols = emit
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{}:[{}]", n.as_global().index(), cols)
+        };
+        format!("{} ∖ {}", side(&self.positive), side(&self.negative))
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        vec![
+            (self.positive.0.as_global(), Some(self.positive.1[column])),
+            (self.negative.0.as_global(), Some(self.negative.1[column])),
+        ]
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+        g.set_op(
+            "except",
+            &["e0", "e1"],
+            Except::new((l.as_global(), vec![0, 1]), (r.as_global(), vec![0, 2])),
+            false,
+        );
+        (g, l, r)
+    }
+
+    fn left(k: i32) -> Vec<DataType> {
+        vec![k.into(), "a".into()]
+    }
+
+    fn right(k: i32) -> Vec<DataType> {
+        vec![k.into(), "skipped".into(), "a".into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let (g, l, r) = setup();
+        assert_eq!(
+            g.node().description(true),
+            format!(
+                "{}:[0, 1] ∖ {}:[0, 2]",
+                l.as_global().index(),
+                r.as_global().index()
+            )
+        );
+    }
+
+    #[test]
+    fn it_materializes_itself() {
+        let (g, _, _) = setup();
+        let me = 2.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![0, 1]);
+        assert_eq!(g.node().resolve(0), None);
+    }
+
+    #[test]
+    fn it_rejects_non_existing_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+        let err = g
+            .try_set_op(
+                "except",
+                &["e0", "e1"],
+                Except::new((l.as_global(), vec![0, 2]), (r.as_global(), vec![0, 2])),
+                false,
+            )
+            .unwrap_err();
+        assert!(err.contains("non-existing column 2"), "{}", err);
+    }
+
+    #[test]
+    fn it_subtracts() {
+        let (mut g, l, r) = setup();
+        let rs = g.one(l, vec![left(1), left(2)], false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_positive(&left(1)[..]));
+        assert!(rs.has_positive(&left(2)[..]));
+
+        assert_eq!(g.one_row(r, right(2), false), vec![(left(2), false)].into());
+
+        // records on the negative side cancel out copies that arrive later on the positive side
+        assert_eq!(g.one_row(r, right(3), false), Records::default());
+        assert_eq!(g.one_row(l, left(3), false), Records::default());
+    }
+
+    #[test]
+    fn it_crosses_the_boundary_both_ways() {
+        let (mut g, l, r) = setup();
+
+        // two copies on the positive side outweigh one on the negative side
+        assert_eq!(
+            g.one(l, vec![left(1), left(1)], false),
+            vec![left(1)].into()
+        );
+        assert_eq!(g.one_row(r, right(1), false), Records::default());
+
+        // a delete on the positive side brings the counts level, so the record is revoked
+        assert_eq!(
+            g.one_row(l, (left(1), false), false),
+            vec![(left(1), false)].into()
+        );
+
+        // and a delete on the negative side brings it back
+        assert_eq!(g.one_row(r, (right(1), false), false), vec![left(1)].into());
+
+        // a batch that crosses and recrosses the boundary changes nothing
+        assert_eq!(
+            g.one(r, vec![(right(1), true), (right(1), false)], false),
+            Records::default()
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display};
+use std::ops::Bound;
 use std::sync;
 
+use regex::Regex;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
 use crate::prelude::*;
 pub use nom_sql::Operator;
 
@@ -11,6 +16,77 @@ pub use nom_sql::Operator;
 pub struct Filter {
     src: IndexPair,
     filter: sync::Arc<Vec<(usize, FilterCondition)>>,
+    // the pre-built form of each condition in `filter` that has one, by position
+    compiled: sync::Arc<Vec<Option<Compiled>>>,
+}
+
+/// The form of a condition that is built once, when the filter is constructed, rather than for
+/// every record.
+///
+/// Other operators that evaluate conditions against every record keep these too; see
+/// `Compiled::new` and `evaluate`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Compiled {
+    /// The values of an IN condition, for constant-time membership tests.
+    Set(HashSet<DataType>),
+    /// The expression of a regex condition.
+    Regex(Pattern),
+    /// The pre-built forms of the branches of an AND or OR condition, by position.
+    Branches(Vec<Option<Compiled>>),
+}
+
+impl Compiled {
+    /// Build the form of `cond`, if it has one, or fail if `cond` is invalid; see `validate`.
+    pub(crate) fn new(cond: &FilterCondition) -> Result<Option<Compiled>, String> {
+        Ok(match *cond {
+            FilterCondition::In { ref values, .. } => {
+                Some(Compiled::Set(values.iter().cloned().collect()))
+            }
+            FilterCondition::Regex { ref pattern, .. } => {
+                Some(Compiled::Regex(Pattern(compile(pattern)?)))
+            }
+            FilterCondition::And(ref branches) | FilterCondition::Or(ref branches) => {
+                Some(Compiled::Branches(
+                    branches
+                        .iter()
+                        .map(|(_, c)| Compiled::new(c))
+                        .collect::<Result<_, _>>()?,
+                ))
+            }
+            FilterCondition::Comparison(..) | FilterCondition::Range { .. } => None,
+        })
+    }
+}
+
+/// A compiled regular expression that is serialized as its pattern, and compiled again when it is
+/// deserialized.
+#[derive(Debug, Clone)]
+struct Pattern(Regex);
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(Pattern).map_err(de::Error::custom)
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("invalid filter pattern {:?}: {}", pattern, e))
+}
+
+/// Checks that every condition in `filter` can be evaluated, so that invalid conditions, like
+/// regex conditions whose pattern doesn't compile, are rejected when a query is planned.
+pub fn validate(filter: &[(usize, FilterCondition)]) -> Result<(), String> {
+    for (_, cond) in filter {
+        Compiled::new(cond)?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -36,18 +112,310 @@ impl Display for Value {
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum FilterCondition {
+    /// Compares values with `Value`.
+    ///
+    /// Like in SQL, a comparison with a NULL value is unknown, except for equality and inequality
+    /// with a constant NULL, which is how `IS NULL` and `IS NOT NULL` are expressed: those match
+    /// values that are, or aren't, NULL.
     Comparison(Operator, Value),
-    In(Vec<DataType>),
+    /// Matches values that are one of `values`, or, if `negated`, that are none of them.
+    ///
+    /// Like in SQL, a NULL value never matches `NOT IN`.
+    In {
+        values: Vec<DataType>,
+        negated: bool,
+    },
+    /// Matches values between `lower` and `upper`, like `BETWEEN` in SQL, in the order of
+    /// `DataType`. Either bound may be `Unbounded` to express an open-ended range.
+    ///
+    /// Like in SQL, a NULL value is never in a range.
+    Range {
+        lower: Bound<DataType>,
+        upper: Bound<DataType>,
+    },
+    /// Matches text values that contain a match of the regular expression `pattern`, or, if
+    /// `negated`, that don't. Anchor the pattern to match the whole value instead.
+    ///
+    /// Values that aren't text never match, whether or not the condition is negated, and whether a
+    /// NULL matches is unknown.
+    Regex { pattern: String, negated: bool },
+    /// Matches if every one of the conditions on the given columns does, like `AND` in SQL.
+    ///
+    /// Each branch names the column it applies to, so the column this condition is attached to is
+    /// ignored.
+    And(Vec<(usize, FilterCondition)>),
+    /// Matches if any one of the conditions on the given columns does, like `OR` in SQL.
+    ///
+    /// Each branch names the column it applies to, so the column this condition is attached to is
+    /// ignored.
+    Or(Vec<(usize, FilterCondition)>),
+}
+
+/// The truth value of a condition under SQL's three-valued logic.
+///
+/// A comparison with NULL is neither true nor false, but unknown. Only conditions that are true
+/// let a record through a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truth {
+    /// The condition holds.
+    True,
+    /// The condition does not hold.
+    False,
+    /// The condition involves a NULL, so whether it holds is unknown.
+    Unknown,
+}
+
+impl Truth {
+    /// Combines two truth values like `AND` in SQL: false if either is false, and otherwise unknown
+    /// if either is unknown.
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::Unknown, _) | (_, Truth::Unknown) => Truth::Unknown,
+            (Truth::True, Truth::True) => Truth::True,
+        }
+    }
+
+    /// Combines two truth values like `OR` in SQL: true if either is true, and otherwise unknown if
+    /// either is unknown.
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::Unknown, _) | (_, Truth::Unknown) => Truth::Unknown,
+            (Truth::False, Truth::False) => Truth::False,
+        }
+    }
+
+    /// Whether a record with this truth value passes a filter.
+    pub fn is_true(self) -> bool {
+        self == Truth::True
+    }
+}
+
+impl From<bool> for Truth {
+    fn from(b: bool) -> Self {
+        if b {
+            Truth::True
+        } else {
+            Truth::False
+        }
+    }
+}
+
+/// Describes the branches of an AND (or, if `or`, an OR) condition, like `(f0 = 1 ∨ f1 > 2)`.
+pub fn describe_branches(branches: &[(usize, FilterCondition)], or: bool) -> String {
+    let describe = |&(i, ref cond): &(usize, FilterCondition)| match *cond {
+        FilterCondition::Comparison(ref op, ref x) => format!("f{} {} {}", i, op, x),
+        FilterCondition::In {
+            values: ref xs,
+            negated,
+        } => format!(
+            "f{} {}IN ({})",
+            i,
+            if negated { "NOT " } else { "" },
+            xs.iter()
+                .map(|d| format!("{}", d))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        FilterCondition::Range {
+            ref lower,
+            ref upper,
+        } => format!("f{} {}", i, describe_range(lower, upper)),
+        FilterCondition::Regex {
+            ref pattern,
+            negated,
+        } => format!("f{} {}~ /{}/", i, if negated { "!" } else { "" }, pattern),
+        FilterCondition::And(ref bs) => describe_branches(bs, false),
+        FilterCondition::Or(ref bs) => describe_branches(bs, true),
+    };
+    format!(
+        "({})",
+        branches
+            .iter()
+            .map(describe)
+            .collect::<Vec<_>>()
+            .join(if or { " ∨ " } else { " ∧ " })
+    )
+}
+
+/// Describes the range between `lower` and `upper` in interval notation, like `∈ [1, 5)`.
+pub fn describe_range(lower: &Bound<DataType>, upper: &Bound<DataType>) -> String {
+    let lower = match *lower {
+        Bound::Included(ref lo) => format!("[{}", lo),
+        Bound::Excluded(ref lo) => format!("({}", lo),
+        Bound::Unbounded => String::from("(-∞"),
+    };
+    let upper = match *upper {
+        Bound::Included(ref hi) => format!("{}]", hi),
+        Bound::Excluded(ref hi) => format!("{})", hi),
+        Bound::Unbounded => String::from("∞)"),
+    };
+    format!("∈ {}, {}", lower, upper)
 }
 
 impl Filter {
     /// Construct a new filter operator. The `filter` vector must have as many elements as the
     /// `src` node has columns. Each column that is set to `None` matches any value, while columns
     /// in the filter that have values set will check for equality on that column.
-    pub fn new(src: NodeIndex, filter: &[(usize, FilterCondition)]) -> Filter {
-        Filter {
+    ///
+    /// Returns an error if a condition is invalid; see `validate`.
+    pub fn new(src: NodeIndex, filter: &[(usize, FilterCondition)]) -> Result<Filter, String> {
+        let compiled = filter
+            .iter()
+            .map(|(_, cond)| Compiled::new(cond))
+            .collect::<Result<_, _>>()?;
+        Ok(Filter {
             src: src.into(),
             filter: sync::Arc::new(Vec::from(filter)),
+            compiled: sync::Arc::new(compiled),
+        })
+    }
+}
+
+/// Returns true if `r` satisfies every condition in `filter`.
+///
+/// Like in a SQL `WHERE` clause, conditions whose truth is unknown are not satisfied.
+pub(crate) fn matches(filter: &[(usize, FilterCondition)], r: &[DataType]) -> bool {
+    evaluate(filter, &[], r).is_true()
+}
+
+/// Returns true if `r` satisfies every condition in `filter`, using the pre-built forms in
+/// `compiled` rather than building them again.
+fn matches_compiled(
+    filter: &[(usize, FilterCondition)],
+    compiled: &[Option<Compiled>],
+    r: &[DataType],
+) -> bool {
+    evaluate(filter, compiled, r).is_true()
+}
+
+/// Evaluates the conjunction of the conditions in `filter` against `r`.
+///
+/// `compiled` holds the pre-built form of each condition by position, and may be shorter than
+/// `filter` if some conditions should be built on the fly.
+pub(crate) fn evaluate(
+    filter: &[(usize, FilterCondition)],
+    compiled: &[Option<Compiled>],
+    r: &[DataType],
+) -> Truth {
+    let mut truth = Truth::True;
+    for (n, &(i, ref cond)) in filter.iter().enumerate() {
+        let c = compiled.get(n).and_then(Option::as_ref);
+        truth = truth.and(condition_truth(cond, &r[i], r, c));
+        if truth == Truth::False {
+            break;
+        }
+    }
+    truth
+}
+
+/// Evaluates `cond` against the value `d` in the record `r`, using its pre-built form `compiled`
+/// if there is one.
+pub(crate) fn condition_truth(
+    cond: &FilterCondition,
+    d: &DataType,
+    r: &[DataType],
+    compiled: Option<&Compiled>,
+) -> Truth {
+    match *cond {
+        FilterCondition::Comparison(ref op, ref f) => {
+            let v = match *f {
+                Value::Constant(DataType::None) => match *op {
+                    Operator::Equal => return Truth::from(d.is_none()),
+                    Operator::NotEqual => return Truth::from(!d.is_none()),
+                    _ => return Truth::Unknown,
+                },
+                Value::Constant(ref dt) => dt,
+                Value::Column(c) => &r[c],
+            };
+            if d.is_none() || v.is_none() {
+                return Truth::Unknown;
+            }
+            Truth::from(match *op {
+                Operator::Equal => d == v,
+                Operator::NotEqual => d != v,
+                Operator::Greater => d > v,
+                Operator::GreaterOrEqual => d >= v,
+                Operator::Less => d < v,
+                Operator::LessOrEqual => d <= v,
+                Operator::In => unreachable!(),
+                _ => unimplemented!(),
+            })
+        }
+        FilterCondition::In {
+            ref values,
+            negated,
+        } => {
+            if d.is_none() {
+                return Truth::Unknown;
+            }
+            let (found, has_null) = match compiled {
+                Some(Compiled::Set(set)) => (set.contains(d), set.contains(&DataType::None)),
+                _ => (values.contains(d), values.contains(&DataType::None)),
+            };
+            if !found && has_null {
+                // the value might have been the NULL
+                return Truth::Unknown;
+            }
+            Truth::from(found != negated)
+        }
+        FilterCondition::Range {
+            ref lower,
+            ref upper,
+        } => {
+            if d.is_none() {
+                return Truth::Unknown;
+            }
+            let above = match *lower {
+                Bound::Included(ref lo) => d >= lo,
+                Bound::Excluded(ref lo) => d > lo,
+                Bound::Unbounded => true,
+            };
+            let below = match *upper {
+                Bound::Included(ref hi) => d <= hi,
+                Bound::Excluded(ref hi) => d < hi,
+                Bound::Unbounded => true,
+            };
+            Truth::from(above && below)
+        }
+        FilterCondition::Regex {
+            ref pattern,
+            negated,
+        } => {
+            if d.is_none() {
+                return Truth::Unknown;
+            } else if !d.is_string() {
+                return Truth::False;
+            }
+            let found = match compiled {
+                Some(Compiled::Regex(Pattern(re))) => re.is_match(<&str>::from(d)),
+                // a pattern that doesn't compile matches nothing; planning rejects those anyway
+                _ => compile(pattern).map_or(false, |re| re.is_match(<&str>::from(d))),
+            };
+            Truth::from(found != negated)
+        }
+        FilterCondition::And(ref branches) => {
+            let compiled = match compiled {
+                Some(Compiled::Branches(cs)) => &cs[..],
+                _ => &[][..],
+            };
+            evaluate(branches, compiled, r)
+        }
+        FilterCondition::Or(ref branches) => {
+            let compiled = match compiled {
+                Some(Compiled::Branches(cs)) => &cs[..],
+                _ => &[][..],
+            };
+            let mut truth = Truth::False;
+            for (n, &(i, ref cond)) in branches.iter().enumerate() {
+                let c = compiled.get(n).and_then(Option::as_ref);
+                truth = truth.or(condition_truth(cond, &r[i], r, c));
+                if truth == Truth::True {
+                    break;
+                }
+            }
+            truth
         }
     }
 }
@@ -61,11 +429,12 @@ impl Ingredient for Filter {
         vec![self.src.as_global()]
     }
 
-    fn on_connected(&mut self, g: &Graph) {
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
         let srcn = &g[self.src.as_global()];
         // N.B.: <= because the adjacent node might be a base with a suffix of removed columns.
         // It's okay to just ignore those.
         assert!(self.filter.len() <= srcn.fields().len());
+        Ok(())
     }
 
     fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
@@ -81,31 +450,7 @@ impl Ingredient for Filter {
         _: &DomainNodes,
         _: &StateMap,
     ) -> ProcessingResult {
-        rs.retain(|r| {
-            self.filter.iter().all(|(i, cond)| {
-                // check if this filter matches
-                let d = &r[*i];
-                match cond {
-                    FilterCondition::Comparison(ref op, ref f) => {
-                        let v = match *f {
-                            Value::Constant(ref dt) => dt,
-                            Value::Column(c) => &r[c],
-                        };
-                        match *op {
-                            Operator::Equal => d == v,
-                            Operator::NotEqual => d != v,
-                            Operator::Greater => d > v,
-                            Operator::GreaterOrEqual => d >= v,
-                            Operator::Less => d < v,
-                            Operator::LessOrEqual => d <= v,
-                            Operator::In => unreachable!(),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    FilterCondition::In(ref fs) => fs.contains(d),
-                }
-            })
-        });
+        rs.retain(|r| matches_compiled(&self.filter[..], &self.compiled[..], r));
 
         ProcessingResult {
             results: rs,
@@ -122,8 +467,6 @@ impl Ingredient for Filter {
     }
 
     fn description(&self, detailed: bool) -> String {
-        use regex::Regex;
-
         if !detailed {
             return String::from("σ");
         }
@@ -142,14 +485,33 @@ impl Ingredient for Filter {
                     FilterCondition::Comparison(ref op, ref x) => {
                         Some(format!("f{} {} {}", i, escape(&format!("{}", op)), x))
                     }
-                    FilterCondition::In(ref xs) => Some(format!(
-                        "f{} IN ({})",
+                    FilterCondition::In {
+                        values: ref xs,
+                        negated,
+                    } => Some(format!(
+                        "f{} {}IN ({})",
                         i,
+                        if negated { "NOT " } else { "" },
                         xs.iter()
                             .map(|d| format!("{}", d))
                             .collect::<Vec<_>>()
                             .join(", ")
                     )),
+                    FilterCondition::Range {
+                        ref lower,
+                        ref upper,
+                    } => Some(format!("f{} {}", i, describe_range(lower, upper))),
+                    FilterCondition::Regex {
+                        ref pattern,
+                        negated,
+                    } => Some(format!(
+                        "f{} {}~ /{}/",
+                        i,
+                        if negated { "!" } else { "" },
+                        escape(pattern)
+                    )),
+                    FilterCondition::And(ref bs) => Some(escape(&describe_branches(bs, false))),
+                    FilterCondition::Or(ref bs) => Some(escape(&describe_branches(bs, true))),
                 })
                 .collect::<Vec<_>>()
                 .as_slice()
@@ -172,30 +534,8 @@ impl Ingredient for Filter {
         self.lookup(*self.src, columns, key, nodes, states)
             .and_then(|result| {
                 let f = self.filter.clone();
-                let filter = move |r: &[DataType]| {
-                    f.iter().all(|(i, ref cond)| {
-                        // check if this filter matches
-                        let d = &r[*i];
-                        match *cond {
-                            FilterCondition::Comparison(ref op, ref f) => {
-                                let v = match *f {
-                                    Value::Constant(ref dt) => dt,
-                                    Value::Column(c) => &r[c],
-                                };
-                                match *op {
-                                    Operator::Equal => d == v,
-                                    Operator::NotEqual => d != v,
-                                    Operator::Greater => d > v,
-                                    Operator::GreaterOrEqual => d >= v,
-                                    Operator::Less => d < v,
-                                    Operator::LessOrEqual => d <= v,
-                                    _ => unimplemented!(),
-                                }
-                            }
-                            FilterCondition::In(ref fs) => fs.contains(d),
-                        }
-                    })
-                };
+                let compiled = self.compiled.clone();
+                let filter = move |r: &[DataType]| matches_compiled(&f[..], &compiled[..], r);
 
                 match result {
                     Some(rs) => {
@@ -237,7 +577,8 @@ mod tests {
                     1,
                     FilterCondition::Comparison(Operator::Equal, Value::Constant("a".into())),
                 )]),
-            ),
+            )
+            .unwrap(),
             materialized,
         );
         g
@@ -397,8 +738,20 @@ mod tests {
         let mut g = setup(
             false,
             Some(&[
-                (0, FilterCondition::In(vec![2.into(), 42.into()])),
-                (1, FilterCondition::In(vec!["b".into()])),
+                (
+                    0,
+                    FilterCondition::In {
+                        values: vec![2.into(), 42.into()],
+                        negated: false,
+                    },
+                ),
+                (
+                    1,
+                    FilterCondition::In {
+                        values: vec!["b".into()],
+                        negated: false,
+                    },
+                ),
             ]),
         );
 
@@ -420,4 +773,348 @@ mod tests {
         left = vec![42.into(), "b".into()];
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
+    #[test]
+    fn it_works_with_not_in_list() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::In {
+                    values: vec![1.into(), 3.into(), 5.into()],
+                    negated: true,
+                },
+            )]),
+        );
+        assert_eq!(g.node().description(true), "σ[f0 NOT IN (1, 3, 5)]");
+
+        let mut left: Vec<DataType>;
+
+        // 2 NOT IN (1, 3, 5)
+        left = vec![2.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // 3 IN (1, 3, 5)
+        left = vec![3.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // NULL is neither in nor not in the list
+        left = vec![DataType::None, "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+    #[test]
+    fn it_works_with_inclusive_ranges() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Range {
+                    lower: Bound::Included(2.into()),
+                    upper: Bound::Included(4.into()),
+                },
+            )]),
+        );
+        assert_eq!(g.node().description(true), "σ[f0 ∈ [2, 4]]");
+
+        let mut left: Vec<DataType>;
+
+        // 3 BETWEEN 2 AND 4
+        left = vec![3.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // reals compare with integer bounds by value
+        left = vec![DataType::from(4.5), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+        left = vec![5.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // NULL is in no range
+        left = vec![DataType::None, "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_with_open_ranges() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Range {
+                    lower: Bound::Excluded(2.into()),
+                    upper: Bound::Unbounded,
+                },
+            )]),
+        );
+        assert_eq!(g.node().description(true), "σ[f0 ∈ (2, ∞)]");
+
+        let mut left: Vec<DataType>;
+
+        // the excluded lower bound itself doesn't match
+        left = vec![2.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // but anything above it does, however large
+        left = vec![DataType::from(2.5), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![DataType::BigInt(1 << 40), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+    }
+
+    #[test]
+    fn it_includes_range_boundaries() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::Range {
+                    lower: Bound::Unbounded,
+                    upper: Bound::Included("b".into()),
+                },
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        // exactly on the upper bound
+        left = vec![1.into(), "b".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![1.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![1.into(), "ba".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_with_timestamp_ranges() {
+        use chrono::NaiveDate;
+        let ts = |d| DataType::Timestamp(NaiveDate::from_ymd(2020, 3, d).and_hms(0, 0, 0));
+
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Range {
+                    lower: Bound::Included(ts(2)),
+                    upper: Bound::Excluded(ts(4)),
+                },
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        left = vec![ts(3), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![ts(2), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![ts(4), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_with_regexes() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::Regex {
+                    pattern: String::from("^a.*z$"),
+                    negated: false,
+                },
+            )]),
+        );
+        assert_eq!(g.node().description(true), "σ[f1 ~ /^a.*z$/]");
+
+        let mut left: Vec<DataType>;
+
+        left = vec![1.into(), "abcz".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // long strings are matched too
+        left = vec![1.into(), "a long string that doesn't fit inline z".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![1.into(), "abc".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_with_negated_regexes() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::Regex {
+                    pattern: String::from("^a"),
+                    negated: true,
+                },
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        left = vec![1.into(), "ba".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![1.into(), "ab".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_drops_comparisons_with_null() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Constant(1.into())),
+            )]),
+        );
+
+        // NULL = 1 is unknown, so the row is dropped
+        assert!(g
+            .narrow_one_row(vec![DataType::None, "a".into()], false)
+            .is_empty());
+
+        // and so is NULL = NULL
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Column(1)),
+            )]),
+        );
+        assert!(g
+            .narrow_one_row(vec![DataType::None, DataType::None], false)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_matches_is_null() {
+        let null = vec![DataType::None, "a".into()];
+        let one = vec![1.into(), "a".into()];
+
+        // IS NULL keeps exactly the NULLs
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Constant(DataType::None)),
+            )]),
+        );
+        assert_eq!(
+            g.narrow_one_row(null.clone(), false),
+            vec![null.clone()].into()
+        );
+        assert!(g.narrow_one_row(one.clone(), false).is_empty());
+
+        // and IS NOT NULL drops them
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::NotEqual, Value::Constant(DataType::None)),
+            )]),
+        );
+        assert!(g.narrow_one_row(null, false).is_empty());
+        assert_eq!(g.narrow_one_row(one.clone(), false), vec![one].into());
+    }
+
+    #[test]
+    fn it_follows_three_valued_logic() {
+        let branches = vec![
+            (
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Constant(true.into())),
+            ),
+            (
+                1,
+                FilterCondition::Comparison(Operator::Equal, Value::Constant(1.into())),
+            ),
+        ];
+
+        let mut and = setup(false, Some(&[(0, FilterCondition::And(branches.clone()))]));
+        assert_eq!(and.node().description(true), "σ[(f0 = TRUE ∧ f1 = 1)]");
+        let mut or = setup(false, Some(&[(0, FilterCondition::Or(branches))]));
+        assert_eq!(or.node().description(true), "σ[(f0 = TRUE ∨ f1 = 1)]");
+
+        let true_null = vec![true.into(), DataType::None];
+        let false_null = vec![false.into(), DataType::None];
+        let true_true = vec![true.into(), 1.into()];
+
+        // TRUE AND NULL is unknown, so the row is dropped
+        assert!(and.narrow_one_row(true_null.clone(), false).is_empty());
+        assert_eq!(
+            and.narrow_one_row(true_true.clone(), false),
+            vec![true_true.clone()].into()
+        );
+
+        // TRUE OR NULL is true, so the row is kept
+        assert_eq!(
+            or.narrow_one_row(true_null.clone(), false),
+            vec![true_null].into()
+        );
+        // but FALSE OR NULL is unknown
+        assert!(or.narrow_one_row(false_null, false).is_empty());
+    }
+
+    #[test]
+    fn it_treats_null_in_lists_as_unknown() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::In {
+                    values: vec![1.into(), DataType::None],
+                    negated: true,
+                },
+            )]),
+        );
+
+        // 2 NOT IN (1, NULL) is unknown, since the NULL might have been 2
+        assert!(g
+            .narrow_one_row(vec![2.into(), "a".into()], false)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_does_not_match_regexes_on_numbers() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                1,
+                FilterCondition::Regex {
+                    pattern: String::from("1"),
+                    negated: false,
+                },
+            )]),
+        );
+
+        assert!(g.narrow_one_row(vec![1.into(), 1.into()], false).is_empty());
+        assert!(g
+            .narrow_one_row(vec![1.into(), DataType::None], false)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_rejects_invalid_regexes() {
+        let invalid = FilterCondition::Regex {
+            pattern: String::from("a("),
+            negated: false,
+        };
+        let err = Filter::new(0.into(), &[(1, invalid.clone())]).unwrap_err();
+        assert!(err.contains("invalid filter pattern"), "{}", err);
+
+        // also when nested in another condition
+        let nested = FilterCondition::Or(vec![
+            (
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Constant(1.into())),
+            ),
+            (1, invalid),
+        ]);
+        assert!(validate(&[(0, nested.clone())]).is_err());
+        assert!(Filter::new(0.into(), &[(0, nested)]).is_err());
+    }
 }
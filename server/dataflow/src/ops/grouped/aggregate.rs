@@ -9,6 +9,10 @@ pub enum Aggregation {
     /// Count the number of records for each group. The value for the `over` column is ignored.
     COUNT,
     /// Sum the value of the `over` column for all records of each group.
+    ///
+    /// Integers and decimals are both summed exactly. The sum is a decimal if any value in the
+    /// group ever was, and an integer otherwise. A decimal sum that is out of range is NULL, and
+    /// stays NULL, since the exact sum is no longer known.
     SUM,
 }
 
@@ -59,8 +63,17 @@ pub struct Aggregator {
     group: Vec<usize>,
 }
 
+/// Adds two exact values, each a mantissa and the scale of a decimal, or `None` for an integer.
+fn add_exact((a, sa): (i128, Option<u8>), (b, sb): (i128, Option<u8>)) -> (i128, Option<u8>) {
+    let scale = sa.max(sb);
+    let rescale = |n: i128, from: Option<u8>| {
+        n * 10i128.pow(u32::from(scale.unwrap_or(0) - from.unwrap_or(0)))
+    };
+    (rescale(a, sa) + rescale(b, sb), scale)
+}
+
 impl GroupedOperation for Aggregator {
-    type Diff = i128;
+    type Diff = (i128, Option<u8>);
 
     fn setup(&mut self, parent: &Node) {
         assert!(
@@ -75,40 +88,48 @@ impl GroupedOperation for Aggregator {
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
         match self.op {
-            Aggregation::COUNT if pos => 1,
-            Aggregation::COUNT => -1,
+            Aggregation::COUNT if pos => (1, None),
+            Aggregation::COUNT => (-1, None),
             Aggregation::SUM => {
-                let v = match r[self.over] {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    DataType::None => 0,
+                let (v, scale) = match r[self.over] {
+                    DataType::Int(n) => (i128::from(n), None),
+                    DataType::UnsignedInt(n) => (i128::from(n), None),
+                    DataType::BigInt(n) => (i128::from(n), None),
+                    DataType::UnsignedBigInt(n) => (i128::from(n), None),
+                    DataType::Decimal(m, scale) => (i128::from(m), Some(scale)),
+                    DataType::None => (0, None),
                     ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
                 };
                 if pos {
-                    v
+                    (v, scale)
                 } else {
-                    0i128 - v
+                    (0i128 - v, scale)
                 }
             }
         }
     }
 
     fn apply(
-        &self,
+        &mut self,
+        _: &[DataType],
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType {
+    ) -> Option<DataType> {
         let n = match current {
-            Some(&DataType::Int(n)) => i128::from(n),
-            Some(&DataType::UnsignedInt(n)) => i128::from(n),
-            Some(&DataType::BigInt(n)) => i128::from(n),
-            Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
-            None => 0,
+            Some(&DataType::Int(n)) => (i128::from(n), None),
+            Some(&DataType::UnsignedInt(n)) => (i128::from(n), None),
+            Some(&DataType::BigInt(n)) => (i128::from(n), None),
+            Some(&DataType::UnsignedBigInt(n)) => (i128::from(n), None),
+            Some(&DataType::Decimal(m, scale)) => (i128::from(m), Some(scale)),
+            // the sum went out of range, and what it was is lost
+            Some(&DataType::None) => return Some(DataType::None),
+            None => (0, None),
             _ => unreachable!(),
         };
-        diffs.fold(n, |n, d| n + d).into()
+        Some(match diffs.fold(n, add_exact) {
+            (n, None) => n.into(),
+            (m, Some(scale)) => DataType::decimal(m, scale),
+        })
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -389,6 +410,54 @@ mod tests {
 
     // TODO: also test SUM
 
+    #[test]
+    fn it_sums_decimals_exactly() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "sum",
+            &["x", "ys"],
+            Aggregation::SUM.over(s.as_global(), 1, &[0]),
+            true,
+        );
+
+        for _ in 0..9 {
+            g.narrow_one_row(vec![1.into(), DataType::Decimal(1, 1)], true);
+        }
+        let rs = g.narrow_one_row(vec![1.into(), DataType::Decimal(1, 1)], true);
+        // ten tenths are exactly one, not 0.9999999999999999
+        assert!(rs.has_positive(&[1.into(), DataType::Decimal(10, 1)][..]));
+        assert!(rs.has_negative(&[1.into(), DataType::Decimal(9, 1)][..]));
+
+        // integers mix into a decimal sum
+        let rs = g.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert!(rs.has_positive(&[1.into(), DataType::Decimal(30, 1)][..]));
+    }
+
+    #[test]
+    fn it_keeps_out_of_range_decimal_sums_null() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "sum",
+            &["x", "ys"],
+            Aggregation::SUM.over(s.as_global(), 1, &[0]),
+            true,
+        );
+
+        let max = DataType::Decimal(i64::max_value(), 0);
+        g.narrow_one_row(vec![1.into(), max.clone()], true);
+        let rs = g.narrow_one_row(vec![1.into(), DataType::Decimal(1, 0)], true);
+        assert!(rs.has_negative(&[1.into(), max][..]));
+        assert!(rs.has_positive(&[1.into(), DataType::None][..]));
+
+        // later updates leave the group NULL rather than failing
+        let rs = g.narrow_one_row((vec![1.into(), DataType::Decimal(1, 0)], false), true);
+        assert!(rs.is_empty());
+        let rs = g.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert!(rs.is_empty());
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = 1.into();
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// The running sum and count of the values in one group.
+///
+/// Integers and decimals are summed exactly, separately from reals, so that deleting one always
+/// undoes adding it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Sum {
+    ints: i128,
+    reals: f64,
+    count: i64,
+
+    // the mantissa and scale of the sum of all decimals, if the group holds any
+    decimals: Option<(i128, u8)>,
+    // the number of decimals and reals among the `count` values
+    decimal_count: i64,
+    real_count: i64,
+}
+
+/// A single value added to or removed from a group's average.
+pub enum Delta {
+    Int(i128, bool),
+    Real(f64, bool),
+    Decimal(i128, u8, bool),
+    Null,
+}
+
+/// `Average` computes the mean of the `over` column in each group, like `AVG(over)` in SQL.
+///
+/// Since an average can't be updated from the previous average alone, the operator keeps the sum
+/// and count of every group, and emits their quotient as a real. Integer and real columns are
+/// both supported, and NULL values are ignored. Groups of decimals, possibly mixed with integers,
+/// are averaged exactly, and their average is emitted as a decimal with four more digits after
+/// the decimal point than the values. When the last record of a group is deleted, the
+/// group's average is revoked rather than divided by zero.
+///
+/// Since the sums are built from every record the operator has seen, its output must be fully
+/// materialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Average {
+    over: usize,
+    group: Vec<usize>,
+
+    sums: HashMap<Vec<DataType>, Sum>,
+}
+
+impl Average {
+    /// Construct a new `Average` operator.
+    ///
+    /// The operator averages the value in column number `over` from its inputs (i.e., from the
+    /// `src` node in the graph), and uses the columns in the `group_by` array as a group
+    /// identifier. The `over` column should not be in the `group_by` array.
+    pub fn new(src: NodeIndex, over: usize, group_by: &[usize]) -> GroupedOperator<Average> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by aggregation column"
+        );
+        GroupedOperator::new(
+            src,
+            Average {
+                over,
+                group: group_by.into(),
+                sums: HashMap::new(),
+            },
+        )
+    }
+}
+
+impl GroupedOperation for Average {
+    type Diff = Delta;
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        match r[self.over] {
+            DataType::Int(n) => Delta::Int(i128::from(n), pos),
+            DataType::UnsignedInt(n) => Delta::Int(i128::from(n), pos),
+            DataType::BigInt(n) => Delta::Int(i128::from(n), pos),
+            DataType::UnsignedBigInt(n) => Delta::Int(i128::from(n), pos),
+            ref v @ DataType::Real(..) => Delta::Real(f64::from(v), pos),
+            DataType::Decimal(m, scale) => Delta::Decimal(i128::from(m), scale, pos),
+            DataType::None => Delta::Null,
+            ref x => unreachable!("tried to average over {:?} on {:?}", x, r),
+        }
+    }
+
+    fn apply(
+        &mut self,
+        group: &[DataType],
+        _: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<DataType> {
+        let mut sum = self.sums.remove(group).unwrap_or_default();
+        for d in diffs {
+            let pos = match d {
+                Delta::Int(v, pos) => {
+                    sum.ints += if pos { v } else { -v };
+                    pos
+                }
+                Delta::Real(v, pos) => {
+                    sum.reals += if pos { v } else { -v };
+                    sum.real_count += if pos { 1 } else { -1 };
+                    pos
+                }
+                Delta::Decimal(m, scale, pos) => {
+                    let m = if pos { m } else { -m };
+                    sum.decimals = Some(match sum.decimals {
+                        None => (m, scale),
+                        Some((n, s)) if s >= scale => (n + m * 10i128.pow(u32::from(s - scale)), s),
+                        Some((n, s)) => (n * 10i128.pow(u32::from(scale - s)) + m, scale),
+                    });
+                    sum.decimal_count += if pos { 1 } else { -1 };
+                    pos
+                }
+                Delta::Null => continue,
+            };
+            sum.count += if pos { 1 } else { -1 };
+        }
+
+        if sum.count <= 0 {
+            // the group has no values left
+            return Some(DataType::None);
+        }
+        if sum.decimal_count <= 0 {
+            // so that the average's type only depends on the values the group holds now
+            sum.decimals = None;
+        }
+
+        let avg = match sum.decimals {
+            Some((m, scale)) if sum.real_count == 0 => {
+                let total = &DataType::decimal(m, scale) + &DataType::from(sum.ints);
+                &total / &DataType::from(sum.count)
+            }
+            Some((m, scale)) => {
+                let decimals = m as f64 / 10f64.powi(i32::from(scale));
+                ((sum.ints as f64 + decimals + sum.reals) / sum.count as f64).into()
+            }
+            None => ((sum.ints as f64 + sum.reals) / sum.count as f64).into(),
+        };
+        self.sums.insert(group.to_vec(), sum);
+        Some(avg)
+    }
+
+    fn is_empty(&self, value: &DataType) -> bool {
+        value.is_none()
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("AVG");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("avg({}) γ[{}]", self.over, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "agg",
+            &["x", "ys"],
+            Average::new(s.as_global(), 1, &[0]),
+            true,
+        );
+        g
+    }
+
+    fn avg(group: i32, v: f64) -> Vec<DataType> {
+        vec![group.into(), v.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let c = setup();
+        assert_eq!(c.node().description(true), "avg(1) γ[0]");
+    }
+
+    #[test]
+    fn it_averages_mixed_deltas() {
+        let mut c = setup();
+
+        assert_eq!(
+            c.narrow_one(
+                vec![vec![1.into(), 1.into()], vec![1.into(), 2.into()]],
+                true
+            ),
+            vec![avg(1, 1.5)].into()
+        );
+
+        // a batch that both adds and removes values moves the average by their net effect
+        let u = vec![
+            (vec![1.into(), 1.into()], false),
+            (vec![1.into(), 4.into()], true),
+            (vec![1.into(), 6.into()], true),
+        ];
+        assert_eq!(
+            c.narrow_one(u, true),
+            vec![(avg(1, 1.5), false), (avg(1, 4.0), true)].into()
+        );
+
+        // reals and NULLs mix with integers
+        let u = vec![
+            vec![1.into(), DataType::from(0.5)],
+            vec![1.into(), DataType::None],
+        ];
+        assert_eq!(
+            c.narrow_one(u, true),
+            vec![(avg(1, 4.0), false), (avg(1, 3.125), true)].into()
+        );
+    }
+
+    #[test]
+    fn it_averages_decimals_exactly() {
+        let mut c = setup();
+
+        let u: Vec<_> = (0..10)
+            .map(|_| vec![1.into(), DataType::Decimal(1, 1)])
+            .collect();
+        let rs = c.narrow_one(u, true);
+        assert_eq!(rs, vec![vec![1.into(), DataType::Decimal(1, 1)]].into());
+        assert_eq!(rs[0][1].to_string(), "0.10000");
+
+        // integers keep the average exact
+        let rs = c.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert!(rs.has_positive(&[1.into(), DataType::Decimal(27_273, 5)][..]));
+    }
+
+    #[test]
+    fn it_stops_averaging_decimals_once_they_are_gone() {
+        let mut c = setup();
+        let decimal = vec![1.into(), DataType::Decimal(15, 1)];
+        c.narrow_one(vec![decimal.clone(), vec![1.into(), 2.into()]], true);
+
+        let rs = c.narrow_one_row((decimal, false), true);
+        assert!(rs.has_positive(&avg(1, 2.0)[..]));
+    }
+
+    #[test]
+    fn it_revokes_empty_groups() {
+        let mut c = setup();
+        c.narrow_one_row(vec![1.into(), 3.into()], true);
+        c.narrow_one_row(vec![2.into(), 5.into()], true);
+
+        assert_eq!(
+            c.narrow_one_row((vec![1.into(), 3.into()], false), true),
+            vec![(avg(1, 3.0), false)].into()
+        );
+
+        // the group starts over once it has values again
+        assert_eq!(
+            c.narrow_one_row(vec![1.into(), 8.into()], true),
+            vec![avg(1, 8.0)].into()
+        );
+    }
+}
@@ -93,11 +93,12 @@ impl GroupConcat {
                         let text: &str = (&rec[*i]).into();
                         s.push_str(text);
                     }
+                    DataType::Bool(..) => s.push_str(&rec[*i].to_string()),
                     DataType::Int(ref n) => s.push_str(&n.to_string()),
                     DataType::UnsignedInt(ref n) => s.push_str(&n.to_string()),
                     DataType::BigInt(ref n) => s.push_str(&n.to_string()),
                     DataType::UnsignedBigInt(ref n) => s.push_str(&n.to_string()),
-                    DataType::Real(..) => s.push_str(&rec[*i].to_string()),
+                    DataType::Real(..) | DataType::Decimal(..) => s.push_str(&rec[*i].to_string()),
                     DataType::Timestamp(ref ts) => s.push_str(&ts.format("%+").to_string()),
                     DataType::None => unreachable!(),
                 },
@@ -151,10 +152,11 @@ impl GroupedOperation for GroupConcat {
     }
 
     fn apply(
-        &self,
+        &mut self,
+        _: &[DataType],
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType {
+    ) -> Option<DataType> {
         use std::collections::BTreeSet;
         use std::iter::FromIterator;
 
@@ -200,7 +202,7 @@ impl GroupedOperation for GroupConcat {
         // we pushed one separator too many above
         let real_len = new.len() - self.separator.len();
         new.truncate(real_len);
-        new.into()
+        Some(new.into())
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+// variances smaller than this are treated as zero, since retractions leave rounding errors behind
+const MIN_VARIANCE: f64 = 1e-9;
+
+/// Running means and co-moments of two columns, maintained with Welford's method.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Moments {
+    n: u64,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    m2_y: f64,
+    c_xy: f64,
+}
+
+impl Moments {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        let n = self.n as f64;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += dx / n;
+        self.mean_y += dy / n;
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.c_xy += dx * (y - self.mean_y);
+    }
+
+    fn remove(&mut self, x: f64, y: f64) {
+        if self.n <= 1 {
+            *self = Moments::default();
+            return;
+        }
+
+        // exactly undo the corresponding `add`
+        self.n -= 1;
+        let n = self.n as f64;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x -= dx / n;
+        self.mean_y -= dy / n;
+        self.m2_x -= (x - self.mean_x) * dx;
+        self.m2_y -= (y - self.mean_y) * dy;
+        self.c_xy -= (x - self.mean_x) * dy;
+    }
+
+    /// The Pearson correlation coefficient, or `None` if either column has no variance.
+    fn correlation(&self) -> Option<f64> {
+        if self.n < 2 || self.m2_x < MIN_VARIANCE || self.m2_y < MIN_VARIANCE {
+            return None;
+        }
+        Some(self.c_xy / (self.m2_x * self.m2_y).sqrt())
+    }
+}
+
+/// A single point added to or removed from a group.
+pub struct Point {
+    x: f64,
+    y: f64,
+    positive: bool,
+}
+
+/// `Correlation` emits, for every group, the Pearson correlation coefficient between two numeric
+/// columns over all the group's records.
+///
+/// The operator keeps the running means and co-moments of both columns for every group, so both
+/// new records and retractions are applied in constant time. If either column has no variance
+/// within a group, the correlation is undefined and emitted as NULL. When the last record of a
+/// group is deleted, the group's row is revoked.
+///
+/// A group whose moments are not at hand has them rebuilt from its records in the ancestor, so the
+/// output may be partially materialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correlation {
+    group: Vec<usize>,
+    x: usize,
+    y: usize,
+
+    moments: HashMap<Vec<DataType>, Moments>,
+}
+
+impl Correlation {
+    /// Construct a new `Correlation` operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that correlations are
+    /// computed within, and `x` and `y` are the columns that are correlated. Neither `x` nor `y`
+    /// should be in the `group_by` array.
+    pub fn new(
+        src: NodeIndex,
+        group_by: &[usize],
+        x: usize,
+        y: usize,
+    ) -> GroupedOperator<Correlation> {
+        assert!(
+            !group_by.iter().any(|&i| i == x || i == y),
+            "cannot group by correlated column"
+        );
+
+        GroupedOperator::new(
+            src,
+            Correlation {
+                group: group_by.into(),
+                x,
+                y,
+                moments: HashMap::new(),
+            },
+        )
+    }
+}
+
+impl GroupedOperation for Correlation {
+    type Diff = Point;
+
+    fn setup(&mut self, parent: &Node) {
+        let cols = parent.fields().len();
+        assert!(
+            self.x < cols && self.y < cols,
+            "cannot correlate non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        Point {
+            x: f64::from(&r[self.x]),
+            y: f64::from(&r[self.y]),
+            positive: pos,
+        }
+    }
+
+    fn apply_rows(
+        &mut self,
+        group: &[DataType],
+        current: &[&[DataType]],
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let mut m = if current.is_empty() {
+            // a group without a row has no records besides those in `diffs`
+            Moments::default()
+        } else {
+            self.moments.remove(group)?
+        };
+
+        for p in diffs {
+            if p.positive {
+                m.add(p.x, p.y);
+            } else {
+                m.remove(p.x, p.y);
+            }
+        }
+
+        if m.n == 0 {
+            // the group has no records left
+            return Some(Vec::new());
+        }
+
+        let corr = m
+            .correlation()
+            .map(DataType::from)
+            .unwrap_or(DataType::None);
+        self.moments.insert(group.to_vec(), m);
+        Some(vec![vec![corr]])
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("Corr");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Corr({}, {}) γ[{}]", self.x, self.y, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.x, self.y]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["g", "x", "y"]);
+        g.set_op(
+            "corr",
+            &["g", "corr"],
+            Correlation::new(s.as_global(), &[0], 1, 2),
+            true,
+        );
+        g
+    }
+
+    fn row(x: i32, y: i32) -> Vec<DataType> {
+        vec![1.into(), x.into(), y.into()]
+    }
+
+    /// Compute the correlation of `points` directly.
+    fn batch(points: &[(i32, i32)]) -> f64 {
+        let n = points.len() as f64;
+        let mx = points.iter().map(|&(x, _)| f64::from(x)).sum::<f64>() / n;
+        let my = points.iter().map(|&(_, y)| f64::from(y)).sum::<f64>() / n;
+        let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+        for &(x, y) in points {
+            let (dx, dy) = (f64::from(x) - mx, f64::from(y) - my);
+            sxy += dx * dy;
+            sxx += dx * dx;
+            syy += dy * dy;
+        }
+        sxy / (sxx * syy).sqrt()
+    }
+
+    /// The correlation in the single positive record of `rs`.
+    fn emitted(rs: &Records) -> DataType {
+        let pos: Vec<_> = rs.iter().filter(|r| r.is_positive()).collect();
+        assert_eq!(pos.len(), 1);
+        pos[0][1].clone()
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup();
+        assert_eq!(g.node().description(true), "Corr(1, 2) γ[0]");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 1.into();
+        let g = setup();
+        let idx = g.node().suggest_indexes(me);
+
+        // should index own columns, and the ancestor by group so that moments can be rebuilt
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![0]);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_matches_batch_correlation() {
+        let mut g = setup();
+
+        // a single point has no variance
+        let rs = g.narrow_one_row(row(1, 2), true);
+        assert_eq!(rs, vec![vec![1.into(), DataType::None]].into());
+
+        let points = [(1, 2), (2, 4), (3, 7), (4, 8), (5, 9)];
+        let mut rs = Records::default();
+        for &(x, y) in &points[1..] {
+            rs = g.narrow_one_row(row(x, y), true);
+        }
+        let corr = emitted(&rs);
+        assert!((f64::from(&corr) - batch(&points)).abs() < 1e-6);
+
+        // retracting a point should give the same result as never having seen it
+        let rs = g.narrow_one_row((row(3, 7), false), true);
+        assert!(rs.has_negative(&[1.into(), corr][..]));
+        let remaining = [(1, 2), (2, 4), (4, 8), (5, 9)];
+        assert!((f64::from(emitted(&rs)) - batch(&remaining)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_handles_zero_variance() {
+        let mut g = setup();
+        g.narrow_one_row(row(1, 2), true);
+        let rs = g.narrow_one_row(row(1, 5), true);
+        assert!(rs.is_empty());
+
+        // once x varies, the correlation is defined
+        let rs = g.narrow_one_row(row(2, 5), true);
+        assert_eq!(rs.len(), 2);
+        assert_ne!(emitted(&rs), DataType::None);
+
+        // and once the group is empty, no row is emitted at all
+        let rs = g.narrow_one(
+            vec![(row(1, 2), false), (row(1, 5), false), (row(2, 5), false)],
+            true,
+        );
+        assert_eq!(rs.len(), 1);
+        assert!(!rs.iter().any(|r| r.is_positive()));
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// `CountDistinct` counts the number of distinct values of the `over` column in each group, like
+/// `COUNT(DISTINCT over)` in SQL.
+///
+/// Unlike `COUNT`, the distinct count cannot be derived from the current count and the incoming
+/// records alone, so the operator keeps the multiset of values in every group. A record only
+/// changes the count when the multiplicity of its value crosses zero: the first copy of a value
+/// increments the count, and deleting the last copy decrements it. NULL values are not counted.
+///
+/// Since the multisets are built from every record the operator has seen, its output must be fully
+/// materialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountDistinct {
+    over: usize,
+    group: Vec<usize>,
+
+    // the number of copies of each value in each group
+    values: HashMap<Vec<DataType>, HashMap<DataType, i64>>,
+}
+
+impl CountDistinct {
+    /// Construct a new `CountDistinct` operator.
+    ///
+    /// The operator counts the distinct values in column number `over` from its inputs (i.e., from
+    /// the `src` node in the graph), and uses the columns in the `group_by` array as a group
+    /// identifier. The `over` column should not be in the `group_by` array.
+    pub fn new(src: NodeIndex, over: usize, group_by: &[usize]) -> GroupedOperator<CountDistinct> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by aggregation column"
+        );
+        GroupedOperator::new(
+            src,
+            CountDistinct {
+                over,
+                group: group_by.into(),
+                values: HashMap::new(),
+            },
+        )
+    }
+}
+
+impl GroupedOperation for CountDistinct {
+    type Diff = (DataType, bool);
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        (r[self.over].clone(), pos)
+    }
+
+    fn apply(
+        &mut self,
+        group: &[DataType],
+        _: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<DataType> {
+        let mut values = self.values.remove(group).unwrap_or_default();
+        for (v, pos) in diffs {
+            if v.is_none() {
+                continue;
+            }
+
+            let n = values.entry(v).or_insert(0);
+            *n += if pos { 1 } else { -1 };
+            debug_assert!(*n >= 0, "deleted a value that was never counted");
+        }
+        values.retain(|_, &mut n| n > 0);
+
+        let count = values.len() as i64;
+        if !values.is_empty() {
+            self.values.insert(group.to_vec(), values);
+        }
+        Some(count.into())
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("|≠|");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("|≠{}| γ[{}]", self.over, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "agg",
+            &["x", "ys"],
+            CountDistinct::new(s.as_global(), 1, &[0]),
+            true,
+        );
+        g
+    }
+
+    fn count(group: i32, n: i64) -> Vec<DataType> {
+        vec![group.into(), n.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let c = setup();
+        assert_eq!(c.node().description(true), "|≠1| γ[0]");
+    }
+
+    #[test]
+    fn it_counts_each_value_once() {
+        let mut c = setup();
+
+        assert_eq!(
+            c.narrow_one_row(vec![1.into(), "a".into()], true),
+            vec![count(1, 1)].into()
+        );
+
+        // a second copy of the same value doesn't change the count
+        assert!(c
+            .narrow_one_row(vec![1.into(), "a".into()], true)
+            .is_empty());
+
+        // but a new value does
+        assert_eq!(
+            c.narrow_one_row(vec![1.into(), "b".into()], true),
+            vec![(count(1, 1), false), (count(1, 2), true)].into()
+        );
+
+        // and NULLs are not counted at all
+        assert!(c
+            .narrow_one_row(vec![1.into(), DataType::None], true)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_counts_down_on_the_last_copy() {
+        let mut c = setup();
+        c.narrow_one(
+            vec![
+                vec![1.into(), "a".into()],
+                vec![1.into(), "a".into()],
+                vec![1.into(), "b".into()],
+            ],
+            true,
+        );
+
+        // deleting one of two copies leaves the value counted
+        assert!(c
+            .narrow_one_row((vec![1.into(), "a".into()], false), true)
+            .is_empty());
+
+        // deleting the last copy doesn't
+        assert_eq!(
+            c.narrow_one_row((vec![1.into(), "a".into()], false), true),
+            vec![(count(1, 2), false), (count(1, 1), true)].into()
+        );
+
+        // and a value that crosses zero within a batch changes nothing
+        assert!(c
+            .narrow_one(
+                vec![
+                    (vec![1.into(), "b".into()], false),
+                    (vec![1.into(), "b".into()], true),
+                ],
+                true
+            )
+            .is_empty());
+    }
+}
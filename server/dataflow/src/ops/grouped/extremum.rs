@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, HashMap};
+
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 
@@ -23,6 +25,30 @@ impl Extremum {
         src: NodeIndex,
         over: usize,
         group_by: &[usize],
+    ) -> GroupedOperator<ExtremumOperator> {
+        self.build(src, over, group_by, None)
+    }
+
+    /// Like `over`, but keep the ordered multiset of values in every group, so that the new
+    /// extremum can be found without querying the ancestor when the current one is deleted.
+    ///
+    /// The multisets are built from every record the operator sees, so its output must be fully
+    /// materialized.
+    pub fn over_all_values(
+        self,
+        src: NodeIndex,
+        over: usize,
+        group_by: &[usize],
+    ) -> GroupedOperator<ExtremumOperator> {
+        self.build(src, over, group_by, Some(HashMap::new()))
+    }
+
+    fn build(
+        self,
+        src: NodeIndex,
+        over: usize,
+        group_by: &[usize],
+        values: Option<HashMap<Vec<DataType>, BTreeMap<DataType, usize>>>,
     ) -> GroupedOperator<ExtremumOperator> {
         assert!(
             !group_by.iter().any(|&i| i == over),
@@ -34,6 +60,7 @@ impl Extremum {
                 op: self,
                 over,
                 group: group_by.into(),
+                values,
             },
         )
     }
@@ -51,16 +78,28 @@ impl Extremum {
 /// incoming record. The output record is constructed by concatenating the columns identifying the
 /// group, and appending the aggregated value. For example, for a sum with `self.over == 1`, a
 /// previous sum of `3`, and an incoming record with `[a, 1, x]`, the output would be `[a, x, 4]`.
+///
+/// Any numeric or timestamp column can be aggregated over, and the extremum is emitted with the
+/// type of the value it came from.
+///
+/// When the current extremum is deleted and nothing in the same batch replaces it, the new one is
+/// found by looking at all of the group's records in the ancestor. Operators made with
+/// `Extremum::over_all_values` instead keep the ordered multiset of values in every group, at the
+/// cost of requiring full materialization. When the last record of a group is deleted, the group's
+/// extremum is revoked and no new one is emitted.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExtremumOperator {
     op: Extremum,
     over: usize,
     group: Vec<usize>,
+
+    // the number of copies of each value in each group, if we keep them
+    values: Option<HashMap<Vec<DataType>, BTreeMap<DataType, usize>>>,
 }
 
 pub enum DiffType {
-    Insert(i128),
-    Remove(i128),
+    Insert(DataType),
+    Remove(DataType),
 }
 
 impl GroupedOperation for ExtremumOperator {
@@ -79,10 +118,13 @@ impl GroupedOperation for ExtremumOperator {
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
         let v = match r[self.over] {
-            DataType::Int(n) => i128::from(n),
-            DataType::UnsignedInt(n) => i128::from(n),
-            DataType::BigInt(n) => i128::from(n),
-            DataType::UnsignedBigInt(n) => i128::from(n),
+            ref v @ DataType::Int(..)
+            | ref v @ DataType::UnsignedInt(..)
+            | ref v @ DataType::BigInt(..)
+            | ref v @ DataType::UnsignedBigInt(..)
+            | ref v @ DataType::Real(..)
+            | ref v @ DataType::Decimal(..)
+            | ref v @ DataType::Timestamp(..) => v.clone(),
             _ => {
                 // the column we're aggregating over is non-numerical (or rather, this value is).
                 // if you've removed a column, chances are the  default value has the wrong type.
@@ -105,46 +147,31 @@ impl GroupedOperation for ExtremumOperator {
     }
 
     fn apply(
-        &self,
+        &mut self,
+        group: &[DataType],
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType {
-        // Extreme values are those that are at least as extreme as the current min/max (if any).
-        // let mut is_extreme_value : Box<dyn Fn(i64) -> bool> = Box::new(|_|true);
-        let mut extreme_values: Vec<i128> = vec![];
-        if let Some(data) = current {
-            match *data {
-                DataType::Int(n) => extreme_values.push(i128::from(n)),
-                DataType::UnsignedInt(n) => extreme_values.push(i128::from(n)),
-                DataType::BigInt(n) => extreme_values.push(i128::from(n)),
-                DataType::UnsignedBigInt(n) => extreme_values.push(i128::from(n)),
-                _ => unreachable!(),
-            }
-        };
+    ) -> Option<DataType> {
+        if self.values.is_some() {
+            return Some(self.apply_all_values(group, diffs));
+        }
 
-        let is_extreme_value = |x: i128| {
-            if let Some(data) = current {
-                let n = match *data {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    _ => unreachable!(),
-                };
-                match self.op {
-                    Extremum::MAX => x >= n,
-                    Extremum::MIN => x <= n,
-                }
-            } else {
-                true
-            }
+        // Extreme values are those that are at least as extreme as the current min/max (if any).
+        let op = &self.op;
+        let is_extreme_value = |x: &DataType| match current {
+            Some(n) => match *op {
+                Extremum::MAX => x >= n,
+                Extremum::MIN => x <= n,
+            },
+            None => true,
         };
 
+        let mut extreme_values: Vec<DataType> = current.into_iter().cloned().collect();
         for d in diffs {
             match d {
-                DiffType::Insert(v) if is_extreme_value(v) => extreme_values.push(v),
-                DiffType::Remove(v) if is_extreme_value(v) => {
-                    if let Some(i) = extreme_values.iter().position(|x: &i128| *x == v) {
+                DiffType::Insert(v) if is_extreme_value(&v) => extreme_values.push(v),
+                DiffType::Remove(v) if is_extreme_value(&v) => {
+                    if let Some(i) = extreme_values.iter().position(|x| *x == v) {
                         extreme_values.swap_remove(i);
                     }
                 }
@@ -157,12 +184,21 @@ impl GroupedOperation for ExtremumOperator {
             Extremum::MAX => extreme_values.into_iter().max(),
         };
 
-        if let Some(extreme) = extreme {
-            return extreme.into();
+        match extreme {
+            Some(extreme) => Some(extreme),
+            // the current extremum was deleted, and we don't know what the runner-up is
+            None if current.is_some() => None,
+            // the group has no records left
+            None => Some(DataType::None),
         }
+    }
+
+    fn recomputes(&self) -> bool {
+        self.values.is_none()
+    }
 
-        // TODO: handle this case by querying into the parent.
-        unimplemented!();
+    fn is_empty(&self, value: &DataType) -> bool {
+        value.is_none()
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -189,6 +225,46 @@ impl GroupedOperation for ExtremumOperator {
     fn over_columns(&self) -> Vec<usize> {
         vec![self.over]
     }
+
+    fn requires_full_materialization(&self) -> bool {
+        self.values.is_some()
+    }
+}
+
+impl ExtremumOperator {
+    fn apply_all_values(
+        &mut self,
+        group: &[DataType],
+        diffs: &mut dyn Iterator<Item = DiffType>,
+    ) -> DataType {
+        let all = self.values.as_mut().unwrap();
+        let mut values = all.remove(group).unwrap_or_default();
+        for d in diffs {
+            match d {
+                DiffType::Insert(v) => *values.entry(v).or_insert(0) += 1,
+                DiffType::Remove(v) => {
+                    if let Some(n) = values.get_mut(&v) {
+                        *n -= 1;
+                        if *n == 0 {
+                            values.remove(&v);
+                        }
+                    }
+                }
+            }
+        }
+
+        let extreme = match self.op {
+            Extremum::MIN => values.keys().next(),
+            Extremum::MAX => values.keys().next_back(),
+        }
+        .cloned();
+        if !values.is_empty() {
+            all.insert(group.to_vec(), values);
+        }
+
+        // the group has no records left if there is no extreme
+        extreme.unwrap_or(DataType::None)
+    }
 }
 
 #[cfg(test)]
@@ -205,6 +281,15 @@ mod tests {
         g
     }
 
+    fn setup_all_values(op: Extremum) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+
+        let op = op.over_all_values(s.as_global(), 1, &[0]);
+        g.set_op("agg", &["x", "ys"], op, true);
+        g
+    }
+
     fn assert_positive_record(group: i32, new: i32, rs: Records) {
         assert_eq!(rs.len(), 1);
 
@@ -309,6 +394,114 @@ mod tests {
         assert_record_change(key, 7, 5, out);
     }
 
+    #[test]
+    fn it_queries_the_ancestor_when_the_extreme_is_deleted() {
+        let mut c = setup(Extremum::MAX, true);
+        let s = c.narrow_base_id();
+        let key = 1;
+        c.narrow_one(
+            vec![
+                vec![key.into(), 4.into()],
+                vec![key.into(), 9.into()],
+                vec![key.into(), 7.into()],
+            ],
+            true,
+        );
+
+        // Deleting the max with nothing to replace it in the same batch finds the runner-up among
+        // the records that are left in the ancestor.
+        c.seed(s, vec![key.into(), 4.into()]);
+        c.seed(s, vec![key.into(), 7.into()]);
+        let out = c.narrow_one_row((vec![key.into(), 9.into()], false), true);
+        assert_record_change(key, 9, 7, out);
+
+        // And if there is nothing left, the group's max is only revoked.
+        c.unseed(s);
+        let u = vec![
+            (vec![key.into(), 7.into()], false),
+            (vec![key.into(), 4.into()], false),
+        ];
+        let out = c.narrow_one(u, true);
+        assert_eq!(out, vec![(vec![key.into(), 7.into()], false)].into());
+    }
+
+    #[test]
+    fn it_finds_the_runner_up_when_the_extreme_is_deleted() {
+        let mut c = setup_all_values(Extremum::MAX);
+        let key = 1;
+        c.narrow_one(
+            vec![
+                vec![key.into(), 4.into()],
+                vec![key.into(), 9.into()],
+                vec![key.into(), 9.into()],
+                vec![key.into(), 7.into()],
+            ],
+            true,
+        );
+
+        // Deleting one of two copies of the max leaves it the max.
+        let rs = c.narrow_one_row((vec![key.into(), 9.into()], false), true);
+        assert!(rs.is_empty());
+
+        // Deleting the last copy promotes the runner-up.
+        let out = c.narrow_one_row((vec![key.into(), 9.into()], false), true);
+        assert_record_change(key, 9, 7, out);
+
+        // Deleting the rest of the group only revokes its max.
+        let u = vec![
+            (vec![key.into(), 7.into()], false),
+            (vec![key.into(), 4.into()], false),
+        ];
+        let out = c.narrow_one(u, true);
+        assert_eq!(out, vec![(vec![key.into(), 7.into()], false)].into());
+
+        // And a new record starts the group over.
+        let out = c.narrow_one_row(vec![key.into(), 2.into()], true);
+        assert_positive_record(key, 2, out);
+    }
+
+    #[test]
+    fn it_finds_the_runner_up_when_the_minimum_is_deleted() {
+        let mut c = setup_all_values(Extremum::MIN);
+        let key = 1;
+        c.narrow_one(
+            vec![vec![key.into(), 4.into()], vec![key.into(), 9.into()]],
+            true,
+        );
+
+        let out = c.narrow_one_row((vec![key.into(), 4.into()], false), true);
+        assert_record_change(key, 4, 9, out);
+    }
+
+    #[test]
+    fn it_finds_extreme_timestamps() {
+        use chrono::NaiveDate;
+        let ts = |h| DataType::Timestamp(NaiveDate::from_ymd(2020, 3, 1).and_hms(h, 0, 0));
+
+        let mut c = setup_all_values(Extremum::MAX);
+        c.narrow_one(
+            vec![
+                vec![1.into(), ts(9)],
+                vec![1.into(), ts(17)],
+                vec![1.into(), ts(12)],
+            ],
+            true,
+        );
+        let out = c.narrow_one_row((vec![1.into(), ts(17)], false), true);
+        assert_eq!(
+            out,
+            vec![
+                (vec![1.into(), ts(17)], false),
+                (vec![1.into(), ts(12)], true)
+            ]
+            .into()
+        );
+
+        let mut c = setup(Extremum::MIN, true);
+        let out = c.narrow_one(vec![vec![1.into(), ts(9)], vec![1.into(), ts(8)]], true);
+        assert_eq!(out, vec![vec![1.into(), ts(8)]].into());
+    }
+
     #[test]
     fn it_cancels_out_opposite_records() {
         let mut c = setup(Extremum::MAX, true);
@@ -329,12 +522,20 @@ mod tests {
         let c = setup(Extremum::MAX, false);
         let idx = c.node().suggest_indexes(me);
 
-        // should only add index on own columns
-        assert_eq!(idx.len(), 1);
+        // should index own columns, and the ancestor to find a deleted extreme's replacement
+        assert_eq!(idx.len(), 2);
         assert!(idx.contains_key(&me));
+        assert!(idx.contains_key(&c.narrow_base_id().as_global()));
 
         // should only index on the group-by column
         assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&c.narrow_base_id().as_global()], vec![0]);
+
+        // unless it keeps all the values itself
+        let c = setup_all_values(Extremum::MAX);
+        let idx = c.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert!(c.node().requires_full_materialization());
     }
 
     #[test]
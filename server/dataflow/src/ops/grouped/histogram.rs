@@ -0,0 +1,257 @@
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// A single record added to or removed from a group, and the bucket its value falls into.
+pub struct Count {
+    bucket: Option<usize>,
+    positive: bool,
+}
+
+/// `Histogram` emits, for every group, the number of records whose value in one column falls into
+/// each of a fixed set of buckets.
+///
+/// The buckets are given by a sorted list of boundaries. With boundaries `[b0, b1]`, there are
+/// three buckets: values below `b0`, values in `[b0, b1)`, and values at or above `b1`. Each output
+/// row holds the group columns followed by one count column per bucket. NULL values are not
+/// counted in any bucket.
+///
+/// The counts are updated from the group's current row, so the operator keeps no state of its own.
+/// Only when every count drops to zero can it not tell whether the group still has records with
+/// NULL values, and it then counts the group's records in the ancestor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Histogram {
+    group: Vec<usize>,
+    over: usize,
+    boundaries: Vec<DataType>,
+}
+
+impl Histogram {
+    /// Construct a new `Histogram` operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that histograms are
+    /// computed within, `over` is the column whose values are counted, and `boundaries` are the
+    /// boundaries between buckets in ascending order.
+    pub fn new(
+        src: NodeIndex,
+        group_by: &[usize],
+        over: usize,
+        boundaries: Vec<DataType>,
+    ) -> GroupedOperator<Histogram> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by histogram column"
+        );
+        assert!(
+            boundaries.windows(2).all(|w| w[0] < w[1]),
+            "histogram boundaries must be strictly ascending"
+        );
+
+        GroupedOperator::new(
+            src,
+            Histogram {
+                group: group_by.into(),
+                over,
+                boundaries,
+            },
+        )
+    }
+
+    fn bucket(&self, v: &DataType) -> usize {
+        match self.boundaries.binary_search(v) {
+            // a value equal to a boundary belongs to the bucket that starts there
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+}
+
+impl GroupedOperation for Histogram {
+    type Diff = Count;
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot compute histogram over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        let v = &r[self.over];
+        Count {
+            bucket: if v.is_none() {
+                None
+            } else {
+                Some(self.bucket(v))
+            },
+            positive: pos,
+        }
+    }
+
+    fn apply_rows(
+        &mut self,
+        _: &[DataType],
+        current: &[&[DataType]],
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let mut buckets: Vec<i64> = match current.first() {
+            Some(row) => row.iter().map(i64::from).collect(),
+            None => vec![0; self.boundaries.len() + 1],
+        };
+
+        let mut records = 0;
+        let mut retracted = false;
+        for c in diffs {
+            let diff = if c.positive { 1 } else { -1 };
+            records += diff;
+            retracted |= !c.positive;
+            if let Some(bucket) = c.bucket {
+                buckets[bucket] += diff;
+            }
+        }
+
+        if current.is_empty() {
+            // a group without a row has no records besides those in `diffs`
+            if records <= 0 {
+                return Some(Vec::new());
+            }
+        } else if retracted && buckets.iter().all(|&n| n == 0) {
+            // the group may still have records with NULL values, which aren't counted
+            return None;
+        }
+
+        Some(vec![buckets.into_iter().map(DataType::from).collect()])
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("Hist");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let boundaries = self
+            .boundaries
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Hist({}) [{}] γ[{}]", self.over, boundaries, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "hist",
+            &["x", "lt10", "lt20", "rest"],
+            Histogram::new(s.as_global(), &[0], 1, vec![10.into(), 20.into()]),
+            true,
+        );
+        g
+    }
+
+    fn row(x: i32, y: i32) -> Vec<DataType> {
+        vec![x.into(), y.into()]
+    }
+
+    fn hist(x: i32, counts: [i64; 3]) -> Vec<DataType> {
+        vec![
+            x.into(),
+            counts[0].into(),
+            counts[1].into(),
+            counts[2].into(),
+        ]
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup();
+        assert_eq!(g.node().description(true), "Hist(1) [10, 20] γ[0]");
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup();
+        let src = g.narrow_base_id().as_global();
+        assert_eq!(g.node().resolve(0), Some(vec![(src, 0)]));
+        assert_eq!(g.node().resolve(1), None);
+        assert_eq!(g.node().resolve(3), None);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_counts_into_buckets() {
+        let mut g = setup();
+
+        assert_eq!(
+            g.narrow_one_row(row(1, 5), true),
+            vec![hist(1, [1, 0, 0])].into()
+        );
+
+        // boundaries belong to the bucket above them
+        assert_eq!(
+            g.narrow_one_row(row(1, 10), true),
+            vec![(hist(1, [1, 0, 0]), false), (hist(1, [1, 1, 0]), true)].into()
+        );
+
+        let rs = g.narrow_one(vec![row(1, 25), row(1, 100), row(2, 15)], true);
+        assert_eq!(rs.len(), 3);
+        assert!(rs.has_negative(&hist(1, [1, 1, 0])[..]));
+        assert!(rs.has_positive(&hist(1, [1, 1, 2])[..]));
+        assert!(rs.has_positive(&hist(2, [0, 1, 0])[..]));
+    }
+
+    #[test]
+    fn it_retracts_from_buckets() {
+        let mut g = setup();
+        let s = g.narrow_base_id();
+        g.narrow_one(vec![row(1, 5), row(1, 15), row(1, 15)], true);
+
+        assert_eq!(
+            g.narrow_one_row((row(1, 15), false), true),
+            vec![(hist(1, [1, 2, 0]), false), (hist(1, [1, 1, 0]), true)].into()
+        );
+
+        // nulls aren't counted, but still keep the group around
+        let null = vec![1.into(), DataType::None];
+        assert_eq!(g.narrow_one_row(null.clone(), true), Records::default());
+        g.seed(s, null.clone());
+        let rs = g.narrow_one(vec![(row(1, 5), false), (row(1, 15), false)], true);
+        assert_eq!(
+            rs,
+            vec![(hist(1, [1, 1, 0]), false), (hist(1, [0, 0, 0]), true)].into()
+        );
+
+        // once every record is gone, so is the histogram
+        g.unseed(s);
+        assert_eq!(
+            g.narrow_one_row((null, false), true),
+            vec![(hist(1, [0, 0, 0]), false)].into()
+        );
+    }
+}
@@ -7,12 +7,25 @@ use crate::prelude::*;
 
 // pub mod latest;
 pub mod aggregate;
+pub mod average;
 pub mod concat;
+pub mod correlation;
+pub mod countdistinct;
 pub mod extremum;
 pub mod filteraggregate;
+pub mod histogram;
+pub mod movingavg;
+pub mod reservoir;
+pub mod session;
+pub mod setunion;
+pub mod spacesaving;
+pub mod stringagg;
 
 /// Trait for implementing operations that collapse a group of records into a single record.
 ///
+/// Operations that emit several records for a group, or several computed columns, do so by
+/// implementing `GroupedOperation::apply_rows` instead of `GroupedOperation::apply`.
+///
 /// Implementors of this trait can be used as nodes in a `flow::FlowGraph` by wrapping them in a
 /// `GroupedOperator`.
 ///
@@ -53,14 +66,75 @@ pub trait GroupedOperation: fmt::Debug + Clone {
 
     /// Given the given `current` value, and a number of changes for a group (`diffs`), compute the
     /// updated group value.
+    ///
+    /// `group` holds the values of the `group_by` columns of the group, so that operations that
+    /// keep per-group state of their own can find it.
+    ///
+    /// Operations for which `recomputes` is true may return `None` if the updated value cannot be
+    /// derived from `current` and `diffs` alone. `apply` is then called again with no current value
+    /// and an insertion for each of the group's records in the ancestor, and must return a value.
+    ///
+    /// Operations that implement `apply_rows` instead need not implement this.
     fn apply(
-        &self,
-        current: Option<&DataType>,
+        &mut self,
+        _group: &[DataType],
+        _current: Option<&DataType>,
+        _diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<DataType> {
+        unreachable!("{} computes rows, not values", self.description(false))
+    }
+
+    /// Like `apply`, but for operations that emit any number of rows for a group, each holding the
+    /// group columns followed by one or more computed columns.
+    ///
+    /// `current` holds the computed columns of each of the group's current output rows. If it is
+    /// empty, `diffs` hold all of the group's records. The returned rows replace the current ones,
+    /// so returning no rows revokes the group. Like `apply`, operations for which `recomputes` is
+    /// true may return `None` to be called again with the whole group.
+    ///
+    /// The default implementation emits the value computed by `apply`, unless `is_empty` says that
+    /// the group has no records left.
+    fn apply_rows(
+        &mut self,
+        group: &[DataType],
+        current: &[&[DataType]],
         diffs: &mut dyn Iterator<Item = Self::Diff>,
-    ) -> DataType;
+    ) -> Option<Vec<Vec<DataType>>> {
+        debug_assert!(current.len() <= 1, "a group had more than 1 result");
+        let new = self.apply(group, current.first().map(|r| &r[0]), diffs)?;
+        if self.is_empty(&new) {
+            Some(Vec::new())
+        } else {
+            Some(vec![vec![new]])
+        }
+    }
+
+    /// Whether `apply` may ask for a group to be recomputed from all of its records, in which case
+    /// the ancestor is also indexed by the group columns.
+    ///
+    /// The default implementation returns `false`.
+    fn recomputes(&self) -> bool {
+        false
+    }
+
+    /// Whether `value`, as returned by `apply`, means that the group has no records left.
+    ///
+    /// The output record of such a group is revoked, and no new one is emitted in its place. The
+    /// default implementation returns `false`, so every group always has an output record.
+    fn is_empty(&self, _value: &DataType) -> bool {
+        false
+    }
 
     fn description(&self, detailed: bool) -> String;
     fn over_columns(&self) -> Vec<usize>;
+
+    /// Whether the operation keeps per-group state that can only be built from every record, so
+    /// that its output cannot be partially materialized.
+    ///
+    /// The default implementation returns `false`.
+    fn requires_full_materialization(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -113,6 +187,37 @@ fn get_group_values(group_by: &[usize], row: &Record) -> Vec<DataType> {
     group
 }
 
+/// All the records in `group` of the ancestor `src` of a grouped operator, which must be indexed by
+/// the `group_by` columns.
+fn ancestor_group<'a>(
+    src: IndexPair,
+    group_by: &[usize],
+    group: &[DataType],
+    nodes: &DomainNodes,
+    states: &'a StateMap,
+) -> Box<dyn Iterator<Item = Cow<'a, [DataType]>> + 'a> {
+    let key = KeyType::from(group);
+    let rs = match states.get(*src) {
+        Some(state) => match state.lookup(group_by, &key) {
+            LookupResult::Some(rs) => Some(Box::new(rs.into_iter()) as Box<_>),
+            LookupResult::Missing => None,
+        },
+        None => {
+            // our ancestor may be queried *through* if it isn't materialized itself
+            let parent = nodes[*src].borrow();
+            let rs = if parent.is_internal() {
+                parent.query_through(group_by, &key, nodes, states)
+            } else {
+                None
+            };
+            rs.expect("grouped operator must have its ancestor's state materialized")
+        }
+    };
+    // our state for the group is present, so the ancestor's must be too: had the ancestor
+    // evicted the group, the eviction would have reached us as well.
+    rs.expect("grouped operator's ancestor is missing state for a group we hold")
+}
+
 impl<T: GroupedOperation + Send + 'static> Ingredient for GroupedOperator<T>
 where
     Self: Into<NodeOperator>,
@@ -125,7 +230,7 @@ where
         vec![self.src.as_global()]
     }
 
-    fn on_connected(&mut self, g: &Graph) {
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
         let srcn = &g[self.src.as_global()];
 
         // give our inner operation a chance to initialize
@@ -148,6 +253,7 @@ where
             })
             .collect();
         self.colfix.extend(colfix.into_iter());
+        Ok(())
     }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
@@ -164,7 +270,7 @@ where
         from: LocalNodeIndex,
         rs: Records,
         replay_key_cols: Option<&[usize]>,
-        _: &DomainNodes,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -200,6 +306,7 @@ where
         let mut lookups = Vec::new();
         let mut out = Vec::new();
         {
+            let src = self.src;
             let out_key = &self.out_key;
             let mut handle_group =
                 |inner: &mut T,
@@ -220,7 +327,6 @@ where
                                     });
                                 }
 
-                                debug_assert!(rs.len() <= 1, "a group had more than 1 result");
                                 rs
                             }
                             LookupResult::Missing => {
@@ -236,33 +342,43 @@ where
                         }
                     };
 
-                    let old = rs.into_iter().next();
-                    // current value is in the last output column
-                    // or "" if there is no current group
-                    let current = old.as_ref().map(|rows| match rows {
-                        Cow::Borrowed(rs) => Cow::Borrowed(&rs[rs.len() - 1]),
-                        Cow::Owned(rs) => Cow::Owned(rs[rs.len() - 1].clone()),
-                    });
-
-                    // new is the result of applying all diffs for the group to the current value
-                    let new = inner.apply(current.as_ref().map(|v| &**v), &mut diffs as &mut _);
-                    match current {
-                        Some(ref current) if new == **current => {
-                            // no change
+                    // the current rows of the group, whose computed columns follow the group's
+                    let old: Vec<_> = rs.into_iter().collect();
+                    let current: Vec<_> = old.iter().map(|r| &r[group.len()..]).collect();
+
+                    // new is the result of applying all diffs for the group to the current rows
+                    let new = inner
+                        .apply_rows(&group, &current, &mut diffs as &mut _)
+                        .unwrap_or_else(|| {
+                            // the operation needs to see the whole group to tell
+                            let rs = ancestor_group(src, group_by, &group, nodes, state);
+                            let all: Vec<_> = rs.map(|r| inner.to_diff(&r[..], true)).collect();
+                            let mut all = all.into_iter();
+                            inner
+                                .apply_rows(&group, &[], &mut all as &mut _)
+                                .expect("grouped operation must compute a value from scratch")
+                        });
+
+                    // revoke the rows that are gone, and emit the ones that are new. rows that
+                    // did not change are left alone.
+                    let mut kept = vec![false; old.len()];
+                    let mut added = Vec::new();
+                    for values in new {
+                        match (0..old.len()).find(|&i| !kept[i] && current[i] == &values[..]) {
+                            Some(i) => kept[i] = true,
+                            None => added.push(values),
                         }
-                        _ => {
-                            if let Some(old) = old {
-                                // revoke old value
-                                debug_assert!(current.is_some());
-                                out.push(Record::Negative(old.into_owned()));
-                            }
-
-                            // emit positive, which is group + new.
-                            let mut rec = group;
-                            rec.push(new);
-                            out.push(Record::Positive(rec));
+                    }
+                    for (row, kept) in old.into_iter().zip(kept) {
+                        if !kept {
+                            out.push(Record::Negative(row.into_owned()));
                         }
                     }
+                    for values in added {
+                        let mut rec = group.clone();
+                        rec.extend(values);
+                        out.push(Record::Positive(rec));
+                    }
                 };
 
             let mut diffs = Vec::new();
@@ -288,11 +404,16 @@ where
 
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         // index by our primary key
-        Some((this, self.out_key.clone())).into_iter().collect()
+        let mut idx: HashMap<_, _> = Some((this, self.out_key.clone())).into_iter().collect();
+        if self.inner.recomputes() {
+            // and our ancestor by the group, so that we can find all the records in a group
+            idx.insert(self.src.as_global(), self.group_by.clone());
+        }
+        idx
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
-        if col == self.colfix.len() {
+        if col >= self.colfix.len() {
             return None;
         }
         Some(vec![(self.src.as_global(), self.colfix[col])])
@@ -303,7 +424,7 @@ where
     }
 
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
-        if column == self.colfix.len() {
+        if column >= self.colfix.len() {
             return vec![(self.src.as_global(), None)];
         }
         vec![(self.src.as_global(), Some(self.colfix[column]))]
@@ -312,4 +433,8 @@ where
     fn is_selective(&self) -> bool {
         true
     }
+
+    fn requires_full_materialization(&self) -> bool {
+        self.inner.requires_full_materialization()
+    }
 }
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// A single record added to or removed from a group's window.
+pub struct Change {
+    order: DataType,
+    value: DataType,
+    positive: bool,
+}
+
+/// The most recent records of one group, and how many records the group has in all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Window {
+    // the ordering and averaged values of the records in the window, oldest first
+    rows: Vec<(DataType, DataType)>,
+    count: usize,
+}
+
+/// `MovingAverage` computes, for every group, the average of the `over` column over the `window`
+/// most recent records of that group, where recency is determined by an ordering column.
+///
+/// Records that tie on the ordering column are ordered by their averaged value, so which of them
+/// fall in the window does not depend on the order they arrived in. Records whose averaged value
+/// is NULL or not a number are skipped. The average is emitted as a real, and is summed afresh
+/// from the window whenever it changes, so that it does not drift as records come and go.
+///
+/// Every group keeps the records currently in its window. A new record that is more recent than
+/// the oldest of them evicts it. Deleting a record from a full window lets the most recent record
+/// outside of it back in, which the operator does not keep, so the group's window is then rebuilt
+/// from its records in the ancestor. Windows are also rebuilt when a group is replayed, so the
+/// output may be partially materialized. When the last record of a group is deleted, the group's
+/// average is revoked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovingAverage {
+    over: usize,
+    order_by: usize,
+    group: Vec<usize>,
+    window: usize,
+
+    windows: HashMap<Vec<DataType>, Window>,
+}
+
+impl MovingAverage {
+    /// Construct a new `MovingAverage` operator.
+    ///
+    /// The operator averages the value in column number `over` from its inputs (i.e., from the
+    /// `src` node in the graph) over the `window` records with the greatest value in column
+    /// `order_by`, and uses the columns in the `group_by` array as a group identifier. Neither
+    /// `over` nor `order_by` should be in the `group_by` array.
+    pub fn new(
+        src: NodeIndex,
+        over: usize,
+        order_by: usize,
+        group_by: &[usize],
+        window: usize,
+    ) -> GroupedOperator<MovingAverage> {
+        assert!(window > 0, "moving average window must be non-empty");
+        assert!(
+            !group_by.iter().any(|&i| i == over || i == order_by),
+            "cannot group by aggregation column"
+        );
+        GroupedOperator::new(
+            src,
+            MovingAverage {
+                over,
+                order_by,
+                group: group_by.into(),
+                window,
+                windows: HashMap::new(),
+            },
+        )
+    }
+}
+
+/// The value of `v` as a float, if it is a number.
+fn numeric(v: &DataType) -> Option<f64> {
+    match *v {
+        DataType::Int(n) => Some(f64::from(n)),
+        DataType::UnsignedInt(n) => Some(f64::from(n)),
+        DataType::BigInt(n) => Some(n as f64),
+        DataType::UnsignedBigInt(n) => Some(n as f64),
+        DataType::Real(..) | DataType::Decimal(..) => Some(f64::from(v)),
+        _ => None,
+    }
+}
+
+impl GroupedOperation for MovingAverage {
+    type Diff = Option<Change>;
+
+    fn setup(&mut self, parent: &Node) {
+        let cols = parent.fields().len();
+        assert!(
+            self.over < cols && self.order_by < cols,
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        if numeric(&r[self.over]).is_none() {
+            return None;
+        }
+        Some(Change {
+            order: r[self.order_by].clone(),
+            value: r[self.over].clone(),
+            positive: pos,
+        })
+    }
+
+    fn apply(
+        &mut self,
+        group: &[DataType],
+        current: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<DataType> {
+        let mut w = match current {
+            // a group without an average has no records besides those in `diffs`
+            None => Window::default(),
+            Some(_) => self.windows.remove(group)?,
+        };
+
+        for change in diffs.flatten() {
+            let row = (change.order, change.value);
+            if change.positive {
+                w.count += 1;
+                let i = w.rows.iter().position(|r| r > &row).unwrap_or(w.rows.len());
+                w.rows.insert(i, row);
+                if w.rows.len() > self.window {
+                    w.rows.remove(0);
+                }
+            } else {
+                w.count = w.count.saturating_sub(1);
+                // a record that isn't in the window is older than all of the window's records
+                if let Some(i) = w.rows.iter().position(|r| r == &row) {
+                    w.rows.remove(i);
+                }
+            }
+        }
+
+        if w.rows.len() < w.count.min(self.window) {
+            // a record outside the window now belongs in it
+            return None;
+        }
+        if w.rows.is_empty() {
+            // the group has no values left
+            return Some(DataType::None);
+        }
+
+        let sum: f64 = w.rows.iter().filter_map(|(_, v)| numeric(v)).sum();
+        let avg = DataType::from(sum / w.rows.len() as f64);
+        self.windows.insert(group.to_vec(), w);
+        Some(avg)
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn is_empty(&self, value: &DataType) -> bool {
+        value.is_none()
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("MovingAvg");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "MovingAvg({}) γ[{}] ↑{} ⧖{}",
+            self.over, group_cols, self.order_by, self.window
+        )
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(window: usize) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "t", "v"]);
+        g.set_op(
+            "avg",
+            &["x", "avg"],
+            MovingAverage::new(s.as_global(), 2, 1, &[0], window),
+            true,
+        );
+        g
+    }
+
+    fn row(x: i32, t: i32, v: i32) -> Vec<DataType> {
+        vec![x.into(), t.into(), v.into()]
+    }
+
+    fn avg(x: i32, v: f64) -> Vec<DataType> {
+        vec![x.into(), v.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup(3);
+        assert_eq!(g.node().description(true), "MovingAvg(2) γ[0] ↑1 ⧖3");
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup(3);
+        let src = g.narrow_base_id().as_global();
+        assert_eq!(g.node().resolve(0), Some(vec![(src, 0)]));
+        assert_eq!(g.node().resolve(1), None);
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 1.into();
+        let g = setup(3);
+        let idx = g.node().suggest_indexes(me);
+
+        // should index own columns, and the ancestor by group so that windows can be rebuilt
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![0]);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_slides() {
+        let mut g = setup(2);
+
+        assert_eq!(
+            g.narrow_one_row(row(1, 1, 10), true),
+            vec![avg(1, 10.0)].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(row(1, 2, 20), true),
+            vec![(avg(1, 10.0), false), (avg(1, 15.0), true)].into()
+        );
+
+        // the window is full, so the oldest record is evicted
+        assert_eq!(
+            g.narrow_one_row(row(1, 3, 40), true),
+            vec![(avg(1, 15.0), false), (avg(1, 30.0), true)].into()
+        );
+
+        // a late record that would still be in the window displaces the oldest one
+        assert_eq!(
+            g.narrow_one_row(row(1, 2, 30), true),
+            vec![(avg(1, 30.0), false), (avg(1, 35.0), true)].into()
+        );
+
+        // but one older than the whole window changes nothing
+        assert!(g.narrow_one_row(row(1, 0, 100), true).is_empty());
+
+        // other groups have their own windows
+        assert_eq!(
+            g.narrow_one_row(row(2, 1, 5), true),
+            vec![avg(2, 5.0)].into()
+        );
+    }
+
+    #[test]
+    fn it_refills_window_from_ancestor() {
+        let mut g = setup(2);
+        let s = g.narrow_base_id();
+        g.narrow_one(vec![row(1, 1, 10), row(1, 2, 20), row(1, 3, 60)], true);
+
+        // deleting a record in the window lets the most recent one outside of it back in
+        g.seed(s, row(1, 1, 10));
+        g.seed(s, row(1, 2, 20));
+        assert_eq!(
+            g.narrow_one_row((row(1, 3, 60), false), true),
+            vec![(avg(1, 40.0), false), (avg(1, 15.0), true)].into()
+        );
+
+        // deleting a record that has already left the window changes nothing
+        g.narrow_one_row(row(1, 4, 30), true);
+        assert!(g.narrow_one_row((row(1, 1, 10), false), true).is_empty());
+
+        // and deleting everything revokes the average
+        let rs = vec![(row(1, 2, 20), false), (row(1, 4, 30), false)];
+        assert_eq!(g.narrow_one(rs, true), vec![(avg(1, 25.0), false)].into());
+    }
+
+    #[test]
+    fn it_skips_non_numeric_values() {
+        let mut g = setup(2);
+        let rs = vec![
+            vec![1.into(), 1.into(), DataType::None],
+            vec![1.into(), 2.into(), "x".into()],
+            row(1, 3, 10),
+        ];
+        assert_eq!(g.narrow_one(rs, true), vec![avg(1, 10.0)].into());
+        assert!(g
+            .narrow_one_row((vec![1.into(), 1.into(), DataType::None], false), true)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_does_not_drift() {
+        let mut g = setup(1);
+        let big = vec![1.into(), 1.into(), DataType::BigInt(10_000_000_000_000_000)];
+        g.narrow_one_row(big, true);
+
+        // a running sum would lose the small value next to the big one it replaces
+        assert_eq!(
+            g.narrow_one_row(row(1, 2, 1), true),
+            vec![(avg(1, 1e16), false), (avg(1, 1.0), true)].into()
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// A single row added to or removed from a group, without its group columns.
+pub struct Row {
+    values: Vec<DataType>,
+    positive: bool,
+}
+
+/// `ReservoirSample` emits a uniform random sample of at most `size` rows from every group.
+///
+/// Each output row holds the group columns followed by the sampled row's other columns, in the
+/// order they appear in the ancestor.
+///
+/// Unlike rate-based sampling in `Union`, the sample always holds `size` rows as long as the group
+/// has at least that many. New rows are admitted using reservoir sampling: the `n`th row of a
+/// full group replaces a random sampled row with probability `size / n`, and is otherwise dropped.
+///
+/// The sample itself is the group's output, so the operator only keeps how many rows each group
+/// has. Rows that were dropped are not remembered, so when a sampled row is retracted from a group
+/// that has more rows than are sampled, the group's sample is drawn afresh from its rows in the
+/// ancestor. Groups that are replayed are sampled from their rows in the ancestor too, so the
+/// output may be partially materialized. A fresh sample is uniformly distributed over the rows the
+/// ancestor holds, but it replaces the whole previous sample rather than just the retracted row.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReservoirSample {
+    group: Vec<usize>,
+    size: usize,
+
+    // the ancestor columns that follow the group columns in each output row
+    values: Vec<usize>,
+    // the number of rows in every group
+    seen: HashMap<Vec<DataType>, u64>,
+}
+
+impl ReservoirSample {
+    /// Construct a new `ReservoirSample` operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that samples are taken
+    /// within, and `size` is the number of rows sampled from each group.
+    pub fn new(
+        src: NodeIndex,
+        group_by: &[usize],
+        size: usize,
+    ) -> GroupedOperator<ReservoirSample> {
+        assert!(!group_by.is_empty(), "reservoir samples must be grouped");
+        assert!(size > 0, "reservoir size must be positive");
+
+        GroupedOperator::new(
+            src,
+            ReservoirSample {
+                group: group_by.into(),
+                size,
+                values: Vec::new(),
+                seen: HashMap::new(),
+            },
+        )
+    }
+}
+
+impl GroupedOperation for ReservoirSample {
+    type Diff = Row;
+
+    fn setup(&mut self, parent: &Node) {
+        let cols = parent.fields().len();
+        assert!(
+            self.group.iter().all(|&c| c < cols),
+            "cannot group reservoir sample by non-existing column"
+        );
+        self.values = (0..cols).filter(|c| !self.group.contains(c)).collect();
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        Row {
+            values: self.values.iter().map(|&c| r[c].clone()).collect(),
+            positive: pos,
+        }
+    }
+
+    fn apply_rows(
+        &mut self,
+        group: &[DataType],
+        current: &[&[DataType]],
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let mut seen = if current.is_empty() {
+            // a group without a sample has no rows besides those in `diffs`
+            0
+        } else {
+            *self.seen.get(group)?
+        };
+        let mut rows: Vec<_> = current.iter().map(|r| r.to_vec()).collect();
+
+        let mut rng = rand::thread_rng();
+        let mut shrunk = false;
+        for row in diffs {
+            if row.positive {
+                seen += 1;
+                if rows.len() < self.size {
+                    rows.push(row.values);
+                } else {
+                    let i = rng.gen_range(0, seen) as usize;
+                    if i < self.size {
+                        rows[i] = row.values;
+                    }
+                }
+            } else {
+                seen = seen.saturating_sub(1);
+                if let Some(i) = rows.iter().position(|s| *s == row.values) {
+                    rows.swap_remove(i);
+                    shrunk = true;
+                }
+            }
+        }
+
+        if shrunk && (rows.len() as u64) < seen && rows.len() < self.size {
+            // the rows that could take the retracted ones' places were dropped
+            return None;
+        }
+
+        if seen == 0 {
+            self.seen.remove(group);
+        } else {
+            self.seen.insert(group.to_vec(), seen);
+        }
+        Some(rows)
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("ReservoirSample");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Reservoir({}) γ[{}]", self.size, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        self.values.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(size: usize) -> (ops::test::MockGraph, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["post", "vote"]);
+        g.set_op(
+            "sample",
+            &["post", "vote"],
+            ReservoirSample::new(s.as_global(), &[0], size),
+            true,
+        );
+        (g, s)
+    }
+
+    /// The change in the number of sampled rows that `rs` makes.
+    fn growth(rs: &Records) -> i64 {
+        rs.iter()
+            .map(|r| if r.is_positive() { 1 } else { -1 })
+            .sum()
+    }
+
+    #[test]
+    fn it_describes() {
+        let (g, _) = setup(3);
+        assert_eq!(g.node().description(true), "Reservoir(3) γ[0]");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 1.into();
+        let (g, s) = setup(3);
+        let idx = g.node().suggest_indexes(me);
+
+        // should index own columns, and the ancestor by group so that samples can be redrawn
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&s.as_global()], vec![0]);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_keeps_reservoir_size() {
+        let (mut g, _) = setup(3);
+
+        // the first rows of a group are always sampled
+        for v in 0..3 {
+            let row: Vec<DataType> = vec![1.into(), v.into()];
+            assert_eq!(g.narrow_one_row(row.clone(), true), vec![row].into());
+        }
+
+        // after that, a new row is either dropped or replaces exactly one sampled row
+        for v in 3..100 {
+            let rs = g.narrow_one_row(vec![1.into(), v.into()], true);
+            assert!(rs.is_empty() || rs.len() == 2);
+            assert_eq!(growth(&rs), 0);
+        }
+
+        // other groups have their own reservoirs
+        let rs = g.narrow_one_row(vec![2.into(), 0.into()], true);
+        assert_eq!(rs, vec![vec![2.into(), 0.into()]].into());
+    }
+
+    #[test]
+    fn it_refills_on_retraction() {
+        let (mut g, s) = setup(1);
+        let a: Vec<DataType> = vec![1.into(), "a".into()];
+        let b: Vec<DataType> = vec![1.into(), "b".into()];
+
+        g.seed(s, a.clone());
+        assert_eq!(g.narrow_one_row(a.clone(), true), vec![a.clone()].into());
+        g.seed(s, b.clone());
+        let rs = g.narrow_one_row(b.clone(), true);
+
+        // whichever row is sampled, retracting it brings in the other one
+        let (kept, other) = if rs.is_empty() { (a, b) } else { (b, a) };
+        g.unseed(s);
+        g.seed(s, other.clone());
+        assert_eq!(
+            g.narrow_one_row((kept.clone(), false), true),
+            vec![(kept, false), (other.clone(), true)].into()
+        );
+
+        // retracting the last row empties the group
+        g.unseed(s);
+        assert_eq!(
+            g.narrow_one_row((other.clone(), false), true),
+            vec![(other, false)].into()
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// A run of records in one group whose timestamps are each at most `gap` apart.
+#[derive(Debug, Clone)]
+struct Session {
+    start: i64,
+    end: i64,
+    records: i64,
+}
+
+/// A single timestamp added to or removed from a group.
+pub struct Stamp {
+    time: i64,
+    positive: bool,
+}
+
+/// `SessionWindow` groups the records of every group into sessions, and emits one row per session.
+///
+/// A session is a maximal run of records whose timestamps, in order, are each at most `gap` after
+/// the previous one. Each output row holds the group columns, followed by the timestamps of the
+/// first and last record of the session, and the number of records in it.
+///
+/// A new record may extend a session, start a new one, or bridge the gap between two sessions and
+/// merge them. Likewise, retracting a record may shrink or split a session. In all cases, the rows
+/// of every affected session are revoked and the rows of the resulting sessions emitted. Records
+/// with a NULL timestamp are ignored.
+///
+/// New records are merged into the group's current sessions, so the operator keeps no state of its
+/// own. The sessions do not say where the records between their first and last timestamps are, so
+/// a retraction that may shrink or split a session has the group's sessions rebuilt from its
+/// records in the ancestor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionWindow {
+    group: Vec<usize>,
+    timestamp: usize,
+    gap: i64,
+}
+
+impl SessionWindow {
+    /// Construct a new `SessionWindow` operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that sessions are
+    /// computed within, `timestamp` is the column holding each record's (integer) timestamp, and
+    /// `gap` is the largest difference between consecutive timestamps in the same session.
+    pub fn new(
+        src: NodeIndex,
+        group_by: &[usize],
+        timestamp: usize,
+        gap: i64,
+    ) -> GroupedOperator<SessionWindow> {
+        assert!(
+            !group_by.iter().any(|&i| i == timestamp),
+            "cannot group by session timestamp column"
+        );
+        assert!(gap >= 0, "session gap cannot be negative");
+
+        GroupedOperator::new(
+            src,
+            SessionWindow {
+                group: group_by.into(),
+                timestamp,
+                gap,
+            },
+        )
+    }
+}
+
+impl GroupedOperation for SessionWindow {
+    type Diff = Option<Stamp>;
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.timestamp < parent.fields().len(),
+            "cannot compute sessions over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        if r[self.timestamp].is_none() {
+            return None;
+        }
+        Some(Stamp {
+            time: i64::from(&r[self.timestamp]),
+            positive: pos,
+        })
+    }
+
+    fn apply_rows(
+        &mut self,
+        _: &[DataType],
+        current: &[&[DataType]],
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let mut sessions: Vec<_> = current
+            .iter()
+            .map(|r| Session {
+                start: i64::from(&r[0]),
+                end: i64::from(&r[1]),
+                records: i64::from(&r[2]),
+            })
+            .collect();
+        sessions.sort_by_key(|s| s.start);
+
+        for stamp in diffs.flatten() {
+            let t = stamp.time;
+            if stamp.positive {
+                // merge the record with every session it is within the gap of
+                let mut merged = Session {
+                    start: t,
+                    end: t,
+                    records: 1,
+                };
+                sessions.retain(|s| {
+                    if s.start.saturating_sub(self.gap) > t || s.end.saturating_add(self.gap) < t {
+                        return true;
+                    }
+                    merged.start = merged.start.min(s.start);
+                    merged.end = merged.end.max(s.end);
+                    merged.records += s.records;
+                    false
+                });
+                let i = sessions
+                    .iter()
+                    .position(|s| s.start > merged.start)
+                    .unwrap_or(sessions.len());
+                sessions.insert(i, merged);
+            } else {
+                match sessions.iter().position(|s| s.start <= t && t <= s.end) {
+                    Some(i) if sessions[i].start == sessions[i].end => {
+                        // every record of the session has this timestamp
+                        sessions[i].records -= 1;
+                        if sessions[i].records <= 0 {
+                            sessions.remove(i);
+                        }
+                    }
+                    Some(_) => {
+                        // the session may shrink or split, depending on its other records
+                        return None;
+                    }
+                    None => {
+                        // not a record that the group holds
+                    }
+                }
+            }
+        }
+
+        Some(
+            sessions
+                .into_iter()
+                .map(|s| vec![s.start.into(), s.end.into(), s.records.into()])
+                .collect(),
+        )
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("Session");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "Session({}) ⧖{} γ[{}]",
+            self.timestamp, self.gap, group_cols
+        )
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.timestamp]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["user", "ts"]);
+        g.set_op(
+            "session",
+            &["user", "start", "end", "records"],
+            SessionWindow::new(s.as_global(), &[0], 1, 10),
+            true,
+        );
+        g
+    }
+
+    fn row(user: i32, ts: i64) -> Vec<DataType> {
+        vec![user.into(), ts.into()]
+    }
+
+    fn session(user: i32, start: i64, end: i64, records: i64) -> Vec<DataType> {
+        vec![user.into(), start.into(), end.into(), records.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup();
+        assert_eq!(g.node().description(true), "Session(1) ⧖10 γ[0]");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 1.into();
+        let g = setup();
+        let idx = g.node().suggest_indexes(me);
+
+        // should index own columns, and the ancestor by group so that sessions can be rebuilt
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![0]);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_splits_sessions_at_gaps() {
+        let mut g = setup();
+
+        assert_eq!(
+            g.narrow_one_row(row(1, 100), true),
+            vec![session(1, 100, 100, 1)].into()
+        );
+
+        // within the gap, so the session is extended
+        assert_eq!(
+            g.narrow_one_row(row(1, 110), true),
+            vec![
+                (session(1, 100, 100, 1), false),
+                (session(1, 100, 110, 2), true)
+            ]
+            .into()
+        );
+
+        // beyond the gap, so a new session starts
+        assert_eq!(
+            g.narrow_one_row(row(1, 121), true),
+            vec![session(1, 121, 121, 1)].into()
+        );
+
+        // other groups have their own sessions
+        assert_eq!(
+            g.narrow_one_row(row(2, 105), true),
+            vec![session(2, 105, 105, 1)].into()
+        );
+    }
+
+    #[test]
+    fn it_merges_and_splits_sessions() {
+        let mut g = setup();
+        let s = g.narrow_base_id();
+        g.narrow_one(vec![row(1, 100), row(1, 120)], true);
+
+        // a record in between bridges the two sessions
+        let rs = g.narrow_one_row(row(1, 110), true);
+        assert_eq!(rs.len(), 3);
+        assert!(rs.has_negative(&session(1, 100, 100, 1)[..]));
+        assert!(rs.has_negative(&session(1, 120, 120, 1)[..]));
+        assert!(rs.has_positive(&session(1, 100, 120, 3)[..]));
+
+        // and retracting it splits them again, which takes the records in the ancestor
+        g.seed(s, row(1, 100));
+        g.seed(s, row(1, 120));
+        let rs = g.narrow_one_row((row(1, 110), false), true);
+        assert_eq!(rs.len(), 3);
+        assert!(rs.has_negative(&session(1, 100, 120, 3)[..]));
+        assert!(rs.has_positive(&session(1, 100, 100, 1)[..]));
+        assert!(rs.has_positive(&session(1, 120, 120, 1)[..]));
+
+        // retracting the only record of a session needs no records from the ancestor
+        g.unseed(s);
+        assert_eq!(
+            g.narrow_one_row((row(1, 120), false), true),
+            vec![(session(1, 120, 120, 1), false)].into()
+        );
+
+        // nulls are ignored entirely
+        assert!(g
+            .narrow_one_row(vec![1.into(), DataType::None], true)
+            .is_empty());
+    }
+}
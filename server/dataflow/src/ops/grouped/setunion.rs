@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// The values admitted to the set of one group, and how many records the group has in total.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Members {
+    records: i64,
+    admitted: BTreeMap<DataType, i64>,
+    dropped: i64,
+}
+
+/// A single value added to or removed from a group.
+pub struct Change {
+    value: DataType,
+    positive: bool,
+}
+
+/// `SetUnion` emits, for every group, the set of distinct values in one column, holding at most
+/// `cap` values.
+///
+/// Each output row holds the group columns followed by the set, encoded as a JSON array of the
+/// values' text representations in ascending order of the values. NULL values are not added to
+/// the set.
+///
+/// A value is admitted to the set if it is already in it, or if the set has fewer than `cap`
+/// values; otherwise it is dropped. To bound memory, the operator only counts dropped records
+/// rather than remembering their values. Retracting the last record of an admitted value frees its
+/// slot, which the next new value to arrive is admitted into; values dropped earlier are not
+/// reconsidered until they arrive again. Retracting a record whose value isn't in the set is
+/// assumed to retract a dropped record, so if a value was dropped and later admitted, retracting
+/// its dropped records may remove it from the set while it still has records.
+///
+/// A group that is replayed, or whose set is not at hand, has its set rebuilt from its records in
+/// the ancestor, so the output may be partially materialized. Values are then admitted in the order
+/// the ancestor holds them, which need not be the order they arrived in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetUnion {
+    group: Vec<usize>,
+    over: usize,
+    cap: usize,
+
+    sets: HashMap<Vec<DataType>, Members>,
+}
+
+impl SetUnion {
+    /// Construct a new `SetUnion` operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that sets are computed
+    /// within, `over` is the column whose values are collected, and `cap` is the largest number of
+    /// values in any one set.
+    pub fn new(
+        src: NodeIndex,
+        group_by: &[usize],
+        over: usize,
+        cap: usize,
+    ) -> GroupedOperator<SetUnion> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by set union column"
+        );
+        assert!(cap > 0, "set union cap must be positive");
+
+        GroupedOperator::new(
+            src,
+            SetUnion {
+                group: group_by.into(),
+                over,
+                cap,
+                sets: HashMap::new(),
+            },
+        )
+    }
+
+    fn admit(&self, members: &mut Members, v: DataType, positive: bool) {
+        members.records += if positive { 1 } else { -1 };
+        if v.is_none() {
+            return;
+        }
+
+        if positive {
+            if let Some(n) = members.admitted.get_mut(&v) {
+                *n += 1;
+            } else if members.admitted.len() < self.cap {
+                members.admitted.insert(v, 1);
+            } else {
+                members.dropped += 1;
+            }
+        } else if let Some(n) = members.admitted.get_mut(&v) {
+            *n -= 1;
+            if *n <= 0 {
+                members.admitted.remove(&v);
+            }
+        } else {
+            members.dropped = (members.dropped - 1).max(0);
+        }
+    }
+}
+
+impl GroupedOperator<SetUnion> {
+    /// The number of records in every group whose values were dropped because its set was full.
+    pub fn dropped(&self) -> HashMap<Vec<DataType>, i64> {
+        self.inner
+            .sets
+            .iter()
+            .filter(|(_, m)| m.dropped > 0)
+            .map(|(group, m)| (group.clone(), m.dropped))
+            .collect()
+    }
+}
+
+impl GroupedOperation for SetUnion {
+    type Diff = Change;
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot compute set union over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        Change {
+            value: r[self.over].clone(),
+            positive: pos,
+        }
+    }
+
+    fn apply_rows(
+        &mut self,
+        group: &[DataType],
+        current: &[&[DataType]],
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let mut members = if current.is_empty() {
+            // a group without a row has no records besides those in `diffs`
+            Members::default()
+        } else {
+            self.sets.remove(group)?
+        };
+
+        for c in diffs {
+            self.admit(&mut members, c.value, c.positive);
+        }
+
+        if members.records <= 0 {
+            // the group has no records left
+            return Some(Vec::new());
+        }
+
+        let values: Vec<String> = members
+            .admitted
+            .keys()
+            .map(|v| match *v {
+                DataType::Text(..) | DataType::TinyText(..) => <&str>::from(v).to_owned(),
+                _ => v.to_string(),
+            })
+            .collect();
+        self.sets.insert(group.to_vec(), members);
+        Some(vec![vec![serde_json::to_string(&values).unwrap().into()]])
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("SetUnion");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("∪({}) ≤{} γ[{}]", self.over, self.cap, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["post", "tag"]);
+        g.set_op(
+            "tags",
+            &["post", "tags"],
+            SetUnion::new(s.as_global(), &[0], 1, 2),
+            true,
+        );
+        g
+    }
+
+    fn row(post: i32, tag: &str) -> Vec<DataType> {
+        vec![post.into(), tag.into()]
+    }
+
+    fn tags(post: i32, tags: &str) -> Vec<DataType> {
+        vec![post.into(), tags.into()]
+    }
+
+    fn dropped(g: &ops::test::MockGraph, post: i32) -> i64 {
+        match **g.node() {
+            NodeOperator::SetUnion(ref s) => s
+                .dropped()
+                .get(&vec![DataType::from(post)])
+                .cloned()
+                .unwrap_or(0),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup();
+        assert_eq!(g.node().description(true), "∪(1) ≤2 γ[0]");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 1.into();
+        let g = setup();
+        let idx = g.node().suggest_indexes(me);
+
+        // should index own columns, and the ancestor by group so that sets can be rebuilt
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![0]);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_caps_sets() {
+        let mut g = setup();
+
+        assert_eq!(
+            g.narrow_one_row(row(1, "b"), true),
+            vec![tags(1, r#"["b"]"#)].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(row(1, "a"), true),
+            vec![
+                (tags(1, r#"["b"]"#), false),
+                (tags(1, r#"["a","b"]"#), true)
+            ]
+            .into()
+        );
+
+        // the set is full, so a new value is dropped, but one already in it is still counted
+        assert!(g.narrow_one_row(row(1, "c"), true).is_empty());
+        assert!(g.narrow_one_row(row(1, "a"), true).is_empty());
+        assert_eq!(dropped(&g, 1), 1);
+
+        // other groups have their own sets
+        assert_eq!(
+            g.narrow_one_row(row(2, "c"), true),
+            vec![tags(2, r#"["c"]"#)].into()
+        );
+    }
+
+    #[test]
+    fn it_frees_slots_on_retraction() {
+        let mut g = setup();
+        g.narrow_one(
+            vec![row(1, "a"), row(1, "b"), row(1, "b"), row(1, "c")],
+            true,
+        );
+        assert_eq!(dropped(&g, 1), 1);
+
+        // retracting one of two records of a value keeps it in the set
+        assert!(g.narrow_one_row((row(1, "b"), false), true).is_empty());
+
+        // retracting the last record of a value frees its slot for the next new value
+        assert_eq!(
+            g.narrow_one_row((row(1, "b"), false), true),
+            vec![
+                (tags(1, r#"["a","b"]"#), false),
+                (tags(1, r#"["a"]"#), true)
+            ]
+            .into()
+        );
+        assert_eq!(
+            g.narrow_one_row(row(1, "d"), true),
+            vec![
+                (tags(1, r#"["a"]"#), false),
+                (tags(1, r#"["a","d"]"#), true)
+            ]
+            .into()
+        );
+
+        // retracting a dropped value only forgets that it was dropped
+        assert!(g.narrow_one_row((row(1, "c"), false), true).is_empty());
+        assert_eq!(dropped(&g, 1), 0);
+
+        // once every record is gone, so is the set
+        assert_eq!(
+            g.narrow_one(vec![(row(1, "a"), false), (row(1, "d"), false)], true),
+            vec![(tags(1, r#"["a","d"]"#), false)].into()
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// The estimated count of one monitored value, and by how much that estimate may be too high.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Counter {
+    count: i64,
+    error: i64,
+}
+
+/// A single value added to or removed from a group.
+pub struct Change {
+    value: DataType,
+    positive: bool,
+}
+
+/// `SpaceSaving` approximates the `k` most frequent values of a column in every group, using the
+/// Space-Saving algorithm with at most `capacity` counters per group.
+///
+/// Each output row holds the group columns, one of the group's approximate top `k` values, and its
+/// estimated count. Values are ranked by estimated count, with ties broken by the smaller value.
+/// NULL values are not counted.
+///
+/// When a value that isn't monitored arrives at a group that already has `capacity` counters, the
+/// counter with the lowest count is given to the new value, which inherits that count plus one;
+/// the inherited count is remembered as the counter's error. For a group that has only received
+/// positive records, `N` of them, this guarantees that:
+///
+///  - every estimated count is at least the true count, and at most `N / capacity` above it;
+///  - every value whose true count exceeds `N / capacity` is monitored.
+///
+/// Retractions are approximate: a negative record decrements its value's counter if the value is
+/// monitored, and is otherwise ignored, since the operator does not know which counter absorbed
+/// it. Once a group has seen retractions, estimates may be below the true count, and the bounds
+/// above only hold for the positive records the group has received in total.
+///
+/// A group that is replayed, or whose counters are not at hand, has its counters rebuilt from its
+/// records in the ancestor, so the output may be partially materialized. Since those records only
+/// include ones that have not been retracted, rebuilt counters are subject to the bounds above.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpaceSaving {
+    group: Vec<usize>,
+    over: usize,
+    k: usize,
+    capacity: usize,
+
+    counters: HashMap<Vec<DataType>, HashMap<DataType, Counter>>,
+}
+
+impl SpaceSaving {
+    /// Construct a new `SpaceSaving` operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that frequent values are
+    /// found within, `over` is the column whose values are counted, `k` is the number of values to
+    /// emit per group, and `capacity` is the number of values each group monitors, which must be
+    /// at least `k`. A larger capacity gives more accurate counts at the cost of memory.
+    pub fn new(
+        src: NodeIndex,
+        group_by: &[usize],
+        over: usize,
+        k: usize,
+        capacity: usize,
+    ) -> GroupedOperator<SpaceSaving> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by top-k column"
+        );
+        assert!(k > 0, "top-k must emit at least one value");
+        assert!(capacity >= k, "top-k must monitor at least k values");
+
+        GroupedOperator::new(
+            src,
+            SpaceSaving {
+                group: group_by.into(),
+                over,
+                k,
+                capacity,
+                counters: HashMap::new(),
+            },
+        )
+    }
+
+    fn ranked(counters: &HashMap<DataType, Counter>) -> Vec<(&DataType, &Counter)> {
+        let mut ranked: Vec<_> = counters.iter().collect();
+        ranked.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(b.0)));
+        ranked
+    }
+
+    fn count(&self, counters: &mut HashMap<DataType, Counter>, v: DataType, positive: bool) {
+        if v.is_none() {
+            return;
+        }
+
+        if !positive {
+            if let Some(c) = counters.get_mut(&v) {
+                c.count -= 1;
+                c.error = c.error.min(c.count);
+                if c.count <= 0 {
+                    counters.remove(&v);
+                }
+            }
+            return;
+        }
+
+        if let Some(c) = counters.get_mut(&v) {
+            c.count += 1;
+        } else if counters.len() < self.capacity {
+            counters.insert(v, Counter { count: 1, error: 0 });
+        } else {
+            // replace the least frequent value, breaking ties by the smaller value so that the
+            // outcome does not depend on iteration order
+            let (min, count) = counters
+                .iter()
+                .min_by(|a, b| a.1.count.cmp(&b.1.count).then_with(|| a.0.cmp(b.0)))
+                .map(|(v, c)| (v.clone(), c.count))
+                .unwrap();
+            counters.remove(&min);
+            counters.insert(
+                v,
+                Counter {
+                    count: count + 1,
+                    error: count,
+                },
+            );
+        }
+    }
+}
+
+impl GroupedOperator<SpaceSaving> {
+    /// The values monitored in `group`, with their estimated count and the most that estimate may
+    /// be too high by, in rank order.
+    pub fn estimates(&self, group: &[DataType]) -> Vec<(DataType, i64, i64)> {
+        self.inner
+            .counters
+            .get(group)
+            .map(|counters| {
+                SpaceSaving::ranked(counters)
+                    .into_iter()
+                    .map(|(v, c)| (v.clone(), c.count, c.error))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl GroupedOperation for SpaceSaving {
+    type Diff = Change;
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot compute top-k over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        Change {
+            value: r[self.over].clone(),
+            positive: pos,
+        }
+    }
+
+    fn apply_rows(
+        &mut self,
+        group: &[DataType],
+        current: &[&[DataType]],
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let mut counters = if current.is_empty() {
+            // a group without a row has no records besides those in `diffs`
+            HashMap::new()
+        } else {
+            self.counters.remove(group)?
+        };
+
+        for c in diffs {
+            self.count(&mut counters, c.value, c.positive);
+        }
+
+        let top = Self::ranked(&counters)
+            .into_iter()
+            .take(self.k)
+            .map(|(v, c)| vec![v.clone(), c.count.into()])
+            .collect();
+        if !counters.is_empty() {
+            self.counters.insert(group.to_vec(), counters);
+        }
+        Some(top)
+    }
+
+    fn recomputes(&self) -> bool {
+        true
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("SpaceSaving");
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "~TopK({}, {}) ≤{} γ[{}]",
+            self.over, self.k, self.capacity, group_cols
+        )
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(k: usize, capacity: usize) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["category", "item"]);
+        g.set_op(
+            "top",
+            &["category", "item", "count"],
+            SpaceSaving::new(s.as_global(), &[0], 1, k, capacity),
+            true,
+        );
+        g
+    }
+
+    fn estimates(g: &ops::test::MockGraph, category: i32) -> Vec<(DataType, i64, i64)> {
+        match **g.node() {
+            NodeOperator::SpaceSaving(ref s) => s.estimates(&[category.into()]),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup(2, 4);
+        assert_eq!(g.node().description(true), "~TopK(1, 2) ≤4 γ[0]");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 1.into();
+        let g = setup(2, 4);
+        let idx = g.node().suggest_indexes(me);
+
+        // should index own columns, and the ancestor by group so that counters can be rebuilt
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![0]);
+        assert!(!g.node().requires_full_materialization());
+    }
+
+    #[test]
+    fn it_emits_changes_to_top_k() {
+        let mut g = setup(1, 2);
+        let row = |item: i32| -> Vec<DataType> { vec![1.into(), item.into()] };
+        let top =
+            |item: i32, count: i64| -> Vec<DataType> { vec![1.into(), item.into(), count.into()] };
+
+        assert_eq!(g.narrow_one_row(row(5), true), vec![top(5, 1)].into());
+        assert_eq!(
+            g.narrow_one_row(row(5), true),
+            vec![(top(5, 1), false), (top(5, 2), true)].into()
+        );
+
+        // a less frequent value doesn't change the top value
+        assert!(g.narrow_one(vec![row(7), row(7)], true).is_empty());
+        assert_eq!(
+            g.narrow_one_row(row(7), true),
+            vec![(top(5, 2), false), (top(7, 3), true)].into()
+        );
+
+        // retracting a monitored value lowers its count, here back into a tie that 5 wins
+        assert_eq!(
+            g.narrow_one_row((row(7), false), true),
+            vec![(top(7, 3), false), (top(5, 2), true)].into()
+        );
+    }
+
+    #[test]
+    fn it_approximates_skewed_top_k() {
+        let (k, capacity) = (5, 20);
+        let mut g = setup(k, capacity);
+
+        // value v occurs 1000 / (v + 1) times, with all values interleaved
+        let truth = |v: i32| 1000 / (i64::from(v) + 1);
+        let mut rows: Vec<Vec<DataType>> = Vec::new();
+        for round in 0..1000 {
+            for v in 0..50 {
+                if truth(v) > round {
+                    rows.push(vec![1.into(), v.into()]);
+                }
+            }
+        }
+        let n = rows.len() as i64;
+        let bound = n / capacity as i64;
+
+        let out = g.narrow_one(rows, true);
+        let mut emitted: Vec<_> = out.iter().map(|r| i64::from(&r[1])).collect();
+        emitted.sort();
+        assert_eq!(emitted, vec![0, 1, 2, 3, 4]);
+
+        let estimates = estimates(&g, 1);
+        assert_eq!(estimates.len(), capacity);
+        for (i, (v, count, error)) in estimates.into_iter().enumerate() {
+            let v = i64::from(&v) as i32;
+            assert!(count >= truth(v), "{} underestimated", v);
+            assert!(count - error <= truth(v), "{} error too small", v);
+            assert!(error <= bound, "{} error exceeds bound", v);
+            if i < k {
+                // the top values are exactly the most frequent ones
+                assert_eq!(v, i as i32);
+            }
+        }
+    }
+}
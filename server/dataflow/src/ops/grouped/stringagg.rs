@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use crate::prelude::*;
+
+/// A single value added to or removed from a group's concatenation.
+pub struct Change {
+    value: String,
+    order: DataType,
+    positive: bool,
+}
+
+/// `StringAgg` joins the values of the `over` column in each group with a separator, like
+/// `GROUP_CONCAT(over ORDER BY order_by SEPARATOR sep)` in SQL.
+///
+/// Unlike `GroupConcat`, which groups by every column it doesn't emit and keeps each distinct
+/// string once, in sorted order, `StringAgg` keeps every value, including duplicates. Values are
+/// ordered by the `order_by` column if one is given, and otherwise in the order they arrived;
+/// values with equal ordering keys also keep their arrival order. NULL values are skipped.
+///
+/// Since a deletion can remove a value from anywhere in the joined string, the operator keeps the
+/// ordered values of every group and rebuilds the string from them, so its output must be fully
+/// materialized. When the last value of a group is deleted, the group's string is revoked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StringAgg {
+    over: usize,
+    group: Vec<usize>,
+    separator: String,
+    order_by: Option<usize>,
+
+    // the values of each group, by ordering key and then by arrival
+    values: HashMap<Vec<DataType>, BTreeMap<(DataType, u64), String>>,
+    arrivals: u64,
+}
+
+impl StringAgg {
+    /// Construct a new `StringAgg` operator.
+    ///
+    /// The operator joins the values in column number `over` from its inputs (i.e., from the `src`
+    /// node in the graph) with `separator`, and uses the columns in the `group_by` array as a
+    /// group identifier. If `order_by` is given, values are joined in the order of that column
+    /// rather than in the order they arrived. Neither `over` nor `order_by` should be in the
+    /// `group_by` array.
+    pub fn new(
+        src: NodeIndex,
+        over: usize,
+        group_by: &[usize],
+        separator: String,
+        order_by: Option<usize>,
+    ) -> GroupedOperator<StringAgg> {
+        assert!(
+            !group_by.iter().any(|&i| i == over || Some(i) == order_by),
+            "cannot group by aggregation column"
+        );
+        GroupedOperator::new(
+            src,
+            StringAgg {
+                over,
+                group: group_by.into(),
+                separator,
+                order_by,
+                values: HashMap::new(),
+                arrivals: 0,
+            },
+        )
+    }
+}
+
+impl GroupedOperation for StringAgg {
+    type Diff = Option<Change>;
+
+    fn setup(&mut self, parent: &Node) {
+        let cols = parent.fields().len();
+        assert!(
+            self.over < cols && self.order_by.map(|c| c < cols).unwrap_or(true),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        let value = match r[self.over] {
+            DataType::None => return None,
+            ref v @ DataType::Text(..) | ref v @ DataType::TinyText(..) => {
+                <&str>::from(v).to_owned()
+            }
+            ref v => v.to_string(),
+        };
+        Some(Change {
+            value,
+            order: self
+                .order_by
+                .map(|c| r[c].clone())
+                .unwrap_or(DataType::None),
+            positive: pos,
+        })
+    }
+
+    fn apply(
+        &mut self,
+        group: &[DataType],
+        _: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Option<DataType> {
+        let mut values = self.values.remove(group).unwrap_or_default();
+        for change in diffs.flatten() {
+            if change.positive {
+                self.arrivals += 1;
+                values.insert((change.order, self.arrivals), change.value);
+            } else {
+                // any copy of the value will do, so remove the one that arrived first
+                let first = values
+                    .range((change.order.clone(), 0)..=(change.order, u64::max_value()))
+                    .find(|&(_, v)| *v == change.value)
+                    .map(|(k, _)| k.clone());
+                if let Some(k) = first {
+                    values.remove(&k);
+                }
+            }
+        }
+
+        if values.is_empty() {
+            // the group has no values left
+            return Some(DataType::None);
+        }
+
+        let joined = values
+            .values()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(&self.separator);
+        self.values.insert(group.to_vec(), values);
+        Some(joined.into())
+    }
+
+    fn is_empty(&self, value: &DataType) -> bool {
+        value.is_none()
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("CONCAT");
+        }
+
+        let order = self
+            .order_by
+            .map(|c| format!(" ↑{}", c))
+            .unwrap_or_default();
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "||({}, \"{}\"{}) γ[{}]",
+            self.over, self.separator, order, group_cols
+        )
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(order_by: Option<usize>) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        let c = StringAgg::new(s.as_global(), 1, &[0], String::from(","), order_by);
+        g.set_op("concat", &["x", "ys"], c, true);
+        g
+    }
+
+    fn row(v: &str, order: i32) -> Vec<DataType> {
+        vec![1.into(), v.into(), order.into()]
+    }
+
+    fn joined(s: &str) -> Vec<DataType> {
+        vec![1.into(), s.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        assert_eq!(setup(None).node().description(true), "||(1, \",\") γ[0]");
+        assert_eq!(
+            setup(Some(2)).node().description(true),
+            "||(1, \",\" ↑2) γ[0]"
+        );
+    }
+
+    #[test]
+    fn it_keeps_arrival_order() {
+        let mut c = setup(None);
+        c.narrow_one_row(row("c", 0), true);
+        c.narrow_one_row(row("a", 0), true);
+        assert_eq!(
+            c.narrow_one_row(row("c", 0), true),
+            vec![(joined("c,a"), false), (joined("c,a,c"), true)].into()
+        );
+    }
+
+    #[test]
+    fn it_removes_a_middle_value() {
+        let mut c = setup(Some(2));
+        c.narrow_one(vec![row("c", 3), row("a", 1), row("b", 2)], true);
+
+        assert_eq!(
+            c.narrow_one_row((row("b", 2), false), true),
+            vec![(joined("a,b,c"), false), (joined("a,c"), true)].into()
+        );
+
+        // removing everything revokes the group
+        assert_eq!(
+            c.narrow_one(vec![(row("a", 1), false), (row("c", 3), false)], true),
+            vec![(joined("a,c"), false)].into()
+        );
+    }
+}
@@ -25,7 +25,9 @@ impl Ingredient for Identity {
         vec![self.src.as_global()]
     }
 
-    fn on_connected(&mut self, _: &Graph) {}
+    fn on_connected(&mut self, _: &Graph) -> Result<(), String> {
+        Ok(())
+    }
 
     fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.src.remap(remap);
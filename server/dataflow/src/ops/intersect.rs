@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::prelude::*;
+
+/// How an `Intersect` treats an ancestor that has not yet sent any records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Emptiness {
+    /// The ancestor is empty, so the intersection is empty until every ancestor has sent records.
+    Empty,
+    /// The ancestor is not yet populated, and is left out of the intersection until it is.
+    ///
+    /// Records may then be emitted before every ancestor has sent records, and are revoked when a
+    /// newly populated ancestor turns out not to contain them.
+    NotReady,
+}
+
+/// Intersect emits every distinct record that is present in all of its ancestors.
+///
+/// Like a union, an intersection selects a set of columns from each ancestor, and compares records
+/// on only those columns. The operator counts how many times each record has been received from
+/// each ancestor, and emits a record when it is present in every ancestor that takes part in the
+/// intersection. Which ancestors take part initially depends on the `Emptiness` policy.
+///
+/// With `with_multiplicities`, the intersection keeps duplicates like SQL's `INTERSECT ALL`: a
+/// record is emitted as many times as the fewest copies any taking part ancestor has of it.
+///
+/// The counts exist only in the operator, so its output is always fully materialized, and
+/// replays are served from that materialization rather than traced through to the ancestors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Intersect {
+    emit: Vec<(IndexPair, Vec<usize>)>,
+    emptiness: Emptiness,
+    multiplicities: bool,
+
+    // the number of times each record has been received from each ancestor, in `emit` order
+    counts: HashMap<Vec<DataType>, Vec<i64>>,
+    // ancestors that have sent at least one record
+    populated: HashSet<usize>,
+}
+
+impl Intersect {
+    /// Construct a new intersect operator.
+    ///
+    /// When receiving an update from node `a`, the columns selected in `emit[a]` are compared with
+    /// records from the other ancestors. Every ancestor must select the same number of columns.
+    pub fn new(emit: HashMap<NodeIndex, Vec<usize>>, emptiness: Emptiness) -> Self {
+        assert!(emit.len() > 1, "intersection needs at least two ancestors");
+        let mut emit: Vec<_> = emit.into_iter().map(|(n, e)| (n.into(), e)).collect();
+        emit.sort_by_key(|&(n, _): &(IndexPair, _)| n.as_global());
+        let width = emit[0].1.len();
+        assert!(
+            emit.iter().all(|(_, e)| e.len() == width),
+            "all ancestors of an intersection must emit the same number of columns"
+        );
+
+        Intersect {
+            emit,
+            emptiness,
+            multiplicities: false,
+            counts: HashMap::new(),
+            populated: HashSet::new(),
+        }
+    }
+
+    /// Emit every record as many times as the ancestor with the fewest copies of it holds.
+    ///
+    /// Ancestors are then also asked to index the selected columns, so that the copies of a
+    /// record are cheap to look up.
+    pub fn with_multiplicities(mut self) -> Self {
+        self.multiplicities = true;
+        self
+    }
+
+    /// The number of copies of a record with the given `counts` in the intersection.
+    fn multiplicity(&self, counts: &[i64]) -> i64 {
+        let mut min = None;
+        for (i, &n) in counts.iter().enumerate() {
+            if self.emptiness == Emptiness::NotReady && !self.populated.contains(&i) {
+                continue;
+            }
+            if n <= 0 {
+                return 0;
+            }
+            min = Some(min.map_or(n, |m: i64| m.min(n)));
+        }
+        match min {
+            Some(n) if self.multiplicities => n,
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+}
+
+impl Ingredient for Intersect {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        self.emit.iter().map(|(n, _)| n.as_global()).collect()
+    }
+
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
+        for (n, emit) in &self.emit {
+            let cols = g[n.as_global()].fields().len();
+            if let Some(c) = emit.iter().find(|&&c| c >= cols) {
+                return Err(format!(
+                    "cannot intersect non-existing column {} of ancestor with {} columns",
+                    c, cols
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        for (n, _) in &mut self.emit {
+            n.remap(remap);
+        }
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        let (src, emit) = match self
+            .emit
+            .iter()
+            .enumerate()
+            .find(|&(_, (n, _))| **n == from)
+        {
+            Some((i, (_, emit))) => (i, emit.clone()),
+            None => {
+                // records from a node that isn't one of our ancestors can't be in the
+                // intersection
+                return ProcessingResult::default();
+            }
+        };
+
+        // a newly populated ancestor may change whether any record is in the intersection
+        let newly_populated = !rs.is_empty() && !self.populated.contains(&src);
+        let mut before: HashMap<Vec<DataType>, i64> = if newly_populated {
+            self.counts
+                .iter()
+                .map(|(r, counts)| (r.clone(), self.multiplicity(counts)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        // apply all records first, so that each record changes at most once per batch
+        let nancestors = self.emit.len();
+        for r in rs {
+            let (r, positive) = r.extract();
+            let row: Vec<_> = emit.iter().map(|&c| r[c].clone()).collect();
+            if !before.contains_key(&row) {
+                let was = self
+                    .counts
+                    .get(&row)
+                    .map(|counts| self.multiplicity(counts))
+                    .unwrap_or(0);
+                before.insert(row.clone(), was);
+            }
+            let counts = self
+                .counts
+                .entry(row)
+                .or_insert_with(|| vec![0; nancestors]);
+            counts[src] += if positive { 1 } else { -1 };
+        }
+        if newly_populated {
+            self.populated.insert(src);
+        }
+
+        let mut out = Vec::new();
+        for (row, was) in before {
+            let is = self
+                .counts
+                .get(&row)
+                .map(|counts| self.multiplicity(counts))
+                .unwrap_or(0);
+            if self.counts[&row].iter().all(|&n| n <= 0) {
+                self.counts.remove(&row);
+            }
+            for _ in is..was {
+                out.push(Record::Negative(row.clone()));
+            }
+            for _ in was..is {
+                out.push(Record::Positive(row.clone()));
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // counts are kept in internal state, so our output must be materialized for replays to
+        // be served from it rather than counted again
+        let all = (0..self.emit[0].1.len()).collect();
+        let mut idx: HashMap<_, _> = Some((this, all)).into_iter().collect();
+        if self.multiplicities {
+            idx.extend(
+                self.emit
+                    .iter()
+                    .map(|(n, emit)| (n.as_global(), emit.clone())),
+            );
+        }
+        idx
+    }
+
+    fn resolve(&self, _: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        // whether a record is emitted depends on counts that only we hold
+        None
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("∩");
+        }
+
+        let emit = self
+            .emit
+            .iter()
+            .map(|(n, emit)| {
+                let cols = emit
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{}:[{}]", n.as_global().index(), cols)
+            })
+            .collect::<Vec<_>>()
+            .join(" ∩ ");
+        let emit = if self.multiplicities {
+            format!("{} (all)", emit)
+        } else {
+            emit
+        };
+        match self.emptiness {
+            Emptiness::Empty => emit,
+            Emptiness::NotReady => format!("{} (populated only)", emit),
+        }
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        self.emit
+            .iter()
+            .map(|(n, emit)| (n.as_global(), Some(emit[column])))
+            .collect()
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(emptiness: Emptiness) -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        setup_with(Intersect::new, emptiness)
+    }
+
+    fn setup_with<F>(new: F, emptiness: Emptiness) -> (ops::test::MockGraph, IndexPair, IndexPair)
+    where
+        F: FnOnce(HashMap<NodeIndex, Vec<usize>>, Emptiness) -> Intersect,
+    {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("intersect", &["i0", "i1"], new(emits, emptiness), false);
+        (g, l, r)
+    }
+
+    fn left(k: i32) -> Vec<DataType> {
+        vec![k.into(), "a".into()]
+    }
+
+    fn right(k: i32) -> Vec<DataType> {
+        vec![k.into(), "skipped".into(), "a".into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let (g, l, r) = setup(Emptiness::NotReady);
+        assert_eq!(
+            g.node().description(true),
+            format!(
+                "{}:[0, 1] ∩ {}:[0, 2] (populated only)",
+                l.as_global().index(),
+                r.as_global().index()
+            )
+        );
+    }
+
+    #[test]
+    fn it_materializes_itself() {
+        let (g, _, _) = setup(Emptiness::Empty);
+        let me = 3.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![0, 1]);
+        assert_eq!(g.node().resolve(0), None);
+    }
+
+    #[test]
+    fn it_rejects_non_existing_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 2]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let err = g
+            .try_set_op(
+                "intersect",
+                &["i0", "i1"],
+                Intersect::new(emits, Emptiness::Empty),
+                false,
+            )
+            .unwrap_err();
+        assert!(err.contains("non-existing column 2"), "{}", err);
+    }
+
+    #[test]
+    fn it_intersects() {
+        let (mut g, l, r) = setup(Emptiness::Empty);
+        assert_eq!(g.one(l, vec![left(1), left(2)], false), Records::default());
+        assert_eq!(g.one_row(r, right(2), false), vec![left(2)].into());
+
+        // duplicates are only emitted once, and only revoked once all copies are gone
+        assert_eq!(g.one_row(l, left(2), false), Records::default());
+        assert_eq!(g.one_row(l, (left(2), false), false), Records::default());
+        assert_eq!(
+            g.one_row(l, (left(2), false), false),
+            vec![(left(2), false)].into()
+        );
+    }
+
+    #[test]
+    fn it_intersects_multiplicities() {
+        let all = |emit, emptiness| Intersect::new(emit, emptiness).with_multiplicities();
+        let (mut g, l, r) = setup_with(all, Emptiness::Empty);
+
+        // ancestors are indexed on the intersected columns, as well as our own output
+        let me = 3.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 3);
+        assert_eq!(idx[&me], vec![0, 1]);
+        assert_eq!(idx[&l.as_global()], vec![0, 1]);
+        assert_eq!(idx[&r.as_global()], vec![0, 2]);
+
+        // a record on only one side is not emitted, however many copies it has
+        assert_eq!(g.one(l, vec![left(1), left(1)], false), Records::default());
+        assert_eq!(g.one(l, vec![left(2), left(2)], false), Records::default());
+
+        // the fewest copies on either side decide how many are emitted
+        assert_eq!(
+            g.one(r, vec![right(2), right(2), right(2)], false),
+            vec![left(2), left(2)].into()
+        );
+
+        // deleting from one side revokes copies until that side has none
+        assert_eq!(g.one_row(r, (right(2), false), false), Records::default());
+        assert_eq!(
+            g.one_row(r, (right(2), false), false),
+            vec![(left(2), false)].into()
+        );
+        assert_eq!(
+            g.one_row(r, (right(2), false), false),
+            vec![(left(2), false)].into()
+        );
+        assert_eq!(g.one_row(l, left(2), false), Records::default());
+    }
+
+    #[test]
+    fn it_treats_unpopulated_ancestors_as_empty() {
+        let (mut g, l, r) = setup(Emptiness::Empty);
+
+        // nothing is emitted until every ancestor has records
+        assert_eq!(g.one(l, vec![left(1), left(2)], false), Records::default());
+        assert_eq!(g.one_row(r, right(3), false), Records::default());
+        assert_eq!(g.one_row(r, right(1), false), vec![left(1)].into());
+    }
+
+    #[test]
+    fn it_waits_for_unpopulated_ancestors() {
+        let (mut g, l, r) = setup(Emptiness::NotReady);
+
+        // with only one populated ancestor, everything it sends is in the intersection
+        let rs = g.one(l, vec![left(1), left(2)], false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_positive(&left(1)[..]));
+        assert!(rs.has_positive(&left(2)[..]));
+
+        // once the other ancestor is populated, records it doesn't have are revoked
+        let rs = g.one(r, vec![right(1), right(3)], false);
+        assert_eq!(rs, vec![(left(2), false)].into());
+        assert_eq!(g.one_row(r, right(2), false), vec![left(2)].into());
+    }
+}
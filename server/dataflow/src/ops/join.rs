@@ -218,7 +218,9 @@ impl Ingredient for Join {
         }
     }
 
-    fn on_connected(&mut self, _g: &Graph) {}
+    fn on_connected(&mut self, _g: &Graph) -> Result<(), String> {
+        Ok(())
+    }
 
     fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.left.remap(remap);
@@ -737,6 +739,77 @@ mod tests {
         assert_eq!(rs.len(), 0);
     }
 
+    #[test]
+    fn it_emits_nulls_for_unmatched_left() {
+        let (mut j, l, r) = setup();
+        let l_a1 = vec![1.into(), "a".into()];
+
+        j.seed(r, vec![2.into(), "z".into()]);
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), "a".into(), DataType::None], true)].into()
+        );
+
+        // retracting the unmatched left row retracts its null-padded row too
+        let rs = j.one_row(l, (l_a1, false), false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), "a".into(), DataType::None], false)].into()
+        );
+    }
+
+    #[test]
+    fn it_retracts_nulls_on_first_match() {
+        let (mut j, l, r) = setup();
+        let l_a1 = vec![1.into(), "a".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y1 = vec![1.into(), "y".into()];
+
+        j.seed(l, l_a1.clone());
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1, false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "a".into(), DataType::None], false),
+                (vec![1.into(), "a".into(), "x".into()], true),
+            ]
+            .into()
+        );
+
+        // a second match only adds a joined row; the null row is already gone
+        j.seed(r, r_y1.clone());
+        let rs = j.one_row(r, r_y1, false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), "a".into(), "y".into()], true)].into()
+        );
+    }
+
+    #[test]
+    fn it_restores_nulls_when_last_match_is_deleted() {
+        let (mut j, l, r) = setup();
+        let l_a1 = vec![1.into(), "a".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+
+        j.seed(l, l_a1);
+        j.seed(r, r_x1.clone());
+
+        // right state no longer holds the match by the time the deletion reaches the join
+        j.unseed(r);
+        let rs = j.one_row(r, (r_x1, false), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "a".into(), "x".into()], false),
+                (vec![1.into(), "a".into(), DataType::None], true),
+            ]
+            .into()
+        );
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
@@ -37,7 +37,9 @@ impl Ingredient for Latest {
         vec![self.src.as_global()]
     }
 
-    fn on_connected(&mut self, _: &Graph) {}
+    fn on_connected(&mut self, _: &Graph) -> Result<(), String> {
+        Ok(())
+    }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.src.remap(remap);
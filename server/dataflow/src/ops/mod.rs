@@ -4,14 +4,20 @@ use std::collections::{HashMap, HashSet};
 
 use crate::prelude::*;
 
+pub mod asofjoin;
 pub mod distinct;
+pub mod except;
 pub mod filter;
 pub mod grouped;
 pub mod identity;
+pub mod intersect;
 pub mod join;
 pub mod latest;
 pub mod project;
+pub mod rank;
 pub mod rewrite;
+pub mod semijoin;
+pub mod sketch;
 pub mod topk;
 pub mod trigger;
 pub mod union;
@@ -33,6 +39,21 @@ pub enum NodeOperator {
     Trigger(trigger::Trigger),
     Rewrite(rewrite::Rewrite),
     Distinct(distinct::Distinct),
+    AsOfJoin(asofjoin::AsOfJoin),
+    DenseRank(rank::DenseRank),
+    MovingAverage(grouped::GroupedOperator<grouped::movingavg::MovingAverage>),
+    Correlation(grouped::GroupedOperator<grouped::correlation::Correlation>),
+    Histogram(grouped::GroupedOperator<grouped::histogram::Histogram>),
+    SessionWindow(grouped::GroupedOperator<grouped::session::SessionWindow>),
+    Intersect(intersect::Intersect),
+    SetUnion(grouped::GroupedOperator<grouped::setunion::SetUnion>),
+    ReservoirSample(grouped::GroupedOperator<grouped::reservoir::ReservoirSample>),
+    SpaceSaving(grouped::GroupedOperator<grouped::spacesaving::SpaceSaving>),
+    Except(except::Except),
+    CountDistinct(grouped::GroupedOperator<grouped::countdistinct::CountDistinct>),
+    Average(grouped::GroupedOperator<grouped::average::Average>),
+    StringAgg(grouped::GroupedOperator<grouped::stringagg::StringAgg>),
+    SemiJoin(semijoin::SemiJoin),
 }
 
 macro_rules! nodeop_from_impl {
@@ -71,6 +92,51 @@ nodeop_from_impl!(NodeOperator::TopK, topk::TopK);
 nodeop_from_impl!(NodeOperator::Trigger, trigger::Trigger);
 nodeop_from_impl!(NodeOperator::Rewrite, rewrite::Rewrite);
 nodeop_from_impl!(NodeOperator::Distinct, distinct::Distinct);
+nodeop_from_impl!(NodeOperator::AsOfJoin, asofjoin::AsOfJoin);
+nodeop_from_impl!(NodeOperator::DenseRank, rank::DenseRank);
+nodeop_from_impl!(
+    NodeOperator::MovingAverage,
+    grouped::GroupedOperator<grouped::movingavg::MovingAverage>
+);
+nodeop_from_impl!(
+    NodeOperator::Correlation,
+    grouped::GroupedOperator<grouped::correlation::Correlation>
+);
+nodeop_from_impl!(
+    NodeOperator::Histogram,
+    grouped::GroupedOperator<grouped::histogram::Histogram>
+);
+nodeop_from_impl!(
+    NodeOperator::SessionWindow,
+    grouped::GroupedOperator<grouped::session::SessionWindow>
+);
+nodeop_from_impl!(NodeOperator::Intersect, intersect::Intersect);
+nodeop_from_impl!(
+    NodeOperator::SetUnion,
+    grouped::GroupedOperator<grouped::setunion::SetUnion>
+);
+nodeop_from_impl!(
+    NodeOperator::ReservoirSample,
+    grouped::GroupedOperator<grouped::reservoir::ReservoirSample>
+);
+nodeop_from_impl!(
+    NodeOperator::SpaceSaving,
+    grouped::GroupedOperator<grouped::spacesaving::SpaceSaving>
+);
+nodeop_from_impl!(NodeOperator::Except, except::Except);
+nodeop_from_impl!(
+    NodeOperator::CountDistinct,
+    grouped::GroupedOperator<grouped::countdistinct::CountDistinct>
+);
+nodeop_from_impl!(
+    NodeOperator::Average,
+    grouped::GroupedOperator<grouped::average::Average>
+);
+nodeop_from_impl!(
+    NodeOperator::StringAgg,
+    grouped::GroupedOperator<grouped::stringagg::StringAgg>
+);
+nodeop_from_impl!(NodeOperator::SemiJoin, semijoin::SemiJoin);
 
 macro_rules! impl_ingredient_fn_mut {
     ($self:ident, $fn:ident, $( $arg:ident ),* ) => {
@@ -89,6 +155,21 @@ macro_rules! impl_ingredient_fn_mut {
             NodeOperator::Trigger(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::AsOfJoin(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::DenseRank(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::MovingAverage(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Correlation(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Histogram(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::SessionWindow(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Intersect(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::SetUnion(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::ReservoirSample(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::SpaceSaving(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Except(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::CountDistinct(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Average(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::StringAgg(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::SemiJoin(ref mut i) => i.$fn($($arg),*),
         }
     }
 }
@@ -110,6 +191,21 @@ macro_rules! impl_ingredient_fn_ref {
             NodeOperator::Trigger(ref i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref i) => i.$fn($($arg),*),
+            NodeOperator::AsOfJoin(ref i) => i.$fn($($arg),*),
+            NodeOperator::DenseRank(ref i) => i.$fn($($arg),*),
+            NodeOperator::MovingAverage(ref i) => i.$fn($($arg),*),
+            NodeOperator::Correlation(ref i) => i.$fn($($arg),*),
+            NodeOperator::Histogram(ref i) => i.$fn($($arg),*),
+            NodeOperator::SessionWindow(ref i) => i.$fn($($arg),*),
+            NodeOperator::Intersect(ref i) => i.$fn($($arg),*),
+            NodeOperator::SetUnion(ref i) => i.$fn($($arg),*),
+            NodeOperator::ReservoirSample(ref i) => i.$fn($($arg),*),
+            NodeOperator::SpaceSaving(ref i) => i.$fn($($arg),*),
+            NodeOperator::Except(ref i) => i.$fn($($arg),*),
+            NodeOperator::CountDistinct(ref i) => i.$fn($($arg),*),
+            NodeOperator::Average(ref i) => i.$fn($($arg),*),
+            NodeOperator::StringAgg(ref i) => i.$fn($($arg),*),
+            NodeOperator::SemiJoin(ref i) => i.$fn($($arg),*),
         }
     }
 }
@@ -139,7 +235,13 @@ impl Ingredient for NodeOperator {
     fn probe(&self) -> HashMap<String, String> {
         impl_ingredient_fn_ref!(self, probe,)
     }
-    fn on_connected(&mut self, graph: &Graph) {
+    fn metrics(&self) -> Option<noria::debug::stats::OpMetrics> {
+        impl_ingredient_fn_ref!(self, metrics,)
+    }
+    fn can_bypass(&self) -> Option<NodeIndex> {
+        impl_ingredient_fn_ref!(self, can_bypass,)
+    }
+    fn on_connected(&mut self, graph: &Graph) -> Result<(), String> {
         impl_ingredient_fn_mut!(self, on_connected, graph)
     }
     fn on_commit(&mut self, you: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
@@ -228,15 +330,23 @@ impl Ingredient for NodeOperator {
 #[cfg(test)]
 pub mod test {
     use std::cell;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     use crate::node;
     use crate::prelude::*;
 
     use petgraph::graph::NodeIndex;
 
+    struct Ex;
+
+    impl Executor for Ex {
+        fn ack(&mut self, _: SourceChannelIdentifier) {}
+        fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+        fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
+    }
+
     pub(super) struct MockGraph {
-        graph: Graph,
+        pub(super) graph: Graph,
         source: NodeIndex,
         nut: Option<IndexPair>, // node under test
         pub(super) states: StateMap,
@@ -295,13 +405,29 @@ pub mod test {
             ip
         }
 
-        pub fn set_op<I>(&mut self, name: &str, fields: &[&str], mut i: I, materialized: bool)
+        pub fn set_op<I>(&mut self, name: &str, fields: &[&str], i: I, materialized: bool)
+        where
+            I: Ingredient + Into<NodeOperator>,
+        {
+            if let Err(e) = self.try_set_op(name, fields, i, materialized) {
+                panic!("{}", e);
+            }
+        }
+
+        /// Like `set_op`, but returns the error if the node under test fails to connect.
+        pub fn try_set_op<I>(
+            &mut self,
+            name: &str,
+            fields: &[&str],
+            mut i: I,
+            materialized: bool,
+        ) -> Result<(), String>
         where
             I: Ingredient + Into<NodeOperator>,
         {
             assert!(self.nut.is_none(), "only one node under test is supported");
 
-            i.on_connected(&self.graph);
+            i.on_connected(&self.graph)?;
             let parents = i.ancestors();
             assert!(!parents.is_empty(), "node under test should have ancestors");
 
@@ -373,6 +499,7 @@ pub mod test {
                 .into_iter()
                 .map(|(_, n)| (n.local_addr(), cell::RefCell::new(n)))
                 .collect();
+            Ok(())
         }
 
         pub fn seed(&mut self, base: IndexPair, data: Vec<DataType>) {
@@ -416,14 +543,6 @@ pub mod test {
             assert!(self.nut.is_some());
             assert!(!remember || self.states.contains_key(*self.nut.unwrap()));
 
-            struct Ex;
-
-            impl Executor for Ex {
-                fn ack(&mut self, _: SourceChannelIdentifier) {}
-                fn create_universe(&mut self, _: HashMap<String, DataType>) {}
-                fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
-            }
-
             let mut u = {
                 let id = self.nut.unwrap();
                 let mut n = self.nodes[*id].borrow_mut();
@@ -440,6 +559,52 @@ pub mod test {
             u
         }
 
+        /// Feed a partial replay piece for `keys` from `src` through the node under test.
+        pub(crate) fn replay_piece<U: Into<Records>>(
+            &mut self,
+            src: IndexPair,
+            u: U,
+            key_cols: &[usize],
+            keys: &HashSet<Vec<DataType>>,
+            tag: Tag,
+            requesting_shard: usize,
+        ) -> RawProcessingResult {
+            self.one_raw(
+                src,
+                u,
+                ReplayContext::Partial {
+                    key_cols,
+                    keys,
+                    requesting_shard,
+                    tag,
+                    unishard: false,
+                },
+            )
+        }
+
+        /// Feed `u` from `src` through the raw input path of the node under test.
+        pub(crate) fn one_raw<U: Into<Records>>(
+            &mut self,
+            src: IndexPair,
+            u: U,
+            replay: ReplayContext,
+        ) -> RawProcessingResult {
+            assert!(self.nut.is_some());
+
+            let log = slog::Logger::root(slog::Discard, o!());
+            let id = self.nut.unwrap();
+            let mut n = self.nodes[*id].borrow_mut();
+            n.on_input_raw(
+                &mut Ex,
+                *src,
+                u.into(),
+                replay,
+                &self.nodes,
+                &self.states,
+                &log,
+            )
+        }
+
         pub fn one_row<R: Into<Record>>(
             &mut self,
             src: IndexPair,
@@ -462,6 +627,10 @@ pub mod test {
             self.nodes[*self.nut.unwrap()].borrow()
         }
 
+        pub fn node_mut(&self) -> cell::RefMut<Node> {
+            self.nodes[*self.nut.unwrap()].borrow_mut()
+        }
+
         pub fn narrow_base_id(&self) -> IndexPair {
             assert_eq!(self.remap.len(), 2 /* base + nut */);
             *self
@@ -4,12 +4,15 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::ops::filter::{self, FilterCondition, Truth, Value};
 use crate::prelude::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProjectExpressionBase {
     Column(usize),
     Literal(DataType),
+    /// The result of another expression, so that expressions can be nested.
+    Expression(Box<ProjectExpression>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +30,27 @@ impl ProjectExpression {
     ) -> ProjectExpression {
         ProjectExpression { op, left, right }
     }
+
+    /// The input columns this expression reads, in order and without duplicates.
+    pub fn columns(&self) -> Vec<usize> {
+        fn collect(base: &ProjectExpressionBase, cols: &mut Vec<usize>) {
+            match *base {
+                ProjectExpressionBase::Column(c) => cols.push(c),
+                ProjectExpressionBase::Literal(_) => {}
+                ProjectExpressionBase::Expression(ref e) => {
+                    collect(&e.left, cols);
+                    collect(&e.right, cols);
+                }
+            }
+        }
+
+        let mut cols = Vec::new();
+        collect(&self.left, &mut cols);
+        collect(&self.right, &mut cols);
+        cols.sort();
+        cols.dedup();
+        cols
+    }
 }
 
 impl fmt::Display for ProjectExpressionBase {
@@ -34,6 +58,7 @@ impl fmt::Display for ProjectExpressionBase {
         match *self {
             ProjectExpressionBase::Column(u) => write!(f, "{}", u),
             ProjectExpressionBase::Literal(ref l) => write!(f, "(lit: {})", l),
+            ProjectExpressionBase::Expression(ref e) => write!(f, "({})", e),
         }
     }
 }
@@ -51,6 +76,165 @@ impl fmt::Display for ProjectExpression {
     }
 }
 
+/// A conditional output column, like `CASE WHEN <col> <condition> THEN <then> ELSE <otherwise>
+/// END` in SQL.
+///
+/// Like in SQL, comparing NULL to anything is neither true nor false, so if the compared column,
+/// or the column it is compared against, is NULL, the result is NULL rather than either branch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectCase {
+    col: usize,
+    when: FilterCondition,
+    // the pre-built form of `when`, if it has one
+    compiled: Option<filter::Compiled>,
+    then: DataType,
+    otherwise: DataType,
+}
+
+impl ProjectCase {
+    /// Construct a conditional column that is `then` if column `col` satisfies `when`, and
+    /// `otherwise` if it doesn't.
+    ///
+    /// Returns an error if the condition is invalid; see `filter::validate`.
+    pub fn new(
+        col: usize,
+        when: FilterCondition,
+        then: DataType,
+        otherwise: DataType,
+    ) -> Result<Self, String> {
+        let compiled = filter::Compiled::new(&when)?;
+        Ok(ProjectCase {
+            col,
+            when,
+            compiled,
+            then,
+            otherwise,
+        })
+    }
+
+    fn eval(&self, record: &[DataType]) -> DataType {
+        let truth = filter::condition_truth(
+            &self.when,
+            &record[self.col],
+            record,
+            self.compiled.as_ref(),
+        );
+        match truth {
+            Truth::True => self.then.clone(),
+            Truth::False => self.otherwise.clone(),
+            Truth::Unknown => DataType::None,
+        }
+    }
+
+    /// The input columns this condition reads.
+    pub fn columns(&self) -> Vec<usize> {
+        match self.when {
+            FilterCondition::Comparison(_, Value::Column(c)) if c != self.col => {
+                let mut cols = vec![self.col, c];
+                cols.sort();
+                cols
+            }
+            FilterCondition::And(ref bs) | FilterCondition::Or(ref bs) => {
+                // the condition's own column is ignored in favor of those of its branches
+                let mut cols: Vec<_> = bs.iter().map(|&(c, _)| c).collect();
+                cols.sort();
+                cols.dedup();
+                cols
+            }
+            _ => vec![self.col],
+        }
+    }
+}
+
+impl fmt::Display for ProjectCase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let when = match self.when {
+            FilterCondition::Comparison(ref op, ref v) => format!("{} {}", op, v),
+            FilterCondition::In {
+                values: ref vs,
+                negated,
+            } => format!(
+                "{}IN ({})",
+                if negated { "NOT " } else { "" },
+                vs.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            FilterCondition::Range {
+                ref lower,
+                ref upper,
+            } => filter::describe_range(lower, upper),
+            FilterCondition::Regex {
+                ref pattern,
+                negated,
+            } => format!("{}~ /{}/", if negated { "!" } else { "" }, pattern),
+            FilterCondition::And(ref bs) => filter::describe_branches(bs, false),
+            FilterCondition::Or(ref bs) => filter::describe_branches(bs, true),
+        };
+        write!(
+            f,
+            "case {} {} then {} else {}",
+            self.col, when, self.then, self.otherwise
+        )
+    }
+}
+
+/// An expression that computes a key from a record, for operators that match records on something
+/// other than a plain column value.
+///
+/// Both sides of a match should use expressions that compute comparable keys, so that, for example,
+/// `Lower(Column(0))` on one side matches `Lower(Column(2))` on the other regardless of casing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KeyExpression {
+    /// The value of a column.
+    Column(usize),
+    /// A text value in lower case. Values that aren't text are left as they are.
+    Lower(Box<KeyExpression>),
+    /// A text value in upper case. Values that aren't text are left as they are.
+    Upper(Box<KeyExpression>),
+    /// A text value with leading and trailing whitespace removed.
+    Trim(Box<KeyExpression>),
+    /// The result of an arithmetic expression.
+    Arithmetic(ProjectExpression),
+}
+
+impl KeyExpression {
+    /// Compute the key of `record`.
+    pub fn eval(&self, record: &[DataType]) -> DataType {
+        let text = |e: &KeyExpression, f: fn(&str) -> String| {
+            let v = e.eval(record);
+            match v {
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = (&v).into();
+                    DataType::from(f(s))
+                }
+                _ => v,
+            }
+        };
+
+        match *self {
+            KeyExpression::Column(i) => record[i].clone(),
+            KeyExpression::Lower(ref e) => text(e, str::to_lowercase),
+            KeyExpression::Upper(ref e) => text(e, str::to_uppercase),
+            KeyExpression::Trim(ref e) => text(e, |s| s.trim().to_owned()),
+            KeyExpression::Arithmetic(ref e) => eval_expression(e, record),
+        }
+    }
+}
+
+impl fmt::Display for KeyExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyExpression::Column(i) => write!(f, "{}", i),
+            KeyExpression::Lower(ref e) => write!(f, "lower({})", e),
+            KeyExpression::Upper(ref e) => write!(f, "upper({})", e),
+            KeyExpression::Trim(ref e) => write!(f, "trim({})", e),
+            KeyExpression::Arithmetic(ref e) => write!(f, "({})", e),
+        }
+    }
+}
+
 /// Permutes or omits columns from its source node, or adds additional literal value columns.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Project {
@@ -58,6 +242,8 @@ pub struct Project {
     emit: Option<Vec<usize>>,
     additional: Option<Vec<DataType>>,
     expressions: Option<Vec<ProjectExpression>>,
+    #[serde(default)]
+    cases: Option<Vec<ProjectCase>>,
     src: IndexPair,
     cols: usize,
 }
@@ -74,12 +260,24 @@ impl Project {
             emit: Some(emit.into()),
             additional,
             expressions,
+            cases: None,
             src: src.into(),
             cols: 0,
             us: None,
         }
     }
 
+    /// Also emit the given conditional columns, after any arithmetic expressions and before any
+    /// additional literal columns.
+    pub fn with_cases(mut self, cases: Vec<ProjectCase>) -> Project {
+        assert!(
+            self.emit.is_some(),
+            "conditional columns require an explicit emit list"
+        );
+        self.cases = Some(cases);
+        self
+    }
+
     fn resolve_col(&self, col: usize) -> usize {
         if self.emit.is_some() && col >= self.emit.as_ref().unwrap().len() {
             panic!(
@@ -91,6 +289,37 @@ impl Project {
         }
     }
 
+    /// The input columns that output column `col` is computed from.
+    ///
+    /// Unlike `parent_columns`, which can only map an output column to a single input column, this
+    /// reports every column an arithmetic expression reads. Literal columns depend on no input
+    /// columns.
+    pub fn depends_on(&self, col: usize) -> Vec<usize> {
+        let emitted = self.emit.as_ref().map_or(usize::max_value(), Vec::len);
+        if col < emitted {
+            return vec![self.resolve_col(col)];
+        }
+        let expressions = self.expressions.as_ref().map_or(&[][..], Vec::as_slice);
+        if let Some(e) = expressions.get(col - emitted) {
+            return e.columns();
+        }
+        self.cases
+            .as_ref()
+            .and_then(|c| c.get(col - emitted - expressions.len()))
+            .map(ProjectCase::columns)
+            .unwrap_or_default()
+    }
+
+    /// Whether output column `col` is computed by an arithmetic expression or a condition.
+    fn is_computed(&self, col: usize) -> bool {
+        let computed =
+            self.expressions.as_ref().map_or(0, Vec::len) + self.cases.as_ref().map_or(0, Vec::len);
+        match self.emit {
+            Some(ref emit) => col >= emit.len() && col < emit.len() + computed,
+            None => false,
+        }
+    }
+
     pub fn emits(&self) -> (&[usize], &[DataType], &[ProjectExpression]) {
         (
             self.emit.as_ref().map(Vec::as_slice).unwrap_or(&[]),
@@ -100,16 +329,38 @@ impl Project {
     }
 }
 
+/// Whether `v` is zero, if it is a number at all.
+fn numeric_zero(v: &DataType) -> Option<bool> {
+    match *v {
+        DataType::Int(..)
+        | DataType::UnsignedInt(..)
+        | DataType::BigInt(..)
+        | DataType::UnsignedBigInt(..) => Some(i128::from(v) == 0),
+        DataType::Real(i, f) => Some(i == 0 && f == 0),
+        DataType::Decimal(m, _) => Some(m == 0),
+        _ => None,
+    }
+}
+
 fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
-    let left = match expression.left {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
+    let eval_base = |base: &ProjectExpressionBase| match *base {
+        ProjectExpressionBase::Column(i) => Cow::Borrowed(&record[i]),
+        ProjectExpressionBase::Literal(ref data) => Cow::Borrowed(data),
+        ProjectExpressionBase::Expression(ref e) => Cow::Owned(eval_expression(e, record)),
     };
-
-    let right = match expression.right {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
+    let left = eval_base(&expression.left);
+    let right = eval_base(&expression.right);
+    let (left, right) = (&*left, &*right);
+
+    // like in SQL, arithmetic on NULL yields NULL, and like in MySQL, so does arithmetic on values
+    // that aren't numbers, and division by zero, rather than an error
+    let divisor_is_zero = match (numeric_zero(left), numeric_zero(right)) {
+        (Some(_), Some(zero)) => zero,
+        _ => return DataType::None,
     };
+    if divisor_is_zero && matches!(expression.op, ArithmeticOperator::Divide) {
+        return DataType::None;
+    }
 
     match expression.op {
         ArithmeticOperator::Add => left + right,
@@ -143,6 +394,7 @@ impl Ingredient for Project {
         let emit = self.emit.clone();
         let additional = self.additional.clone();
         let expressions = self.expressions.clone();
+        let cases = self.cases.clone();
 
         // translate output columns to input columns
         let mut in_cols = Cow::Borrowed(columns);
@@ -172,6 +424,9 @@ impl Ingredient for Project {
                             } else {
                                 vec![]
                             };
+                            if let Some(ref c) = cases {
+                                expr.extend(c.iter().map(|c| c.eval(&r[..])));
+                            }
 
                             new_r.extend(
                                 r.into_owned()
@@ -197,8 +452,9 @@ impl Ingredient for Project {
             })
     }
 
-    fn on_connected(&mut self, g: &Graph) {
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
         self.cols = g[self.src.as_global()].fields().len();
+        Ok(())
     }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
@@ -209,8 +465,10 @@ impl Ingredient for Project {
         // the inputs, so we don't needlessly perform extra work on each
         // update.
         self.emit = self.emit.take().and_then(|emit| {
-            let complete =
-                emit.len() == self.cols && self.additional.is_none() && self.expressions.is_none();
+            let complete = emit.len() == self.cols
+                && self.additional.is_none()
+                && self.expressions.is_none()
+                && self.cases.is_none();
             let sequential = emit.iter().enumerate().all(|(i, &j)| i == j);
             if complete && sequential {
                 None
@@ -242,6 +500,10 @@ impl Ingredient for Project {
                     new_r.extend(e.iter().map(|i| eval_expression(i, &r[..])));
                 }
 
+                if let Some(ref c) = self.cases {
+                    new_r.extend(c.iter().map(|c| c.eval(&r[..])));
+                }
+
                 if let Some(ref a) = self.additional {
                     new_r.append(&mut a.clone());
                 }
@@ -261,6 +523,10 @@ impl Ingredient for Project {
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if self.is_computed(col) {
+            // computed from possibly several parent columns
+            return None;
+        }
         Some(vec![(self.src.as_global(), self.resolve_col(col))])
     }
 
@@ -284,6 +550,10 @@ impl Ingredient for Project {
                     );
                 }
 
+                if let Some(ref cases) = self.cases {
+                    emit_cols.extend(cases.iter().map(|c| format!("{}", c)));
+                }
+
                 if let Some(ref add) = self.additional {
                     emit_cols.extend(
                         add.iter()
@@ -687,6 +957,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_yields_null_for_invalid_arithmetic() {
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Divide);
+        for zero in vec![DataType::from(0), 0.0.into(), DataType::Decimal(0, 2)] {
+            assert_eq!(
+                p.narrow_one_row(vec![10.into(), zero.clone()], false),
+                vec![vec![10.into(), zero, DataType::None]].into()
+            );
+        }
+
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Add);
+        assert_eq!(
+            p.narrow_one_row(vec![true.into(), 1.into()], false),
+            vec![vec![true.into(), 1.into(), DataType::None]].into()
+        );
+        assert_eq!(
+            p.narrow_one_row(vec![1.into(), "a".into()], false),
+            vec![vec![1.into(), "a".into(), DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_propagates_null_through_arithmetic() {
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Add);
+        let rec = vec![10.into(), DataType::None];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), DataType::None, DataType::None]].into()
+        );
+
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Multiply,
+            ProjectExpressionBase::Column(0),
+            ProjectExpressionBase::Literal(2.into()),
+        );
+        let mut p = setup_arithmetic(expression);
+        assert_eq!(
+            p.narrow_one_row(vec![DataType::None, 1.into()], false),
+            vec![vec![DataType::None, 1.into(), DataType::None]].into()
+        );
+        assert_eq!(
+            p.narrow_one_row(vec![21.into(), 1.into()], false),
+            vec![vec![21.into(), 1.into(), 42.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_nested_arithmetic() {
+        // (0 + 1) * 2
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Multiply,
+            ProjectExpressionBase::Expression(Box::new(ProjectExpression::new(
+                ArithmeticOperator::Add,
+                ProjectExpressionBase::Column(0),
+                ProjectExpressionBase::Column(1),
+            ))),
+            ProjectExpressionBase::Literal(2.into()),
+        );
+        let mut p = setup_arithmetic(expression);
+        assert_eq!(p.node().description(true), "π[0, 1, (0 + 1) * (lit: 2)]");
+        assert_eq!(
+            p.narrow_one_row(vec![3.into(), 4.into()], false),
+            vec![vec![3.into(), 4.into(), 14.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_conditional_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let case = ProjectCase::new(
+            0,
+            FilterCondition::Comparison(filter::Operator::Greater, Value::Constant(10.into())),
+            "hi".into(),
+            "lo".into(),
+        )
+        .unwrap();
+        let p = Project::new(s.as_global(), &[0], None, None).with_cases(vec![case]);
+        g.set_op("permute", &["x", "level"], p, false);
+
+        assert_eq!(
+            g.node().description(true),
+            "π[0, case 0 > 10 then \"hi\" else \"lo\"]"
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![20.into(), 0.into()], false),
+            vec![vec![20.into(), "hi".into()]].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![5.into(), 0.into()], false),
+            vec![vec![5.into(), "lo".into()]].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(vec![DataType::None, 0.into()], false),
+            vec![vec![DataType::None, DataType::None]].into()
+        );
+
+        // the condition isn't any single parent column
+        assert_eq!(g.node().resolve(1), None);
+        assert_eq!(
+            g.node().parent_columns(1),
+            vec![(g.narrow_base_id().as_global(), None)]
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_case_conditions() {
+        let when = FilterCondition::Regex {
+            pattern: "(".into(),
+            negated: false,
+        };
+        let err = ProjectCase::new(0, when, "hi".into(), "lo".into()).unwrap_err();
+        assert!(err.contains("invalid filter pattern"), "{}", err);
+    }
+
+    #[test]
+    fn it_does_not_resolve_arithmetic() {
+        let p = setup_column_arithmetic(ArithmeticOperator::Add);
+        assert_eq!(p.node().resolve(2), None);
+        assert_eq!(
+            p.node().parent_columns(2),
+            vec![(p.narrow_base_id().as_global(), None)]
+        );
+
+        match **p.node() {
+            NodeOperator::Project(ref p) => {
+                assert_eq!(p.depends_on(0), vec![0]);
+                assert_eq!(p.depends_on(2), vec![0, 1]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     #[should_panic(expected = "can't resolve literal column")]
     fn it_fails_to_resolve_literal() {
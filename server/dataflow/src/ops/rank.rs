@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use crate::prelude::*;
+
+/// DenseRank appends the dense rank of every record within its group, ordered ascending by a
+/// single column.
+///
+/// Records that tie on the ordering column share a rank, and ranks have no gaps. Inserting a new
+/// distinct value into a group shifts the rank of every record ordered after it, so the operator
+/// keeps every group ordered in internal state and revokes and re-emits exactly those records.
+/// Each distinct value also keeps its current rank, which is adjusted along with those records,
+/// so that ranking a record does not need to count the values ordered before it.
+///
+/// Since the groups exist only in the operator, its output is always fully materialized, and
+/// replays are served from that materialization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DenseRank {
+    src: IndexPair,
+
+    // some cache state
+    cols: usize,
+
+    group_by: Vec<usize>,
+    over: usize,
+
+    // the rank of each distinct value in a group, and the records with that value
+    groups: HashMap<Vec<DataType>, BTreeMap<DataType, (usize, Vec<Vec<DataType>>)>>,
+}
+
+impl DenseRank {
+    /// Construct a new dense rank operator.
+    ///
+    /// `src` is this operator's ancestor, `group_by` indicates the columns that ranks are
+    /// computed within, and `over` is the column records are ranked by.
+    pub fn new(src: NodeIndex, group_by: Vec<usize>, over: usize) -> Self {
+        let mut group_by = group_by;
+        group_by.sort();
+
+        DenseRank {
+            src: src.into(),
+            cols: 0,
+            group_by,
+            over,
+            groups: HashMap::new(),
+        }
+    }
+
+    fn ranked(row: &[DataType], rank: usize) -> Vec<DataType> {
+        let mut row = row.to_vec();
+        row.push(rank.into());
+        row
+    }
+}
+
+impl Ingredient for DenseRank {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
+        self.cols = g[self.src.as_global()].fields().len();
+        Ok(())
+    }
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.src.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        let mut out = Vec::new();
+        for r in rs {
+            let (r, positive) = r.extract();
+            let group: Vec<_> = self.group_by.iter().map(|&c| r[c].clone()).collect();
+            let value = r[self.over].clone();
+
+            if positive {
+                let values = self.groups.entry(group).or_insert_with(BTreeMap::new);
+                let (rank, new_value) = match values.get_mut(&value) {
+                    Some((rank, rows)) => {
+                        rows.push(r.clone());
+                        (*rank, false)
+                    }
+                    None => {
+                        // a new value ranks right after the closest value below it
+                        let rank = values
+                            .range((Bound::Unbounded, Bound::Excluded(&value)))
+                            .next_back()
+                            .map(|(_, &(rank, _))| rank + 1)
+                            .unwrap_or(1);
+                        values.insert(value.clone(), (rank, vec![r.clone()]));
+                        (rank, true)
+                    }
+                };
+                out.push(Record::Positive(Self::ranked(&r, rank)));
+
+                // adding a distinct value shifts every later value up by one rank
+                if new_value {
+                    let later = values.range_mut((Bound::Excluded(&value), Bound::Unbounded));
+                    for (_, (rank, rows)) in later {
+                        for row in rows.iter() {
+                            out.push(Record::Negative(Self::ranked(row, *rank)));
+                            out.push(Record::Positive(Self::ranked(row, *rank + 1)));
+                        }
+                        *rank += 1;
+                    }
+                }
+            } else {
+                // a negative for a record we don't hold has nothing to retract
+                let values = match self.groups.get_mut(&group) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                let (rank, now_empty) = match values.get_mut(&value) {
+                    Some((rank, rows)) => match rows.iter().position(|row| row == &r) {
+                        Some(i) => {
+                            rows.swap_remove(i);
+                            (*rank, rows.is_empty())
+                        }
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                out.push(Record::Negative(Self::ranked(&r, rank)));
+
+                // removing the last record with a value shifts every later value down by one rank
+                if now_empty {
+                    values.remove(&value);
+                    let later = values.range_mut((Bound::Excluded(&value), Bound::Unbounded));
+                    for (_, (rank, rows)) in later {
+                        for row in rows.iter() {
+                            out.push(Record::Negative(Self::ranked(row, *rank)));
+                            out.push(Record::Positive(Self::ranked(row, *rank - 1)));
+                        }
+                        *rank -= 1;
+                    }
+                    if values.is_empty() {
+                        self.groups.remove(&group);
+                    }
+                }
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // groups are kept in internal state, so our output must be materialized for replays to
+        // be served from it rather than ranked again
+        Some((this, self.group_by.clone())).into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.cols {
+            None
+        } else {
+            Some(vec![(self.src.as_global(), col)])
+        }
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("DenseRank");
+        }
+
+        let group_cols = self
+            .group_by
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("DenseRank γ[{}] ↑{}", group_cols, self.over)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if column == self.cols {
+            return vec![(self.src.as_global(), None)];
+        }
+        vec![(self.src.as_global(), Some(column))]
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "rank",
+            &["x", "y", "z", "rank"],
+            DenseRank::new(s.as_global(), vec![0], 1),
+            false,
+        );
+        g
+    }
+
+    fn row(x: i32, y: i32, z: &str) -> Vec<DataType> {
+        vec![x.into(), y.into(), z.into()]
+    }
+
+    fn ranked(x: i32, y: i32, z: &str, rank: usize) -> Vec<DataType> {
+        vec![x.into(), y.into(), z.into(), rank.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup();
+        assert_eq!(g.node().description(true), "DenseRank γ[0] ↑1");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let g = setup();
+        let me = 1.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![0]);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup();
+        let src = g.narrow_base_id().as_global();
+        assert_eq!(g.node().resolve(1), Some(vec![(src, 1)]));
+        assert_eq!(g.node().resolve(3), None);
+    }
+
+    #[test]
+    fn it_ranks_ties_densely() {
+        let mut g = setup();
+
+        assert_eq!(
+            g.narrow_one_row(row(1, 10, "a"), false),
+            vec![ranked(1, 10, "a", 1)].into()
+        );
+        assert_eq!(
+            g.narrow_one_row(row(1, 20, "b"), false),
+            vec![ranked(1, 20, "b", 2)].into()
+        );
+
+        // a tie shares the rank, and doesn't shift anything
+        assert_eq!(
+            g.narrow_one_row(row(1, 10, "c"), false),
+            vec![ranked(1, 10, "c", 1)].into()
+        );
+
+        // other groups are ranked independently
+        assert_eq!(
+            g.narrow_one_row(row(2, 30, "d"), false),
+            vec![ranked(2, 30, "d", 1)].into()
+        );
+    }
+
+    #[test]
+    fn it_shifts_ranks() {
+        let mut g = setup();
+        g.narrow_one_row(row(1, 10, "a"), false);
+        g.narrow_one_row(row(1, 10, "b"), false);
+        g.narrow_one_row(row(1, 20, "c"), false);
+
+        // a new lowest value bumps everything else
+        let rs = g.narrow_one_row(row(1, 5, "d"), false);
+        assert_eq!(rs.len(), 7);
+        assert!(rs.has_positive(&ranked(1, 5, "d", 1)[..]));
+        assert!(rs.has_negative(&ranked(1, 10, "a", 1)[..]));
+        assert!(rs.has_positive(&ranked(1, 10, "a", 2)[..]));
+        assert!(rs.has_negative(&ranked(1, 10, "b", 1)[..]));
+        assert!(rs.has_positive(&ranked(1, 10, "b", 2)[..]));
+        assert!(rs.has_negative(&ranked(1, 20, "c", 2)[..]));
+        assert!(rs.has_positive(&ranked(1, 20, "c", 3)[..]));
+
+        // removing one of two tied records shifts nothing
+        let rs = g.narrow_one_row((row(1, 10, "a"), false), false);
+        assert_eq!(rs, vec![(ranked(1, 10, "a", 2), false)].into());
+
+        // but removing the last record with a value closes the gap
+        let rs = g.narrow_one_row((row(1, 10, "b"), false), false);
+        assert_eq!(rs.len(), 3);
+        assert!(rs.has_negative(&ranked(1, 10, "b", 2)[..]));
+        assert!(rs.has_negative(&ranked(1, 20, "c", 3)[..]));
+        assert!(rs.has_positive(&ranked(1, 20, "c", 2)[..]));
+    }
+
+    #[test]
+    fn it_ignores_negatives_for_unknown_records() {
+        let mut g = setup();
+        g.narrow_one_row(row(1, 10, "a"), false);
+        g.narrow_one_row(row(1, 20, "b"), false);
+
+        // neither a value nor a group that was never seen shifts anything
+        assert!(g.narrow_one_row((row(1, 5, "x"), false), false).is_empty());
+        assert!(g.narrow_one_row((row(2, 5, "x"), false), false).is_empty());
+
+        // and neither does a record that was never seen with a value that was
+        assert!(g.narrow_one_row((row(1, 10, "x"), false), false).is_empty());
+
+        assert_eq!(
+            g.narrow_one_row(row(1, 15, "c"), false).len(),
+            3,
+            "ranks are still in order after the unknown negatives"
+        );
+    }
+}
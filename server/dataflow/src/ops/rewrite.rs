@@ -64,7 +64,9 @@ impl Ingredient for Rewrite {
         Some(Some(self.src.as_global()).into_iter().collect())
     }
 
-    fn on_connected(&mut self, _: &Graph) {}
+    fn on_connected(&mut self, _: &Graph) -> Result<(), String> {
+        Ok(())
+    }
 
     fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.src.remap(remap);
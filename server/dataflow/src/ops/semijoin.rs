@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+/// Kind of semijoin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SemiJoinKind {
+    /// Emit left rows that have at least one match on the right (`EXISTS`)
+    Semi,
+    /// Emit left rows that have no match on the right (`NOT EXISTS`)
+    Anti,
+}
+
+/// SemiJoin filters its left ancestor on whether each row has a match in its right ancestor.
+///
+/// Unlike a join, no columns are taken from the right ancestor, and a left row is emitted at most
+/// once no matter how many right rows share its key. The operator counts the right rows it has seen
+/// for each key, and keeps the left rows for each key around so that they can be emitted or
+/// revoked when that count moves between zero and non-zero.
+///
+/// Since that state exists only in the operator, its output is always fully materialized, and
+/// replays are served from that materialization rather than traced through to the left ancestor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SemiJoin {
+    left: IndexPair,
+    right: IndexPair,
+
+    // Key column in the left and right parents respectively
+    on: (usize, usize),
+
+    kind: SemiJoinKind,
+
+    // the number of right rows seen for each key
+    matches: HashMap<DataType, i64>,
+    // all left rows seen for each key
+    lefts: HashMap<DataType, Vec<Vec<DataType>>>,
+}
+
+impl SemiJoin {
+    /// Construct a new semijoin operator.
+    ///
+    /// `left` and `right` are the left and right parents respectively, and `on` gives the join
+    /// column in each: (left_parent_column, right_parent_column). The operator emits exactly the
+    /// columns of `left`.
+    pub fn new(left: NodeIndex, right: NodeIndex, on: (usize, usize), kind: SemiJoinKind) -> Self {
+        assert_ne!(left, right, "cannot semijoin an ancestor with itself");
+
+        SemiJoin {
+            left: left.into(),
+            right: right.into(),
+            on,
+            kind,
+            matches: HashMap::new(),
+            lefts: HashMap::new(),
+        }
+    }
+
+    fn passes(&self, matches: i64) -> bool {
+        match self.kind {
+            SemiJoinKind::Semi => matches > 0,
+            SemiJoinKind::Anti => matches <= 0,
+        }
+    }
+
+    fn matches(&self, key: &DataType) -> i64 {
+        self.matches.get(key).cloned().unwrap_or(0)
+    }
+}
+
+impl Ingredient for SemiJoin {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.left.as_global(), self.right.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
+        if self.on.0 >= g[self.left.as_global()].fields().len() {
+            return Err(format!(
+                "cannot semijoin on non-existing left column {}",
+                self.on.0
+            ));
+        }
+        if self.on.1 >= g[self.right.as_global()].fields().len() {
+            return Err(format!(
+                "cannot semijoin on non-existing right column {}",
+                self.on.1
+            ));
+        }
+        Ok(())
+    }
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.left.remap(remap);
+        self.right.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        let mut out = Vec::new();
+
+        if from == *self.left {
+            // the right side does not change within this batch, so each left row can be decided
+            // on its own
+            for r in rs {
+                let (row, positive) = r.extract();
+                let key = row[self.on.0].clone();
+                let passes = self.passes(self.matches(&key));
+
+                if positive {
+                    self.lefts
+                        .entry(key)
+                        .or_insert_with(Vec::new)
+                        .push(row.clone());
+                } else if let Some(rows) = self.lefts.get_mut(&key) {
+                    if let Some(i) = rows.iter().position(|r| r == &row) {
+                        rows.swap_remove(i);
+                    }
+                    if rows.is_empty() {
+                        self.lefts.remove(&key);
+                    }
+                }
+
+                if passes {
+                    out.push((row, positive).into());
+                }
+            }
+        } else {
+            assert_eq!(
+                from, *self.right,
+                "semijoin received records from unknown ancestor"
+            );
+
+            // apply all records first, so that each key changes at most once per batch
+            let mut before = HashMap::new();
+            for r in rs {
+                let key = r[self.on.1].clone();
+                let matches = self.matches(&key);
+                before.entry(key.clone()).or_insert(matches);
+
+                let delta = if r.is_positive() { 1 } else { -1 };
+                *self.matches.entry(key).or_insert(0) += delta;
+            }
+
+            for (key, was) in before {
+                let now = self.matches(&key);
+                if now == 0 {
+                    self.matches.remove(&key);
+                }
+
+                let positive = match (self.passes(was), self.passes(now)) {
+                    (false, true) => true,
+                    (true, false) => false,
+                    _ => continue,
+                };
+                if let Some(rows) = self.lefts.get(&key) {
+                    out.extend(rows.iter().map(|row| (row.clone(), positive).into()));
+                }
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // counts and left rows are kept in internal state, so our output must be materialized
+        // for replays to be served from it rather than applied to that state again
+        Some((this, vec![self.on.0])).into_iter().collect()
+    }
+
+    fn resolve(&self, _: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        // whether a left row is emitted depends on counts that only we hold
+        None
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        let op = match self.kind {
+            SemiJoinKind::Semi => "∃",
+            SemiJoinKind::Anti => "∄",
+        };
+
+        if !detailed {
+            return String::from(op);
+        }
+
+        format!(
+            "{}:{} {} {}:{}",
+            self.left.as_global().index(),
+            self.on.0,
+            op,
+            self.right.as_global().index(),
+            self.on.1
+        )
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if col == self.on.0 {
+            // Join column comes from both parents
+            vec![
+                (self.left.as_global(), Some(self.on.0)),
+                (self.right.as_global(), Some(self.on.1)),
+            ]
+        } else {
+            vec![(self.left.as_global(), Some(col))]
+        }
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(kind: SemiJoinKind) -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+        g.set_op(
+            "semijoin",
+            &["s0", "s1"],
+            SemiJoin::new(l.as_global(), r.as_global(), (0, 1), kind),
+            false,
+        );
+        (g, l, r)
+    }
+
+    fn left(k: i32, v: &str) -> Vec<DataType> {
+        vec![k.into(), v.into()]
+    }
+
+    fn right(k: i32) -> Vec<DataType> {
+        vec!["skipped".into(), k.into()]
+    }
+
+    #[test]
+    fn it_describes() {
+        let (g, l, r) = setup(SemiJoinKind::Semi);
+        assert_eq!(
+            g.node().description(true),
+            format!("{}:0 ∃ {}:1", l.as_global().index(), r.as_global().index())
+        );
+
+        let (g, l, r) = setup(SemiJoinKind::Anti);
+        assert_eq!(
+            g.node().description(true),
+            format!("{}:0 ∄ {}:1", l.as_global().index(), r.as_global().index())
+        );
+    }
+
+    #[test]
+    fn it_semijoins() {
+        let (mut g, l, r) = setup(SemiJoinKind::Semi);
+
+        // no match yet
+        assert_eq!(g.one_row(l, left(1, "a"), false), Records::default());
+
+        // the first match emits the left row, and later ones do not emit it again
+        assert_eq!(g.one_row(r, right(1), false), vec![left(1, "a")].into());
+        assert_eq!(g.one_row(r, right(1), false), Records::default());
+
+        // left rows that arrive while a match exists pass straight through
+        assert_eq!(g.one_row(l, left(1, "b"), false), vec![left(1, "b")].into());
+
+        // removing one of two matches changes nothing, but removing the last revokes all rows
+        assert_eq!(g.one_row(r, (right(1), false), false), Records::default());
+        let rs = g.one_row(r, (right(1), false), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_negative(&left(1, "a")[..]));
+        assert!(rs.has_negative(&left(1, "b")[..]));
+
+        // a batch that adds and removes a match changes nothing
+        assert_eq!(
+            g.one(r, vec![(right(1), true), (right(1), false)], false),
+            Records::default()
+        );
+    }
+
+    #[test]
+    fn it_antijoins() {
+        let (mut g, l, r) = setup(SemiJoinKind::Anti);
+
+        // no match, so the left row passes
+        assert_eq!(g.one_row(l, left(1, "a"), false), vec![left(1, "a")].into());
+
+        // the first match revokes it
+        assert_eq!(
+            g.one_row(r, right(1), false),
+            vec![(left(1, "a"), false)].into()
+        );
+
+        // left rows that arrive while a match exists are dropped
+        assert_eq!(g.one_row(l, left(1, "b"), false), Records::default());
+
+        // removing the sole match brings back every left row for the key
+        let rs = g.one_row(r, (right(1), false), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_positive(&left(1, "a")[..]));
+        assert!(rs.has_positive(&left(1, "b")[..]));
+
+        // deleted left rows are not brought back
+        assert_eq!(
+            g.one_row(l, (left(1, "b"), false), false),
+            vec![(left(1, "b"), false)].into()
+        );
+        assert_eq!(
+            g.one_row(r, right(1), false),
+            vec![(left(1, "a"), false)].into()
+        );
+    }
+
+    #[test]
+    fn it_materializes_itself() {
+        let (g, _, _) = setup(SemiJoinKind::Semi);
+        let me = 3.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![0]);
+    }
+
+    #[test]
+    fn it_rejects_non_existing_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+        let err = g
+            .try_set_op(
+                "semijoin",
+                &["s0", "s1"],
+                SemiJoin::new(l.as_global(), r.as_global(), (0, 2), SemiJoinKind::Semi),
+                false,
+            )
+            .unwrap_err();
+        assert!(err.contains("right column 2"), "{}", err);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, l, r) = setup(SemiJoinKind::Semi);
+        assert_eq!(g.node().resolve(1), None);
+        assert_eq!(
+            g.node().parent_columns(0),
+            vec![(l.as_global(), Some(0)), (r.as_global(), Some(1))]
+        );
+    }
+}
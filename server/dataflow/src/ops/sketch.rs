@@ -0,0 +1,116 @@
+use crate::prelude::*;
+
+/// A mergeable sketch of a distribution, used to estimate quantiles.
+///
+/// The sketch keeps at most `max` centroids, each of which is the mean of some number of values.
+/// When there are too many centroids, the two adjacent centroids with the smallest combined weight
+/// are merged. This keeps centroids roughly equally heavy, so that the rank of any estimated
+/// quantile is off by at most about `2 / max` of the number of values.
+///
+/// Sketches are stored in columns as text, so that they can flow through the dataflow like any
+/// other value. See `to_datatype` and `from_datatype`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QuantileSketch {
+    max: usize,
+    // (mean, weight), ordered by mean
+    centroids: Vec<(f64, u64)>,
+}
+
+impl QuantileSketch {
+    /// Construct an empty sketch with at most `max` centroids.
+    pub fn new(max: usize) -> Self {
+        assert!(max > 1);
+        QuantileSketch {
+            max,
+            centroids: Vec::new(),
+        }
+    }
+
+    /// The number of values in the sketch.
+    pub fn count(&self) -> u64 {
+        self.centroids.iter().map(|&(_, w)| w).sum()
+    }
+
+    /// Add `v` to the sketch.
+    pub fn insert(&mut self, v: f64) {
+        let i = self.centroids.iter().take_while(|&&(m, _)| m < v).count();
+        self.centroids.insert(i, (v, 1));
+        self.compress();
+    }
+
+    /// Add all the values in `other` to this sketch.
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        self.centroids.extend_from_slice(&other.centroids);
+        self.centroids
+            .sort_by(|a, b| a.0.partial_cmp(&b.0).expect("sketched NaN"));
+        self.compress();
+    }
+
+    /// Estimate the `q`th quantile of the sketched values, or `None` if the sketch is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&q));
+        let target = q * self.count() as f64;
+        let mut seen = 0;
+        for &(m, w) in &self.centroids {
+            seen += w;
+            if seen as f64 >= target {
+                return Some(m);
+            }
+        }
+        self.centroids.last().map(|&(m, _)| m)
+    }
+
+    /// Encode this sketch as a value that can be stored in a column.
+    pub fn to_datatype(&self) -> DataType {
+        serde_json::to_string(self).unwrap().into()
+    }
+
+    /// Decode a sketch stored in a column, or `None` if `v` does not hold a sketch.
+    pub fn from_datatype(v: &DataType) -> Option<Self> {
+        match *v {
+            DataType::Text(..) | DataType::TinyText(..) => {
+                let s: &str = v.into();
+                serde_json::from_str(s).ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn compress(&mut self) {
+        while self.centroids.len() > self.max {
+            let i = (0..self.centroids.len() - 1)
+                .min_by_key(|&i| self.centroids[i].1 + self.centroids[i + 1].1)
+                .unwrap();
+            let (m1, w1) = self.centroids[i];
+            let (m2, w2) = self.centroids.remove(i + 1);
+            let w = w1 + w2;
+            self.centroids[i] = ((m1 * w1 as f64 + m2 * w2 as f64) / w as f64, w);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_estimates_quantiles() {
+        let mut s = QuantileSketch::new(20);
+        assert_eq!(s.quantile(0.5), None);
+        for v in 1..=1000 {
+            s.insert(f64::from(v));
+        }
+        assert_eq!(s.count(), 1000);
+        assert!((s.quantile(0.5).unwrap() - 500.0).abs() <= 100.0);
+        assert!(s.quantile(1.0).unwrap() > 900.0);
+    }
+
+    #[test]
+    fn it_roundtrips_through_datatype() {
+        let mut s = QuantileSketch::new(4);
+        s.insert(1.5);
+        s.insert(-2.0);
+        assert_eq!(QuantileSketch::from_datatype(&s.to_datatype()), Some(s));
+        assert_eq!(QuantileSketch::from_datatype(&1.into()), None);
+    }
+}
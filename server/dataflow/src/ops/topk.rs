@@ -32,8 +32,9 @@ impl From<Vec<(usize, OrderType)>> for Order {
 /// TopK provides an operator that will produce the top k elements for each group.
 ///
 /// Positives are generally fast to process, while negative records can trigger expensive backwards
-/// queries. It is also worth noting that due the nature of Soup, the results of this operator are
-/// unordered.
+/// queries: when a row leaves a group that had k rows, the next best row has to be found in the
+/// ancestor's state, which is therefore kept materialized. It is also worth noting that due the
+/// nature of Soup, the results of this operator are unordered.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TopK {
     src: IndexPair,
@@ -52,9 +53,10 @@ pub struct TopK {
 impl TopK {
     /// Construct a new TopK operator.
     ///
-    /// `src` is this operator's ancestor, `over` is the column to compute the top K over,
-    /// `group_by` indicates the columns that this operator is keyed on, and k is the maximum number
-    /// of results per group.
+    /// `src` is this operator's ancestor, `order` gives the columns (and directions) to order rows
+    /// by, `group_by` indicates the columns that this operator is keyed on, and k is the maximum
+    /// number of results per group. The k rows that come *last* in `order` are kept, so ordering a
+    /// column with `OrderAscending` keeps the rows with the largest values in that column.
     pub fn new(
         src: NodeIndex,
         order: Vec<(usize, OrderType)>,
@@ -75,6 +77,48 @@ impl TopK {
             k,
         }
     }
+
+    /// Whether rows left a group that had `grpk` rows before this batch, in which case some of
+    /// the rows we left out of it may now belong in the top k.
+    ///
+    /// Rows we left out never need to come back otherwise, since each of them is worse than every
+    /// row we kept.
+    fn lost_rows(&self, current: &[(Cow<[DataType]>, bool)], grpk: usize) -> bool {
+        grpk == self.k && current.iter().filter(|&&(_, is_new)| !is_new).count() < grpk
+    }
+
+    /// Add the rows of group `grp` in our ancestor that are not already in `current` to it.
+    ///
+    /// The added rows are marked as new, since they are not in our output.
+    fn refill<'a>(
+        &self,
+        current: &mut Vec<(Cow<'a, [DataType]>, bool)>,
+        grp: &[DataType],
+        nodes: &DomainNodes,
+        state: &'a StateMap,
+    ) {
+        // our state for the group is present, so the ancestor's must be too: had the ancestor
+        // evicted the group, the eviction would have reached us as well.
+        let rs = self
+            .lookup(*self.src, &self.group_by, &KeyType::from(grp), nodes, state)
+            .expect("topk must have its ancestor's state materialized")
+            .expect("topk's ancestor is missing state for a group we hold");
+
+        // rows may repeat, so each one in current can only account for one in the ancestor
+        let mut matched = vec![false; current.len()];
+        let mut added = Vec::new();
+        for r in rs {
+            let known = current
+                .iter()
+                .zip(matched.iter_mut())
+                .find(|(c, m)| !**m && c.0 == r);
+            match known {
+                Some((_, m)) => *m = true,
+                None => added.push((r, true)),
+            }
+        }
+        current.extend(added);
+    }
 }
 
 impl Ingredient for TopK {
@@ -98,9 +142,10 @@ impl Ingredient for TopK {
         vec![self.src.as_global()]
     }
 
-    fn on_connected(&mut self, g: &Graph) {
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
         let srcn = &g[self.src.as_global()];
         self.cols = srcn.fields().len();
+        Ok(())
     }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
@@ -118,7 +163,7 @@ impl Ingredient for TopK {
         from: LocalNodeIndex,
         rs: Records,
         replay_key_cols: Option<&[usize]>,
-        _: &DomainNodes,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -159,38 +204,11 @@ impl Ingredient for TopK {
         let mut lookups = Vec::new();
 
         macro_rules! post_group {
-            ($out:ident, $current:ident, $grpk:expr, $k:expr, $order:expr) => {{
+            ($out:ident, $current:ident, $k:expr, $order:expr) => {{
                 $current.sort_unstable_by(|a, b| $order.cmp(&*a.0, &*b.0));
 
                 let start = $current.len().saturating_sub($k);
 
-                if $grpk == $k {
-                    if $current.len() < $grpk {
-                        // there used to be k things in the group
-                        // now there are fewer than k
-                        // we don't know if querying would bring us back to k
-                        unimplemented!();
-                    }
-
-                    // FIXME: if all the elements with the smallest value in the new topk are new,
-                    // then it *could* be that there exists some value that is greater than all
-                    // those values, and <= the smallest old value. we would only discover that by
-                    // querying. unfortunately, the check below isn't *quite* right because it does
-                    // not consider old rows that were removed in this batch (which should still be
-                    // counted for this condition).
-                    if false {
-                        let all_new_bottom = $current[start..]
-                            .iter()
-                            .take_while(|(ref r, _)| {
-                                $order.cmp(r, &$current[start].0) == Ordering::Equal
-                            })
-                            .all(|&(_, is_new)| is_new);
-                        if all_new_bottom {
-                            eprintln!("topk is guesstimating bottom row");
-                        }
-                    }
-                }
-
                 // optimization: if we don't *have to* remove something, we don't
                 for i in start..$current.len() {
                     if $current[i].1 {
@@ -229,7 +247,10 @@ impl Ingredient for TopK {
 
                 // first, tidy up the old one
                 if !grp.is_empty() {
-                    post_group!(out, current, grpk, self.k, self.order);
+                    if !missed && self.lost_rows(&current, grpk) {
+                        self.refill(&mut current, &grp, nodes, state);
+                    }
+                    post_group!(out, current, self.k, self.order);
                 }
 
                 // make ready for the new one
@@ -280,7 +301,10 @@ impl Ingredient for TopK {
             }
         }
         if !grp.is_empty() {
-            post_group!(out, current, grpk, self.k, self.order);
+            if !missed && self.lost_rows(&current, grpk) {
+                self.refill(&mut current, &grp, nodes, state);
+            }
+            post_group!(out, current, self.k, self.order);
         }
 
         ProcessingResult {
@@ -291,7 +315,14 @@ impl Ingredient for TopK {
     }
 
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        vec![(this, self.group_by.clone())].into_iter().collect()
+        // we need our own state to know the current top k, and our ancestor's to find the rows
+        // that replace any that leave it
+        vec![
+            (this, self.group_by.clone()),
+            (self.src.as_global(), self.group_by.clone()),
+        ]
+        .into_iter()
+        .collect()
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
@@ -395,7 +426,6 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn it_must_query() {
         let (mut g, s) = setup(false);
 
@@ -472,13 +502,98 @@ mod tests {
         assert!(a.iter().any(|r| r == &(r15.clone(), true).into()));
     }
 
+    #[test]
+    fn it_must_query_reversed() {
+        let (mut g, s) = setup(true);
+
+        let r12: Vec<DataType> = vec![1.into(), "z".into(), 12.into()];
+        let r10: Vec<DataType> = vec![2.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![3.into(), "z".into(), 11.into()];
+        let r5: Vec<DataType> = vec![4.into(), "z".into(), 5.into()];
+        let r15: Vec<DataType> = vec![5.into(), "z".into(), 15.into()];
+
+        // fill topk, which keeps the smallest values
+        g.narrow_one_row(r12.clone(), true);
+        g.narrow_one_row(r10.clone(), true);
+        g.narrow_one_row(r11.clone(), true);
+
+        // a smaller value displaces the current k-th row
+        let a = g.narrow_one_row(r5.clone(), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r12.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r5.clone(), true).into()));
+
+        // a larger one doesn't get in
+        let a = g.narrow_one_row(r15.clone(), true);
+        assert_eq!(a.len(), 0);
+
+        // removing a member brings back the best row that was left out
+        g.seed(s, r12.clone());
+        g.seed(s, r11.clone());
+        g.seed(s, r5.clone());
+        g.seed(s, r15.clone());
+        let a = g.narrow_one_row((r10.clone(), false), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r10.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r12.clone(), true).into()));
+        g.unseed(s);
+    }
+
+    #[test]
+    fn it_shrinks_when_nothing_is_left_out() {
+        let (mut g, s) = setup(false);
+        let ni = g.node().local_addr();
+
+        let r12: Vec<DataType> = vec![1.into(), "z".into(), 12.into()];
+        let r10: Vec<DataType> = vec![2.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![3.into(), "z".into(), 11.into()];
+
+        g.narrow_one_row(r12.clone(), true);
+        g.narrow_one_row(r10.clone(), true);
+        g.narrow_one_row(r11.clone(), true);
+
+        // the ancestor has no other rows, so the group simply loses one
+        g.seed(s, r12.clone());
+        g.seed(s, r10.clone());
+        let a = g.narrow_one_row((r11.clone(), false), true);
+        assert_eq!(a, vec![(r11.clone(), false)].into());
+        assert_eq!(g.states[ni].rows(), 2);
+    }
+
+    #[test]
+    fn it_must_query_when_a_batch_swaps_rows() {
+        let (mut g, s) = setup(false);
+
+        let r12: Vec<DataType> = vec![1.into(), "z".into(), 12.into()];
+        let r10: Vec<DataType> = vec![2.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![3.into(), "z".into(), 11.into()];
+        let r5: Vec<DataType> = vec![4.into(), "z".into(), 5.into()];
+        let r4: Vec<DataType> = vec![5.into(), "z".into(), 4.into()];
+
+        g.narrow_one_row(r12.clone(), true);
+        g.narrow_one_row(r10.clone(), true);
+        g.narrow_one_row(r11.clone(), true);
+        g.narrow_one_row(r5.clone(), true);
+
+        // the new row would fill the group back up to k, but the row we left out is better
+        g.seed(s, r10.clone());
+        g.seed(s, r11.clone());
+        g.seed(s, r5.clone());
+        g.seed(s, r4.clone());
+        let a = g.narrow_one(vec![(r12.clone(), false), (r4.clone(), true)], true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r12.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r5.clone(), true).into()));
+    }
+
     #[test]
     fn it_suggests_indices() {
-        let (g, _) = setup(false);
+        let (g, s) = setup(false);
         let me = 2.into();
         let idx = g.node().suggest_indexes(me);
-        assert_eq!(idx.len(), 1);
-        assert_eq!(*idx.iter().next().unwrap().1, vec![1]);
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![1]);
+        assert_eq!(idx[&s.as_global()], vec![1]);
     }
 
     #[test]
@@ -73,7 +73,9 @@ impl Ingredient for Trigger {
         vec![self.src.as_global()]
     }
 
-    fn on_connected(&mut self, _: &Graph) {}
+    fn on_connected(&mut self, _: &Graph) -> Result<(), String> {
+        Ok(())
+    }
 
     fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
         self.src.remap(remap);
@@ -1,7 +1,16 @@
 use slog::Logger;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::time;
 
+use crate::ops::filter;
+use crate::ops::sketch::QuantileSketch;
 use crate::prelude::*;
+use crate::state::{KeyedStore, SpillingStore};
+use noria::debug::stats::{Histogram, OpMetrics};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 enum Emit {
@@ -13,6 +22,14 @@ enum Emit {
         emit_l: BTreeMap<LocalNodeIndex, Vec<usize>>,
         cols: HashMap<IndexPair, usize>,
         cols_l: BTreeMap<LocalNodeIndex, usize>,
+
+        // whether each ancestor's records are emitted exactly as they are, so they can be passed
+        // through without copying any cells
+        identity: HashMap<IndexPair, bool>,
+
+        // unions with exactly two parents are by far the most common, so for those we keep both
+        // emit vectors inline to avoid a map lookup on every input.
+        pair: Option<[(LocalNodeIndex, Vec<usize>); 2]>,
     },
 }
 
@@ -29,7 +46,305 @@ enum FullWait {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct ReplayPieces {
     buffered: HashMap<LocalNodeIndex, Records>,
+    /// Further answers from ancestors that had already answered, say because the upquery was
+    /// retried. They are kept apart so that each answer numbers its copies of a row from zero.
+    repeated: Vec<(LocalNodeIndex, Records)>,
     evict: bool,
+    /// When the remaining pieces are expected to have arrived by, if replays have a deadline.
+    #[serde(skip)]
+    deadline: Option<time::Instant>,
+}
+
+/// A unit that timestamps can be expressed in, as a number of units since the epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeUnit {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimeUnit {
+    fn per_second(self) -> i64 {
+        match self {
+            TimeUnit::Seconds => 1,
+            TimeUnit::Millis => 1_000,
+            TimeUnit::Micros => 1_000_000,
+            TimeUnit::Nanos => 1_000_000_000,
+        }
+    }
+}
+
+/// How one union ancestor represents timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampFormat {
+    /// The unit the ancestor's timestamps are in.
+    pub unit: TimeUnit,
+    /// The number of seconds to add to the ancestor's timestamps to get UTC.
+    pub utc_offset: i64,
+}
+
+impl TimestampFormat {
+    /// Convert `ts` from this format to UTC in `unit`.
+    ///
+    /// Converting to a coarser unit rounds towards negative infinity. NULL is left as NULL.
+    pub fn normalize(&self, ts: &DataType, unit: TimeUnit) -> DataType {
+        if ts.is_none() {
+            return DataType::None;
+        }
+
+        let (from, to) = (self.unit.per_second(), unit.per_second());
+        let ts = i64::from(ts) + self.utc_offset * from;
+        if to >= from {
+            (ts * (to / from)).into()
+        } else {
+            ts.div_euclid(from / to).into()
+        }
+    }
+}
+
+/// How a union handles records from different ancestors that conflict in a released replay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// Keep the record that was assembled first.
+    FirstWins,
+    /// Keep the record that was assembled last.
+    LastWins,
+    /// Keep the record that was assembled first, and count the others as rejected, since
+    /// conflicting records indicate a bug upstream. See `Union::rejected_conflicts`.
+    Error,
+}
+
+/// Resolve positive records in `rs` that share the same values in the `key` columns, and return how
+/// many records were rejected under `ConflictPolicy::Error`.
+fn resolve_conflicts(key: &[usize], policy: ConflictPolicy, rs: &mut Records) -> u64 {
+    use std::collections::hash_map::Entry;
+
+    // records we've kept, with replaced ones set to None so that the rest keep their positions
+    let mut out: Vec<Option<Record>> = Vec::with_capacity(rs.len());
+    let mut kept = HashMap::new();
+    let mut rejected = 0;
+    for r in std::mem::take(rs) {
+        if !r.is_positive() {
+            out.push(Some(r));
+            continue;
+        }
+
+        let k: Vec<_> = key.iter().map(|&c| r[c].clone()).collect();
+        match kept.entry(k) {
+            Entry::Vacant(e) => {
+                e.insert(out.len());
+                out.push(Some(r));
+            }
+            Entry::Occupied(mut e) => match policy {
+                ConflictPolicy::FirstWins => {}
+                ConflictPolicy::LastWins => {
+                    out[*e.get()] = None;
+                    e.insert(out.len());
+                    out.push(Some(r));
+                }
+                ConflictPolicy::Error => rejected += 1,
+            },
+        }
+    }
+    rs.extend(out.into_iter().flatten());
+    rejected
+}
+
+/// A reason why a union cannot be constructed from a given set of emitted columns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnionError {
+    /// The union has no ancestors.
+    NoAncestors,
+    /// The union emits no columns from the given ancestor.
+    NoColumns(NodeIndex),
+    /// The union emits a column from an ancestor that only has the given number of columns.
+    ColumnOutOfRange {
+        src: NodeIndex,
+        col: usize,
+        columns: usize,
+    },
+    /// The union emits a different number of columns from the given ancestor than from others.
+    ArityMismatch {
+        src: NodeIndex,
+        columns: usize,
+        expected: usize,
+    },
+    /// The given ancestor was added to a `UnionBuilder` more than once.
+    DuplicateAncestor(NodeIndex),
+    /// The condition of a `Case` for the given ancestor is invalid.
+    InvalidCase { src: NodeIndex, error: String },
+    /// The union emits an empty range of columns from the given ancestor.
+    EmptyRange { src: NodeIndex, range: Range<usize> },
+}
+
+impl fmt::Display for UnionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnionError::NoAncestors => write!(f, "union has no ancestors"),
+            UnionError::NoColumns(src) => {
+                write!(f, "union emits no columns from ancestor {}", src.index())
+            }
+            UnionError::ColumnOutOfRange { src, col, columns } => write!(
+                f,
+                "union emits column {} from ancestor {}, which only has {} columns",
+                col,
+                src.index(),
+                columns
+            ),
+            UnionError::ArityMismatch {
+                src,
+                columns,
+                expected,
+            } => write!(
+                f,
+                "union emits {} columns from ancestor {}, but {} from its other ancestors",
+                columns,
+                src.index(),
+                expected
+            ),
+            UnionError::DuplicateAncestor(src) => {
+                write!(f, "union has ancestor {} more than once", src.index())
+            }
+            UnionError::EmptyRange { src, ref range } => write!(
+                f,
+                "union emits empty range {:?} from ancestor {}",
+                range,
+                src.index()
+            ),
+            UnionError::InvalidCase { src, ref error } => write!(
+                f,
+                "union has an invalid case for ancestor {}: {}",
+                src.index(),
+                error
+            ),
+        }
+    }
+}
+
+fn validate_emit(emit: &HashMap<NodeIndex, Vec<usize>>) -> Result<(), UnionError> {
+    if emit.is_empty() {
+        return Err(UnionError::NoAncestors);
+    }
+    for (&src, cols) in emit {
+        if cols.is_empty() {
+            return Err(UnionError::NoColumns(src));
+        }
+    }
+    validate_arity(emit.iter().map(|(&src, cols)| (src, cols.len())))
+}
+
+/// Check that the union emits the same number of columns from every ancestor.
+///
+/// The ancestor with the lowest index decides the expected number, so that the reported
+/// ancestor does not depend on iteration order.
+fn validate_arity(widths: impl Iterator<Item = (NodeIndex, usize)>) -> Result<(), UnionError> {
+    let mut widths: Vec<_> = widths.collect();
+    widths.sort();
+    let expected = match widths.first() {
+        Some(&(_, expected)) => expected,
+        None => return Ok(()),
+    };
+    match widths.into_iter().find(|&(_, columns)| columns != expected) {
+        Some((src, columns)) => Err(UnionError::ArityMismatch {
+            src,
+            columns,
+            expected,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// An output column of a union branch, as given to `Union::new_with_literals`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EmitCol {
+    /// Emit the given column of the ancestor.
+    Column(usize),
+    /// Emit the given value for every record from the ancestor.
+    Literal(DataType),
+    /// Emit NULL for every record from the ancestor, for columns the ancestor does not have.
+    Null,
+}
+
+/// How a union rewrites the values in a column that encode a missing value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NullMapping {
+    /// Replace any of these sentinel values with NULL.
+    ToNull(Vec<DataType>),
+    /// Replace NULL with this sentinel value.
+    FromNull(DataType),
+}
+
+impl NullMapping {
+    /// Rewrite `v` according to this mapping.
+    pub fn apply(&self, v: &mut DataType) {
+        match *self {
+            NullMapping::ToNull(ref sentinels) => {
+                if sentinels.contains(v) {
+                    *v = DataType::None;
+                }
+            }
+            NullMapping::FromNull(ref sentinel) => {
+                if v.is_none() {
+                    *v = sentinel.clone();
+                }
+            }
+        }
+    }
+}
+
+/// An output column whose value is chosen from one of two ancestor columns by a condition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Case {
+    /// The output column.
+    pub col: usize,
+    /// The condition, evaluated against the ancestor's record.
+    pub when: Vec<(usize, filter::FilterCondition)>,
+    /// The ancestor column to emit if the condition holds.
+    pub then: usize,
+    /// The ancestor column to emit otherwise.
+    pub otherwise: usize,
+}
+
+/// How a union treats the records of one of its ancestors, beyond which columns it emits.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Source {
+    /// Priority when several ancestors' records are released in one batch.
+    priority: usize,
+    /// The fraction of records to keep, if the ancestor is sampled.
+    sample_rate: Option<f64>,
+    /// How the ancestor represents the timestamp column, if not in the output unit.
+    timestamp_format: Option<TimestampFormat>,
+    /// Output columns whose value depends on the record, and the pre-built forms of their
+    /// conditions.
+    cases: Vec<(Case, Vec<Option<filter::Compiled>>)>,
+    /// Ancestor column holding the partition key of the ancestor's records, if forwarded.
+    partition_col: Option<usize>,
+    /// Rewrites of missing values in output columns.
+    null_mappings: Vec<(usize, NullMapping)>,
+    /// The schema version the ancestor's emit map was written for, and its columns.
+    schema_version: Option<(u64, Vec<String>)>,
+    /// Output columns that hold a constant rather than an ancestor column.
+    literals: Vec<(usize, DataType)>,
+}
+
+impl Source {
+    /// Whether the ancestor's records are emitted exactly the same way under `other`.
+    ///
+    /// Priorities and schema versions only affect the order records are emitted in, or whether
+    /// the union connects at all, so they are not compared.
+    fn emits_like(&self, other: &Source) -> bool {
+        self.sample_rate == other.sample_rate
+            && self.timestamp_format == other.timestamp_format
+            && self
+                .cases
+                .iter()
+                .map(|(c, _)| c)
+                .eq(other.cases.iter().map(|(c, _)| c))
+            && self.partition_col == other.partition_col
+            && self.null_mappings == other.null_mappings
+            && self.literals == other.literals
+    }
 }
 
 /// A union of a set of views.
@@ -51,6 +366,124 @@ pub struct Union {
     /// This map's key is really (Tag, Key, requesting_shard)
     replay_pieces: BTreeMap<(Tag, Vec<DataType>, usize), ReplayPieces>,
 
+    /// The maximum number of replay keys that may be assembled at any one time for a given tag
+    /// and requesting shard, if any.
+    replay_concurrency: Option<usize>,
+
+    /// Replay keys, per tag and requesting shard, that arrived while `replay_concurrency` keys
+    /// for that tag and shard were already admitted.
+    ///
+    /// Pieces for these keys are still kept in `replay_pieces`, since updates that race with the
+    /// replay must be folded into them, but they are not released until the key has been admitted
+    /// into a free slot. Keys are admitted in arrival order. Slots are only freed by releasing a
+    /// key of the same tag and shard, which is also the only replay the freed key can be released
+    /// with.
+    replay_deferred: HashMap<(Tag, usize), VecDeque<Vec<DataType>>>,
+
+    /// Whether to append a deterministic deduplication id to every emitted record.
+    dedup_ids: bool,
+
+    /// The number of regular (non-replay) records received from each ancestor so far.
+    ///
+    /// These are part of the operator rather than transient state: they are kept when the union
+    /// is cloned, and serialized along with it, so that ids are not handed out again.
+    offsets: HashMap<NodeIndex, u64>,
+
+    /// How many copies of each record from each ancestor the current replay has emitted so far.
+    replay_copies: HashMap<(NodeIndex, Vec<DataType>), u64>,
+
+    /// Output columns that identify a row, and how long a negative may be held back while
+    /// waiting for a positive it was reordered ahead of, if negatives are held back at all.
+    reorder: Option<(Vec<usize>, time::Duration)>,
+
+    /// For every key, how many positives have been forwarded and not yet revoked.
+    outstanding: HashMap<Vec<DataType>, usize>,
+
+    /// Negatives that are being held back by key, along with when each must be released.
+    pending_negatives: HashMap<Vec<DataType>, VecDeque<(Vec<DataType>, time::Instant)>>,
+
+    /// Output columns to group every emitted batch by, if any.
+    group_output: Option<Vec<usize>>,
+
+    /// The largest batch a shard merger sorts to make its output order deterministic, if any.
+    deterministic_merge: Option<usize>,
+
+    /// Whether to record how long each batch takes to process.
+    track_latency: bool,
+
+    /// Processing time of each batch, if `track_latency` is set.
+    latency: Histogram,
+
+    /// Output columns the query compiler expects this union to produce, if known.
+    schema: Option<Vec<String>>,
+
+    /// How records from each ancestor are treated, by global index as given, and by local index
+    /// once the union has been committed.
+    sources: HashMap<NodeIndex, Source>,
+    sources_l: HashMap<LocalNodeIndex, Source>,
+
+    /// For every key, the latest row from each shard, and the merged row last emitted.
+    shard_sketches:
+        HashMap<Vec<DataType>, (BTreeMap<LocalNodeIndex, Vec<DataType>>, Vec<DataType>)>,
+
+    /// Output columns that decide whether a record is sampled. Ancestors without a sampling rate
+    /// keep every record.
+    sample_key: Vec<usize>,
+
+    /// The widest row this union may emit, whether to also check every emitted row, and how many
+    /// rows that check has dropped.
+    max_width: Option<(usize, bool)>,
+    too_wide: u64,
+
+    /// The longest text value, in bytes, that projection emits, and how many values have been
+    /// truncated to fit it.
+    max_text_len: Option<usize>,
+    truncated: u64,
+
+    /// Output column that holds a timestamp, and the unit it is emitted in. Ancestors without a
+    /// timestamp format are assumed to already use the output unit.
+    timestamp: Option<(usize, TimeUnit)>,
+
+    /// For each ancestor, how many partial replay pieces it has sent, and how many of those have
+    /// been released because the pieces from every other ancestor arrived too.
+    replay_completion: HashMap<LocalNodeIndex, (u64, u64)>,
+
+    /// Whether to drop duplicate records from replays, and the dedup ids a full replay has emitted.
+    dedup_replays: bool,
+    replay_seen: HashSet<DataType>,
+
+    /// How long a replay key may wait for pieces from all ancestors, if there is a limit.
+    replay_deadline: Option<time::Duration>,
+
+    /// Output columns that identify a record in a released replay, and how to handle conflicts.
+    conflicts: Option<(Vec<usize>, ConflictPolicy)>,
+    /// The number of conflicting records dropped under `ConflictPolicy::Error` so far.
+    rejected_conflicts: u64,
+
+    /// Key columns and the column holding a `QuantileSketch`, if a shard merger merges sketches.
+    sketch_merge: Option<(Vec<usize>, usize)>,
+
+    /// Duplicate rate at which to start deduplicating output, and how many records to observe first.
+    adaptive_distinct: Option<(f64, u64)>,
+
+    /// Whether output is deduplicated, how many copies of each row have been emitted, and how
+    /// many positive records and duplicates of those have been observed.
+    distinct: bool,
+    #[serde(skip, default = "copies_in_memory")]
+    emitted_copies: Box<dyn KeyedStore<usize>>,
+    observed_duplicates: (u64, u64),
+
+    /// How many rows' copy counts to keep in memory before spilling the rest to disk, if bounded.
+    spill_budget: Option<usize>,
+
+    /// Ancestors whose first record has been checked to hold every column the union reads, and
+    /// how many batches have been dropped because their first record did not.
+    validated: HashSet<LocalNodeIndex>,
+    too_narrow: u64,
+
+    /// The number of replay keys released after all their pieces arrived.
+    replay_releases: u64,
+
     required: usize,
 
     full_wait_state: FullWait,
@@ -58,778 +491,4533 @@ pub struct Union {
     me: Option<NodeIndex>,
 }
 
+fn copies_in_memory() -> Box<dyn KeyedStore<usize>> {
+    Box::new(HashMap::new())
+}
+
+fn copy_store(budget: Option<usize>) -> Box<dyn KeyedStore<usize>> {
+    match budget {
+        Some(budget) => Box::new(SpillingStore::new(budget)),
+        None => copies_in_memory(),
+    }
+}
+
 impl Clone for Union {
     fn clone(&self) -> Self {
+        // the configuration carries over, but the state of replays, counters, and the like does not
         Union {
-            emit: self.emit.clone(),
-            required: self.required,
-            replay_key: Default::default(),
-            replay_pieces: Default::default(),
-            full_wait_state: FullWait::None,
+            replay_concurrency: self.replay_concurrency,
+            dedup_ids: self.dedup_ids,
+            offsets: self.offsets.clone(),
+            reorder: self.reorder.clone(),
+            group_output: self.group_output.clone(),
+            deterministic_merge: self.deterministic_merge,
+            track_latency: self.track_latency,
+            schema: self.schema.clone(),
+            sources: self.sources.clone(),
+            sources_l: self.sources_l.clone(),
+            sample_key: self.sample_key.clone(),
+            max_width: self.max_width,
+            max_text_len: self.max_text_len,
+            timestamp: self.timestamp,
+            dedup_replays: self.dedup_replays,
+            replay_deadline: self.replay_deadline,
+            conflicts: self.conflicts.clone(),
+            sketch_merge: self.sketch_merge.clone(),
+            adaptive_distinct: self.adaptive_distinct,
+            // adaptive unions start out passing duplicates through again
+            distinct: self.distinct && self.adaptive_distinct.is_none(),
+            emitted_copies: copy_store(self.spill_budget),
+            spill_budget: self.spill_budget,
+            me: self.me,
+            ..Union::with_emit(self.emit.clone(), self.required)
+        }
+    }
+}
+
+/// Assembles the columns a union emits from each of its ancestors one ancestor at a time.
+///
+/// This is useful when ancestors are discovered incrementally; `Union::new` is equivalent to
+/// adding every entry of its map and then calling `build`.
+#[derive(Clone, Debug, Default)]
+pub struct UnionBuilder {
+    emit: HashMap<NodeIndex, Vec<usize>>,
+    duplicate: Option<NodeIndex>,
+}
+
+impl UnionBuilder {
+    /// Construct a builder for a union with no ancestors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emit the columns `emit` of `node` for every record the union receives from it.
+    ///
+    /// Adding the same ancestor twice makes `build` fail.
+    pub fn add_ancestor(&mut self, node: NodeIndex, emit: Vec<usize>) -> &mut Self {
+        if self.emit.insert(node, emit).is_some() && self.duplicate.is_none() {
+            self.duplicate = Some(node);
+        }
+        self
+    }
 
-            me: self.me.clone(),
+    /// Construct the union, returning an error if the ancestors added so far are invalid.
+    ///
+    /// See `Union::new` for what makes a union valid.
+    pub fn build(&self) -> Result<Union, UnionError> {
+        if let Some(src) = self.duplicate {
+            return Err(UnionError::DuplicateAncestor(src));
         }
+        validate_emit(&self.emit)?;
+        Ok(Union::build(self.emit.clone()))
     }
 }
 
 impl Union {
-    /// Construct a new union operator.
+    /// Construct a new union operator, returning an error if `emit` is invalid.
     ///
     /// When receiving an update from node `a`, a union will emit the columns selected in `emit[a]`.
-    /// `emit` only supports omitting columns, not rearranging them.
-    pub fn new(emit: HashMap<NodeIndex, Vec<usize>>) -> Union {
-        assert!(!emit.is_empty());
-        for emit in emit.values() {
-            let mut last = &emit[0];
-            for i in emit {
-                if i < last {
-                    unimplemented!(
-                        "union doesn't support column reordering; got emit = {:?}",
-                        emit
+    /// Columns are emitted in exactly the order given, so `emit` may omit, reorder, and repeat
+    /// columns. A repeated column, as in `[0, 0, 1]`, fans a single ancestor column out into
+    /// several output columns, each of which resolves back to it.
+    ///
+    /// Every ancestor must have the same number of columns emitted. Whether the emitted columns
+    /// exist can only be checked once the ancestors are known; see `validate_columns`.
+    pub fn new(emit: HashMap<NodeIndex, Vec<usize>>) -> Result<Union, UnionError> {
+        let mut builder = UnionBuilder::new();
+        for (src, cols) in emit {
+            builder.add_ancestor(src, cols);
+        }
+        builder.build()
+    }
+
+    /// Construct a new union operator like `new`, but panic if `emit` is invalid.
+    ///
+    /// The structure of `emit` is only validated in debug builds.
+    pub fn new_unchecked(emit: HashMap<NodeIndex, Vec<usize>>) -> Union {
+        if cfg!(debug_assertions) {
+            if let Err(e) = validate_emit(&emit) {
+                panic!("{}", e);
+            }
+        }
+        Union::build(emit)
+    }
+
+    /// Construct a new union operator that emits the columns in the given ranges of each ancestor.
+    ///
+    /// This is equivalent to calling `new` with every range expanded into its column indices. The
+    /// ranges of each ancestor must be non-empty, but may be in any order. Like with `new`, columns
+    /// are checked against each ancestor's arity once the union is connected.
+    pub fn new_ranges(emit: HashMap<NodeIndex, Vec<Range<usize>>>) -> Result<Union, UnionError> {
+        let emit = emit
+            .into_iter()
+            .map(|(src, ranges)| {
+                if let Some(range) = ranges.iter().find(|r| r.start >= r.end) {
+                    return Err(UnionError::EmptyRange {
+                        src,
+                        range: range.clone(),
+                    });
+                }
+                Ok((src, ranges.into_iter().flatten().collect()))
+            })
+            .collect::<Result<_, _>>()?;
+        Union::new(emit)
+    }
+
+    /// Construct a new union operator with UNION DISTINCT semantics.
+    ///
+    /// Columns are selected from each ancestor like with `new`, but each distinct output row is
+    /// emitted only once, however many copies of it the ancestors hold. The union counts the
+    /// copies of every row, and retracts a row only once its last copy is removed.
+    pub fn new_distinct(emit: HashMap<NodeIndex, Vec<usize>>) -> Result<Union, UnionError> {
+        let mut u = Union::new(emit)?;
+        u.distinct = true;
+        Ok(u)
+    }
+
+    /// Construct a new union operator whose branches may emit constants as well as columns.
+    ///
+    /// This is like `new`, except that each output column of a branch is either a column of its
+    /// ancestor, or a literal value emitted for every record from that ancestor, such as a tag
+    /// identifying the branch, or NULL where the ancestor lacks a column that other branches have.
+    /// Literal columns have no parent column, so a column that is a literal in any branch does not
+    /// resolve, and partial replays cannot be keyed on it.
+    pub fn new_with_literals(emit: HashMap<NodeIndex, Vec<EmitCol>>) -> Result<Union, UnionError> {
+        let mut sources = HashMap::new();
+        let emit = emit
+            .into_iter()
+            .map(|(src, cols)| {
+                let mut lits = Vec::new();
+                let cols = cols
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, col)| match col {
+                        EmitCol::Column(c) => c,
+                        EmitCol::Literal(v) => {
+                            lits.push((i, v));
+                            // every ancestor has a first column, and the value is replaced anyway
+                            0
+                        }
+                        EmitCol::Null => {
+                            lits.push((i, DataType::None));
+                            0
+                        }
+                    })
+                    .collect();
+                if !lits.is_empty() {
+                    sources.insert(
+                        src,
+                        Source {
+                            literals: lits,
+                            ..Source::default()
+                        },
                     );
                 }
-                last = i;
+                (src, cols)
+            })
+            .collect();
+
+        let mut u = Union::new(emit)?;
+        u.sources = sources;
+        Ok(u)
+    }
+
+    /// The options given for ancestor `src`, if any.
+    fn source(&self, src: IndexPair) -> Option<&Source> {
+        self.sources.get(&src.as_global())
+    }
+
+    fn literal_of(&self, src: IndexPair, col: usize) -> Option<&DataType> {
+        self.source(src)
+            .and_then(|s| s.literals.iter().find(|&&(c, _)| c == col))
+            .map(|(_, v)| v)
+    }
+
+    /// Check that this union emits the same number of columns from every ancestor, and that every
+    /// column it emits exists in its ancestor in `g`.
+    ///
+    /// The union checks this itself when it is connected, and then fails to connect, so a
+    /// migration that adds a mis-specified union gets the error back from `try_add_ingredient`.
+    pub fn validate_columns(&self, g: &Graph) -> Result<(), UnionError> {
+        if let Emit::Project { ref emit, .. } = self.emit {
+            validate_arity(emit.iter().map(|(src, cols)| (src.as_global(), cols.len())))?;
+            for (src, emit) in emit {
+                let columns = g[src.as_global()].fields().len();
+                if let Some(&col) = emit.iter().find(|&&c| c >= columns) {
+                    return Err(UnionError::ColumnOutOfRange {
+                        src: src.as_global(),
+                        col,
+                        columns,
+                    });
+                }
             }
         }
+        Ok(())
+    }
+
+    fn build(emit: HashMap<NodeIndex, Vec<usize>>) -> Union {
         let emit: HashMap<_, _> = emit.into_iter().map(|(k, v)| (k.into(), v)).collect();
         let parents = emit.len();
-        Union {
-            emit: Emit::Project {
+        Union::with_emit(
+            Emit::Project {
                 emit,
                 emit_l: BTreeMap::new(),
                 cols: HashMap::new(),
                 cols_l: BTreeMap::new(),
+                identity: HashMap::new(),
+                pair: None,
             },
-            required: parents,
-            replay_key: Default::default(),
-            replay_pieces: Default::default(),
-            full_wait_state: FullWait::None,
-            me: None,
-        }
+            parents,
+        )
     }
 
     /// Construct a new union operator meant to de-shard a sharded data-flow subtree.
     pub fn new_deshard(parent: NodeIndex, sharding: Sharding) -> Union {
         let shards = sharding.shards().unwrap();
+        Union::with_emit(Emit::AllFrom(parent.into(), sharding), shards)
+    }
+
+    /// Construct a union that emits `emit` once it has heard from `required` ancestors or shards,
+    /// with every option turned off.
+    fn with_emit(emit: Emit, required: usize) -> Union {
         Union {
-            emit: Emit::AllFrom(parent.into(), sharding),
-            required: shards,
+            emit,
+            required,
             replay_key: Default::default(),
             replay_pieces: Default::default(),
+            replay_concurrency: None,
+            replay_deferred: Default::default(),
+            dedup_ids: false,
+            offsets: Default::default(),
+            replay_copies: Default::default(),
+            reorder: None,
+            outstanding: Default::default(),
+            pending_negatives: Default::default(),
+            group_output: None,
+            deterministic_merge: None,
+            track_latency: false,
+            latency: Default::default(),
+            schema: None,
+            sources: HashMap::new(),
+            sources_l: HashMap::new(),
+            shard_sketches: HashMap::new(),
+            sample_key: Vec::new(),
+            max_width: None,
+            too_wide: 0,
+            max_text_len: None,
+            truncated: 0,
+            timestamp: None,
+            replay_completion: Default::default(),
+            dedup_replays: false,
+            replay_seen: Default::default(),
+            replay_deadline: None,
+            conflicts: None,
+            rejected_conflicts: 0,
+            sketch_merge: None,
+            adaptive_distinct: None,
+            distinct: false,
+            emitted_copies: copies_in_memory(),
+            observed_duplicates: (0, 0),
+            spill_budget: None,
+            validated: HashSet::new(),
+            too_narrow: 0,
+            replay_releases: 0,
             full_wait_state: FullWait::None,
             me: None,
         }
     }
 
-    pub fn is_shard_merger(&self) -> bool {
-        if let Emit::AllFrom(..) = self.emit {
-            true
-        } else {
-            false
-        }
+    /// Limit the number of replay keys that may be assembled at the same time for each tag and
+    /// requesting shard.
+    ///
+    /// Replay pieces for keys beyond this limit are held back, and the key is not released until
+    /// earlier keys for the same tag and shard have completed and freed up a slot.
+    pub fn with_replay_concurrency(mut self, max: usize) -> Union {
+        assert!(max > 0);
+        self.replay_concurrency = Some(max);
+        self
     }
-}
 
-impl Ingredient for Union {
-    fn take(&mut self) -> NodeOperator {
-        Clone::clone(self).into()
+    /// Append a deduplication id to every record this union emits.
+    ///
+    /// The id is a fixed hash of the record's source, its offset among the regular records
+    /// received from that source, and its contents, so the same input always yields the same id.
+    /// This lets a downstream consumer that crashes and re-applies output skip records it has
+    /// already seen. Replays do not advance the offsets; a replayed record's id is instead derived
+    /// from how many copies of it the same replay has already emitted from the same source.
+    pub fn with_dedup_ids(mut self) -> Union {
+        assert!(
+            !self.is_shard_merger(),
+            "shard mergers do not project, and cannot add a dedup column"
+        );
+        self.dedup_ids = true;
+        self
     }
 
-    fn ancestors(&self) -> Vec<NodeIndex> {
+    /// The number of columns this union emits, not counting any dedup or partition key column.
+    fn emitted_columns(&self) -> Option<usize> {
         match self.emit {
-            Emit::AllFrom(p, _) => vec![p.as_global()],
-            Emit::Project { ref emit, .. } => emit.keys().map(IndexPair::as_global).collect(),
+            Emit::AllFrom(..) => None,
+            Emit::Project { ref emit, .. } => emit.values().next().map(Vec::len),
         }
     }
 
-    fn probe(&self) -> HashMap<String, String> {
-        let mut hm = HashMap::new();
-        hm.insert("captured".into(), format!("{}", self.replay_pieces.len()));
-        hm
-    }
-    fn on_connected(&mut self, g: &Graph) {
-        if let Emit::Project {
-            ref mut cols,
-            ref emit,
-            ..
-        } = self.emit
-        {
-            cols.extend(emit.keys().map(|&n| (n, g[n.as_global()].fields().len())));
+    /// The output column that holds the partition key, if records carry one.
+    fn partition_column(&self) -> Option<usize> {
+        if !self.sources.values().any(|s| s.partition_col.is_some()) {
+            return None;
         }
+        self.emitted_columns().map(|n| n + self.dedup_ids as usize)
     }
 
-    fn on_commit(&mut self, me: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
-        self.me = Some(me);
-        match self.emit {
-            Emit::Project {
-                ref mut emit,
-                ref mut cols,
-                ref mut emit_l,
-                ref mut cols_l,
-            } => {
-                let mapped_emit = emit
-                    .drain()
-                    .map(|(mut k, v)| {
-                        k.remap(remap);
-                        emit_l.insert(*k, v.clone());
-                        (k, v)
-                    })
-                    .collect();
-                let mapped_cols = cols
-                    .drain()
-                    .map(|(mut k, v)| {
-                        k.remap(remap);
-                        cols_l.insert(*k, v);
-                        (k, v)
-                    })
-                    .collect();
-                *emit = mapped_emit;
-                *cols = mapped_cols;
-            }
-            Emit::AllFrom(ref mut p, _) => {
-                p.remap(remap);
-            }
-        }
+    /// Hold back negatives for up to `window` in case their positive was delayed.
+    ///
+    /// Some upstreams may deliver a negative before the positive it revokes. With this enabled,
+    /// the union counts the positives it forwards for each value of the output columns `key`. A
+    /// negative for a key with no such positive outstanding is held back rather than forwarded.
+    /// If an identical positive arrives within the window, the two cancel out and neither is
+    /// emitted. Otherwise, the negative is forwarded once the window has passed, either with the
+    /// next batch the union processes, or when `expire_negatives` is called, which the owner of
+    /// the union should do periodically if batches may be far apart.
+    pub fn with_negative_reordering(mut self, key: Vec<usize>, window: time::Duration) -> Union {
+        assert!(!key.is_empty());
+        self.reorder = Some((key, window));
+        self
     }
 
-    fn on_input(
-        &mut self,
-        _: &mut dyn Executor,
-        from: LocalNodeIndex,
-        rs: Records,
-        _: Option<&[usize]>,
-        _: &DomainNodes,
-        _: &StateMap,
-    ) -> ProcessingResult {
-        match self.emit {
-            Emit::AllFrom(..) => ProcessingResult {
-                results: rs,
-                ..Default::default()
-            },
-            Emit::Project { ref emit_l, .. } => {
-                let rs = rs
-                    .into_iter()
-                    .map(move |rec| {
-                        let (r, pos) = rec.extract();
-
-                        // yield selected columns for this source
-                        // TODO: if emitting all in same order then avoid clone
-                        let res = emit_l[&from].iter().map(|&col| r[col].clone()).collect();
+    /// Pair up positives with held-back negatives, hold back negatives that have no positive to
+    /// revoke, and release any negatives whose window has passed by `now`.
+    fn correct_signs(&mut self, rs: Records, now: time::Instant) -> Records {
+        let (key, window) = match self.reorder {
+            Some((ref key, window)) => (key, window),
+            None => return rs,
+        };
 
-                        // return new row with appropriate sign
-                        if pos {
-                            Record::Positive(res)
-                        } else {
-                            Record::Negative(res)
+        let mut out = Vec::with_capacity(rs.len());
+        for r in rs {
+            let (row, positive) = r.extract();
+            let k: Vec<_> = key.iter().map(|&c| row[c].clone()).collect();
+            if positive {
+                let pending = self.pending_negatives.get_mut(&k);
+                let paired = pending.and_then(|pending| {
+                    let i = pending.iter().position(|(r, _)| r == &row)?;
+                    pending.remove(i);
+                    Some(pending.is_empty())
+                });
+                match paired {
+                    Some(drained) => {
+                        if drained {
+                            self.pending_negatives.remove(&k);
                         }
-                    })
-                    .collect();
-                ProcessingResult {
-                    results: rs,
-                    ..Default::default()
+                    }
+                    None => {
+                        *self.outstanding.entry(k).or_insert(0) += 1;
+                        out.push(Record::Positive(row));
+                    }
+                }
+            } else {
+                match self.outstanding.get_mut(&k) {
+                    Some(n) => {
+                        *n -= 1;
+                        if *n == 0 {
+                            self.outstanding.remove(&k);
+                        }
+                        out.push(Record::Negative(row));
+                    }
+                    None => self
+                        .pending_negatives
+                        .entry(k)
+                        .or_insert_with(VecDeque::new)
+                        .push_back((row, now + window)),
                 }
             }
         }
+
+        out.extend(self.expire_negatives(now));
+        out.into()
     }
 
-    fn on_input_raw(
+    /// Count the positives in a replay that this union forwarded, so that negatives for them are
+    /// not held back.
+    fn count_replayed(&mut self, rs: &Records) {
+        if let Some((ref key, _)) = self.reorder {
+            for r in rs.iter().filter(|r| r.is_positive()) {
+                let k = key.iter().map(|&c| r[c].clone()).collect();
+                *self.outstanding.entry(k).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Release every held-back negative whose window has passed by `now`.
+    ///
+    /// This happens whenever the union processes a regular batch, but the owner of the union may
+    /// also call this on a timer, so that negatives are not held back indefinitely when no more
+    /// updates arrive.
+    pub fn expire_negatives(&mut self, now: time::Instant) -> Records {
+        let mut out = Vec::new();
+        self.pending_negatives.retain(|_, pending| {
+            while pending.front().map(|&(_, d)| d <= now).unwrap_or(false) {
+                out.push(Record::Negative(pending.pop_front().unwrap().0));
+            }
+            !pending.is_empty()
+        });
+        out.into()
+    }
+
+    /// Group the records of every batch this union emits by the given output columns.
+    ///
+    /// Records with equal values in `key` will be contiguous in each emitted batch, and their
+    /// relative order is preserved. This lets a downstream grouped operator process each group as
+    /// a single run.
+    pub fn with_grouped_output(mut self, key: Vec<usize>) -> Union {
+        assert!(!key.is_empty());
+        self.group_output = Some(key);
+        self
+    }
+
+    /// Make the order in which a shard merger emits records independent of arrival order.
+    ///
+    /// Every batch of at most `max_batch` records is sorted by row content, and replay pieces from
+    /// different shards are released in order of shard index, so identical input produces
+    /// identical output regardless of how the shards' messages interleave. The sort is stable, so
+    /// a positive and a negative of the same row keep their relative order. Larger batches are
+    /// left in arrival order, since sorting them would be too costly.
+    pub fn with_deterministic_merge(mut self, max_batch: usize) -> Union {
+        assert!(
+            self.is_shard_merger(),
+            "only shard mergers can merge deterministically"
+        );
+        self.deterministic_merge = Some(max_batch);
+        self
+    }
+
+    /// Declare the output columns this union is expected to produce.
+    ///
+    /// When the union is connected, every ancestor's emitted columns are checked against the
+    /// declared schema, and a mismatch fails the connection with an error, so that it surfaces
+    /// while the graph is being built rather than as wrong query results. The dataflow graph doesn't track column types, so only
+    /// the arity of each ancestor's contribution, and that every emitted column exists, is checked.
+    pub fn with_declared_schema(mut self, columns: Vec<String>) -> Union {
+        assert!(!columns.is_empty());
+        self.schema = Some(columns);
+        self
+    }
+
+    /// Record that the emit map for `src` was written for version `version` of its schema, which
+    /// has the given columns.
+    ///
+    /// When the union is connected, `src`'s current columns are compared with those of the
+    /// recorded version. If they differ, connecting the union fails, rather than have it apply
+    /// the emit map to the wrong columns.
+    pub fn with_schema_version(
+        mut self,
+        src: NodeIndex,
+        version: u64,
+        columns: Vec<String>,
+    ) -> Union {
+        if let Emit::Project { ref emit, .. } = self.emit {
+            let emit = &emit[&IndexPair::from(src)];
+            assert!(
+                emit.iter().all(|&c| c < columns.len()),
+                "union emits {:?} from ancestor {}, but schema version {} only has {} columns",
+                emit,
+                src.index(),
+                version,
+                columns.len()
+            );
+        }
+        self.sources.entry(src).or_default().schema_version = Some((version, columns));
+        self
+    }
+
+    /// Give records from `src` priority `priority` when ordering emitted batches.
+    ///
+    /// When replay pieces from several ancestors are released together, the records of ancestors
+    /// with higher priority precede those with lower priority in the emitted batch. Ancestors
+    /// default to priority 0.
+    pub fn with_source_priority(mut self, src: NodeIndex, priority: usize) -> Union {
+        self.sources.entry(src).or_default().priority = priority;
+        self
+    }
+
+    fn priority_of(&self, from: LocalNodeIndex) -> usize {
+        self.sources_l.get(&from).map(|s| s.priority).unwrap_or(0)
+    }
+
+    /// Resolve output column `col` to a single ancestor column.
+    ///
+    /// `resolve` gives every ancestor an output column comes from, in no particular order. Callers
+    /// that need exactly one provenance, such as when picking an index to use, should use this
+    /// instead so that they consistently pick the same ancestor: the one with the lowest index.
+    pub fn resolve_primary(&self, col: usize) -> Option<(NodeIndex, usize)> {
+        self.resolve(col).and_then(|srcs| srcs.into_iter().min())
+    }
+
+    /// Forward the partition key of records from `src`, found in its column `col`, along with
+    /// every record.
+    ///
+    /// Once any ancestor has a partition key, every emitted record carries an extra last column,
+    /// after the dedup id if there is one, that holds the key, or NULL for records from ancestors
+    /// without a partition key. The key is there even if the union does not emit `col`, so that a
+    /// downstream re-sharder can shard by that column rather than recompute the key.
+    pub fn with_partition_key(mut self, src: NodeIndex, col: usize) -> Union {
+        assert!(
+            !self.is_shard_merger(),
+            "shard mergers do not project, and cannot add a partition key column"
+        );
+        self.sources.entry(src).or_default().partition_col = Some(col);
+        self
+    }
+
+    /// Only keep a `rate` fraction of the records from `src`.
+    ///
+    /// Whether a record is kept is decided deterministically from the values in its output columns
+    /// `key`, so a positive and a later negative for the same key are always either both kept or
+    /// both dropped. All sampled ancestors must use the same `key`.
+    pub fn with_sampling_rate(mut self, src: NodeIndex, key: Vec<usize>, rate: f64) -> Union {
+        assert!(!key.is_empty());
+        assert!(self.sample_key.is_empty() || self.sample_key == key);
+        assert!((0.0..=1.0).contains(&rate));
+        self.sample_key = key;
+        self.sources.entry(src).or_default().sample_rate = Some(rate);
+        self
+    }
+
+    /// Change the fraction of records from `from` that are kept.
+    ///
+    /// Note that records that were emitted at the old rate are not revoked, so a negative for such
+    /// a record may be dropped at the new rate (or vice versa).
+    pub fn set_sampling_rate(&mut self, from: LocalNodeIndex, rate: f64) {
+        assert!(
+            !self.sample_key.is_empty(),
+            "cannot change sampling rate of a union that does not sample"
+        );
+        assert!((0.0..=1.0).contains(&rate));
+        let src = match self.emit {
+            Emit::Project { ref emit, .. } => emit
+                .keys()
+                .find(|&src| **src == from)
+                .expect("cannot sample non-existing ancestor")
+                .as_global(),
+            Emit::AllFrom(..) => unreachable!("shard mergers do not sample"),
+        };
+        self.sources.entry(src).or_default().sample_rate = Some(rate);
+        self.sources_l.entry(from).or_default().sample_rate = Some(rate);
+    }
+
+    /// Estimate how many records this union holds given estimates for each of its ancestors.
+    ///
+    /// A union that keeps every record from every ancestor holds the sum of the ancestors'
+    /// estimates. A distinct union scales that sum by the fraction of the records it has seen so
+    /// far that were not duplicates, and by nothing if it has not seen any yet. Ancestors without
+    /// an estimate are assumed to be empty.
+    pub fn estimated_cardinality(&self, parent_estimates: &HashMap<NodeIndex, usize>) -> usize {
+        let n: usize = self
+            .ancestors()
+            .iter()
+            .filter_map(|src| parent_estimates.get(src))
+            .sum();
+        match self.observed_duplicates {
+            (seen, duplicates) if self.distinct && seen > 0 && n > 0 => {
+                let distinctness = 1.0 - duplicates as f64 / seen as f64;
+                ((n as f64 * distinctness).round() as usize).max(1)
+            }
+            _ => n,
+        }
+    }
+
+    /// Refuse to produce rows with more than `max` columns.
+    ///
+    /// The width of the projected output is checked when the union is connected, and a union that
+    /// is too wide fails to connect. If `at_runtime` is set, every emitted row is also checked as
+    /// it is produced, and rows that are too wide are dropped; their number is reported by
+    /// `too_wide`.
+    pub fn with_max_width(mut self, max: usize, at_runtime: bool) -> Union {
+        self.max_width = Some((max, at_runtime));
+        self
+    }
+
+    /// The number of rows dropped by `with_max_width` for being too wide so far.
+    pub fn too_wide(&self) -> u64 {
+        self.too_wide
+    }
+
+    /// The number of batches dropped so far because their first record lacked columns that the
+    /// union reads from its ancestor.
+    pub fn too_narrow(&self) -> u64 {
+        self.too_narrow
+    }
+
+    /// Truncate text values longer than `max` bytes when projecting records.
+    ///
+    /// Values are cut at the last character boundary at or before `max`, so that oversized
+    /// columns from an ancestor cannot blow up the rows stored downstream. Unions that forward
+    /// records without projecting them, like shard mergers, are not affected. The number of
+    /// truncated values is reported by `truncated`.
+    pub fn with_max_text_len(mut self, max: usize) -> Union {
+        self.max_text_len = Some(max);
+        self
+    }
+
+    /// The number of text values truncated by `with_max_text_len` so far.
+    pub fn truncated(&self) -> u64 {
+        self.truncated
+    }
+
+    /// Emit output column `col`, which holds a timestamp, as UTC in `unit`.
+    ///
+    /// Ancestors that represent timestamps differently should declare their format with
+    /// `with_timestamp_format`.
+    pub fn with_timestamp_column(mut self, col: usize, unit: TimeUnit) -> Union {
+        self.timestamp = Some((col, unit));
+        self
+    }
+
+    /// Declare how `src` represents the timestamp column given to `with_timestamp_column`.
+    pub fn with_timestamp_format(mut self, src: NodeIndex, format: TimestampFormat) -> Union {
+        self.sources.entry(src).or_default().timestamp_format = Some(format);
+        self
+    }
+
+    /// The fraction of partial replay pieces received from each ancestor that have been released.
+    ///
+    /// A piece is released once every other ancestor has sent its piece for the same key, so an
+    /// ancestor with a ratio consistently higher than the others is likely waiting on an upstream
+    /// that fails to respond.
+    pub fn replay_completion(&self) -> HashMap<LocalNodeIndex, f64> {
+        self.replay_completion
+            .iter()
+            .map(|(&from, &(sent, released))| (from, released as f64 / sent as f64))
+            .collect()
+    }
+
+    /// Drop records from replays whose dedup id the replay has already produced.
+    ///
+    /// Records are only considered the same if they are the same copy of a row from the same
+    /// ancestor, as when an ancestor answers the same upquery twice; equal rows from different
+    /// ancestors, or several copies of a row in one ancestor, are all kept. Regular updates are
+    /// passed through as-is, so this costs nothing outside of replays. A full replay remembers the
+    /// id of every record it has emitted until it finishes.
+    pub fn with_replay_deduplication(mut self) -> Union {
+        assert!(
+            self.dedup_ids,
+            "replays are deduplicated by dedup id, so call with_dedup_ids first"
+        );
+        self.dedup_replays = true;
+        self
+    }
+
+    /// Emit `case.col` for records from `src` as either `case.then` or `case.otherwise`, depending
+    /// on whether the record matches `case.when`.
+    ///
+    /// The condition is built once, here, so an invalid condition, like a regex condition whose
+    /// pattern doesn't compile, is returned as an error. Since the value of `case.col` depends on
+    /// the record, partial replays cannot be keyed on it.
+    pub fn with_case(mut self, src: NodeIndex, case: Case) -> Result<Union, UnionError> {
+        if let Emit::Project { ref emit, .. } = self.emit {
+            assert!(case.col < emit[&IndexPair::from(src)].len());
+        }
+        let compiled = case
+            .when
+            .iter()
+            .map(|(_, cond)| filter::Compiled::new(cond))
+            .collect::<Result<_, _>>()
+            .map_err(|error| UnionError::InvalidCase { src, error })?;
+        self.sources
+            .entry(src)
+            .or_default()
+            .cases
+            .push((case, compiled));
+        Ok(self)
+    }
+
+    fn case_of(&self, src: IndexPair, col: usize) -> Option<&Case> {
+        self.source(src)
+            .and_then(|s| s.cases.iter().find(|(case, _)| case.col == col))
+            .map(|(case, _)| case)
+    }
+
+    /// Give every replay key that starts buffering pieces a deadline of `timeout` from then.
+    ///
+    /// Keys that miss their deadline are reported by `expired_replays`, but are otherwise kept
+    /// buffered; it's up to the caller to re-request or evict them.
+    pub fn with_replay_deadline(mut self, timeout: time::Duration) -> Union {
+        self.replay_deadline = Some(timeout);
+        self
+    }
+
+    /// The keys of all buffered replays whose deadline is at or before `now`.
+    pub fn expired_replays(&self, now: time::Instant) -> Vec<Vec<DataType>> {
+        let mut keys: Vec<_> = self
+            .replay_pieces
+            .iter()
+            .filter(|(_, pieces)| pieces.deadline.map(|d| d <= now).unwrap_or(false))
+            .map(|((_, key, _), _)| key.clone())
+            .collect();
+        // the same key may be buffered for several tags or shards
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    /// Check that the records released for a partial replay are unique in the output columns
+    /// `key`, and resolve any conflicts according to `policy`.
+    ///
+    /// Pieces are assembled in descending order of ancestor priority (see
+    /// `with_source_priority`), with ties broken by ancestor index.
+    pub fn with_replay_conflicts(mut self, key: Vec<usize>, policy: ConflictPolicy) -> Union {
+        self.conflicts = Some((key, policy));
+        self
+    }
+
+    /// The number of conflicting records dropped from released replays under
+    /// `ConflictPolicy::Error` so far.
+    pub fn rejected_conflicts(&self) -> u64 {
+        self.rejected_conflicts
+    }
+
+    /// Merge the `QuantileSketch`es in column `col` of rows with the same `key` from different
+    /// shards into a single row.
+    ///
+    /// Each shard is expected to hold at most one row per key, such as the output of a sharded
+    /// aggregation. The merged row takes its other columns from the row of the lowest-numbered
+    /// shard, so they should be the same across shards. Only shard mergers can merge sketches.
+    pub fn with_sketch_merge(mut self, key: Vec<usize>, col: usize) -> Union {
+        assert!(
+            self.is_shard_merger(),
+            "only shard mergers can merge sketches"
+        );
+        assert!(
+            !key.contains(&col),
+            "sketch column cannot be part of the key"
+        );
+        self.sketch_merge = Some((key, col));
+        self
+    }
+
+    fn merge_sketches(&mut self, from: LocalNodeIndex, rs: Records) -> Records {
+        let (ref key, col) = *self.sketch_merge.as_ref().unwrap();
+
+        let mut touched = HashSet::new();
+        for r in rs {
+            let (r, positive) = r.extract();
+            let k: Vec<_> = key.iter().map(|&c| r[c].clone()).collect();
+            let shards = &mut self
+                .shard_sketches
+                .entry(k.clone())
+                .or_insert_with(|| (BTreeMap::new(), Vec::new()))
+                .0;
+            if positive {
+                shards.insert(from, r);
+            } else if shards.get(&from) == Some(&r) {
+                shards.remove(&from);
+            }
+            touched.insert(k);
+        }
+
+        let mut out = Vec::new();
+        for k in touched {
+            let (shards, emitted) = self.shard_sketches.get_mut(&k).unwrap();
+            let merged = shards.values().next().map(|first| {
+                let mut sketch: Option<QuantileSketch> = None;
+                for row in shards.values() {
+                    let s = QuantileSketch::from_datatype(&row[col])
+                        .expect("shard emitted a row without a sketch");
+                    match sketch {
+                        Some(ref mut sketch) => sketch.merge(&s),
+                        None => sketch = Some(s),
+                    }
+                }
+                let mut row = first.clone();
+                row[col] = sketch.unwrap().to_datatype();
+                row
+            });
+
+            if merged.as_ref() == Some(&*emitted) {
+                continue;
+            }
+            if !emitted.is_empty() {
+                out.push(Record::Negative(std::mem::take(emitted)));
+            }
+            match merged {
+                Some(row) => {
+                    *emitted = row.clone();
+                    out.push(Record::Positive(row));
+                }
+                None => {
+                    self.shard_sketches.remove(&k);
+                }
+            }
+        }
+        out.into()
+    }
+
+    /// Rewrite missing values in output column `col` of records from `src` using `mapping`.
+    pub fn with_null_mapping(mut self, src: NodeIndex, col: usize, mapping: NullMapping) -> Union {
+        if let Emit::Project { ref emit, .. } = self.emit {
+            assert!(col < emit[&IndexPair::from(src)].len());
+        }
+        self.sources
+            .entry(src)
+            .or_default()
+            .null_mappings
+            .push((col, mapping));
+        self
+    }
+
+    /// Start out passing duplicates through, but deduplicate output once at least `min_records`
+    /// positive records have been emitted and at least `threshold` of them were duplicates.
+    ///
+    /// Deciding this requires counting the copies of every row emitted, so that the surplus copies
+    /// emitted before the promotion can be retracted by `upgrade_to_distinct`.
+    pub fn with_adaptive_distinct(mut self, threshold: f64, min_records: u64) -> Union {
+        assert!((0.0..=1.0).contains(&threshold));
+        self.adaptive_distinct = Some((threshold, min_records));
+        self
+    }
+
+    /// Whether this union deduplicates its output.
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /// Whether `other` emits exactly the same records as this union for the same input, so that
+    /// a union with both of them as ancestors could read from just one of them instead.
+    ///
+    /// Both unions must project the same columns from the same ancestors, and rewrite, filter,
+    /// and deduplicate the records they emit in the same way. Settings that only affect when or
+    /// in what order records are emitted are ignored. Shard mergers are never flattenable.
+    pub fn is_flattenable_with(&self, other: &Union) -> bool {
+        fn by_ancestor<T>(map: &HashMap<IndexPair, T>) -> BTreeMap<NodeIndex, &T> {
+            // compare by global index, so that committed and uncommitted unions compare equal
+            map.iter().map(|(src, v)| (src.as_global(), v)).collect()
+        }
+
+        match (&self.emit, &other.emit) {
+            (Emit::Project { emit: ref a, .. }, Emit::Project { emit: ref b, .. }) => {
+                if by_ancestor(a) != by_ancestor(b) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+
+        let none = Source::default();
+        self.ancestors().into_iter().all(|src| {
+            let a = self.sources.get(&src).unwrap_or(&none);
+            a.emits_like(other.sources.get(&src).unwrap_or(&none))
+        }) && self.sample_key == other.sample_key
+            && self.timestamp == other.timestamp
+            && self.max_text_len == other.max_text_len
+            && self.distinct == other.distinct
+            && self.adaptive_distinct == other.adaptive_distinct
+            && self.dedup_ids == other.dedup_ids
+            && self.dedup_replays == other.dedup_replays
+            && self.conflicts == other.conflicts
+    }
+
+    /// Keep the copy counts of at most about `rows` distinct rows in memory, and spill the least
+    /// recently used counts to disk beyond that.
+    ///
+    /// This only matters for unions that count copies, i.e., distinct and adaptive unions. Spilled
+    /// counts are read back in when their row is next emitted or retracted.
+    pub fn with_spill_budget(mut self, rows: usize) -> Union {
+        self.spill_budget = Some(rows);
+        self.emitted_copies = copy_store(self.spill_budget);
+        self
+    }
+
+    /// Deduplicate output from now on, and retract every surplus copy of a row emitted so far.
+    ///
+    /// Replays are deduplicated too from then on, by row rather than by dedup id. If the
+    /// copy counts cannot be read back from disk, the union is left as it was.
+    pub fn upgrade_to_distinct(&mut self) -> io::Result<Records> {
+        assert!(
+            self.adaptive_distinct.is_some(),
+            "only adaptive unions count the rows they emit"
+        );
+        let surplus = self
+            .emitted_copies
+            .entries()?
+            .into_iter()
+            .flat_map(|(row, n)| (1..n).map(move |_| Record::Negative(row.clone())))
+            .collect();
+        self.distinct = true;
+        Ok(surplus)
+    }
+
+    /// Count the copies of every row in `rs`, drop copies beyond the first if this union is
+    /// distinct, and promote it to distinct if the observed duplicate rate calls for it.
+    ///
+    /// A row whose count cannot be read back from disk is passed through as-is, unless this union
+    /// is distinct, in which case it is dropped, since it cannot tell whether the row is a
+    /// duplicate. Negatives for rows that have no copies are never emitted by a distinct union.
+    fn count_copies(&mut self, rs: &mut Records, log: &Logger) {
+        if !self.distinct && self.adaptive_distinct.is_none() {
+            return;
+        }
+
+        let mut out = Vec::with_capacity(rs.len());
+        for r in rs.drain(..) {
+            let (row, positive) = r.extract();
+            let copies = match self.emitted_copies.get_mut(&row) {
+                Ok(Some(copies)) => *copies,
+                Ok(None) => 0,
+                Err(e) => {
+                    error!(log, "failed to read spilled union row count"; "error" => %e);
+                    if !self.distinct {
+                        out.push((row, positive).into());
+                    }
+                    continue;
+                }
+            };
+            let emit = if positive {
+                self.observed_duplicates.0 += 1;
+                if copies > 0 {
+                    self.observed_duplicates.1 += 1;
+                }
+                self.emitted_copies.insert(row.clone(), copies + 1);
+                copies == 0
+            } else if copies == 0 {
+                // a retraction of a row that was never counted, so there is nothing to retract
+                false
+            } else {
+                let gone = copies == 1;
+                if gone {
+                    // the count is in memory, since it was just read
+                    let _ = self.emitted_copies.remove(&row);
+                } else {
+                    self.emitted_copies.insert(row.clone(), copies - 1);
+                }
+                gone
+            };
+            if emit || !self.distinct {
+                out.push((row, positive).into());
+            }
+        }
+
+        if let (Some((threshold, min_records)), false) = (self.adaptive_distinct, self.distinct) {
+            let (seen, duplicates) = self.observed_duplicates;
+            if seen >= min_records && seen > 0 && duplicates as f64 / seen as f64 >= threshold {
+                match self.upgrade_to_distinct() {
+                    Ok(surplus) => out.extend(surplus),
+                    Err(e) => {
+                        error!(log, "failed to read spilled union row counts"; "error" => %e)
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = self.emitted_copies.flush() {
+            warn!(log, "failed to spill union row counts"; "error" => %e);
+        }
+        *rs = out.into();
+    }
+
+    /// Record the wall-clock time spent processing each batch this union receives.
+    pub fn with_latency_tracking(mut self) -> Union {
+        self.track_latency = true;
+        self
+    }
+
+    /// The distribution of time spent processing each batch so far.
+    ///
+    /// This is always empty unless latency tracking was enabled with `with_latency_tracking`.
+    pub fn latency_histogram(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// A snapshot of this union's counters as `(name, value)` records.
+    ///
+    /// Every counter is always reported, so that the snapshot can be diffed against an earlier
+    /// one, or fed into a view that monitors the union.
+    pub fn stats_records(&self) -> Records {
+        let metrics = Ingredient::metrics(self).unwrap();
+        let deferred = self
+            .replay_deferred
+            .values()
+            .map(VecDeque::len)
+            .sum::<usize>();
+        let pending = self
+            .pending_negatives
+            .values()
+            .map(VecDeque::len)
+            .sum::<usize>();
+        vec![
+            ("in flight keys", metrics.in_flight_keys),
+            ("buffered records", metrics.buffered_records),
+            ("releases", metrics.releases),
+            ("deferred keys", deferred as u64),
+            ("pending negatives", pending as u64),
+            ("truncated", self.truncated),
+            ("too wide", self.too_wide),
+            ("too narrow", self.too_narrow),
+            ("rejected conflicts", self.rejected_conflicts),
+            ("timed batches", self.latency.len()),
+        ]
+        .into_iter()
+        .map(|(name, value)| vec![name.into(), value.into()])
+        .collect::<Vec<Vec<DataType>>>()
+        .into()
+    }
+
+    /// Write a human-readable description of every partially replayed key this union is
+    /// currently buffering to `sink`, including which ancestors have and have not yet replied.
+    pub fn dump_buffered(&self, sink: &mut dyn io::Write) -> io::Result<()> {
+        let mut keyed: Vec<_> = self.replay_key.iter().collect();
+        keyed.sort();
+        for ((tag, shard), cols) in keyed {
+            writeln!(
+                sink,
+                "{:?} from shard {} is keyed on columns {:?}",
+                tag, shard, cols
+            )?;
+        }
+
+        let mut ancestors: Vec<_> = match self.emit {
+            Emit::Project { ref emit_l, .. } => emit_l.keys().cloned().collect(),
+            Emit::AllFrom(p, _) => vec![*p],
+        };
+        ancestors.sort();
+
+        for ((tag, key, shard), pieces) in &self.replay_pieces {
+            let (have, missing): (Vec<_>, Vec<_>) = ancestors
+                .iter()
+                .partition(|a| pieces.buffered.contains_key(*a));
+            let show = |ns: Vec<&LocalNodeIndex>| {
+                ns.into_iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            writeln!(
+                sink,
+                "{:?} key {:?} for shard {}: have [{}], waiting for [{}]{}",
+                tag,
+                key,
+                shard,
+                show(have),
+                show(missing),
+                if pieces.evict { " (evicted)" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The number of keys for `tag` and `requesting_shard` that are buffering pieces and have been
+    /// admitted, i.e., that are not deferred.
+    fn admitted(&self, tag: Tag, requesting_shard: usize) -> usize {
+        let buffering = self
+            .replay_pieces
+            .range((tag, Vec::new(), 0)..)
+            .take_while(|&(&(t, _, _), _)| t == tag)
+            .filter(|&(&(_, _, shard), _)| shard == requesting_shard)
+            .count();
+        let deferred = self
+            .replay_deferred
+            .get(&(tag, requesting_shard))
+            .map(VecDeque::len)
+            .unwrap_or(0);
+        buffering - deferred
+    }
+
+    /// Admit deferred replay keys for `tag` and `requesting_shard` into any free slots, and take
+    /// out those admitted keys that already have pieces from every ancestor.
+    fn admit_deferred(
         &mut self,
-        ex: &mut dyn Executor,
-        from: LocalNodeIndex,
-        rs: Records,
-        replay: ReplayContext,
-        n: &DomainNodes,
-        s: &StateMap,
-        log: &Logger,
-    ) -> RawProcessingResult {
-        use std::mem;
+        tag: Tag,
+        requesting_shard: usize,
+    ) -> Vec<(Vec<DataType>, ReplayPieces)> {
+        let max = match self.replay_concurrency {
+            Some(max) => max,
+            None => return Vec::new(),
+        };
+        let mut admitted = self.admitted(tag, requesting_shard);
+
+        let slot = (tag, requesting_shard);
+        let mut deferred = match self.replay_deferred.remove(&slot) {
+            Some(deferred) => deferred,
+            None => return Vec::new(),
+        };
+
+        let mut released = Vec::new();
+        while admitted < max {
+            let key = match deferred.pop_front() {
+                Some(key) => key,
+                None => break,
+            };
+            let k = (tag, key, requesting_shard);
+            if self.replay_pieces[&k].buffered.len() == self.required {
+                // complete already, so it can go out right away without holding on to the slot
+                let pieces = self.replay_pieces.remove(&k).unwrap();
+                released.push((k.1, pieces));
+            } else {
+                admitted += 1;
+            }
+        }
+
+        if !deferred.is_empty() {
+            self.replay_deferred.insert(slot, deferred);
+        }
+        released
+    }
+
+    /// The symbol that joins ancestors in this union's description: `⋃` for UNION ALL, and `⩁`
+    /// if the union only emits distinct records.
+    fn union_symbol(&self) -> &'static str {
+        if self.distinct {
+            "⩁"
+        } else {
+            "⋃"
+        }
+    }
+
+    pub fn is_shard_merger(&self) -> bool {
+        if let Emit::AllFrom(..) = self.emit {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Emit the records `rs` from the ancestor `from`, which are part of a replay if `replay` is
+    /// set.
+    fn project(&mut self, from: LocalNodeIndex, rs: Records, replay: bool) -> ProcessingResult {
+        let carry_partition = self.partition_column().is_some();
+        let mut results = match self.emit {
+            Emit::AllFrom(..) if self.sketch_merge.is_some() => self.merge_sketches(from, rs),
+            Emit::AllFrom(..) => {
+                let mut rs = rs;
+                if let Some(max) = self.deterministic_merge {
+                    if rs.len() <= max {
+                        rs.sort_by(|a, b| a[..].cmp(&b[..]));
+                    }
+                }
+                rs
+            }
+            Emit::Project {
+                ref emit_l,
+                ref emit,
+                ref identity,
+                ref pair,
+                ..
+            } => {
+                // yield selected columns for this source
+                let select = match *pair {
+                    Some([(a, ref emit_a), (b, ref emit_b)]) => {
+                        if from == a {
+                            emit_a
+                        } else if from == b {
+                            emit_b
+                        } else {
+                            unreachable!("union got records from unknown ancestor {}", from)
+                        }
+                    }
+                    None => &emit_l[&from],
+                };
+
+                // borrow only the field, since dedup below needs self.offsets mutably
+                let source = self.sources_l.get(&from);
+
+                let sample = source
+                    .and_then(|s| s.sample_rate)
+                    .filter(|&rate| rate < 1.0)
+                    .map(|rate| (&self.sample_key[..], rate));
+
+                let cases = source
+                    .map(|s| &s.cases[..])
+                    .filter(|cases| !cases.is_empty());
+
+                let literals = source
+                    .map(|s| &s.literals[..])
+                    .filter(|literals| !literals.is_empty());
+
+                let nulls = source
+                    .map(|s| &s.null_mappings[..])
+                    .filter(|nulls| !nulls.is_empty());
+
+                let partition = source.and_then(|s| s.partition_col);
+
+                let normalize = self.timestamp.and_then(|(col, unit)| {
+                    source
+                        .and_then(|s| s.timestamp_format)
+                        .map(|format| (col, format, unit))
+                });
+
+                let mut truncate = self.max_text_len.map(|max| (max, &mut self.truncated));
+
+                let mut dedup = if self.dedup_ids {
+                    let src = emit
+                        .keys()
+                        .find(|&src| **src == from)
+                        .map(IndexPair::as_global)
+                        .unwrap();
+                    Some((src, self.offsets.entry(src).or_insert(0)))
+                } else {
+                    None
+                };
+                let replay_copies = &mut self.replay_copies;
+
+                // catch records that are narrower than we expect on the first one from each
+                // ancestor, rather than with an index out of bounds somewhere in a batch
+                if !self.validated.contains(&from) {
+                    if let Some(first) = rs.get(0) {
+                        let case_cols = cases.into_iter().flatten().flat_map(|(case, _)| {
+                            case.when
+                                .iter()
+                                .map(|&(c, _)| c)
+                                .chain(vec![case.then, case.otherwise])
+                        });
+                        let reads = select.iter().cloned().chain(case_cols).chain(partition);
+                        if reads.max().map_or(false, |col| col >= first.len()) {
+                            // connecting checked the ancestor's columns, so this batch is
+                            // malformed; drop it rather than take down the domain
+                            self.too_narrow += 1;
+                            return ProcessingResult::default();
+                        }
+                        self.validated.insert(from);
+                    }
+                }
+
+                // records emitted exactly as they came in need no new row at all
+                let reuse = cases.is_none()
+                    && literals.is_none()
+                    && nulls.is_none()
+                    && normalize.is_none()
+                    && truncate.is_none()
+                    && dedup.is_none()
+                    && !carry_partition
+                    && identity
+                        .iter()
+                        .any(|(&ip, &identity)| *ip == from && identity);
+
+                rs.into_iter()
+                    .map(move |rec| {
+                        if reuse {
+                            return rec;
+                        }
+
+                        let (r, pos) = rec.extract();
+                        let mut res: Vec<_> = select.iter().map(|&col| r[col].clone()).collect();
+
+                        for (case, compiled) in cases.into_iter().flatten() {
+                            res[case.col] = if filter::evaluate(&case.when, compiled, &r).is_true()
+                            {
+                                r[case.then].clone()
+                            } else {
+                                r[case.otherwise].clone()
+                            };
+                        }
+
+                        for &(col, ref v) in literals.into_iter().flatten() {
+                            res[col] = v.clone();
+                        }
+
+                        for &(col, ref mapping) in nulls.into_iter().flatten() {
+                            mapping.apply(&mut res[col]);
+                        }
+
+                        if let Some((col, format, unit)) = normalize {
+                            res[col] = format.normalize(&res[col], unit);
+                        }
+
+                        if let Some((max, ref mut truncated)) = truncate {
+                            for v in &mut res {
+                                if truncate_text(v, max) {
+                                    **truncated += 1;
+                                }
+                            }
+                        }
+
+                        if let Some((src, ref mut offset)) = dedup {
+                            if replay {
+                                let copies = replay_copies.entry((src, r.clone())).or_insert(0);
+                                res.push(dedup_id(src, Position::Replayed(*copies), &r));
+                                *copies += 1;
+                            } else {
+                                res.push(dedup_id(src, Position::Offset(**offset), &r));
+                                **offset += 1;
+                            }
+                        }
+
+                        if carry_partition {
+                            res.push(partition.map(|c| r[c].clone()).unwrap_or(DataType::None));
+                        }
+
+                        // return new row with appropriate sign
+                        if pos {
+                            Record::Positive(res)
+                        } else {
+                            Record::Negative(res)
+                        }
+                    })
+                    .filter(|r| match sample {
+                        Some((key, rate)) => sampled(key, rate, r),
+                        None => true,
+                    })
+                    .collect()
+            }
+        };
+
+        if let Some(ref key) = self.group_output {
+            // sort_by is stable, so records keep their relative order within each group
+            results.sort_by(|a, b| key.iter().map(|&c| &a[c]).cmp(key.iter().map(|&c| &b[c])));
+        }
+
+        if let Some((max, true)) = self.max_width {
+            let before = results.len();
+            results.retain(|r| r.len() <= max);
+            self.too_wide += (before - results.len()) as u64;
+        }
+
+        ProcessingResult {
+            results,
+            ..Default::default()
+        }
+    }
+}
+
+/// A 64-bit FNV-1a hash over an encoding of values that is fixed here, rather than left to their
+/// `Hash` implementations, so that it is the same on every machine, and in every build and run.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    /// Hash `v` such that values that compare equal hash the same.
+    fn write_value(&mut self, v: &DataType) {
+        match *v {
+            DataType::None => self.write(&[0]),
+            DataType::Bool(b) => self.write(&[1, b as u8]),
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => {
+                self.write(&[2]);
+                self.write(&i128::from(v).to_le_bytes());
+            }
+            DataType::Real(i, f) => {
+                self.write(&[3]);
+                self.write(&i.to_le_bytes());
+                self.write(&f.to_le_bytes());
+            }
+            DataType::Decimal(mut m, mut scale) => {
+                // equal decimals may have different scales
+                while scale > 0 && m % 10 == 0 {
+                    m /= 10;
+                    scale -= 1;
+                }
+                self.write(&[4, scale]);
+                self.write(&m.to_le_bytes());
+            }
+            DataType::Text(..) | DataType::TinyText(..) => {
+                let s = <&str>::from(v);
+                self.write(&[5]);
+                self.write_u64(s.len() as u64);
+                self.write(s.as_bytes());
+            }
+            DataType::Timestamp(ts) => {
+                self.write(&[6]);
+                self.write(&ts.timestamp().to_le_bytes());
+                self.write(&ts.timestamp_subsec_nanos().to_le_bytes());
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Decide whether to keep the record `row` when sampling at `rate` by the columns in `key`.
+fn sampled(key: &[usize], rate: f64, row: &[DataType]) -> bool {
+    let mut hasher = StableHasher::new();
+    for &c in key {
+        hasher.write_value(&row[c]);
+    }
+    (hasher.finish() as f64) < rate * u64::max_value() as f64
+}
+
+/// Drop positive records from `rs` that are in `seen`, and add the remaining ones to it.
+fn dedup_replayed(seen: &mut HashSet<Vec<DataType>>, rs: &mut Records) {
+    rs.retain(|r| !r.is_positive() || seen.insert(r.to_vec()));
+}
+
+/// Drop positive records from `rs` whose dedup id in column `id` is in `seen`, and add the ids of
+/// the remaining ones to it.
+fn dedup_replayed_ids(seen: &mut HashSet<DataType>, id: usize, rs: &mut Records) {
+    rs.retain(|r| !r.is_positive() || seen.insert(r[id].clone()));
+}
+
+/// Cut the text value `v` down to at most `max` bytes, and report whether it was too long.
+fn truncate_text(v: &mut DataType, max: usize) -> bool {
+    let cut = match *v {
+        DataType::Text(..) | DataType::TinyText(..) => {
+            let s = <&str>::from(&*v);
+            if s.len() <= max {
+                return false;
+            }
+            let end = (0..=max).rev().find(|&i| s.is_char_boundary(i)).unwrap();
+            DataType::from(&s[..end])
+        }
+        _ => return false,
+    };
+    *v = cut;
+    true
+}
+
+/// Where a record falls among the records a union receives from one of its ancestors.
+#[derive(Clone, Copy, Debug)]
+enum Position {
+    /// The record's offset among the regular records received from the ancestor.
+    Offset(u64),
+    /// How many copies of the same record from the ancestor came before it in the same replay.
+    ///
+    /// Replayed records come from the ancestor's state rather than its stream of updates, so they
+    /// have no offset; this instead tells apart the copies of a row in that state.
+    Replayed(u64),
+}
+
+/// Compute the deduplication id for the record `row` received from `src` at `position`.
+fn dedup_id(src: NodeIndex, position: Position, row: &[DataType]) -> DataType {
+    let mut hasher = StableHasher::new();
+    hasher.write_u64(src.index() as u64);
+    match position {
+        Position::Offset(offset) => {
+            hasher.write(&[0]);
+            hasher.write_u64(offset);
+        }
+        Position::Replayed(copy) => {
+            hasher.write(&[1]);
+            hasher.write_u64(copy);
+        }
+    }
+    hasher.write_u64(row.len() as u64);
+    for v in row {
+        hasher.write_value(v);
+    }
+    DataType::UnsignedBigInt(hasher.finish())
+}
+
+impl Ingredient for Union {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        match self.emit {
+            Emit::AllFrom(p, _) => vec![p.as_global()],
+            Emit::Project { ref emit, .. } => emit.keys().map(IndexPair::as_global).collect(),
+        }
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        // the copies of every row are counted in full, so the output must be too
+        self.distinct && self.adaptive_distinct.is_none()
+    }
+
+    fn probe(&self) -> HashMap<String, String> {
+        let mut hm = HashMap::new();
+        hm.insert("captured".into(), format!("{}", self.replay_pieces.len()));
+        hm.insert(
+            "deferred".into(),
+            format!(
+                "{}",
+                self.replay_deferred
+                    .values()
+                    .map(VecDeque::len)
+                    .sum::<usize>()
+            ),
+        );
+        if self.max_text_len.is_some() {
+            hm.insert("truncated".into(), format!("{}", self.truncated));
+        }
+        if let Some((_, true)) = self.max_width {
+            hm.insert("too wide".into(), format!("{}", self.too_wide));
+        }
+        if self.too_narrow > 0 {
+            hm.insert("too narrow".into(), format!("{}", self.too_narrow));
+        }
+        if let Some((_, ConflictPolicy::Error)) = self.conflicts {
+            hm.insert(
+                "rejected conflicts".into(),
+                format!("{}", self.rejected_conflicts),
+            );
+        }
+        if self.spill_budget.is_some() {
+            hm.insert("spills".into(), format!("{}", self.emitted_copies.spills()));
+        }
+        hm.insert(
+            "pending negatives".into(),
+            format!(
+                "{}",
+                self.pending_negatives
+                    .values()
+                    .map(VecDeque::len)
+                    .sum::<usize>()
+            ),
+        );
+        hm
+    }
+    fn can_bypass(&self) -> Option<NodeIndex> {
+        let (src, identity) = match self.emit {
+            Emit::Project {
+                ref emit,
+                ref identity,
+                ..
+            } if emit.len() == 1 => {
+                let src = *emit.keys().next().unwrap();
+                (src, identity.get(&src).cloned().unwrap_or(false))
+            }
+            _ => return None,
+        };
+
+        let none = Source::default();
+        let rewrites = self.sources.values().any(|s| !s.emits_like(&none))
+            || self.max_text_len.is_some()
+            || self.dedup_ids
+            || self.dedup_replays
+            || self.distinct
+            || self.adaptive_distinct.is_some();
+        if identity && !rewrites {
+            Some(src.as_global())
+        } else {
+            None
+        }
+    }
+
+    fn metrics(&self) -> Option<OpMetrics> {
+        Some(OpMetrics {
+            in_flight_keys: self.replay_pieces.len() as u64,
+            buffered_records: self
+                .replay_pieces
+                .values()
+                .flat_map(|pieces| {
+                    pieces
+                        .buffered
+                        .values()
+                        .chain(pieces.repeated.iter().map(|(_, rs)| rs))
+                })
+                .map(|rs| rs.len() as u64)
+                .sum(),
+            releases: self.replay_releases,
+        })
+    }
+
+    fn on_connected(&mut self, g: &Graph) -> Result<(), String> {
+        // rows of varying width would only fail far downstream, so catch them here
+        self.validate_columns(g).map_err(|e| e.to_string())?;
+
+        let mut versioned: Vec<_> = self
+            .sources
+            .iter()
+            .filter_map(|(&src, s)| s.schema_version.as_ref().map(|v| (src, v)))
+            .collect();
+        versioned.sort_by_key(|&(src, _)| src);
+        for (src, (version, columns)) in versioned {
+            let fields = g[src].fields();
+            if fields != columns.as_slice() {
+                return Err(format!(
+                    "union's emit map for ancestor {} was written for schema version {} with columns {:?}, but it now has columns {:?}",
+                    src.index(),
+                    version,
+                    columns,
+                    fields
+                ));
+            }
+        }
+
+        if let Some((max, _)) = self.max_width {
+            let width = match self.emit {
+                Emit::AllFrom(p, _) => g[p.as_global()].fields().len(),
+                Emit::Project { ref emit, .. } => emit.values().map(Vec::len).max().unwrap_or(0),
+            } + self.dedup_ids as usize
+                + self.partition_column().is_some() as usize;
+            if width > max {
+                return Err(format!(
+                    "union emits rows with {} columns, but at most {} are allowed",
+                    width, max
+                ));
+            }
+        }
+
+        if let Emit::Project {
+            ref mut cols,
+            ref mut identity,
+            ref emit,
+            ..
+        } = self.emit
+        {
+            cols.extend(emit.keys().map(|&n| (n, g[n.as_global()].fields().len())));
+            identity.extend(
+                emit.iter()
+                    .map(|(&n, emit)| (n, emit.iter().cloned().eq(0..cols[&n]))),
+            );
+
+            if let Some(ref schema) = self.schema {
+                for (src, emit) in emit {
+                    let fields = g[src.as_global()].fields();
+                    if emit.len() != schema.len() {
+                        return Err(format!(
+                            "union ancestor {} emits {} columns, but declared schema is {:?}",
+                            src.as_global().index(),
+                            emit.len(),
+                            schema
+                        ));
+                    }
+                    if let Some(&c) = emit.iter().find(|&&c| c >= fields.len()) {
+                        return Err(format!(
+                            "union ancestor {} has no column {} (has {:?}) for declared schema {:?}",
+                            src.as_global().index(),
+                            c,
+                            fields,
+                            schema
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_commit(&mut self, me: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.me = Some(me);
+        match self.emit {
+            Emit::Project {
+                ref mut emit,
+                ref mut cols,
+                ref mut emit_l,
+                ref mut cols_l,
+                ref mut identity,
+                ref mut pair,
+            } => {
+                let mapped_emit = emit
+                    .drain()
+                    .map(|(mut k, v)| {
+                        k.remap(remap);
+                        emit_l.insert(*k, v.clone());
+                        (k, v)
+                    })
+                    .collect();
+                let mapped_cols = cols
+                    .drain()
+                    .map(|(mut k, v)| {
+                        k.remap(remap);
+                        cols_l.insert(*k, v);
+                        (k, v)
+                    })
+                    .collect();
+                *emit = mapped_emit;
+                *cols = mapped_cols;
+                *identity = identity
+                    .drain()
+                    .map(|(mut k, v)| {
+                        k.remap(remap);
+                        (k, v)
+                    })
+                    .collect();
+
+                if emit_l.len() == 2 {
+                    let mut emits = emit_l.iter().map(|(&src, emit)| (src, emit.clone()));
+                    *pair = Some([emits.next().unwrap(), emits.next().unwrap()]);
+                }
+            }
+            Emit::AllFrom(ref mut p, _) => {
+                p.remap(remap);
+            }
+        }
+        self.sources_l = self
+            .sources
+            .iter()
+            .map(|(src, s)| (*remap[src], s.clone()))
+            .collect();
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        self.project(from, rs, replay_key_cols.is_some())
+    }
+
+    fn on_input_raw(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay: ReplayContext,
+        _: &DomainNodes,
+        _: &StateMap,
+        log: &Logger,
+    ) -> RawProcessingResult {
+        use std::mem;
+
+        let start = if self.track_latency {
+            Some(time::Instant::now())
+        } else {
+            None
+        };
+        let live = matches!(replay, ReplayContext::None);
+
+        // the batch is processed in a closure so that the accounting below also sees the results
+        // that are returned early
+        let mut result = (|| {
+            // NOTE: in the special case of us being a shard merge node (i.e., when
+            // self.emit.is_empty()), `from` will *actually* hold the shard index of
+            // the sharded egress that sent us this record. this should make everything
+            // below just work out.
+            match replay {
+                ReplayContext::None => {
+                    // prepare for a little song-and-dance for the borrow-checker
+                    let mut absorb_for_full = false;
+                    if let FullWait::Ongoing { ref started, .. } = self.full_wait_state {
+                        // ongoing full replay. is this a record we need to not disappear (i.e.,
+                        // message 2 in the explanation)?
+                        if started.len() != self.required && started.contains(&from) {
+                            // yes! keep it.
+                            // but we can't borrow self mutably here to call on_input, since we
+                            // borrowed started immutably above...
+                            absorb_for_full = true;
+                        }
+                    }
+
+                    if absorb_for_full {
+                        trace!(log, "union absorbing update for full replay");
+
+                        // we shouldn't be stepping on any partial materialization toes, but let's
+                        // make sure. i'm not 100% sure at this time if it's true.
+                        //
+                        // hello future self. clearly, i was correct that i might be incorrect. let me
+                        // help: the only reason this assert is here is because we consume rs so that
+                        // we can process it. the replay code below seems to require rs to be
+                        // *unprocessed* (not sure why), and so once we add it to our buffer, we can't
+                        // also execute the code below. if you fix that, you should be all good!
+                        //
+                        // TODO: why is this an || past self?
+                        assert!(self.replay_key.is_empty() || self.replay_pieces.is_empty());
+
+                        // process the results (self is okay to have mutably borrowed here)
+                        let rs = self.project(from, rs, false).results;
+
+                        // *then* borrow self.full_wait_state again
+                        if let FullWait::Ongoing {
+                            ref mut buffered, ..
+                        } = self.full_wait_state
+                        {
+                            // absorb into the buffer
+                            buffered.extend(rs.iter().cloned());
+                            // we clone above so that we can also return the processed results
+                            return RawProcessingResult::Regular(ProcessingResult {
+                                results: rs,
+                                ..Default::default()
+                            });
+                        } else {
+                            unreachable!();
+                        }
+                    }
+
+                    if self.replay_pieces.is_empty() {
+                        // no replay going on, so we're done.
+                        let mut m = self.project(from, rs, false);
+                        m.results = self.correct_signs(m.results, time::Instant::now());
+                        return RawProcessingResult::Regular(m);
+                    }
+
+                    // partial replays are flowing through us, and at least one piece is being waited
+                    // for. we need to keep track of any records that succeed a replay piece (and thus
+                    // aren't included in it) before the other pieces come in. note that it's perfectly
+                    // safe for us to also forward them, since they'll just be dropped when they miss
+                    // in the downstream node. in fact, we *must* forward them, becuase there may be
+                    // *other* nodes downstream that do *not* have holes for the key in question.
+                    // TODO: is the *must* still true now that we take tags into account?
+                    //
+                    // unfortunately, finding out which things we need to merge is a bit of a pain,
+                    // since the bufferd upquery responses may be for different upquery paths with
+                    // different key columns. in other words, for each record, we conceptually need to
+                    // check each buffered replay.
+                    //
+                    // we have two options here. either, we iterate over the records in an outer loop
+                    // and the buffered upquery responses in the inner loop, or the other way around.
+                    // since iterating over the buffered upquery respones includes a btree loopup, we
+                    // want to do fewer of those, so we do those in the outer loop.
+                    let mut replays = self.replay_pieces.iter_mut();
+                    let mut replay_key = None;
+                    let mut last_tag = None;
+
+                    let rkey_from = if let Emit::AllFrom(..) = self.emit {
+                        // from is the shard index
+                        0
+                    } else {
+                        from.id()
+                    };
+
+                    while let Some((&(tag, ref replaying_key, _), ref mut pieces)) = replays.next()
+                    {
+                        assert!(
+                            !pieces.buffered.is_empty(),
+                            "empty pieces bucket left in replay pieces"
+                        );
+
+                        // first, let's see if _any_ of the records in this batch even affect this
+                        // buffered upquery response.
+                        let buffered = if let Some(rs) = pieces.buffered.get_mut(&from) {
+                            rs
+                        } else {
+                            // we haven't received a replay piece for this key from this ancestor yet,
+                            // so we know that the eventual replay piece must include any records in
+                            // this batch.
+                            continue;
+                        };
+
+                        // make sure we use the right key columns for this tag
+                        if last_tag.map(|lt| lt != tag).unwrap_or(true) {
+                            // starting a new tag
+                            replay_key = Some(&self.replay_key[&(tag, rkey_from)]);
+                        }
+                        let k = replay_key.unwrap();
+                        last_tag = Some(tag);
+
+                        // and finally, check all the records
+                        for r in &rs {
+                            let hit = k
+                                .iter()
+                                .enumerate()
+                                .all(|(ki, &c)| r[c] == replaying_key[ki]);
+                            if !hit {
+                                // this record is irrelevant as far as this buffered upquery response
+                                // goes, since its key does not match the upquery's key.
+                                continue;
+                            }
+
+                            // we've received a replay piece from this ancestor already for this
+                            // key, and are waiting for replay pieces from other ancestors. we need
+                            // to incorporate this record into the replay piece so that it doesn't
+                            // end up getting lost.
+                            buffered.push(r.clone());
+
+                            // it'd be nice if we could avoid doing this exact same key check multiple
+                            // times if the same key is being replayed by multiple `requesting_shard`s.
+                            // in theory, the btreemap could let us do this by walking forward in the
+                            // iterator until we hit the next key or tag, and the rewinding back to
+                            // where we were before continuing to the same record. but that won't work
+                            // because https://github.com/rust-lang/rfcs/pull/2896.
+                            //
+                            // we could emulate the same thing by changing `ReplayPieces` to
+                            // `RefCell<ReplayPieces>`, using an ref-only iterator that is `Clone`, and
+                            // then play some games from there, but it seems not worth it.
+                        }
+                    }
+
+                    let mut m = self.project(from, rs, false);
+                    m.results = self.correct_signs(m.results, time::Instant::now());
+                    RawProcessingResult::Regular(m)
+                }
+                ReplayContext::Full { last } => {
+                    // this part is actually surpringly straightforward, but the *reason* it is
+                    // straightforward is not. let's walk through what we know first:
+                    //
+                    //  - we know that there is only exactly one full replay going on
+                    //  - we know that the target domain buffers any messages not tagged as
+                    //    replays once it has seen the *first* replay
+                    //  - we know that the target domain will apply all bufferd messages after it sees
+                    //    last = true
+                    //
+                    // we therefore have two jobs to do:
+                    //
+                    //  1. ensure that we only send one message with last = true.
+                    //  2. ensure that all messages we forward after we allow the first replay message
+                    //     through logically follow the replay.
+                    //
+                    // step 1 is pretty easy -- we only set last = true when we've seen last = true
+                    // from all our ancestors. until that is the case, we just set last = false in all
+                    // our outgoing messages (even if they had last set).
+                    //
+                    // step 2 is trickier. consider the following in a union U
+                    // across two ancestors, L and R:
+                    //
+                    //  1. L sends first replay
+                    //  2. L sends a normal message
+                    //  3. R sends a normal message
+                    //  4. R sends first replay
+                    //  5. U receives L's replay
+                    //  6. U receives R's message
+                    //  7. U receives R's replay
+                    //
+                    // when should U emit the first replay? if it does it eagerly (i.e., at 1), then
+                    // R's normal message at 3 (which is also present in R's replay) will be buffered
+                    // and replayed at the target domain, since it comes after the first replay
+                    // message. instead, we must delay sending the first replay until we have seen the
+                    // first replay from *every* ancestor. in other words, 1 must be captured, and only
+                    // emitted at 5. unfortunately, 2 also wants to cause us pain. it must *not* be
+                    // sent until after 5 either, because otherwise it would be dropped by the target
+                    // domain, which is *not* okay since it is not included in L's replay.
+                    //
+                    // phew.
+                    //
+                    // first, how do we emit *two* replay messages at 5? it turns out that we're in
+                    // luck. because only one replay can be going on at a time, the target domain
+                    // doesn't actually care about which tag we use for the forward (well, as long as
+                    // it is *one* of the full replay tags). and since we're a union, we can simply
+                    // fold 1 and 4 into a single update, and then emit that!
+                    //
+                    // second, how do we ensure that 2 also gets sent *after* the replay has started.
+                    // again, we're in luck. we can simply absorb 2 into the replay when we detect that
+                    // there's a replay which hasn't started yet! we do that above (in the other match
+                    // arm). feel free to go check. interestingly enough, it's also fine for us to
+                    // still emit 2 (i.e., not capture it), since it'll just be dropped by the target
+                    // domain.
+                    let mut rs = self.project(from, rs, true).results;
+                    // distinct unions count the copies in full replays like any other records
+                    if self.dedup_replays && !self.distinct {
+                        let id = self.emitted_columns().unwrap();
+                        dedup_replayed_ids(&mut self.replay_seen, id, &mut rs);
+                    }
+                    if let FullWait::None = self.full_wait_state {
+                        if self.required == 1 {
+                            // no need to ever buffer
+                            if last {
+                                self.replay_seen.clear();
+                                self.replay_copies.clear();
+                            }
+                            return RawProcessingResult::FullReplay(rs, last);
+                        }
+
+                        debug!(
+                            log,
+                            "union captured start of full replay; has: {}, need: {}",
+                            1,
+                            self.required
+                        );
+
+                        // we need to hold this back until we've received one from every ancestor
+                        let mut s = HashSet::new();
+                        s.insert(from);
+                        self.full_wait_state = FullWait::Ongoing {
+                            started: s,
+                            finished: if last { 1 } else { 0 },
+                            buffered: rs,
+                        };
+                        return RawProcessingResult::CapturedFull;
+                    }
+
+                    let exit;
+                    match self.full_wait_state {
+                        FullWait::Ongoing {
+                            ref mut started,
+                            ref mut finished,
+                            ref mut buffered,
+                        } => {
+                            if last {
+                                *finished += 1;
+                            }
+
+                            if *finished == self.required {
+                                // we can just send everything and we're done!
+                                // make sure to include what's in *this* replay.
+                                buffered.append(&mut *rs);
+                                debug!(log, "union releasing end of full replay");
+                                exit = RawProcessingResult::FullReplay(
+                                    buffered.split_off(0).into(),
+                                    true,
+                                );
+                            // fall through to below match where we'll set FullWait::None
+                            } else {
+                                if started.len() != self.required {
+                                    if started.insert(from) && started.len() == self.required {
+                                        // we can release all buffered replays!
+                                        debug!(log, "union releasing full replay");
+                                        buffered.append(&mut *rs);
+                                        return RawProcessingResult::FullReplay(
+                                            buffered.split_off(0).into(),
+                                            false,
+                                        );
+                                    }
+                                } else {
+                                    // common case: replay has started, and not yet finished
+                                    // no need to buffer, nothing to see here, move along
+                                    debug_assert_eq!(buffered.len(), 0);
+                                    return RawProcessingResult::FullReplay(rs, false);
+                                }
+
+                                debug!(
+                                    log,
+                                    "union captured start of full replay; has: {}, need: {}",
+                                    started.len(),
+                                    self.required
+                                );
+
+                                // if we fell through here, it means we're still missing the first
+                                // replay from at least one ancestor, so we need to buffer
+                                buffered.append(&mut *rs);
+                                return RawProcessingResult::CapturedFull;
+                            }
+                        }
+                        _ => unreachable!(),
+                    }
+
+                    // we only fall through here if we're done!
+                    // and it's only because we can't change self.full_wait_state while matching on it
+                    self.full_wait_state = FullWait::None;
+                    self.replay_seen.clear();
+                    self.replay_copies.clear();
+                    exit
+                }
+                ReplayContext::Partial {
+                    key_cols,
+                    keys,
+                    requesting_shard,
+                    unishard,
+                    tag,
+                } => {
+                    if let Emit::AllFrom(_, _) = self.emit {
+                        if unishard {
+                            // No need to buffer since request should only be for one shard
+                            assert!(self.replay_pieces.is_empty());
+                            return RawProcessingResult::ReplayPiece {
+                                rows: rs,
+                                keys: keys.iter().cloned().collect(),
+                                captured: HashSet::new(),
+                            };
+                        }
+                    }
+
+                    let rkey_from = if let Emit::AllFrom(..) = self.emit {
+                        // from is the shard index
+                        0
+                    } else {
+                        from.id()
+                    };
+
+                    use std::collections::hash_map::Entry;
+                    if let Entry::Vacant(v) = self.replay_key.entry((tag, rkey_from)) {
+                        // the replay key is for our *output* column
+                        // which might translate to different columns in our inputs
+                        match self.emit {
+                            Emit::AllFrom(..) => {
+                                v.insert(Vec::from(key_cols));
+                            }
+                            Emit::Project { ref emit_l, .. } => {
+                                let emit = &emit_l[&from];
+                                v.insert(key_cols.iter().map(|&c| emit[c]).collect());
+
+                                // Also insert for all the other sources while we're at it
+                                for (&src, emit) in emit_l {
+                                    if src != from {
+                                        self.replay_key.insert(
+                                            (tag, src.id()),
+                                            key_cols.iter().map(|&c| emit[c]).collect(),
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    } else {
+                        // we already know the meta info for this tag
+                    }
+
+                    trace!(
+                        log,
+                        "union got replay piece: {:?} with context {:?}",
+                        rs,
+                        replay
+                    );
+
+                    // the records are still in our ancestor's column order
+                    let in_cols = self.replay_key[&(tag, rkey_from)].clone();
+                    let mut rs_by_key = rs
+                        .into_iter()
+                        .map(|r| (in_cols.iter().map(|&c| r[c].clone()).collect::<Vec<_>>(), r))
+                        .fold(HashMap::new(), |mut hm, (key, r)| {
+                            hm.entry(key).or_insert_with(Records::default).push(r);
+                            hm
+                        });
+
+                    // we're going to pull a little hack here for the sake of performance.
+                    // (heard that before...)
+                    // we can't borrow self in both closures below, even though `self.on_input` doesn't
+                    // access `self.replay_pieces`. if only the compiler was more clever. we get around
+                    // this by mem::swapping a temporary (empty) HashMap (which doesn't allocate).
+                    self.replay_completion.entry(from).or_insert((0, 0)).0 += keys.len() as u64;
+
+                    let mut admitted = if self.replay_concurrency.is_some() {
+                        self.admitted(tag, requesting_shard)
+                    } else {
+                        0
+                    };
+                    let mut replay_pieces_tmp = mem::take(&mut self.replay_pieces);
+                    let mut deferred = self
+                        .replay_deferred
+                        .remove(&(tag, requesting_shard))
+                        .unwrap_or_default();
+                    let concurrency = self.replay_concurrency;
+                    let deadline = self.replay_deadline.map(|d| time::Instant::now() + d);
+
+                    let required = self.required; // can't borrow self in closures below
+                    let mut released = HashSet::new();
+                    let mut captured = HashSet::new();
+                    let mut pieces: Vec<(LocalNodeIndex, Records)> = {
+                        keys.iter()
+                            .filter_map(|key| {
+                                let rs =
+                                    rs_by_key.remove(&key[..]).unwrap_or_else(Records::default);
+
+                                // store this replay piece
+                                use std::collections::btree_map::Entry;
+                                match replay_pieces_tmp.entry((tag, key.clone(), requesting_shard))
+                                {
+                                    Entry::Occupied(mut e) => {
+                                        if e.get().buffered.contains_key(&from) {
+                                            // got two upquery responses for the same key for the same
+                                            // downstream shard, say because the upquery was retried.
+                                            // the second piece is released along with the first,
+                                            // rather than counting towards the pieces we're still
+                                            // waiting for.
+                                            e.get_mut().repeated.push((from, rs));
+                                            captured.insert(key.clone());
+                                            return None;
+                                        }
+                                        if e.get().buffered.len() == required - 1
+                                            && !deferred.contains(&e.key().1)
+                                        {
+                                            // release!
+                                            admitted = admitted.saturating_sub(1);
+                                            let mut m = e.remove();
+                                            m.buffered.insert(from, rs);
+                                            Some((key, m))
+                                        } else {
+                                            e.into_mut().buffered.insert(from, rs);
+                                            captured.insert(key.clone());
+                                            None
+                                        }
+                                    }
+                                    Entry::Vacant(h) => {
+                                        let mut m = HashMap::new();
+                                        m.insert(from, rs);
+                                        if required == 1 {
+                                            Some((
+                                                key,
+                                                ReplayPieces {
+                                                    buffered: m,
+                                                    repeated: Vec::new(),
+                                                    evict: false,
+                                                    deadline,
+                                                },
+                                            ))
+                                        } else {
+                                            if concurrency.map(|c| admitted >= c).unwrap_or(false) {
+                                                // too many keys are already buffering, so this one
+                                                // has to wait its turn.
+                                                deferred.push_back(key.clone());
+                                            } else {
+                                                admitted += 1;
+                                            }
+                                            h.insert(ReplayPieces {
+                                                buffered: m,
+                                                repeated: Vec::new(),
+                                                evict: false,
+                                                deadline,
+                                            });
+                                            captured.insert(key.clone());
+                                            None
+                                        }
+                                    }
+                                }
+                            })
+                            .flat_map(|(key, pieces)| {
+                                if pieces.evict {
+                                    // TODO XXX TODO XXX TODO XXX TODO
+                                    eprintln!("!!! need to issue an eviction after replaying key");
+                                }
+                                released.insert(key.clone());
+                                pieces.buffered.into_iter().chain(pieces.repeated)
+                            })
+                            .collect()
+                    };
+
+                    // and swap back replay pieces
+                    self.replay_pieces = replay_pieces_tmp;
+                    if !deferred.is_empty() {
+                        self.replay_deferred
+                            .insert((tag, requesting_shard), deferred);
+                    }
+
+                    // releasing keys may have freed up slots for keys that were deferred
+                    for (key, ps) in self.admit_deferred(tag, requesting_shard) {
+                        captured.remove(&key);
+                        released.insert(key);
+                        pieces.extend(ps.buffered);
+                        pieces.extend(ps.repeated);
+                    }
+
+                    for &(from, _) in &pieces {
+                        self.replay_completion.entry(from).or_insert((0, 0)).1 += 1;
+                    }
+
+                    if self.conflicts.is_some() {
+                        // which record wins a conflict depends on this order, so it must not be
+                        // left up to the order of the buffered pieces
+                        pieces.sort_by_key(|&(from, _)| (Reverse(self.priority_of(from)), from));
+                    } else if self.sources_l.values().any(|s| s.priority != 0) {
+                        // stable, so pieces from equal-priority sources keep their relative order
+                        pieces.sort_by_key(|&(from, _)| Reverse(self.priority_of(from)));
+                    } else if self.deterministic_merge.is_some() {
+                        // for shard mergers, from is the shard index
+                        pieces.sort_by_key(|&(from, _)| from);
+                    }
+                    // copies are numbered per answer, so that an answer repeated by the same ancestor
+                    // gets the same dedup ids as the first
+                    let mut rs: Records = pieces
+                        .into_iter()
+                        .flat_map(|(from, rs)| {
+                            self.replay_copies.clear();
+                            self.project(from, rs, true).results
+                        })
+                        .collect();
+                    self.replay_copies.clear();
+                    if let Some((ref key, policy)) = self.conflicts {
+                        self.rejected_conflicts += resolve_conflicts(key, policy, &mut rs);
+                    }
+
+                    // here's another bit that's a little subtle:
+                    //
+                    // remember how, above, we stripped out the upquery identifier from the replay's
+                    // tag? consider what happens if we buffer a replay with, say, tag 7.2 (so, upquery
+                    // 7, path 2). later, when some other replay comes along with, say, tag 7.1, we
+                    // decide that we're done buffering. we then release the buffered records from the
+                    // first replay alongside the ones that were in the 7.1 replay. but, we just
+                    // effectively _changed_ the tag for the records in that first replay! is that ok?
+                    // it turns out it _is_, and here is the argument for why:
+                    //
+                    // first, for a given upquery, let's consider what paths flow through us when there
+                    // is only a single union on the upquery's path. we know there is then one path for
+                    // each parent we have. an upquery from below must query each of our parents once
+                    // to get the complete results. those queries will all have the same upquery id,
+                    // but different path ids. since there is no union above us or below us on the
+                    // path, there are exactly as many paths as we have ancestors, and those paths only
+                    // branch _above_ us. below us, those different paths are the _same_. this means
+                    // that no matter what path discriminator we forward something with, it will follow
+                    // the right path.
+                    //
+                    // now, what happens if there is a exactly one union on the upquery's path, at or
+                    // above one of our parents. well, _that_ parent will have as many distinct path
+                    // identifiers as that union has parents. we know that it will only produce _one_
+                    // upquery response through (since the union will buffer), and that the repsonse
+                    // will have (an arbitrary chosen) one of those path identifiers. we know that path
+                    // discriminators are distinct, so whichever identifier our union ancestor chooses,
+                    // it will be distinct from the paths that go through our _other_ parents.
+                    // furthermore, we know that all those path identifiers ultimately share the same
+                    // path below us. and we also know that the path identifiers of the paths through
+                    // our other parents share that same path. so choosing any of them is fine.
+                    //
+                    // if we have unions above multiple of our parents, the same argument holds.
+                    //
+                    // if those unions again have ancestors that are unions, the same argument holds.
+                    //
+                    // the missing piece then is how a union that has a union as a _descendant_ knows
+                    // that any choice it makes for path identifier is fine for that descendant. this
+                    // is trickier to argue, but the argument goes like this:
+                    //
+                    // imagine you have a union immediately followed by a union, followed by some node
+                    // that wishes to make an upquery. imagine that each union has two incoming edges:
+                    // the bottom union has two edges to the top union (a "diamond"), and the top union
+                    // has two incoming edges from disjoint parts of the graph. i don't know why you'd
+                    // have that, but let's imagine. for the one upquery id here, there are four path
+                    // identifiers: bottom-left:top-left, bottom-left:top-right, bottom-right:top-left,
+                    // and bottom-right:top-right. as the top union, we will therefore receive two
+                    // NOPE
+
+                    // every key is released exactly once, so there's no need to remember records past
+                    // this batch.
+                    if self.distinct {
+                        dedup_replayed(&mut HashSet::new(), &mut rs);
+                    } else if self.dedup_replays {
+                        let id = self.emitted_columns().unwrap();
+                        dedup_replayed_ids(&mut HashSet::new(), id, &mut rs);
+                    }
+
+                    self.replay_releases += released.len() as u64;
+                    RawProcessingResult::ReplayPiece {
+                        rows: rs,
+                        keys: released,
+                        captured,
+                    }
+                }
+            }
+        })();
+
+        match result {
+            RawProcessingResult::Regular(ref mut r) if live => {
+                self.count_copies(&mut r.results, log);
+            }
+            RawProcessingResult::FullReplay(ref mut rs, _) => {
+                // a full replay rebuilds the state downstream, so the copies in it count too
+                self.count_copies(rs, log);
+                self.count_replayed(rs);
+            }
+            RawProcessingResult::ReplayPiece { ref rows, .. } => self.count_replayed(rows),
+            _ => {}
+        }
+
+        if let Some(start) = start {
+            self.latency.record(start.elapsed());
+        }
+        result
+    }
+
+    fn on_eviction(&mut self, from: LocalNodeIndex, tag: Tag, keys: &[Vec<DataType>]) {
+        for key in keys {
+            // TODO: the key.clone()s here are really sad
+            for (_, e) in self
+                .replay_pieces
+                .range_mut((tag, key.clone(), 0)..=(tag, key.clone(), usize::max_value()))
+            {
+                if e.buffered.contains_key(&from) {
+                    // we've already received something from left, but it has now been evicted.
+                    // we can't remove the buffered replay, since we'll then get confused when the
+                    // other parts of the replay arrive from the other sides. we also can't drop
+                    // the eviction, because the eviction might be there to, say, ensure key
+                    // monotonicity in the face of joins. instead, we have to *buffer* the eviction
+                    // and emit it immediately after releasing the replay for this key. we do need
+                    // to emit the replay, as downstream nodes are waiting for it.
+                    //
+                    // NOTE: e.evict may already be true here, as we have no guarantee that
+                    // upstream nodes won't send multiple evictions in a row (e.g., joins evictions
+                    // could cause this).
+                    e.evict = true;
+                } else if !e.buffered.is_empty() {
+                    // we've received replay pieces for this key from other ancestors, but not from
+                    // this one. this indicates that the eviction happened logically before the
+                    // replay requset came in, so we do in fact want to do the replay first
+                    // downstream.
+                }
+            }
+        }
+    }
+
+    fn suggest_indexes(&self, _: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // index nothing (?)
+        HashMap::new()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if self.dedup_ids && Some(col) == self.emitted_columns() {
+            // the dedup id is generated by us
+            return None;
+        }
+        if Some(col) == self.partition_column() {
+            // some ancestors may not have a key, and replays can't be keyed on the ones that do
+            return None;
+        }
+        match self.emit {
+            Emit::AllFrom(p, _) => Some(vec![(p.as_global(), col)]),
+            Emit::Project { ref emit, .. } => {
+                if emit.keys().any(|&src| self.case_of(src, col).is_some()) {
+                    // a replay keyed on the column could not tell which ancestor column to look
+                    // up a key in, since that depends on the record
+                    return None;
+                }
+                if emit.keys().any(|&src| self.literal_of(src, col).is_some()) {
+                    // the branches with a literal have no column to look up a key in
+                    return None;
+                }
+                Some(
+                    emit.iter()
+                        .map(|(&src, emit)| (src.as_global(), emit[col]))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        // Ensure we get a consistent output by sorting.
+        match self.emit {
+            Emit::AllFrom(_, ref sharding) => match sharding.shards() {
+                Some(shards) if detailed => format!("⊍ ({} shards)", shards),
+                _ => "⊍".to_string(),
+            },
+            Emit::Project { .. } if !detailed => String::from(self.union_symbol()),
+            Emit::Project { ref emit, .. } => {
+                let mut emit = emit.iter().collect::<Vec<_>>();
+                emit.sort();
+                emit.iter()
+                    .map(|&(&src, emit)| {
+                        let cols = emit
+                            .iter()
+                            .enumerate()
+                            .map(|(i, c)| match self.literal_of(src, i) {
+                                Some(v) => format!("(lit: {})", v),
+                                None => c.to_string(),
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!("{}:[{}]", src.as_global().index(), cols)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(&format!(" {} ", self.union_symbol()))
+            }
+        }
+    }
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if self.dedup_ids && Some(col) == self.emitted_columns() {
+            return self.ancestors().into_iter().map(|p| (p, None)).collect();
+        }
+        if Some(col) == self.partition_column() {
+            return self
+                .ancestors()
+                .into_iter()
+                .map(|p| (p, self.sources.get(&p).and_then(|s| s.partition_col)))
+                .collect();
+        }
+        match self.emit {
+            Emit::AllFrom(p, _) => vec![(p.as_global(), Some(col))],
+            Emit::Project { ref emit, .. } => emit
+                .iter()
+                .map(|(&src, emit)| match self.case_of(src, col) {
+                    // the value could come from either column
+                    Some(_) => (src.as_global(), None),
+                    None if self.literal_of(src, col).is_some() => (src.as_global(), None),
+                    None => (src.as_global(), Some(emit[col])),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+        (g, l, r)
+    }
+
+    #[test]
+    fn it_describes() {
+        let (u, l, r) = setup();
+        assert_eq!(
+            u.node().description(true),
+            format!("{}:[0, 1] ⋃ {}:[0, 2]", l, r)
+        );
+        assert_eq!(u.node().description(false), "⋃");
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let distinct = Union::new_distinct(emits).unwrap();
+        assert_eq!(distinct.description(false), "⩁");
+        assert_eq!(
+            distinct.description(true),
+            format!("{}:[0, 1] ⩁ {}:[0, 2]", l, r)
+        );
+
+        let deshard = Union::new_deshard(l.as_global(), Sharding::ByColumn(0, 4));
+        assert_eq!(deshard.description(false), "⊍");
+        assert_eq!(deshard.description(true), "⊍ (4 shards)");
+    }
+
+    #[test]
+    fn it_works() {
+        let (mut u, l, r) = setup();
+
+        // forward from left should emit original record
+        let left: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(u.one_row(l, left.clone(), false), vec![left].into());
+
+        // forward from right should emit subset record
+        let right = vec![1.into(), "skipped".into(), "x".into()];
+        assert_eq!(
+            u.one_row(r, right.clone(), false),
+            vec![vec![1.into(), "x".into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_attaches_dedup_ids() {
+        let setup = || {
+            let mut g = ops::test::MockGraph::new();
+            let l = g.add_base("left", &["l0", "l1"]);
+            let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+            let mut emits = HashMap::new();
+            emits.insert(l.as_global(), vec![0, 1]);
+            emits.insert(r.as_global(), vec![0, 2]);
+            let u = Union::new_unchecked(emits).with_dedup_ids();
+            g.set_op("union", &["u0", "u1", "id"], u, false);
+            (g, l, r)
+        };
+
+        let left: Vec<DataType> = vec![1.into(), "a".into()];
+        let run = || {
+            let (mut u, l, r) = setup();
+            let first = u.one_row(l, left.clone(), false);
+            let second = u.one_row(l, left.clone(), false);
+            let right = u.one_row(r, vec![1.into(), "skipped".into(), "a".into()], false);
+            (first, second, right)
+        };
+
+        let (first, second, right) = run();
+        assert_eq!(first[0].len(), 3);
+        assert_eq!(&first[0][..2], &left[..]);
+
+        // the same record at the same offset from the same source gets the same id across runs
+        assert_eq!(run(), (first.clone(), second.clone(), right.clone()));
+
+        // but the same record at a different offset, or from a different source, does not
+        assert_ne!(first[0][2], second[0][2]);
+        assert_eq!(&right[0][..2], &left[..]);
+        assert_ne!(first[0][2], right[0][2]);
+
+        let (u, _, _) = setup();
+        assert_eq!(u.node().resolve(2), None);
+    }
+
+    #[test]
+    fn it_hashes_dedup_ids_stably() {
+        let mut hasher = StableHasher::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        // ids are pinned, so that they can't change between builds, platforms, or runs
+        let src = NodeIndex::new(0);
+        let row: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(
+            dedup_id(src, Position::Offset(0), &row),
+            DataType::UnsignedBigInt(3_731_967_792_510_996_561)
+        );
+
+        // values that compare equal get the same id
+        let wide: Vec<DataType> = vec![DataType::BigInt(1), "a".into()];
+        assert_eq!(
+            dedup_id(src, Position::Offset(0), &row),
+            dedup_id(src, Position::Offset(0), &wide)
+        );
+        assert_ne!(
+            dedup_id(src, Position::Offset(0), &row),
+            dedup_id(src, Position::Replayed(0), &row)
+        );
+    }
+
+    #[test]
+    fn it_keeps_dedup_offsets_out_of_replays() {
+        let setup = || {
+            let mut g = ops::test::MockGraph::new();
+            let l = g.add_base("left", &["l0", "l1"]);
+            let mut emits = HashMap::new();
+            emits.insert(l.as_global(), vec![0, 1]);
+            let u = Union::new_unchecked(emits).with_dedup_ids();
+            g.set_op("union", &["u0", "u1", "id"], u, false);
+            (g, l)
+        };
+
+        let a: Vec<DataType> = vec![1.into(), "a".into()];
+        let b: Vec<DataType> = vec![2.into(), "b".into()];
+
+        let (mut g, l) = setup();
+        let first = g.one_row(l, a.clone(), false);
+        match g.one_raw(
+            l,
+            vec![a.clone(), a.clone()],
+            ReplayContext::Full { last: true },
+        ) {
+            RawProcessingResult::FullReplay(rs, true) => {
+                // every copy of a row in a replay gets its own id
+                assert_ne!(rs[0][2], rs[1][2]);
+                assert_ne!(rs[0][2], first[0][2]);
+            }
+            _ => unreachable!(),
+        }
+        let second = g.one_row(l, b.clone(), false);
+
+        // the replay took up no offsets
+        let (mut fresh, fl) = setup();
+        fresh.one_row(fl, a, false);
+        assert_eq!(fresh.one_row(fl, b, false), second);
+
+        // and cloning the union keeps them, so it doesn't hand out the same ids again
+        let clone = match **g.node() {
+            NodeOperator::Union(ref u) => u.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(clone.offsets.values().cloned().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn it_pairs_reordered_negatives() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let window = time::Duration::from_secs(60);
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_negative_reordering(vec![0], window);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let regular = |g: &mut ops::test::MockGraph, src: IndexPair, rs: Records| match g.one_raw(
+            src,
+            rs,
+            ReplayContext::None,
+        ) {
+            RawProcessingResult::Regular(m) => m.results,
+            _ => unreachable!(),
+        };
+        let expire = |g: &mut ops::test::MockGraph, at: time::Instant| {
+            g.node_mut().get_union_mut().unwrap().expire_negatives(at)
+        };
+        let a: Vec<DataType> = vec![1.into(), "a".into()];
+        let a2: Vec<DataType> = vec![1.into(), "a2".into()];
+        let b: Vec<DataType> = vec![2.into(), "b".into()];
+
+        // a negative that arrives ahead of its positive is held back
+        let rs = regular(&mut g, l, vec![(a.clone(), false)].into());
+        assert!(rs.is_empty());
+
+        // positives for other rows, even with the same key, still flow through
+        let rs = regular(&mut g, l, vec![b.clone(), a2.clone()].into());
+        assert_eq!(rs, vec![b.clone(), a2.clone()].into());
+
+        // and once the positive shows up within the window, the two cancel out
+        let rs = regular(&mut g, l, vec![a.clone()].into());
+        assert!(rs.is_empty());
+        assert_eq!(g.node().probe()["pending negatives"], "0");
+
+        // a negative for a key with a forwarded positive is not held back at all
+        let rs = regular(&mut g, l, vec![(b.clone(), false)].into());
+        assert_eq!(rs, vec![(b.clone(), false)].into());
+
+        // a negative that never gets paired is forwarded once its window has passed, even if no
+        // other batch arrives
+        let start = time::Instant::now();
+        let rs = regular(&mut g, l, vec![(b.clone(), false)].into());
+        assert!(rs.is_empty());
+        assert!(expire(&mut g, start).is_empty());
+        assert_eq!(
+            expire(&mut g, time::Instant::now() + window),
+            vec![(b, false)].into()
+        );
+        assert_eq!(g.node().probe()["pending negatives"], "0");
+    }
+
+    #[test]
+    fn it_groups_output() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_grouped_output(vec![1]);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let rs = g.one(
+            l,
+            vec![
+                (vec![1.into(), "x".into()], true),
+                (vec![2.into(), "y".into()], true),
+                (vec![3.into(), "x".into()], false),
+                (vec![4.into(), "y".into()], true),
+                (vec![5.into(), "x".into()], true),
+            ],
+            false,
+        );
+
+        let expected: Records = vec![
+            (vec![1.into(), "x".into()], true),
+            (vec![3.into(), "x".into()], false),
+            (vec![5.into(), "x".into()], true),
+            (vec![2.into(), "y".into()], true),
+            (vec![4.into(), "y".into()], true),
+        ]
+        .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_specializes_two_parents() {
+        let (mut u, l, r) = setup();
+        let left = || -> Records {
+            (0..10_000)
+                .map(|i| vec![i.into(), format!("{}", i).into()])
+                .collect()
+        };
+        let right = || -> Records {
+            (0..10_000)
+                .map(|i| vec![i.into(), "skipped".into(), format!("{}", i).into()])
+                .collect()
+        };
+
+        let binary = (u.one(l, left(), false), u.one(r, right(), false));
+
+        // force the general path
+        match **u.node_mut() {
+            NodeOperator::Union(ref mut un) => match un.emit {
+                Emit::Project { ref mut pair, .. } => {
+                    assert!(pair.is_some());
+                    *pair = None;
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+
+        let general = (u.one(l, left(), false), u.one(r, right(), false));
+        assert_eq!(binary, general);
+        assert_eq!(binary.0, left());
+        assert_eq!(binary.0.len(), 10_000);
+    }
+
+    #[test]
+    fn it_records_latency() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_latency_tracking();
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let latency = |g: &ops::test::MockGraph| match **g.node() {
+            NodeOperator::Union(ref u) => u.latency_histogram().clone(),
+            _ => unreachable!(),
+        };
+        assert!(latency(&g).is_empty());
+        assert_eq!(latency(&g).quantile(0.5), None);
+
+        let rs: Records = vec![vec![DataType::from(1), "a".into()]].into();
+        g.one_raw(l, rs, ReplayContext::None);
+        let h = latency(&g);
+        assert_eq!(h.len(), 1);
+        assert!(h.quantile(1.0).is_some());
+    }
+
+    fn declared(l: IndexPair, r: IndexPair, schema: &[&str]) -> Union {
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        Union::new_unchecked(emits).with_declared_schema(schema.iter().map(|&c| c.into()).collect())
+    }
+
+    fn versioned(
+        right: &[&str],
+    ) -> (
+        ops::test::MockGraph,
+        Result<(), String>,
+        IndexPair,
+        IndexPair,
+    ) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", right);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let columns = |cs: &[&str]| -> Vec<String> { cs.iter().map(|&c| c.into()).collect() };
+        let u = Union::new_unchecked(emits)
+            .with_schema_version(l.as_global(), 1, columns(&["l0", "l1"]))
+            .with_schema_version(r.as_global(), 2, columns(&["r0", "r1", "r2"]));
+        let connected = g.try_set_op("union", &["u0", "u1"], u, false);
+        (g, connected, l, r)
+    }
+
+    #[test]
+    fn it_accepts_matching_schema_versions() {
+        let (mut g, connected, l, r) = versioned(&["r0", "r1", "r2"]);
+        assert_eq!(connected, Ok(()));
+
+        let row: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(g.one_row(l, row.clone(), false), vec![row.clone()].into());
+        assert_eq!(
+            g.one_row(r, vec![1.into(), "x".into(), "a".into()], false),
+            vec![row].into()
+        );
+    }
+
+    #[test]
+    fn it_detects_schema_version_mismatch() {
+        // the right ancestor has gained a column since version 2
+        let (_, connected, _, r) = versioned(&["r0", "rnew", "r1", "r2"]);
+        let e = connected.unwrap_err();
+        assert!(
+            e.starts_with(&format!(
+                "union's emit map for ancestor {} was written for schema version 2",
+                r.as_global().index()
+            )),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn it_accepts_matching_schema() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+        g.set_op("union", &["u0", "u1"], declared(l, r, &["u0", "u1"]), false);
+    }
+
+    #[test]
+    fn it_rejects_mismatched_schema() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+        let err = g
+            .try_set_op(
+                "union",
+                &["u0", "u1", "u2"],
+                declared(l, r, &["u0", "u1", "u2"]),
+                false,
+            )
+            .unwrap_err();
+        assert!(
+            err.contains("emits 2 columns, but declared schema is"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn it_guards_max_width() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_max_width(1, true);
+        assert_eq!(
+            g.try_set_op("union", &["u0", "u1"], u, false),
+            Err(String::from(
+                "union emits rows with 2 columns, but at most 1 are allowed"
+            ))
+        );
+    }
+
+    #[test]
+    fn it_allows_max_width() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_max_width(2, true);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let row = vec![DataType::from(1), "a".into()];
+        assert_eq!(g.one_row(l, row.clone(), false), vec![row].into());
+    }
+
+    #[test]
+    fn it_drops_rows_wider_than_max_width() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["s0", "s1"]);
+        let u = Union::new_deshard(s.as_global(), Sharding::ByColumn(0, 2)).with_max_width(2, true);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        // shard mergers forward records as they are, so they can be wider than their ancestor
+        let row: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(g.one_row(s, row.clone(), false), vec![row].into());
+        assert!(g
+            .one_row(s, vec![1.into(), "a".into(), "b".into()], false)
+            .is_empty());
+
+        match **g.node() {
+            NodeOperator::Union(ref u) => assert_eq!(u.too_wide(), 1),
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["too wide"], "1");
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        use std::collections::HashMap;
+        let (u, _, _) = setup();
+        let me = 1.into();
+        assert_eq!(u.node().suggest_indexes(me), HashMap::new());
+    }
+
+    #[test]
+    fn it_defers_replays_beyond_concurrency_cap() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_replay_concurrency(1);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let tag = Tag::new(1);
+        let key = |k: i32| -> HashSet<Vec<DataType>> { Some(vec![k.into()]).into_iter().collect() };
+
+        // the first key takes the only slot
+        match g.replay_piece(l, vec![vec![1.into(), "a".into()]], &[0], &key(1), tag, 0) {
+            RawProcessingResult::ReplayPiece { captured, .. } => assert_eq!(captured, key(1)),
+            _ => unreachable!(),
+        }
+
+        // the second key has to wait, even once it has all its pieces
+        match g.replay_piece(l, vec![vec![2.into(), "b".into()]], &[0], &key(2), tag, 0) {
+            RawProcessingResult::ReplayPiece { captured, .. } => assert_eq!(captured, key(2)),
+            _ => unreachable!(),
+        }
+        let right = vec![vec![2.into(), "skipped".into(), "c".into()]];
+        match g.replay_piece(r, right, &[0], &key(2), tag, 0) {
+            RawProcessingResult::ReplayPiece { keys, captured, .. } => {
+                assert!(keys.is_empty());
+                assert_eq!(captured, key(2));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["deferred"], "1");
+
+        // completing the first key frees up the slot, which releases the second key too
+        let right = vec![vec![1.into(), "skipped".into(), "d".into()]];
+        match g.replay_piece(r, right, &[0], &key(1), tag, 0) {
+            RawProcessingResult::ReplayPiece {
+                rows,
+                keys,
+                captured,
+            } => {
+                assert!(captured.is_empty());
+                assert_eq!(keys.len(), 2);
+                assert_eq!(rows.len(), 4);
+                assert!(rows.has_positive(&vec![DataType::from(1), "a".into()][..]));
+                assert!(rows.has_positive(&vec![DataType::from(2), "c".into()][..]));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["captured"], "0");
+        assert_eq!(g.node().probe()["deferred"], "0");
+    }
+
+    #[test]
+    fn it_defers_replays_per_tag() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_replay_concurrency(1);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let (t1, t2) = (Tag::new(1), Tag::new(2));
+        let key = |k: i32| -> HashSet<Vec<DataType>> { Some(vec![k.into()]).into_iter().collect() };
+        let left = |k: i32| vec![vec![k.into(), "l".into()]];
+        let right = |k: i32| vec![vec![k.into(), "skipped".into(), "r".into()]];
+
+        // each tag has its own slot, taken by keys 1 and 3, so keys 2 and 4 are deferred even once
+        // they have all their pieces
+        for &(tag, k) in &[(t1, 1), (t2, 3), (t1, 2), (t2, 4)] {
+            g.replay_piece(l, left(k), &[0], &key(k), tag, 0);
+        }
+        for &(tag, k) in &[(t1, 2), (t2, 4)] {
+            match g.replay_piece(r, right(k), &[0], &key(k), tag, 0) {
+                RawProcessingResult::ReplayPiece { keys, .. } => assert!(keys.is_empty()),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(g.node().probe()["deferred"], "2");
+
+        // completing the second tag's key releases that tag's deferred key along with it
+        match g.replay_piece(r, right(3), &[0], &key(3), t2, 0) {
+            RawProcessingResult::ReplayPiece { keys, rows, .. } => {
+                let expected: HashSet<Vec<DataType>> =
+                    vec![vec![3.into()], vec![4.into()]].into_iter().collect();
+                assert_eq!(keys, expected);
+                assert_eq!(rows.len(), 4);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["deferred"], "1");
+
+        // and likewise for the first tag
+        match g.replay_piece(r, right(1), &[0], &key(1), t1, 0) {
+            RawProcessingResult::ReplayPiece { keys, rows, .. } => {
+                let expected: HashSet<Vec<DataType>> =
+                    vec![vec![1.into()], vec![2.into()]].into_iter().collect();
+                assert_eq!(keys, expected);
+                assert_eq!(rows.len(), 4);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["deferred"], "0");
+        assert_eq!(g.node().probe()["captured"], "0");
+    }
+
+    #[test]
+    fn it_dumps_buffered_pieces() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+
+        let tag = Tag::new(1);
+        let key: HashSet<_> = Some(vec![DataType::from(1)]).into_iter().collect();
+        g.replay_piece(l, vec![vec![1.into(), "a".into()]], &[0], &key, tag, 0);
+
+        let mut dump = Vec::new();
+        match **g.node() {
+            NodeOperator::Union(ref u) => u.dump_buffered(&mut dump).unwrap(),
+            _ => unreachable!(),
+        }
+        let dump = String::from_utf8(dump).unwrap();
+        assert_eq!(
+            dump,
+            format!(
+                "Tag(1) from shard 0 is keyed on columns [0]\n\
+                 Tag(1) key [Int(1)] for shard 0: have [{}], waiting for [{}]\n",
+                *l, *r
+            )
+        );
+    }
+
+    #[test]
+    fn it_orders_released_pieces_by_priority() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_source_priority(r.as_global(), 1);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let tag = Tag::new(1);
+        let key: HashSet<_> = Some(vec![DataType::from(1)]).into_iter().collect();
+        let left = vec![vec![DataType::from(1), "a".into()]];
+        g.replay_piece(l, left, &[0], &key, tag, 0);
+
+        // the right side arrives last, but its records are emitted first
+        let right = vec![vec![DataType::from(1), "skipped".into(), "b".into()]];
+        match g.replay_piece(r, right, &[0], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, .. } => assert_eq!(
+                rows,
+                vec![
+                    vec![DataType::from(1), "b".into()],
+                    vec![DataType::from(1), "a".into()],
+                ]
+                .into()
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (u, l, r) = setup();
+        let r0 = u.node().resolve(0);
+        assert!(r0
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|&(n, c)| n == l.as_global() && c == 0));
+        assert!(r0
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|&(n, c)| n == r.as_global() && c == 0));
+        let r1 = u.node().resolve(1);
+        assert!(r1
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|&(n, c)| n == l.as_global() && c == 1));
+        assert!(r1
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|&(n, c)| n == r.as_global() && c == 2));
+    }
+
+    #[test]
+    fn it_samples_per_source() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits)
+            .with_sampling_rate(l.as_global(), vec![0], 1.0)
+            .with_sampling_rate(r.as_global(), vec![0], 0.1);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let left: Vec<Vec<DataType>> = (0..1000).map(|i: i32| vec![i.into(), "a".into()]).collect();
+        let right: Vec<Vec<DataType>> = (0..1000)
+            .map(|i: i32| vec![i.into(), "skipped".into(), "b".into()])
+            .collect();
+
+        // everything from the left is kept, and about a tenth of what's on the right
+        assert_eq!(g.one(l, left, false).len(), 1000);
+        let kept = g.one(r, right.clone(), false);
+        assert!(kept.len() > 50 && kept.len() < 150, "kept {}", kept.len());
+
+        // the choice is a fixed hash of the key, so it is the same every time, in every build
+        assert_eq!(g.one(r, right.clone(), false), kept);
+        assert_eq!(kept.len(), 99);
+
+        // and the rate can be changed at runtime
+        g.node_mut()
+            .get_union_mut()
+            .unwrap()
+            .set_sampling_rate(*r, 1.0);
+        assert_eq!(g.one(r, right, false).len(), 1000);
+    }
+
+    #[test]
+    fn it_estimates_cardinality() {
+        let (u, l, r) = setup();
+        let estimate = |estimates: &HashMap<_, _>| match **u.node() {
+            NodeOperator::Union(ref u) => u.estimated_cardinality(estimates),
+            _ => unreachable!(),
+        };
+
+        let mut estimates = HashMap::new();
+        estimates.insert(l.as_global(), 100);
+        assert_eq!(estimate(&estimates), 100);
+        estimates.insert(r.as_global(), 20);
+        assert_eq!(estimate(&estimates), 120);
+    }
+
+    #[test]
+    fn it_estimates_distinct_cardinality() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 1]);
+        g.set_op(
+            "union",
+            &["u0", "u1"],
+            Union::new_distinct(emits).unwrap(),
+            false,
+        );
+        let estimate = |g: &ops::test::MockGraph, estimates: &HashMap<_, _>| match **g.node() {
+            NodeOperator::Union(ref u) => u.estimated_cardinality(estimates),
+            _ => unreachable!(),
+        };
+
+        let mut estimates = HashMap::new();
+        estimates.insert(l.as_global(), 100);
+        estimates.insert(r.as_global(), 20);
+
+        // nothing is known about duplicates before any records arrive
+        assert_eq!(estimate(&g, &estimates), 120);
+
+        // one in four records is a duplicate
+        let row = |i: i32| -> Vec<DataType> { vec![i.into(), "x".into()] };
+        g.one_raw(l, vec![row(1), row(2), row(3)], ReplayContext::None);
+        g.one_raw(r, vec![row(1)], ReplayContext::None);
+        assert_eq!(estimate(&g, &estimates), 90);
+
+        // but an empty union stays empty
+        assert_eq!(estimate(&g, &HashMap::new()), 0);
+    }
+
+    #[test]
+    fn it_normalizes_timestamps() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let seconds = TimestampFormat {
+            unit: TimeUnit::Seconds,
+            utc_offset: 0,
+        };
+        let millis = TimestampFormat {
+            unit: TimeUnit::Millis,
+            utc_offset: 3600,
+        };
+        let u = Union::new_unchecked(emits)
+            .with_timestamp_column(1, TimeUnit::Millis)
+            .with_timestamp_format(l.as_global(), seconds)
+            .with_timestamp_format(r.as_global(), millis);
+        g.set_op("union", &["u0", "ts"], u, false);
+
+        let left = vec![DataType::from(1), 1_500_000_000i64.into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec![DataType::from(1), 1_500_000_000_000i64.into()]].into()
+        );
+
+        // the right side is in millis, but an hour behind
+        let right = vec![DataType::from(2), "x".into(), 1_500_000_000_250i64.into()];
+        assert_eq!(
+            g.one_row(r, right, false),
+            vec![vec![DataType::from(2), 1_500_003_600_250i64.into()]].into()
+        );
+
+        // nulls stay null
+        let right = vec![DataType::from(3), "x".into(), DataType::None];
+        assert_eq!(
+            g.one_row(r, right, false),
+            vec![vec![DataType::from(3), DataType::None]].into()
+        );
+
+        assert_eq!(
+            millis.normalize(&DataType::from(1_999i64), TimeUnit::Seconds),
+            DataType::from(3601i64)
+        );
+    }
+
+    #[test]
+    fn it_tracks_replay_completion() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+
+        let tag = Tag::new(1);
+        let key = |k: i32| -> HashSet<Vec<DataType>> { Some(vec![k.into()]).into_iter().collect() };
+        let completion = |g: &ops::test::MockGraph| match **g.node() {
+            NodeOperator::Union(ref u) => u.replay_completion(),
+            _ => unreachable!(),
+        };
+
+        for k in 1..=3 {
+            g.replay_piece(l, vec![vec![k.into(), "a".into()]], &[0], &key(k), tag, 0);
+        }
+        let right = vec![vec![1.into(), "skipped".into(), "b".into()]];
+        g.replay_piece(r, right, &[0], &key(1), tag, 0);
+
+        // the right side completed everything it sent, but the left side is still waiting
+        let c = completion(&g);
+        assert!((c[&*l] - 1.0 / 3.0).abs() < 1e-9);
+        assert!((c[&*r] - 1.0).abs() < 1e-9);
+
+        let right = vec![vec![2.into(), "skipped".into(), "b".into()]];
+        g.replay_piece(r, right, &[0], &key(2), tag, 0);
+        let c = completion(&g);
+        assert!((c[&*l] - 2.0 / 3.0).abs() < 1e-9);
+        assert!((c[&*r] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_reports_expired_replays() {
+        use std::time::{Duration, Instant};
+
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let timeout = Duration::from_secs(60);
+        let u = Union::new_unchecked(emits).with_replay_deadline(timeout);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let tag = Tag::new(1);
+        let key = |k: i32| -> HashSet<Vec<DataType>> { Some(vec![k.into()]).into_iter().collect() };
+        let expired = |g: &ops::test::MockGraph, now: Instant| match **g.node() {
+            NodeOperator::Union(ref u) => u.expired_replays(now),
+            _ => unreachable!(),
+        };
+
+        let start = Instant::now();
+        for k in 1..=2 {
+            g.replay_piece(l, vec![vec![k.into(), "a".into()]], &[0], &key(k), tag, 0);
+        }
+        assert!(expired(&g, start).is_empty());
+
+        // once the clock passes the deadline, every key still buffering has expired
+        let later = start + timeout * 2;
+        assert_eq!(
+            expired(&g, later),
+            vec![vec![DataType::from(1)], vec![DataType::from(2)]]
+        );
+
+        // completed keys are no longer reported
+        let right = vec![vec![1.into(), "skipped".into(), "b".into()]];
+        g.replay_piece(r, right, &[0], &key(1), tag, 0);
+        assert_eq!(expired(&g, later), vec![vec![DataType::from(2)]]);
+    }
+
+    fn replay_conflict(policy: ConflictPolicy) -> (ops::test::MockGraph, Records) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits)
+            .with_source_priority(l.as_global(), 1)
+            .with_replay_conflicts(vec![0], policy);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec![1.into()]).into_iter().collect();
+        let right: Vec<Vec<DataType>> = vec![
+            vec![1.into(), "x".into(), "right".into()],
+            vec![1.into(), "x".into(), "other".into()],
+        ];
+        match g.replay_piece(r, right, &[0], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, .. } => assert!(rows.is_empty()),
+            _ => unreachable!(),
+        }
+        let left: Vec<Vec<DataType>> = vec![vec![1.into(), "left".into()]];
+        let rows = match g.replay_piece(l, left, &[0], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, .. } => rows,
+            _ => unreachable!(),
+        };
+        (g, rows)
+    }
+
+    #[test]
+    fn it_resolves_replay_conflicts() {
+        // the left side has priority, so its pieces are assembled first
+        assert_eq!(
+            replay_conflict(ConflictPolicy::FirstWins).1,
+            vec![vec![1.into(), "left".into()]].into()
+        );
+        assert_eq!(
+            replay_conflict(ConflictPolicy::LastWins).1,
+            vec![vec![1.into(), "other".into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_rejects_replay_conflicts() {
+        // the conflicting records are dropped and counted, rather than released
+        let (g, rows) = replay_conflict(ConflictPolicy::Error);
+        assert_eq!(rows, vec![vec![1.into(), "left".into()]].into());
+        match **g.node() {
+            NodeOperator::Union(ref u) => assert_eq!(u.rejected_conflicts(), 2),
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["rejected conflicts"], "2");
+    }
+
+    #[test]
+    fn it_collapses_duplicates_when_distinct() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op(
+            "union",
+            &["u0", "u1"],
+            Union::new_distinct(emits).unwrap(),
+            false,
+        );
+        assert!(g.node().requires_full_materialization());
+
+        let regular = |g: &mut ops::test::MockGraph, src: IndexPair, rs: Records| match g.one_raw(
+            src,
+            rs,
+            ReplayContext::None,
+        ) {
+            RawProcessingResult::Regular(r) => r.results,
+            _ => unreachable!(),
+        };
+
+        let a: Vec<DataType> = vec![1.into(), "a".into()];
+        let right: Vec<DataType> = vec![1.into(), "skipped".into(), "a".into()];
+
+        // the same row from two ancestors is only emitted once
+        assert_eq!(
+            regular(&mut g, l, vec![a.clone()].into()),
+            vec![a.clone()].into()
+        );
+        assert!(regular(&mut g, r, vec![right.clone()].into()).is_empty());
+
+        // and only retracted once its last copy is gone
+        assert!(regular(&mut g, l, vec![(a.clone(), false)].into()).is_empty());
+        assert_eq!(
+            regular(&mut g, r, vec![(right.clone(), false)].into()),
+            vec![(a.clone(), false)].into()
+        );
+
+        // after which it can come back
+        assert_eq!(regular(&mut g, r, vec![right].into()), vec![a].into());
+
+        // a row that was never emitted is never retracted
+        let b: Vec<DataType> = vec![2.into(), "b".into()];
+        assert!(regular(&mut g, l, vec![(b, false)].into()).is_empty());
+    }
+
+    #[test]
+    fn it_faults_spilled_copy_counts() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 1]);
+        let u = Union::new_distinct(emits).unwrap().with_spill_budget(1);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let regular = |g: &mut ops::test::MockGraph, src: IndexPair, rs: Records| match g.one_raw(
+            src,
+            rs,
+            ReplayContext::None,
+        ) {
+            RawProcessingResult::Regular(r) => r.results,
+            _ => unreachable!(),
+        };
+
+        let a: Vec<DataType> = vec![1.into(), "a".into()];
+        let b: Vec<DataType> = vec![2.into(), "b".into()];
+
+        // a's count is spilled once b's count no longer fits next to it
+        assert_eq!(
+            regular(&mut g, l, vec![a.clone()].into()),
+            vec![a.clone()].into()
+        );
+        assert_eq!(
+            regular(&mut g, l, vec![b.clone()].into()),
+            vec![b.clone()].into()
+        );
+        assert_eq!(g.node().probe()["spills"], "1");
+
+        // the spilled count is faulted back in, so the duplicate is still recognized
+        assert!(regular(&mut g, r, vec![a.clone()].into()).is_empty());
+        assert_eq!(g.node().probe()["spills"], "2");
+
+        // and a is only retracted once both of its copies are
+        assert!(regular(&mut g, l, vec![(a.clone(), false)].into()).is_empty());
+        assert_eq!(
+            regular(&mut g, r, vec![(a.clone(), false)].into()),
+            vec![(a, false)].into()
+        );
+
+        // b survived being spilled too
+        assert_eq!(
+            regular(&mut g, l, vec![(b.clone(), false)].into()),
+            vec![(b, false)].into()
+        );
+    }
+
+    #[test]
+    fn it_adaptively_promotes_to_distinct() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_adaptive_distinct(0.5, 4);
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let is_distinct = |g: &ops::test::MockGraph| match **g.node() {
+            NodeOperator::Union(ref u) => u.is_distinct(),
+            _ => unreachable!(),
+        };
+        let regular = |g: &mut ops::test::MockGraph, src: IndexPair, rs: Records| match g.one_raw(
+            src,
+            rs,
+            ReplayContext::None,
+        ) {
+            RawProcessingResult::Regular(r) => r.results,
+            _ => unreachable!(),
+        };
+
+        let a: Vec<DataType> = vec![1.into(), "a".into()];
+        let b: Vec<DataType> = vec![2.into(), "b".into()];
+        let right = |row: &[DataType]| -> Vec<DataType> {
+            vec![row[0].clone(), "x".into(), row[1].clone()]
+        };
+
+        // duplicates pass through until enough records have been observed
+        assert_eq!(
+            regular(&mut g, l, vec![a.clone()].into()),
+            vec![a.clone()].into()
+        );
+        assert_eq!(
+            regular(&mut g, r, vec![right(&a)].into()),
+            vec![a.clone()].into()
+        );
+        assert_eq!(
+            regular(&mut g, l, vec![a.clone()].into()),
+            vec![a.clone()].into()
+        );
+        assert!(!is_distinct(&g));
+
+        // the fourth record brings the duplicate rate to 2/4, so the surplus copies are retracted
+        let rs = regular(&mut g, r, vec![right(&b)].into());
+        assert!(is_distinct(&g));
+        assert_eq!(rs.len(), 3);
+        assert!(rs.has_positive(&b[..]));
+        assert_eq!(rs.iter().filter(|r| !r.is_positive()).count(), 2);
+        assert!(rs.iter().all(|r| r.is_positive() || r[..] == a[..]));
+
+        // from then on, only the first copy comes in, and only the last copy goes out
+        assert!(regular(&mut g, l, vec![b.clone()].into()).is_empty());
+        assert!(regular(&mut g, l, vec![(b.clone(), false)].into()).is_empty());
+        assert_eq!(
+            regular(&mut g, r, vec![(right(&b), false)].into()),
+            vec![(b.clone(), false)].into()
+        );
+    }
+
+    #[test]
+    fn it_dedups_replays_by_id() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits)
+            .with_dedup_ids()
+            .with_replay_deduplication();
+        g.set_op("union", &["u0", "u1", "id"], u, false);
+
+        let a = vec![DataType::from(1), "a".into()];
+        let ra = vec![a[0].clone(), "x".into(), a[1].clone()];
+        let rows = |rs: Records| {
+            let mut rows: Vec<_> = rs
+                .into_iter()
+                .map(|r| r.extract().0[..2].to_vec())
+                .collect();
+            rows.sort();
+            rows
+        };
+
+        // regular updates keep their duplicates
+        assert_eq!(g.one_row(l, a.clone(), false).len(), 1);
+        assert_eq!(g.one_row(l, a.clone(), false).len(), 1);
+
+        // and so does a replay, since each copy of a row and each ancestor has its own ids
+        match g.one_raw(
+            l,
+            vec![a.clone(), a.clone()],
+            ReplayContext::Full { last: true },
+        ) {
+            RawProcessingResult::CapturedFull => {}
+            _ => unreachable!(),
+        }
+        match g.one_raw(r, vec![ra.clone()], ReplayContext::Full { last: true }) {
+            RawProcessingResult::FullReplay(rs, true) => {
+                assert_eq!(rows(rs), vec![a.clone(), a.clone(), a.clone()]);
+            }
+            _ => unreachable!(),
+        }
+
+        // but an ancestor answering the same upquery twice only has its answer included once
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec![1.into()]).into_iter().collect();
+        for _ in 0..2 {
+            match g.replay_piece(l, vec![a.clone(), a.clone()], &[0], &key, tag, 0) {
+                RawProcessingResult::ReplayPiece { rows, .. } => assert!(rows.is_empty()),
+                _ => unreachable!(),
+            }
+        }
+        match g.replay_piece(r, vec![ra], &[0], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows: rs, keys, .. } => {
+                assert_eq!(keys, key);
+                assert_eq!(rows(rs), vec![a.clone(), a.clone(), a]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_emits_literal_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(
+            l.as_global(),
+            vec![EmitCol::Column(0), EmitCol::Literal(1.into())],
+        );
+        emits.insert(
+            r.as_global(),
+            vec![EmitCol::Column(2), EmitCol::Literal(2.into())],
+        );
+        g.set_op(
+            "union",
+            &["u0", "branch"],
+            Union::new_with_literals(emits).unwrap(),
+            false,
+        );
+
+        // each record is tagged with the branch it came from
+        assert_eq!(
+            g.one_row(l, vec![DataType::from("a"), "b".into()], false),
+            vec![vec![DataType::from("a"), 1.into()]].into()
+        );
+        assert_eq!(
+            g.one_row(r, vec![DataType::from("c"), "d".into(), "e".into()], false),
+            vec![vec![DataType::from("e"), 2.into()]].into()
+        );
+
+        // and the tag has no parent column
+        assert_eq!(g.node().resolve(1), None);
+        let mut parents = g.node().parent_columns(1);
+        parents.sort();
+        assert_eq!(parents, vec![(l.as_global(), None), (r.as_global(), None)]);
+        let mut resolved = g.node().resolve(0).unwrap();
+        resolved.sort();
+        assert_eq!(resolved, vec![(l.as_global(), 0), (r.as_global(), 2)]);
+    }
+
+    #[test]
+    fn it_pads_missing_columns_with_null() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(
+            l.as_global(),
+            vec![EmitCol::Column(0), EmitCol::Column(1), EmitCol::Null],
+        );
+        emits.insert(
+            r.as_global(),
+            vec![EmitCol::Column(0), EmitCol::Column(1), EmitCol::Column(2)],
+        );
+        g.set_op(
+            "union",
+            &["u0", "u1", "u2"],
+            Union::new_with_literals(emits).unwrap(),
+            false,
+        );
+
+        assert_eq!(
+            g.one_row(l, vec![DataType::from(1), "a".into()], false),
+            vec![vec![DataType::from(1), "a".into(), DataType::None]].into()
+        );
+        assert_eq!(
+            g.one_row(r, vec![DataType::from(2), "b".into(), "c".into()], false),
+            vec![vec![DataType::from(2), "b".into(), "c".into()]].into()
+        );
+
+        // the padded column only comes from the wide branch, so replays cannot be keyed on it
+        assert_eq!(g.node().resolve(2), None);
+        let mut parents = g.node().parent_columns(2);
+        parents.sort();
+        assert_eq!(
+            parents,
+            vec![(l.as_global(), None), (r.as_global(), Some(2))]
+        );
+    }
+
+    #[test]
+    fn it_selects_columns_by_case() {
+        use crate::ops::filter::{FilterCondition, Operator, Value};
+
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let case = Case {
+            col: 1,
+            when: vec![(
+                0,
+                FilterCondition::Comparison(Operator::Greater, Value::Constant(0.into())),
+            )],
+            then: 1,
+            otherwise: 2,
+        };
+        let u = Union::new_unchecked(emits)
+            .with_case(r.as_global(), case)
+            .unwrap();
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let row = |k: i32| vec![k.into(), "one".into(), "two".into()];
+        assert_eq!(
+            g.one_row(r, row(1), false),
+            vec![vec![1.into(), "one".into()]].into()
+        );
+        assert_eq!(
+            g.one_row(r, row(-1), false),
+            vec![vec![(-1).into(), "two".into()]].into()
+        );
+
+        // the other ancestor is unaffected
+        let left = vec![DataType::from(-1), "left".into()];
+        assert_eq!(g.one_row(l, left.clone(), false), vec![left].into());
+
+        // replays cannot be keyed on the case column
+        assert!(g.node().resolve(1).is_none());
+        assert!(g.node().parent_columns(1).contains(&(r.as_global(), None)));
+    }
+
+    #[test]
+    fn it_rejects_invalid_case_conditions() {
+        use crate::ops::filter::FilterCondition;
+
+        let mut emits = HashMap::new();
+        emits.insert(NodeIndex::new(0), vec![0, 1]);
+        emits.insert(NodeIndex::new(1), vec![0, 2]);
+        let case = Case {
+            col: 1,
+            when: vec![(
+                0,
+                FilterCondition::Regex {
+                    pattern: "(".into(),
+                    negated: false,
+                },
+            )],
+            then: 1,
+            otherwise: 2,
+        };
+        match Union::new_unchecked(emits).with_case(NodeIndex::new(1), case) {
+            Err(UnionError::InvalidCase { src, .. }) => assert_eq!(src, NodeIndex::new(1)),
+            r => panic!("expected an invalid case, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn it_rejects_missing_columns_on_connect() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 2]);
+        let err = g
+            .try_set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false)
+            .unwrap_err();
+        assert!(err.contains("which only has 2 columns"), "{}", err);
+    }
+
+    #[test]
+    fn it_emits_ranges() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1", "l2", "l3"]);
+        let r = g.add_base("right", &["r0", "r1", "r2", "r3", "r4"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0..3]);
+        emits.insert(r.as_global(), vec![0..1, 3..5]);
+        let u = Union::new_ranges(emits).unwrap();
+        match u.emit {
+            Emit::Project { ref emit, .. } => {
+                assert_eq!(emit[&IndexPair::from(l.as_global())], vec![0, 1, 2]);
+                assert_eq!(emit[&IndexPair::from(r.as_global())], vec![0, 3, 4]);
+            }
+            _ => unreachable!(),
+        }
+        g.set_op("union", &["u0", "u1", "u2"], u, false);
+
+        let left: Vec<DataType> = vec![1.into(), "a".into(), "b".into(), "c".into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec![1.into(), "a".into(), "b".into()]].into()
+        );
+        let right: Vec<DataType> = vec![2.into(), "x".into(), "y".into(), "z".into(), "w".into()];
+        assert_eq!(
+            g.one_row(r, right, false),
+            vec![vec![2.into(), "z".into(), "w".into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_rejects_empty_ranges() {
+        let mut emits = HashMap::new();
+        emits.insert(NodeIndex::new(0), vec![0..2, 2..2]);
+        assert_eq!(
+            Union::new_ranges(emits).err(),
+            Some(UnionError::EmptyRange {
+                src: NodeIndex::new(0),
+                range: 2..2
+            })
+        );
+    }
+
+    #[test]
+    fn it_validates_emit() {
+        let (a, b) = (NodeIndex::new(0), NodeIndex::new(1));
+        assert_eq!(
+            Union::new(HashMap::new()).err(),
+            Some(UnionError::NoAncestors)
+        );
+
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![0, 2]);
+        assert!(Union::new(emits.clone()).is_ok());
+
+        emits.insert(b, vec![]);
+        assert_eq!(
+            Union::new(emits.clone()).err(),
+            Some(UnionError::NoColumns(b))
+        );
+
+        emits.insert(b, vec![2, 1]);
+        assert!(Union::new(emits).is_ok());
+    }
+
+    #[test]
+    fn it_detects_flattenable_unions() {
+        let (a, b) = (NodeIndex::new(0), NodeIndex::new(1));
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![0, 2]);
+        emits.insert(b, vec![1, 0]);
+
+        // the same projection is flattenable however it was put together
+        let u = Union::new_unchecked(emits.clone());
+        let built = UnionBuilder::new()
+            .add_ancestor(b, vec![1, 0])
+            .add_ancestor(a, vec![0, 2])
+            .build()
+            .unwrap();
+        assert!(u.is_flattenable_with(&built));
+        assert!(built.is_flattenable_with(&u));
+
+        // but not if a column, an ancestor, or deduplication differs
+        let mut other = emits.clone();
+        other.insert(b, vec![1, 1]);
+        assert!(!u.is_flattenable_with(&Union::new_unchecked(other)));
+
+        let mut other = emits.clone();
+        other.insert(NodeIndex::new(2), vec![0, 1]);
+        assert!(!u.is_flattenable_with(&Union::new_unchecked(other)));
+
+        assert!(!u.is_flattenable_with(&Union::new_distinct(emits.clone()).unwrap()));
+
+        // or if one of them rewrites the records it emits
+        let mapped = Union::new_unchecked(emits.clone()).with_null_mapping(
+            a,
+            1,
+            NullMapping::FromNull(0.into()),
+        );
+        assert!(!u.is_flattenable_with(&mapped));
+        assert!(mapped.is_flattenable_with(&mapped.clone()));
+
+        // settings that only affect when records are emitted don't matter
+        let ordered = Union::new_unchecked(emits).with_source_priority(a, 1);
+        assert!(u.is_flattenable_with(&ordered));
+
+        // and shard mergers are never flattenable
+        let deshard = Union::new_deshard(a, Sharding::ByColumn(0, 2));
+        assert!(!deshard.is_flattenable_with(&deshard.clone()));
+    }
+
+    #[test]
+    fn it_builds_incrementally() {
+        let (a, b) = (NodeIndex::new(0), NodeIndex::new(1));
+
+        let mut builder = UnionBuilder::new();
+        assert_eq!(builder.build().err(), Some(UnionError::NoAncestors));
+
+        builder.add_ancestor(a, vec![0, 2]).add_ancestor(b, vec![1]);
+        assert_eq!(
+            builder.build().err(),
+            Some(UnionError::ArityMismatch {
+                src: b,
+                columns: 1,
+                expected: 2,
+            })
+        );
+
+        builder.add_ancestor(b, vec![1, 0]);
+        assert_eq!(
+            builder.build().err(),
+            Some(UnionError::DuplicateAncestor(b))
+        );
+
+        let mut builder = UnionBuilder::new();
+        builder
+            .add_ancestor(a, vec![0, 2])
+            .add_ancestor(b, vec![1, 0]);
+        let u = builder.build().unwrap();
+        let mut ancestors = u.ancestors();
+        ancestors.sort();
+        assert_eq!(ancestors, vec![a, b]);
+    }
+
+    #[test]
+    fn it_validates_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new(emits.clone()).unwrap();
+        assert_eq!(u.validate_columns(&g.graph), Ok(()));
+
+        emits.insert(l.as_global(), vec![0, 2]);
+        let err = Union::new(emits)
+            .unwrap()
+            .validate_columns(&g.graph)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            UnionError::ColumnOutOfRange {
+                src: l.as_global(),
+                col: 2,
+                columns: 2,
+            }
+        );
+        assert!(err.to_string().contains("which only has 2 columns"));
+    }
+
+    #[test]
+    fn it_rejects_mismatched_arity() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0]);
+        let expected = UnionError::ArityMismatch {
+            src: r.as_global(),
+            columns: 1,
+            expected: 2,
+        };
+        assert_eq!(Union::new(emits).unwrap_err(), expected);
+        assert!(expected
+            .to_string()
+            .contains("but 2 from its other ancestors"));
+    }
+
+    #[test]
+    fn it_rejects_mismatched_arity_on_connect() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let mut u = Union::new_unchecked(emits);
+
+        // emit vectors are only checked in debug builds when constructed, but always on connect
+        if let Emit::Project { ref mut emit, .. } = u.emit {
+            emit.insert(r.as_global().into(), vec![0]);
+        }
+        let err = g.try_set_op("union", &["u0", "u1"], u, false).unwrap_err();
+        assert!(
+            err.contains("union emits 1 columns from ancestor"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn it_forwards_partition_keys() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+        let o = g.add_base("other", &["o0", "o1"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        emits.insert(o.as_global(), vec![0, 1]);
+        // the right side is partitioned by a column the union doesn't emit
+        let u = Union::new_unchecked(emits)
+            .with_partition_key(l.as_global(), 0)
+            .with_partition_key(r.as_global(), 1);
+        g.set_op("union", &["u0", "u1", "partition"], u, false);
+
+        let right: Vec<Vec<DataType>> = vec![
+            vec![1.into(), "p1".into(), "a".into()],
+            vec![2.into(), "p2".into(), "b".into()],
+        ];
+        assert_eq!(
+            g.one(r, right, false),
+            vec![
+                vec![1.into(), "a".into(), "p1".into()],
+                vec![2.into(), "b".into(), "p2".into()],
+            ]
+            .into()
+        );
+
+        let left: Vec<DataType> = vec![3.into(), "c".into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec![3.into(), "c".into(), 3.into()]].into()
+        );
+
+        // ancestors without a partition key have a NULL one
+        let other: Vec<DataType> = vec![4.into(), "d".into()];
+        assert_eq!(
+            g.one_row(o, other, false),
+            vec![vec![4.into(), "d".into(), DataType::None]].into()
+        );
+
+        // and the key column comes from where each ancestor keeps its key
+        assert_eq!(g.node().resolve(2), None);
+        let mut parents = g.node().parent_columns(2);
+        parents.sort();
+        assert_eq!(
+            parents,
+            vec![
+                (l.as_global(), Some(0)),
+                (r.as_global(), Some(1)),
+                (o.as_global(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_merges_shards_deterministically() {
+        type Pieces = Vec<(u32, Vec<Vec<DataType>>)>;
+        let merge = |pieces: Pieces| -> Vec<Vec<DataType>> {
+            let mut g = ops::test::MockGraph::new();
+            let s = g.add_base("source", &["s0", "s1"]);
+            let u = Union::new_deshard(s.as_global(), Sharding::ByColumn(1, 2))
+                .with_deterministic_merge(16);
+            g.set_op("union", &["u0", "u1"], u, false);
+
+            let tag = Tag::new(1);
+            let key: HashSet<Vec<DataType>> = Some(vec![1.into()]).into_iter().collect();
+            let mut out = Vec::new();
+            for (shard, rows) in pieces {
+                let mut from: IndexPair = s.as_global().into();
+                from.set_local(unsafe { LocalNodeIndex::make(shard) });
+                if let RawProcessingResult::ReplayPiece { rows, .. } =
+                    g.replay_piece(from, rows, &[0], &key, tag, 0)
+                {
+                    out.extend(rows.into_iter().map(|r| r.extract().0));
+                }
+            }
+            out
+        };
+        let row = |v: &str| -> Vec<DataType> { vec![1.into(), v.into()] };
+
+        // the shards answer in a different order, each with its rows in a different order
+        let first = merge(vec![
+            (0, vec![row("b"), row("a")]),
+            (1, vec![row("d"), row("c"), row("a")]),
+        ]);
+        let second = merge(vec![
+            (1, vec![row("a"), row("c"), row("d")]),
+            (0, vec![row("a"), row("b")]),
+        ]);
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            vec![row("a"), row("b"), row("a"), row("c"), row("d")]
+        );
+    }
+
+    #[test]
+    fn it_merges_shard_sketches() {
+        let mut u = Union::new_deshard(NodeIndex::new(0), Sharding::ByColumn(0, 2))
+            .with_sketch_merge(vec![0], 1);
+        let shard = |i| unsafe { LocalNodeIndex::make(i) };
+        let sketch = |values: std::ops::RangeInclusive<i32>| {
+            let mut s = QuantileSketch::new(50);
+            for v in values {
+                s.insert(f64::from(v));
+            }
+            s.to_datatype()
+        };
+        let merged = |rs: &Records| {
+            let pos: Vec<_> = rs.iter().filter(|r| r.is_positive()).collect();
+            assert_eq!(pos.len(), 1);
+            QuantileSketch::from_datatype(&pos[0][1]).unwrap()
+        };
+
+        let first: Vec<DataType> = vec![1.into(), sketch(1..=500)];
+        let rs = u.merge_sketches(shard(0), vec![first.clone()].into());
+        assert_eq!(rs, vec![first].into());
+
+        // the other shard's sketch is merged in, replacing the previous row
+        let rs = u.merge_sketches(shard(1), vec![vec![1.into(), sketch(501..=1000)]].into());
+        assert_eq!(rs.len(), 2);
+        let s = merged(&rs);
+        assert_eq!(s.count(), 1000);
+        assert!((s.quantile(0.5).unwrap() - 500.0).abs() <= 50.0);
+        assert!((s.quantile(0.9).unwrap() - 900.0).abs() <= 50.0);
+
+        // other keys are merged separately
+        let rs = u.merge_sketches(shard(1), vec![vec![2.into(), sketch(1..=10)]].into());
+        assert_eq!(merged(&rs).count(), 10);
+    }
+
+    #[test]
+    fn it_maps_null_sentinels() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits)
+            .with_null_mapping(l.as_global(), 1, NullMapping::ToNull(vec!["".into()]))
+            .with_null_mapping(r.as_global(), 0, NullMapping::FromNull((-1).into()));
+        g.set_op("union", &["u0", "u1"], u, false);
+
+        let left: Vec<DataType> = vec![1.into(), "".into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec![1.into(), DataType::None]].into()
+        );
+        let left: Vec<DataType> = vec![DataType::None, "a".into()];
+        assert_eq!(g.one_row(l, left.clone(), false), vec![left].into());
+
+        // the right side keeps its empty strings, but has its own mapping
+        let right: Vec<DataType> = vec![DataType::None, "x".into(), "".into()];
+        assert_eq!(
+            g.one_row(r, right, false),
+            vec![vec![(-1).into(), "".into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_duplicates_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 0, 1]);
+        emits.insert(r.as_global(), vec![0, 0, 2]);
+        let u = Union::new(emits).unwrap();
+        g.set_op("union", &["u0", "u1", "u2"], u, false);
+
+        let left: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec![1.into(), 1.into(), DataType::from("a")]].into()
+        );
+
+        // both copies map back to the same ancestor column
+        for col in 0..2 {
+            let mut resolved = g.node().resolve(col).unwrap();
+            resolved.sort();
+            assert_eq!(resolved, vec![(l.as_global(), 0), (r.as_global(), 0)]);
+            let mut parents = g.node().parent_columns(col);
+            parents.sort();
+            assert_eq!(
+                parents,
+                vec![(l.as_global(), Some(0)), (r.as_global(), Some(0))]
+            );
+        }
+    }
+
+    #[test]
+    fn it_reorders_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![1, 0]);
+        emits.insert(r.as_global(), vec![2, 0]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+
+        let left: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec![DataType::from("a"), 1.into()]].into()
+        );
+        let right: Vec<DataType> = vec![2.into(), "skipped".into(), "b".into()];
+        assert_eq!(
+            g.one_row(r, right, false),
+            vec![vec![DataType::from("b"), 2.into()]].into()
+        );
+
+        // output columns map back to the ancestor columns they were taken from
+        let mut resolved = g.node().resolve(0).unwrap();
+        resolved.sort();
+        assert_eq!(resolved, vec![(l.as_global(), 1), (r.as_global(), 2)]);
+        let mut parents = g.node().parent_columns(1);
+        parents.sort();
+        assert_eq!(
+            parents,
+            vec![(l.as_global(), Some(0)), (r.as_global(), Some(0))]
+        );
+
+        // and replays keyed on output column 0 find the key in each ancestor's own column
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec!["a".into()]).into_iter().collect();
+        let lpiece: Vec<Vec<DataType>> = vec![vec![1.into(), "a".into()]];
+        g.replay_piece(l, lpiece, &[0], &key, tag, 0);
+        let rpiece: Vec<Vec<DataType>> = vec![vec![3.into(), "skipped".into(), "a".into()]];
+        match g.replay_piece(r, rpiece, &[0], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, keys, .. } => {
+                assert_eq!(keys, key);
+                assert_eq!(rows.len(), 2);
+                assert!(rows.has_positive(&[DataType::from("a"), 1.into()][..]));
+                assert!(rows.has_positive(&[DataType::from("a"), 3.into()][..]));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_can_be_bypassed_with_one_identity_ancestor() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+        assert_eq!(g.node().can_bypass(), Some(l.as_global()));
+
+        // reordering columns is not a pass-through
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![1, 0]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+        assert_eq!(g.node().can_bypass(), None);
+
+        // and neither is deduplicating
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        g.set_op(
+            "union",
+            &["u0", "u1"],
+            Union::new_distinct(emits).unwrap(),
+            false,
+        );
+        assert_eq!(g.node().can_bypass(), None);
+    }
+
+    #[test]
+    fn it_cannot_be_bypassed_with_several_ancestors() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 1]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
+        assert_eq!(g.node().can_bypass(), None);
+    }
+
+    #[test]
+    fn it_passes_identity_projections_through() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
 
-        // NOTE: in the special case of us being a shard merge node (i.e., when
-        // self.emit.is_empty()), `from` will *actually* hold the shard index of
-        // the sharded egress that sent us this record. this should make everything
-        // below just work out.
-        match replay {
-            ReplayContext::None => {
-                // prepare for a little song-and-dance for the borrow-checker
-                let mut absorb_for_full = false;
-                if let FullWait::Ongoing { ref started, .. } = self.full_wait_state {
-                    // ongoing full replay. is this a record we need to not disappear (i.e.,
-                    // message 2 in the explanation)?
-                    if started.len() != self.required && started.contains(&from) {
-                        // yes! keep it.
-                        // but we can't borrow self mutably here to call on_input, since we
-                        // borrowed started immutably above...
-                        absorb_for_full = true;
-                    }
-                }
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
 
-                if absorb_for_full {
-                    trace!(log, "union absorbing update for full replay");
+        let rows: Vec<Vec<DataType>> = (0..10_000).map(|i| vec![i.into(), "x".into()]).collect();
+        let ptrs: Vec<_> = rows.iter().map(|r| r.as_ptr()).collect();
+        let rs = g.one(l, rows, false);
+        assert_eq!(rs.len(), ptrs.len());
+        assert!(rs.iter().zip(ptrs).all(|(r, p)| r.as_ptr() == p));
 
-                    // we shouldn't be stepping on any partial materialization toes, but let's
-                    // make sure. i'm not 100% sure at this time if it's true.
-                    //
-                    // hello future self. clearly, i was correct that i might be incorrect. let me
-                    // help: the only reason this assert is here is because we consume rs so that
-                    // we can process it. the replay code below seems to require rs to be
-                    // *unprocessed* (not sure why), and so once we add it to our buffer, we can't
-                    // also execute the code below. if you fix that, you should be all good!
-                    //
-                    // TODO: why is this an || past self?
-                    assert!(self.replay_key.is_empty() || self.replay_pieces.is_empty());
+        // ancestors whose columns are selected still have their rows copied
+        let row: Vec<DataType> = vec![1.into(), "skipped".into(), "y".into()];
+        let ptr = row.as_ptr();
+        let rs = g.one_row(r, row, false);
+        assert_eq!(rs, vec![vec![DataType::from(1), "y".into()]].into());
+        assert_ne!(rs[0].as_ptr(), ptr);
+    }
 
-                    // process the results (self is okay to have mutably borrowed here)
-                    let rs = self.on_input(ex, from, rs, None, n, s).results;
+    #[test]
+    fn it_validates_first_record_width() {
+        let (mut g, l, r) = setup();
 
-                    // *then* borrow self.full_wait_state again
-                    if let FullWait::Ongoing {
-                        ref mut buffered, ..
-                    } = self.full_wait_state
-                    {
-                        // absorb into the buffer
-                        buffered.extend(rs.iter().cloned());
-                        // we clone above so that we can also return the processed results
-                        return RawProcessingResult::Regular(ProcessingResult {
-                            results: rs,
-                            ..Default::default()
-                        });
-                    } else {
-                        unreachable!();
-                    }
-                }
+        // well-formed records are only checked once
+        let right: Vec<DataType> = vec![1.into(), "skipped".into(), "a".into()];
+        g.one_row(r, right, false);
 
-                if self.replay_pieces.is_empty() {
-                    // no replay going on, so we're done.
-                    return RawProcessingResult::Regular(self.on_input(ex, from, rs, None, n, s));
-                }
+        // a batch whose first record is too narrow is dropped and counted
+        assert!(g.one_row(l, vec![DataType::from(1)], false).is_empty());
+        match **g.node() {
+            NodeOperator::Union(ref u) => assert_eq!(u.too_narrow(), 1),
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["too narrow"], "1");
 
-                // partial replays are flowing through us, and at least one piece is being waited
-                // for. we need to keep track of any records that succeed a replay piece (and thus
-                // aren't included in it) before the other pieces come in. note that it's perfectly
-                // safe for us to also forward them, since they'll just be dropped when they miss
-                // in the downstream node. in fact, we *must* forward them, becuase there may be
-                // *other* nodes downstream that do *not* have holes for the key in question.
-                // TODO: is the *must* still true now that we take tags into account?
-                //
-                // unfortunately, finding out which things we need to merge is a bit of a pain,
-                // since the bufferd upquery responses may be for different upquery paths with
-                // different key columns. in other words, for each record, we conceptually need to
-                // check each buffered replay.
-                //
-                // we have two options here. either, we iterate over the records in an outer loop
-                // and the buffered upquery responses in the inner loop, or the other way around.
-                // since iterating over the buffered upquery respones includes a btree loopup, we
-                // want to do fewer of those, so we do those in the outer loop.
-                let mut replays = self.replay_pieces.iter_mut();
-                let mut replay_key = None;
-                let mut last_tag = None;
-
-                let rkey_from = if let Emit::AllFrom(..) = self.emit {
-                    // from is the shard index
-                    0
-                } else {
-                    from.id()
-                };
+        // and the ancestor is checked again on its next batch
+        let left: Vec<DataType> = vec![1.into(), "a".into()];
+        assert_eq!(g.one_row(l, left.clone(), false), vec![left].into());
+    }
 
-                while let Some((&(tag, ref replaying_key, _), ref mut pieces)) = replays.next() {
-                    assert!(
-                        !pieces.buffered.is_empty(),
-                        "empty pieces bucket left in replay pieces"
-                    );
+    #[test]
+    fn it_truncates_long_text() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
 
-                    // first, let's see if _any_ of the records in this batch even affect this
-                    // buffered upquery response.
-                    let buffered = if let Some(rs) = pieces.buffered.get_mut(&from) {
-                        rs
-                    } else {
-                        // we haven't received a replay piece for this key from this ancestor yet,
-                        // so we know that the eventual replay piece must include any records in
-                        // this batch.
-                        continue;
-                    };
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_max_text_len(8);
+        g.set_op("union", &["u0", "u1"], u, false);
 
-                    // make sure we use the right key columns for this tag
-                    if last_tag.map(|lt| lt != tag).unwrap_or(true) {
-                        // starting a new tag
-                        replay_key = Some(&self.replay_key[&(tag, rkey_from)]);
-                    }
-                    let k = replay_key.unwrap();
-                    last_tag = Some(tag);
+        // short text and other types pass through untouched
+        let short: Vec<DataType> = vec![1.into(), "short".into()];
+        assert_eq!(g.one_row(l, short.clone(), false), vec![short].into());
 
-                    // and finally, check all the records
-                    for r in &rs {
-                        let hit = k
-                            .iter()
-                            .enumerate()
-                            .all(|(ki, &c)| r[c] == replaying_key[ki]);
-                        if !hit {
-                            // this record is irrelevant as far as this buffered upquery response
-                            // goes, since its key does not match the upquery's key.
-                            continue;
-                        }
+        let long = "a much longer piece of text";
+        let left: Vec<DataType> = vec![long.into(), 2.into()];
+        assert_eq!(
+            g.one_row(l, left, false),
+            vec![vec!["a much l".into(), 2.into()]].into()
+        );
 
-                        // we've received a replay piece from this ancestor already for this
-                        // key, and are waiting for replay pieces from other ancestors. we need
-                        // to incorporate this record into the replay piece so that it doesn't
-                        // end up getting lost.
-                        buffered.push(r.clone());
-
-                        // it'd be nice if we could avoid doing this exact same key check multiple
-                        // times if the same key is being replayed by multiple `requesting_shard`s.
-                        // in theory, the btreemap could let us do this by walking forward in the
-                        // iterator until we hit the next key or tag, and the rewinding back to
-                        // where we were before continuing to the same record. but that won't work
-                        // because https://github.com/rust-lang/rfcs/pull/2896.
-                        //
-                        // we could emulate the same thing by changing `ReplayPieces` to
-                        // `RefCell<ReplayPieces>`, using an ref-only iterator that is `Clone`, and
-                        // then play some games from there, but it seems not worth it.
-                    }
-                }
+        // values are only cut between characters
+        let right: Vec<DataType> = vec![3.into(), "".into(), "aéééé".into()];
+        assert_eq!(
+            g.one_row(r, right, false),
+            vec![vec![3.into(), "aééé".into()]].into()
+        );
 
-                RawProcessingResult::Regular(self.on_input(ex, from, rs, None, n, s))
-            }
-            ReplayContext::Full { last } => {
-                // this part is actually surpringly straightforward, but the *reason* it is
-                // straightforward is not. let's walk through what we know first:
-                //
-                //  - we know that there is only exactly one full replay going on
-                //  - we know that the target domain buffers any messages not tagged as
-                //    replays once it has seen the *first* replay
-                //  - we know that the target domain will apply all bufferd messages after it sees
-                //    last = true
-                //
-                // we therefore have two jobs to do:
-                //
-                //  1. ensure that we only send one message with last = true.
-                //  2. ensure that all messages we forward after we allow the first replay message
-                //     through logically follow the replay.
-                //
-                // step 1 is pretty easy -- we only set last = true when we've seen last = true
-                // from all our ancestors. until that is the case, we just set last = false in all
-                // our outgoing messages (even if they had last set).
-                //
-                // step 2 is trickier. consider the following in a union U
-                // across two ancestors, L and R:
-                //
-                //  1. L sends first replay
-                //  2. L sends a normal message
-                //  3. R sends a normal message
-                //  4. R sends first replay
-                //  5. U receives L's replay
-                //  6. U receives R's message
-                //  7. U receives R's replay
-                //
-                // when should U emit the first replay? if it does it eagerly (i.e., at 1), then
-                // R's normal message at 3 (which is also present in R's replay) will be buffered
-                // and replayed at the target domain, since it comes after the first replay
-                // message. instead, we must delay sending the first replay until we have seen the
-                // first replay from *every* ancestor. in other words, 1 must be captured, and only
-                // emitted at 5. unfortunately, 2 also wants to cause us pain. it must *not* be
-                // sent until after 5 either, because otherwise it would be dropped by the target
-                // domain, which is *not* okay since it is not included in L's replay.
-                //
-                // phew.
-                //
-                // first, how do we emit *two* replay messages at 5? it turns out that we're in
-                // luck. because only one replay can be going on at a time, the target domain
-                // doesn't actually care about which tag we use for the forward (well, as long as
-                // it is *one* of the full replay tags). and since we're a union, we can simply
-                // fold 1 and 4 into a single update, and then emit that!
-                //
-                // second, how do we ensure that 2 also gets sent *after* the replay has started.
-                // again, we're in luck. we can simply absorb 2 into the replay when we detect that
-                // there's a replay which hasn't started yet! we do that above (in the other match
-                // arm). feel free to go check. interestingly enough, it's also fine for us to
-                // still emit 2 (i.e., not capture it), since it'll just be dropped by the target
-                // domain.
-                let mut rs = self.on_input(ex, from, rs, None, n, s).results;
-                if let FullWait::None = self.full_wait_state {
-                    if self.required == 1 {
-                        // no need to ever buffer
-                        return RawProcessingResult::FullReplay(rs, last);
-                    }
+        match **g.node() {
+            NodeOperator::Union(ref u) => assert_eq!(u.truncated(), 2),
+            _ => unreachable!(),
+        }
+        assert_eq!(g.node().probe()["truncated"], "2");
+    }
 
-                    debug!(
-                        log,
-                        "union captured start of full replay; has: {}, need: {}", 1, self.required
-                    );
+    #[test]
+    fn it_reports_replay_metrics() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
 
-                    // we need to hold this back until we've received one from every ancestor
-                    let mut s = HashSet::new();
-                    s.insert(from);
-                    self.full_wait_state = FullWait::Ongoing {
-                        started: s,
-                        finished: if last { 1 } else { 0 },
-                        buffered: rs,
-                    };
-                    return RawProcessingResult::CapturedFull;
-                }
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
 
-                let exit;
-                match self.full_wait_state {
-                    FullWait::Ongoing {
-                        ref mut started,
-                        ref mut finished,
-                        ref mut buffered,
-                    } => {
-                        if last {
-                            *finished += 1;
-                        }
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec![1.into()]).into_iter().collect();
+        let left: Vec<Vec<DataType>> = vec![vec![1.into(), "a".into()], vec![1.into(), "b".into()]];
+        let right: Vec<Vec<DataType>> = vec![vec![1.into(), "x".into(), "c".into()]];
 
-                        if *finished == self.required {
-                            // we can just send everything and we're done!
-                            // make sure to include what's in *this* replay.
-                            buffered.append(&mut *rs);
-                            debug!(log, "union releasing end of full replay");
-                            exit =
-                                RawProcessingResult::FullReplay(buffered.split_off(0).into(), true);
-                        // fall through to below match where we'll set FullWait::None
-                        } else {
-                            if started.len() != self.required {
-                                if started.insert(from) && started.len() == self.required {
-                                    // we can release all buffered replays!
-                                    debug!(log, "union releasing full replay");
-                                    buffered.append(&mut *rs);
-                                    return RawProcessingResult::FullReplay(
-                                        buffered.split_off(0).into(),
-                                        false,
-                                    );
-                                }
-                            } else {
-                                // common case: replay has started, and not yet finished
-                                // no need to buffer, nothing to see here, move along
-                                debug_assert_eq!(buffered.len(), 0);
-                                return RawProcessingResult::FullReplay(rs, false);
-                            }
+        g.replay_piece(l, left, &[0], &key, tag, 0);
+        assert_eq!(
+            g.node().metrics(),
+            Some(OpMetrics {
+                in_flight_keys: 1,
+                buffered_records: 2,
+                releases: 0,
+            })
+        );
 
-                            debug!(
-                                log,
-                                "union captured start of full replay; has: {}, need: {}",
-                                started.len(),
-                                self.required
-                            );
-
-                            // if we fell through here, it means we're still missing the first
-                            // replay from at least one ancestor, so we need to buffer
-                            buffered.append(&mut *rs);
-                            return RawProcessingResult::CapturedFull;
-                        }
-                    }
-                    _ => unreachable!(),
-                }
+        g.replay_piece(r, right, &[0], &key, tag, 0);
+        assert_eq!(
+            g.node().metrics(),
+            Some(OpMetrics {
+                in_flight_keys: 0,
+                buffered_records: 0,
+                releases: 1,
+            })
+        );
+    }
 
-                // we only fall through here if we're done!
-                // and it's only because we can't change self.full_wait_state while matching on it
-                self.full_wait_state = FullWait::None;
-                exit
-            }
-            ReplayContext::Partial {
-                key_cols,
-                keys,
-                requesting_shard,
-                unishard,
-                tag,
-            } => {
-                let mut is_shard_merger = false;
-                if let Emit::AllFrom(_, _) = self.emit {
-                    if unishard {
-                        // No need to buffer since request should only be for one shard
-                        assert!(self.replay_pieces.is_empty());
-                        return RawProcessingResult::ReplayPiece {
-                            rows: rs,
-                            keys: keys.iter().cloned().collect(),
-                            captured: HashSet::new(),
-                        };
-                    }
-                    is_shard_merger = true;
-                }
+    #[test]
+    fn it_snapshots_stats_as_records() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
 
-                let rkey_from = if let Emit::AllFrom(..) = self.emit {
-                    // from is the shard index
-                    0
-                } else {
-                    from.id()
-                };
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        let u = Union::new_unchecked(emits).with_latency_tracking();
+        g.set_op("union", &["u0", "u1"], u, false);
 
-                use std::collections::hash_map::Entry;
-                if let Entry::Vacant(v) = self.replay_key.entry((tag, rkey_from)) {
-                    // the replay key is for our *output* column
-                    // which might translate to different columns in our inputs
-                    match self.emit {
-                        Emit::AllFrom(..) => {
-                            v.insert(Vec::from(key_cols));
-                        }
-                        Emit::Project { ref emit_l, .. } => {
-                            let emit = &emit_l[&from];
-                            v.insert(key_cols.iter().map(|&c| emit[c]).collect());
-
-                            // Also insert for all the other sources while we're at it
-                            for (&src, emit) in emit_l {
-                                if src != from {
-                                    self.replay_key.insert(
-                                        (tag, src.id()),
-                                        key_cols.iter().map(|&c| emit[c]).collect(),
-                                    );
-                                }
-                            }
-                        }
-                    }
-                } else {
-                    // we already know the meta info for this tag
-                }
+        let stats = |g: &ops::test::MockGraph| -> HashMap<String, DataType> {
+            let rs = match **g.node() {
+                NodeOperator::Union(ref u) => u.stats_records(),
+                _ => unreachable!(),
+            };
+            rs.into_iter()
+                .map(|r| {
+                    let row = r.rec();
+                    (<&str>::from(&row[0]).to_owned(), row[1].clone())
+                })
+                .collect()
+        };
 
-                trace!(
-                    log,
-                    "union got replay piece: {:?} with context {:?}",
-                    rs,
-                    replay
-                );
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec![1.into()]).into_iter().collect();
+        let left: Vec<Vec<DataType>> = vec![vec![1.into(), "a".into()], vec![1.into(), "b".into()]];
+        g.replay_piece(l, left, &[0], &key, tag, 0);
 
-                let mut rs_by_key = rs
-                    .into_iter()
-                    .map(|r| {
-                        (
-                            key_cols.iter().map(|&c| r[c].clone()).collect::<Vec<_>>(),
-                            r,
-                        )
-                    })
-                    .fold(HashMap::new(), |mut hm, (key, r)| {
-                        hm.entry(key).or_insert_with(Records::default).push(r);
-                        hm
-                    });
+        let s = stats(&g);
+        assert_eq!(s.len(), 10);
+        assert_eq!(s["in flight keys"], 1u64.into());
+        assert_eq!(s["buffered records"], 2u64.into());
+        assert_eq!(s["releases"], 0u64.into());
+        assert_eq!(s["timed batches"], 1u64.into());
 
-                // we're going to pull a little hack here for the sake of performance.
-                // (heard that before...)
-                // we can't borrow self in both closures below, even though `self.on_input` doesn't
-                // access `self.replay_pieces`. if only the compiler was more clever. we get around
-                // this by mem::swapping a temporary (empty) HashMap (which doesn't allocate).
-                let mut replay_pieces_tmp = mem::take(&mut self.replay_pieces);
-
-                let me = self.me;
-                let required = self.required; // can't borrow self in closures below
-                let mut released = HashSet::new();
-                let mut captured = HashSet::new();
-                let rs = {
-                    keys.iter()
-                        .filter_map(|key| {
-                            let rs = rs_by_key.remove(&key[..]).unwrap_or_else(Records::default);
-
-                            // store this replay piece
-                            use std::collections::btree_map::Entry;
-                            match replay_pieces_tmp.entry((tag, key.clone(), requesting_shard)) {
-                                Entry::Occupied(e) => {
-                                    if e.get().buffered.contains_key(&from) {
-                                        // got two upquery responses for the same key for the same
-                                        // downstream shard. waaaaaaat?
-                                        unimplemented!(
-                                            "downstream shard double-requested key (node: {}, src: {}, key cols: {:?})",
-                                            me.unwrap().index(),
-                                            if is_shard_merger {
-                                                format!("shard {}", from.id())
-                                            } else {
-                                                format!(
-                                                    "node {}",
-                                                    n[from].borrow().global_addr().index()
-                                                )
-                                            },
-                                            key_cols,
-                                        );
-                                    }
-                                    if e.get().buffered.len() == required - 1 {
-                                        // release!
-                                        let mut m = e.remove();
-                                        m.buffered.insert(from, rs);
-                                        Some((key, m))
-                                    } else {
-                                        e.into_mut().buffered.insert(from, rs);
-                                        captured.insert(key.clone());
-                                        None
-                                    }
-                                }
-                                Entry::Vacant(h) => {
-                                    let mut m = HashMap::new();
-                                    m.insert(from, rs);
-                                    if required == 1 {
-                                        Some((
-                                            key,
-                                            ReplayPieces {
-                                                buffered: m,
-                                                evict: false,
-                                            },
-                                        ))
-                                    } else {
-                                        h.insert(ReplayPieces {
-                                            buffered: m,
-                                            evict: false,
-                                        });
-                                        captured.insert(key.clone());
-                                        None
-                                    }
-                                }
-                            }
-                        })
-                        .flat_map(|(key, pieces)| {
-                            if pieces.evict {
-                                // TODO XXX TODO XXX TODO XXX TODO
-                                eprintln!("!!! need to issue an eviction after replaying key");
-                            }
-                            released.insert(key.clone());
-                            pieces.buffered.into_iter()
-                        })
-                        .flat_map(|(from, rs)| {
-                            self.on_input(ex, from, rs, Some(&key_cols[..]), n, s)
-                                .results
-                        })
-                        .collect()
-                };
+        let right: Vec<Vec<DataType>> = vec![vec![1.into(), "x".into(), "c".into()]];
+        g.replay_piece(r, right, &[0], &key, tag, 0);
 
-                // and swap back replay pieces
-                self.replay_pieces = replay_pieces_tmp;
-
-                // here's another bit that's a little subtle:
-                //
-                // remember how, above, we stripped out the upquery identifier from the replay's
-                // tag? consider what happens if we buffer a replay with, say, tag 7.2 (so, upquery
-                // 7, path 2). later, when some other replay comes along with, say, tag 7.1, we
-                // decide that we're done buffering. we then release the buffered records from the
-                // first replay alongside the ones that were in the 7.1 replay. but, we just
-                // effectively _changed_ the tag for the records in that first replay! is that ok?
-                // it turns out it _is_, and here is the argument for why:
-                //
-                // first, for a given upquery, let's consider what paths flow through us when there
-                // is only a single union on the upquery's path. we know there is then one path for
-                // each parent we have. an upquery from below must query each of our parents once
-                // to get the complete results. those queries will all have the same upquery id,
-                // but different path ids. since there is no union above us or below us on the
-                // path, there are exactly as many paths as we have ancestors, and those paths only
-                // branch _above_ us. below us, those different paths are the _same_. this means
-                // that no matter what path discriminator we forward something with, it will follow
-                // the right path.
-                //
-                // now, what happens if there is a exactly one union on the upquery's path, at or
-                // above one of our parents. well, _that_ parent will have as many distinct path
-                // identifiers as that union has parents. we know that it will only produce _one_
-                // upquery response through (since the union will buffer), and that the repsonse
-                // will have (an arbitrary chosen) one of those path identifiers. we know that path
-                // discriminators are distinct, so whichever identifier our union ancestor chooses,
-                // it will be distinct from the paths that go through our _other_ parents.
-                // furthermore, we know that all those path identifiers ultimately share the same
-                // path below us. and we also know that the path identifiers of the paths through
-                // our other parents share that same path. so choosing any of them is fine.
-                //
-                // if we have unions above multiple of our parents, the same argument holds.
-                //
-                // if those unions again have ancestors that are unions, the same argument holds.
-                //
-                // the missing piece then is how a union that has a union as a _descendant_ knows
-                // that any choice it makes for path identifier is fine for that descendant. this
-                // is trickier to argue, but the argument goes like this:
-                //
-                // imagine you have a union immediately followed by a union, followed by some node
-                // that wishes to make an upquery. imagine that each union has two incoming edges:
-                // the bottom union has two edges to the top union (a "diamond"), and the top union
-                // has two incoming edges from disjoint parts of the graph. i don't know why you'd
-                // have that, but let's imagine. for the one upquery id here, there are four path
-                // identifiers: bottom-left:top-left, bottom-left:top-right, bottom-right:top-left,
-                // and bottom-right:top-right. as the top union, we will therefore receive two
-                // NOPE
-
-                RawProcessingResult::ReplayPiece {
-                    rows: rs,
-                    keys: released,
-                    captured,
-                }
-            }
-        }
+        let s = stats(&g);
+        assert_eq!(s["in flight keys"], 0u64.into());
+        assert_eq!(s["buffered records"], 0u64.into());
+        assert_eq!(s["releases"], 1u64.into());
+        assert_eq!(s["timed batches"], 2u64.into());
     }
 
-    fn on_eviction(&mut self, from: LocalNodeIndex, tag: Tag, keys: &[Vec<DataType>]) {
-        for key in keys {
-            // TODO: the key.clone()s here are really sad
-            for (_, e) in self
-                .replay_pieces
-                .range_mut((tag, key.clone(), 0)..=(tag, key.clone(), usize::max_value()))
-            {
-                if e.buffered.contains_key(&from) {
-                    // we've already received something from left, but it has now been evicted.
-                    // we can't remove the buffered replay, since we'll then get confused when the
-                    // other parts of the replay arrive from the other sides. we also can't drop
-                    // the eviction, because the eviction might be there to, say, ensure key
-                    // monotonicity in the face of joins. instead, we have to *buffer* the eviction
-                    // and emit it immediately after releasing the replay for this key. we do need
-                    // to emit the replay, as downstream nodes are waiting for it.
-                    //
-                    // NOTE: e.evict may already be true here, as we have no guarantee that
-                    // upstream nodes won't send multiple evictions in a row (e.g., joins evictions
-                    // could cause this).
-                    e.evict = true;
-                } else if !e.buffered.is_empty() {
-                    // we've received replay pieces for this key from other ancestors, but not from
-                    // this one. this indicates that the eviction happened logically before the
-                    // replay requset came in, so we do in fact want to do the replay first
-                    // downstream.
-                }
-            }
-        }
-    }
+    #[test]
+    fn it_merges_repeated_replay_pieces() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
 
-    fn suggest_indexes(&self, _: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        // index nothing (?)
-        HashMap::new()
-    }
+        let mut emits = HashMap::new();
+        emits.insert(l.as_global(), vec![0, 1]);
+        emits.insert(r.as_global(), vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
 
-    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
-        match self.emit {
-            Emit::AllFrom(p, _) => Some(vec![(p.as_global(), col)]),
-            Emit::Project { ref emit, .. } => Some(
-                emit.iter()
-                    .map(|(src, emit)| (src.as_global(), emit[col]))
-                    .collect(),
-            ),
-        }
-    }
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec![1.into()]).into_iter().collect();
+        let first: Vec<Vec<DataType>> = vec![vec![1.into(), "a".into()]];
+        let second: Vec<Vec<DataType>> = vec![vec![1.into(), "b".into()]];
+        let right: Vec<Vec<DataType>> = vec![vec![1.into(), "x".into(), "c".into()]];
 
-    fn description(&self, detailed: bool) -> String {
-        // Ensure we get a consistent output by sorting.
-        match self.emit {
-            Emit::AllFrom(..) => "⊍".to_string(),
-            Emit::Project { .. } if !detailed => String::from("⋃"),
-            Emit::Project { ref emit, .. } => {
-                let mut emit = emit.iter().collect::<Vec<_>>();
-                emit.sort();
-                emit.iter()
-                    .map(|&(src, emit)| {
-                        let cols = emit
-                            .iter()
-                            .map(ToString::to_string)
-                            .collect::<Vec<_>>()
-                            .join(", ");
-                        format!("{}:[{}]", src.as_global().index(), cols)
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ⋃ ")
+        // the same ancestor answers twice, which must not count as the other ancestor's piece
+        for rs in vec![first, second] {
+            match g.replay_piece(l, rs, &[0], &key, tag, 0) {
+                RawProcessingResult::ReplayPiece { rows, captured, .. } => {
+                    assert!(rows.is_empty());
+                    assert_eq!(captured, key);
+                }
+                _ => unreachable!(),
             }
         }
-    }
-    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
-        match self.emit {
-            Emit::AllFrom(p, _) => vec![(p.as_global(), Some(col))],
-            Emit::Project { ref emit, .. } => emit
-                .iter()
-                .map(|(src, emit)| (src.as_global(), Some(emit[col])))
-                .collect(),
+
+        match g.replay_piece(r, right, &[0], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, keys, .. } => {
+                assert_eq!(keys, key);
+                let mut rows: Vec<_> = rows.into_iter().map(|r| r.extract().0).collect();
+                rows.sort();
+                assert_eq!(
+                    rows,
+                    vec![
+                        vec![1.into(), "a".into()],
+                        vec![1.into(), "b".into()],
+                        vec![1.into(), "c".into()],
+                    ]
+                );
+            }
+            _ => unreachable!(),
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    use crate::ops;
 
-    fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+    #[test]
+    fn it_replays_composite_keys() {
         let mut g = ops::test::MockGraph::new();
         let l = g.add_base("left", &["l0", "l1"]);
         let r = g.add_base("right", &["r0", "r1", "r2"]);
@@ -837,67 +5025,47 @@ mod tests {
         let mut emits = HashMap::new();
         emits.insert(l.as_global(), vec![0, 1]);
         emits.insert(r.as_global(), vec![0, 2]);
-        g.set_op("union", &["u0", "u1"], Union::new(emits), false);
-        (g, l, r)
-    }
-
-    #[test]
-    fn it_describes() {
-        let (u, l, r) = setup();
-        assert_eq!(
-            u.node().description(true),
-            format!("{}:[0, 1] ⋃ {}:[0, 2]", l, r)
-        );
-    }
+        g.set_op("union", &["u0", "u1"], Union::new_unchecked(emits), false);
 
-    #[test]
-    fn it_works() {
-        let (mut u, l, r) = setup();
-
-        // forward from left should emit original record
-        let left = vec![1.into(), "a".into()];
-        assert_eq!(u.one_row(l, left.clone(), false), vec![left].into());
+        // both output columns make up the key, and map to different columns in each ancestor
+        let tag = Tag::new(1);
+        let key: HashSet<Vec<DataType>> = Some(vec![1.into(), "a".into()]).into_iter().collect();
+        let lpiece: Vec<Vec<DataType>> = vec![vec![1.into(), "a".into()]];
+        match g.replay_piece(l, lpiece, &[0, 1], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, captured, .. } => {
+                assert!(rows.is_empty());
+                assert_eq!(captured, key);
+            }
+            _ => unreachable!(),
+        }
 
-        // forward from right should emit subset record
-        let right = vec![1.into(), "skipped".into(), "x".into()];
-        assert_eq!(
-            u.one_row(r, right.clone(), false),
-            vec![vec![1.into(), "x".into()]].into()
-        );
+        let rpiece: Vec<Vec<DataType>> = vec![
+            vec![1.into(), "x".into(), "a".into()],
+            vec![1.into(), "y".into(), "a".into()],
+        ];
+        match g.replay_piece(r, rpiece, &[0, 1], &key, tag, 0) {
+            RawProcessingResult::ReplayPiece { rows, keys, .. } => {
+                assert_eq!(keys, key);
+                let row: Vec<DataType> = vec![1.into(), "a".into()];
+                assert_eq!(rows, vec![row.clone(), row.clone(), row].into());
+            }
+            _ => unreachable!(),
+        }
     }
 
     #[test]
-    fn it_suggests_indices() {
-        use std::collections::HashMap;
-        let (u, _, _) = setup();
-        let me = 1.into();
-        assert_eq!(u.node().suggest_indexes(me), HashMap::new());
-    }
+    fn it_resolves_primary() {
+        let (u, l, _) = setup();
+        let primary = |col| match **u.node() {
+            NodeOperator::Union(ref u) => u.resolve_primary(col),
+            _ => unreachable!(),
+        };
 
-    #[test]
-    fn it_resolves() {
-        let (u, l, r) = setup();
-        let r0 = u.node().resolve(0);
-        assert!(r0
-            .as_ref()
-            .unwrap()
-            .iter()
-            .any(|&(n, c)| n == l.as_global() && c == 0));
-        assert!(r0
-            .as_ref()
-            .unwrap()
-            .iter()
-            .any(|&(n, c)| n == r.as_global() && c == 0));
-        let r1 = u.node().resolve(1);
-        assert!(r1
-            .as_ref()
-            .unwrap()
-            .iter()
-            .any(|&(n, c)| n == l.as_global() && c == 1));
-        assert!(r1
-            .as_ref()
-            .unwrap()
-            .iter()
-            .any(|&(n, c)| n == r.as_global() && c == 2));
+        // left was added first, so it has the lower index
+        assert_eq!(primary(0), Some((l.as_global(), 0)));
+        assert_eq!(primary(1), Some((l.as_global(), 1)));
+        for _ in 0..10 {
+            assert_eq!(primary(0), primary(0));
+        }
     }
 }
@@ -17,11 +17,14 @@ pub struct ReplayPathSegment {
     pub partial_key: Option<Vec<usize>>,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum SourceSelection {
     /// Query only the shard of the source that matches the key.
+    ///
+    /// `key_i_to_shard` holds the positions in the lookup key of the source's sharding columns, in
+    /// the order the source shards by them.
     KeyShard {
-        key_i_to_shard: usize,
+        key_i_to_shard: Vec<usize>,
         nshards: usize,
     },
     /// Query the same shard of the source as the destination.
@@ -162,6 +165,13 @@ pub enum Packet {
         new_txs: (LocalNodeIndex, Vec<ReplicaAddr>),
     },
 
+    /// Change the fraction of records from `from` that a sampling `Union` node lets through.
+    UpdateSamplingRate {
+        node: LocalNodeIndex,
+        from: LocalNodeIndex,
+        rate: f64,
+    },
+
     /// Set up a fresh, empty state for a node, indexed by a particular column.
     ///
     /// This is done in preparation of a subsequent state replay.
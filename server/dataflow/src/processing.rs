@@ -134,6 +134,14 @@ where
         false
     }
 
+    /// The ancestor this node passes every record through from unchanged, if it does nothing
+    /// else, so that the node could be spliced out of the graph.
+    ///
+    /// Only meaningful once the node has been connected to the graph.
+    fn can_bypass(&self) -> Option<NodeIndex> {
+        None
+    }
+
     /// Produce a compact, human-readable description of this node for Graphviz.
     ///
     /// If `detailed` is true, emit more info.
@@ -148,7 +156,10 @@ where
     ///    𝛴    |  Sum
     ///    ⋈    |  Join
     ///    ⋉    |  Left join
+    ///   ⋈≤    |  As-of join
     ///    ⋃    |  Union
+    ///    ⩁    |  Distinct union
+    ///    ⊍    |  Shard merger
     ///    σ    |  Filter
     ///    π    |  Projection
     ///    ≡    |  Identity
@@ -165,11 +176,19 @@ where
         Default::default()
     }
 
+    /// Report counters about partial replays buffered by this operator, if it buffers any.
+    ///
+    /// Unlike `probe`, these are structured so that they can be aggregated across nodes.
+    fn metrics(&self) -> Option<noria::debug::stats::OpMetrics> {
+        None
+    }
+
     /// Called when a node is first connected to the graph.
     ///
     /// All its ancestors are present, but this node and its children may not have been connected
-    /// yet.
-    fn on_connected(&mut self, graph: &Graph);
+    /// yet. An error means the node cannot work with its ancestors as they are, and fails the
+    /// migration that added it.
+    fn on_connected(&mut self, graph: &Graph) -> Result<(), String>;
 
     /// Called when a domain is finalized and is about to be booted.
     ///
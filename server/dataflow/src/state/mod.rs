@@ -3,6 +3,7 @@ mod memory_state;
 mod mk_key;
 mod persistent_state;
 mod single_state;
+mod spill;
 
 use std::borrow::Cow;
 use std::ops::Deref;
@@ -16,6 +17,7 @@ use hashbag::HashBag;
 
 pub(crate) use self::memory_state::MemoryState;
 pub(crate) use self::persistent_state::PersistentState;
+pub(crate) use self::spill::{KeyedStore, SpillingStore};
 
 pub(crate) trait State: SizeOf + Send {
     /// Add an index keyed by the given columns and replayed to by the given partial tags.
@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tempfile::{tempdir, TempDir};
+
+use crate::prelude::*;
+
+/// Keyed state that an operator keeps internally, rather than in a materialization.
+///
+/// `HashMap` is the default, in-memory store. `SpillingStore` bounds how much is kept in memory.
+pub(crate) trait KeyedStore<V>: fmt::Debug + Send {
+    fn get_mut(&mut self, key: &[DataType]) -> io::Result<Option<&mut V>>;
+
+    fn insert(&mut self, key: Vec<DataType>, value: V);
+
+    fn remove(&mut self, key: &[DataType]) -> io::Result<Option<V>>;
+
+    fn len(&self) -> usize;
+
+    /// Every key in the store along with its value, whether or not it is in memory.
+    fn entries(&self) -> io::Result<Vec<(Vec<DataType>, V)>>;
+
+    /// Bring the store back within its memory budget.
+    ///
+    /// Operators call this once they are done with a batch, so that keys are moved out of memory
+    /// together rather than one at a time as the batch touches them.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The number of times a key has been moved out of memory.
+    fn spills(&self) -> usize {
+        0
+    }
+}
+
+impl<V: Clone + fmt::Debug + Send> KeyedStore<V> for HashMap<Vec<DataType>, V> {
+    fn get_mut(&mut self, key: &[DataType]) -> io::Result<Option<&mut V>> {
+        Ok(HashMap::get_mut(self, key))
+    }
+
+    fn insert(&mut self, key: Vec<DataType>, value: V) {
+        HashMap::insert(self, key, value);
+    }
+
+    fn remove(&mut self, key: &[DataType]) -> io::Result<Option<V>> {
+        Ok(HashMap::remove(self, key))
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn entries(&self) -> io::Result<Vec<(Vec<DataType>, V)>> {
+        Ok(self.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+    }
+}
+
+fn invalid_data(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A `KeyedStore` that keeps about `budget` keys in memory.
+///
+/// Keys may exceed the budget while a batch is being processed. `flush` then writes the least
+/// recently used keys to a single segment file in a temporary directory and drops them from
+/// memory, leaving a quarter of the budget free so that the next few new keys do not each cause
+/// another write. Accessing a spilled key reads its whole segment back in, since keys that went
+/// cold together tend to be used together.
+///
+/// I/O errors are returned to the caller rather than treated as fatal. A failed spill leaves its
+/// keys in memory, and a failed read leaves the key on disk, so a later attempt may still succeed.
+pub(crate) struct SpillingStore<V> {
+    budget: usize,
+
+    // in-memory values along with when they were last used, and keys by when they were last used
+    hot: HashMap<Vec<DataType>, (u64, V)>,
+    lru: BTreeMap<u64, Vec<DataType>>,
+    clock: u64,
+
+    // the segment that holds each spilled key, and how many keys each segment still holds
+    cold: HashMap<Vec<DataType>, u64>,
+    segments: HashMap<u64, usize>,
+    // segments that no longer hold any keys, but whose file has not yet been removed
+    dead: Vec<u64>,
+    next_segment: u64,
+    dir: Option<TempDir>,
+
+    spills: usize,
+}
+
+impl<V> fmt::Debug for SpillingStore<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpillingStore")
+            .field("budget", &self.budget)
+            .field("hot", &self.hot.len())
+            .field("cold", &self.cold.len())
+            .field("segments", &self.segments.len())
+            .field("dir", &self.dir.as_ref().map(TempDir::path))
+            .finish()
+    }
+}
+
+impl<V> SpillingStore<V>
+where
+    V: Serialize + DeserializeOwned,
+{
+    pub(crate) fn new(budget: usize) -> Self {
+        assert!(budget > 0);
+        SpillingStore {
+            budget,
+            hot: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            cold: HashMap::new(),
+            segments: HashMap::new(),
+            dead: Vec::new(),
+            next_segment: 0,
+            dir: None,
+            spills: 0,
+        }
+    }
+
+    fn segment_path(&self, segment: u64) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.path().join(segment.to_string()))
+    }
+
+    fn read_segment(&self, segment: u64) -> io::Result<Vec<(Vec<DataType>, V)>> {
+        // a segment is only ever recorded after the directory it was written to was created
+        let path = self.segment_path(segment).unwrap();
+        let data = fs::read(path)?;
+        bincode::deserialize(&data).map_err(invalid_data)
+    }
+
+    /// Mark `key`, which must be in memory, as just used.
+    fn touch(&mut self, key: &[DataType]) {
+        self.clock += 1;
+        let entry = self.hot.get_mut(key).unwrap();
+        let key = self.lru.remove(&entry.0).unwrap();
+        entry.0 = self.clock;
+        self.lru.insert(self.clock, key);
+    }
+
+    fn insert_hot(&mut self, key: Vec<DataType>, value: V) {
+        self.clock += 1;
+        if let Some((last, _)) = self.hot.insert(key.clone(), (self.clock, value)) {
+            self.lru.remove(&last);
+        }
+        self.lru.insert(self.clock, key);
+    }
+
+    /// Forget the spilled copy of `key`, if there is one.
+    fn forget_cold(&mut self, key: &[DataType]) {
+        if let Some(segment) = self.cold.remove(key) {
+            let live = self.segments.get_mut(&segment).unwrap();
+            *live -= 1;
+            if *live == 0 {
+                self.segments.remove(&segment);
+                self.dead.push(segment);
+            }
+        }
+    }
+
+    /// Read the segment holding `key` back into memory, if `key` has been spilled.
+    fn fault(&mut self, key: &[DataType]) -> io::Result<()> {
+        let segment = match self.cold.get(key) {
+            Some(&segment) => segment,
+            None => return Ok(()),
+        };
+
+        for (k, v) in self.read_segment(segment)? {
+            // keys that have been overwritten or removed since the spill are stale in the segment
+            if self.cold.get(&k) == Some(&segment) {
+                self.cold.remove(&k);
+                self.insert_hot(k, v);
+            }
+        }
+        self.segments.remove(&segment);
+        self.dead.push(segment);
+
+        // the requested key is the one that is about to be used
+        self.touch(key);
+        Ok(())
+    }
+}
+
+impl<V> KeyedStore<V> for SpillingStore<V>
+where
+    V: Serialize + DeserializeOwned + Clone + Send,
+{
+    fn get_mut(&mut self, key: &[DataType]) -> io::Result<Option<&mut V>> {
+        if self.hot.contains_key(key) {
+            self.touch(key);
+        } else {
+            self.fault(key)?;
+        }
+        Ok(self.hot.get_mut(key).map(|e| &mut e.1))
+    }
+
+    fn insert(&mut self, key: Vec<DataType>, value: V) {
+        self.forget_cold(&key[..]);
+        self.insert_hot(key, value);
+    }
+
+    fn remove(&mut self, key: &[DataType]) -> io::Result<Option<V>> {
+        self.fault(key)?;
+        Ok(self.hot.remove(key).map(|(last, value)| {
+            self.lru.remove(&last);
+            value
+        }))
+    }
+
+    fn len(&self) -> usize {
+        self.hot.len() + self.cold.len()
+    }
+
+    fn entries(&self) -> io::Result<Vec<(Vec<DataType>, V)>> {
+        let mut entries = Vec::with_capacity(self.len());
+        for &segment in self.segments.keys() {
+            for (k, v) in self.read_segment(segment)? {
+                if self.cold.get(&k) == Some(&segment) {
+                    entries.push((k, v));
+                }
+            }
+        }
+        entries.extend(self.hot.iter().map(|(k, (_, v))| (k.clone(), v.clone())));
+        Ok(entries)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while let Some(segment) = self.dead.pop() {
+            if let Err(e) = fs::remove_file(self.segment_path(segment).unwrap()) {
+                self.dead.push(segment);
+                return Err(e);
+            }
+        }
+
+        if self.hot.len() <= self.budget {
+            return Ok(());
+        }
+
+        if self.dir.is_none() {
+            self.dir = Some(tempdir()?);
+        }
+
+        let keep = self.budget - self.budget / 4;
+        let victims: Vec<u64> = self
+            .lru
+            .keys()
+            .take(self.hot.len() - keep)
+            .cloned()
+            .collect();
+        let data = {
+            let entries: Vec<_> = victims
+                .iter()
+                .map(|t| {
+                    let key = &self.lru[t];
+                    (key, &self.hot[key].1)
+                })
+                .collect();
+            bincode::serialize(&entries).map_err(invalid_data)?
+        };
+
+        let segment = self.next_segment;
+        fs::write(self.segment_path(segment).unwrap(), data)?;
+        self.next_segment += 1;
+
+        self.segments.insert(segment, victims.len());
+        self.spills += victims.len();
+        for t in victims {
+            let key = self.lru.remove(&t).unwrap();
+            self.hot.remove(&key);
+            self.cold.insert(key, segment);
+        }
+        Ok(())
+    }
+
+    fn spills(&self) -> usize {
+        self.spills
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(k: i32) -> Vec<DataType> {
+        vec![k.into()]
+    }
+
+    #[test]
+    fn it_spills_and_faults() {
+        let mut s = SpillingStore::new(2);
+        s.insert(key(1), String::from("a"));
+        s.insert(key(2), String::from("b"));
+        s.flush().unwrap();
+        assert_eq!(s.spills(), 0);
+
+        // using 1 makes 2 the coldest key
+        s.get_mut(&key(1)).unwrap().unwrap().push('!');
+        s.insert(key(3), String::from("c"));
+        assert_eq!(s.spills(), 0);
+        s.flush().unwrap();
+        assert_eq!(s.spills(), 1);
+        assert_eq!(s.len(), 3);
+
+        // faulting 2 back in spills 1 on the next flush
+        assert_eq!(s.get_mut(&key(2)).unwrap().cloned(), Some("b".into()));
+        s.flush().unwrap();
+        assert_eq!(s.spills(), 2);
+
+        let mut entries = s.entries().unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                (key(1), "a!".into()),
+                (key(2), "b".into()),
+                (key(3), "c".into())
+            ]
+        );
+
+        assert_eq!(s.remove(&key(1)).unwrap(), Some("a!".into()));
+        assert_eq!(s.remove(&key(1)).unwrap(), None);
+        assert_eq!(s.get_mut(&key(4)).unwrap(), None);
+        assert_eq!(s.len(), 2);
+    }
+
+    #[test]
+    fn it_spills_in_segments() {
+        let mut s = SpillingStore::new(4);
+        for k in 0..8 {
+            s.insert(key(k), k);
+        }
+
+        // one flush writes every surplus key, leaving room for a quarter of the budget
+        s.flush().unwrap();
+        assert_eq!(s.spills(), 5);
+        assert_eq!(s.segments.len(), 1);
+
+        // overwriting a spilled key makes its spilled copy stale
+        s.insert(key(0), 100);
+        assert_eq!(s.get_mut(&key(1)).unwrap().cloned(), Some(1));
+        assert_eq!(s.get_mut(&key(0)).unwrap().cloned(), Some(100));
+
+        // the whole segment came back in, and its file is removed on the next flush
+        assert!(s.cold.is_empty());
+        assert_eq!(s.len(), 8);
+        s.flush().unwrap();
+        assert!(s.dead.is_empty());
+        assert_eq!(s.segments.len(), 1);
+    }
+}
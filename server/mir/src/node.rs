@@ -866,14 +866,41 @@ impl Debug for MirNodeType {
                             FilterCondition::Comparison(ref op, ref x) => {
                                 Some(format!("f{} {} {:?}", i, escape(&format!("{}", op)), x))
                             }
-                            FilterCondition::In(ref xs) => Some(format!(
-                                "f{} IN ({})",
+                            FilterCondition::In {
+                                values: ref xs,
+                                negated,
+                            } => Some(format!(
+                                "f{} {}IN ({})",
                                 i,
+                                if negated { "NOT " } else { "" },
                                 xs.iter()
                                     .map(|d| format!("{}", d))
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Range {
+                                ref lower,
+                                ref upper,
+                            } => Some(format!(
+                                "f{} {}",
+                                i,
+                                ops::filter::describe_range(lower, upper)
+                            )),
+                            FilterCondition::Regex {
+                                ref pattern,
+                                negated,
+                            } => Some(format!(
+                                "f{} {}~ /{}/",
+                                i,
+                                if negated { "!" } else { "" },
+                                escape(pattern)
+                            )),
+                            FilterCondition::And(ref bs) => {
+                                Some(escape(&ops::filter::describe_branches(bs, false)))
+                            }
+                            FilterCondition::Or(ref bs) => {
+                                Some(escape(&ops::filter::describe_branches(bs, true)))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
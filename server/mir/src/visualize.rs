@@ -3,7 +3,7 @@ use std::fmt::{self, Write};
 
 use crate::node::{MirNode, MirNodeType};
 use crate::query::MirQuery;
-use dataflow::ops::filter::FilterCondition;
+use dataflow::ops::filter::{self, FilterCondition};
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
@@ -185,14 +185,37 @@ impl GraphViz for MirNodeType {
                             FilterCondition::Comparison(ref op, ref x) => {
                                 Some(format!("f{} {} {}", i, escape(&format!("{}", op)), x))
                             }
-                            FilterCondition::In(ref xs) => Some(format!(
-                                "f{} IN ({})",
+                            FilterCondition::In {
+                                values: ref xs,
+                                negated,
+                            } => Some(format!(
+                                "f{} {}IN ({})",
                                 i,
+                                if negated { "NOT " } else { "" },
                                 xs.iter()
                                     .map(|d| format!("{}", d))
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Range {
+                                ref lower,
+                                ref upper,
+                            } => Some(format!("f{} {}", i, filter::describe_range(lower, upper))),
+                            FilterCondition::Regex {
+                                ref pattern,
+                                negated,
+                            } => Some(format!(
+                                "f{} {}~ /{}/",
+                                i,
+                                if negated { "!" } else { "" },
+                                escape(pattern)
+                            )),
+                            FilterCondition::And(ref bs) => {
+                                Some(escape(&filter::describe_branches(bs, false)))
+                            }
+                            FilterCondition::Or(ref bs) => {
+                                Some(escape(&filter::describe_branches(bs, true)))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
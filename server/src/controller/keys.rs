@@ -315,7 +315,7 @@ mod tests {
         let x = g.add_node(node::Node::new(
             "x",
             &["x1", "x2"],
-            ops::NodeOperator::Union(ops::union::Union::new(
+            ops::NodeOperator::Union(ops::union::Union::new_unchecked(
                 vec![(a, vec![0, 1]), (b, vec![0, 1])].into_iter().collect(),
             )),
         ));
@@ -365,7 +365,7 @@ impl<'a> Plan<'a> {
                                         nodes.iter().next().unwrap().1.as_ref().unwrap();
                                     if lookup_key.len() == 1 {
                                         if c == lookup_key[0] {
-                                            Some(0)
+                                            Some(vec![0])
                                         } else {
                                             None
                                         }
@@ -378,9 +378,20 @@ impl<'a> Plan<'a> {
                                         // NOTE: this _could_ be merged with the if arm above,
                                         // but keeping them separate allows us to make this case
                                         // explicit and more obvious
-                                        lookup_key.iter().position(|&kc| kc == c)
+                                        lookup_key.iter().position(|&kc| kc == c).map(|i| vec![i])
                                     }
                                 }
+                                Sharding::ByColumns(ref cs, _) => {
+                                    // the source is sharded by a composite key, so we can only
+                                    // pick a single shard if the lookup key includes every one of
+                                    // its columns. the shard is then found by hashing those
+                                    // values of the lookup key in sharding column order.
+                                    let lookup_key =
+                                        nodes.iter().next().unwrap().1.as_ref().unwrap();
+                                    cs.iter()
+                                        .map(|&c| lookup_key.iter().position(|&kc| kc == c))
+                                        .collect::<Option<Vec<_>>>()
+                                }
                                 s if s.is_none() => None,
                                 s => unreachable!("unhandled new sharding pattern {:?}", s),
                             };
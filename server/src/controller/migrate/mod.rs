@@ -73,8 +73,33 @@ impl<'a> Migration<'a> {
         FS: IntoIterator<Item = S2>,
         I: Into<NodeOperator>,
     {
-        let mut i = node::Node::new(name.to_string(), fields, i.into());
-        i.on_connected(&self.mainline.ingredients);
+        match self.try_add_ingredient(name, fields, i) {
+            Ok(ni) => ni,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Add the given `Ingredient` to the Soup, like `add_ingredient`, unless it cannot work with
+    /// its ancestors.
+    ///
+    /// The ingredient is not added if it reports an error once connected, and that error is
+    /// returned instead, so the caller can fail the migration.
+    pub fn try_add_ingredient<S1, FS, S2, I>(
+        &mut self,
+        name: S1,
+        fields: FS,
+        i: I,
+    ) -> Result<NodeIndex, String>
+    where
+        S1: ToString,
+        S2: ToString,
+        FS: IntoIterator<Item = S2>,
+        I: Into<NodeOperator>,
+    {
+        let name = name.to_string();
+        let mut i = node::Node::new(name.clone(), fields, i.into());
+        i.on_connected(&self.mainline.ingredients)
+            .map_err(|e| format!("cannot add node {}: {}", name, e))?;
         let parents = i.ancestors();
         assert!(!parents.is_empty());
 
@@ -93,7 +118,7 @@ impl<'a> Migration<'a> {
             self.mainline.ingredients.add_edge(parent, ni, ());
         }
         // and tell the caller its id
-        ni
+        Ok(ni)
     }
 
     /// Add the given `Base` to the Soup.
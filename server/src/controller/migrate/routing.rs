@@ -109,16 +109,18 @@ pub fn add(
                 // the ingress is sharded the same way as its target, but with remappings of parent
                 // columns applied
                 let sharding = if graph[parent].is_sharder() {
-                    let parent_out_sharding =
-                        graph[parent].with_sharder(|s| s.sharded_by()).unwrap();
+                    let parent_out_sharding = graph[parent]
+                        .with_sharder(|s| s.sharded_by_columns().to_vec())
+                        .unwrap();
                     // TODO(malte): below is ugly, but the only way to get the sharding width at
                     // this point; the sharder parent does not currently have the information.
                     // Change this once we support per-subgraph sharding widths and
                     // the sharder knows how many children it is supposed to have.
-                    if let Sharding::ByColumn(_, width) = graph[node].sharded_by() {
-                        Sharding::ByColumn(parent_out_sharding, width)
-                    } else {
-                        unreachable!()
+                    match graph[node].sharded_by() {
+                        Sharding::ByColumn(_, width) | Sharding::ByColumns(_, width) => {
+                            Sharding::by_columns(&parent_out_sharding, width)
+                        }
+                        _ => unreachable!(),
                     }
                 } else {
                     graph[parent].sharded_by()
@@ -40,15 +40,13 @@ pub fn shard(
             let s = graph[node]
                 .with_reader(|r| r.key())
                 .unwrap()
-                .and_then(|c| {
-                    if c.len() == 1 {
-                        if graph[node].fields()[c[0]] == "bogokey" {
-                            Some(Sharding::ForcedNone)
-                        } else {
-                            Some(Sharding::ByColumn(c[0], sharding_factor))
-                        }
+                .map(|c| {
+                    if c.iter().any(|&c| graph[node].fields()[c] == "bogokey") {
+                        Sharding::ForcedNone
                     } else {
-                        None
+                        // a multi-column key is sharded by the combination of its columns, which
+                        // is also how the client picks the shard to ask for a key.
+                        Sharding::by_columns(c, sharding_factor)
                     }
                 })
                 .unwrap_or(Sharding::ForcedNone);
@@ -63,7 +61,7 @@ pub fn shard(
 
             if s != input_shardings[&ni] {
                 // input is sharded by different key -- need shuffle
-                reshard(log, new, &mut swaps, graph, ni, node, s);
+                reshard(log, new, &mut swaps, graph, ni, node, s.clone());
             }
             graph.node_weight_mut(node).unwrap().shard_by(s);
             continue;
@@ -74,39 +72,50 @@ pub fn shard(
             HashMap::new()
         };
         if need_sharding.is_empty()
-            && (input_shardings.len() == 1 || input_shardings.iter().all(|(_, &s)| s.is_none()))
+            && (input_shardings.len() == 1 || input_shardings.iter().all(|(_, s)| s.is_none()))
         {
             let mut s = if input_shardings
                 .iter()
-                .any(|(_, &s)| s == Sharding::ForcedNone)
+                .any(|(_, s)| *s == Sharding::ForcedNone)
             {
                 Sharding::ForcedNone
             } else {
-                input_shardings.iter().map(|(_, &s)| s).next().unwrap()
+                input_shardings.values().next().cloned().unwrap()
             };
             info!(log, "preserving sharding of pass-through node";
                   "node" => ?node,
                   "sharding" => ?s);
 
             if graph[node].is_internal() || graph[node].is_base() {
-                if let Sharding::ByColumn(c, shards) = s {
-                    // remap c according to node's semantics
-                    let n = &graph[node];
-                    let src = (0..n.fields().len()).find(|&col| {
+                let n = &graph[node];
+                let remap = |c| {
+                    (0..n.fields().len()).find(|&col| {
                         if let Some(src) = n.parent_columns(col)[0].1 {
                             src == c
                         } else {
                             false
                         }
-                    });
-
-                    if let Some(src) = src {
-                        s = Sharding::ByColumn(src, shards);
-                    } else {
-                        // sharding column is not emitted by this node!
-                        // at this point, sharding is effectively random.
-                        s = Sharding::Random(shards);
+                    })
+                };
+                match s {
+                    Sharding::ByColumn(c, shards) => {
+                        // remap c according to node's semantics
+                        if let Some(src) = remap(c) {
+                            s = Sharding::ByColumn(src, shards);
+                        } else {
+                            // sharding column is not emitted by this node!
+                            // at this point, sharding is effectively random.
+                            s = Sharding::Random(shards);
+                        }
+                    }
+                    Sharding::ByColumns(ref cs, shards) => {
+                        // same as above, but *every* column must still be emitted
+                        s = match cs.iter().map(|&c| remap(c)).collect::<Option<Vec<_>>>() {
+                            Some(srcs) => Sharding::ByColumns(srcs, shards),
+                            None => Sharding::Random(shards),
+                        };
                     }
+                    _ => {}
                 }
             }
             graph.node_weight_mut(node).unwrap().shard_by(s);
@@ -229,7 +238,15 @@ pub fn shard(
                             let need_sharding = Sharding::ByColumn(col, sharding_factor);
                             if input_shardings[&ni] != need_sharding {
                                 // input is sharded by different key -- need shuffle
-                                reshard(log, new, &mut swaps, graph, ni, node, need_sharding);
+                                reshard(
+                                    log,
+                                    new,
+                                    &mut swaps,
+                                    graph,
+                                    ni,
+                                    node,
+                                    need_sharding.clone(),
+                                );
                                 input_shardings.insert(ni, need_sharding);
                             }
                         }
@@ -348,7 +365,7 @@ pub fn shard(
                         if input_shardings[&ni] != need_sharding {
                             debug!(log, "resharding input with sharding {:?} to match desired sharding {:?}",
                                input_shardings[&ni], need_sharding; "node" => ?node, "input" => ?ni);
-                            reshard(log, new, &mut swaps, graph, ni, node, need_sharding);
+                            reshard(log, new, &mut swaps, graph, ni, node, need_sharding.clone());
                             input_shardings.insert(ni, need_sharding);
                         }
                     }
@@ -376,8 +393,8 @@ pub fn shard(
         for (&ni, in_sharding) in &mut input_shardings {
             if !in_sharding.is_none() {
                 // ancestor must be forced to right sharding
-                reshard(log, new, &mut swaps, graph, ni, node, sharding);
-                *in_sharding = sharding;
+                reshard(log, new, &mut swaps, graph, ni, node, sharding.clone());
+                *in_sharding = sharding.clone();
             }
         }
     }
@@ -411,7 +428,15 @@ pub fn shard(
             assert!(!graph[p].is_source());
 
             // and that its children must be sharded somehow (otherwise what is the sharder doing?)
-            let col = graph[n].with_sharder(|s| s.sharded_by()).unwrap();
+            let cols = graph[n]
+                .with_sharder(|s| s.sharded_by_columns().to_vec())
+                .unwrap();
+            if cols.len() != 1 {
+                // TODO: composite sharders could be hoisted too if all columns resolve the same
+                trace!(log, "no, sharder is composite"; "columns" => ?cols);
+                continue;
+            }
+            let col = cols[0];
             let by = Sharding::ByColumn(col, sharding_factor);
 
             // we can only push sharding above newly created nodes that are not already sharded.
@@ -502,13 +527,13 @@ pub fn shard(
             let mut remove = Vec::new();
             for c in graph.neighbors_directed(p, petgraph::EdgeDirection::Outgoing) {
                 // what does c shard by?
-                let col = graph[c].with_sharder(|s| s.sharded_by());
-                if col.is_none() {
+                let cols = graph[c].with_sharder(|s| s.sharded_by_columns().to_vec());
+                if cols.is_none() {
                     // lifting n would shard a node that isn't expecting to be sharded
                     // TODO: we *could* insert a de-shard here
                     continue 'sharders;
                 }
-                let csharding = Sharding::ByColumn(col.unwrap(), sharding_factor);
+                let csharding = Sharding::by_columns(&cols.unwrap(), sharding_factor);
 
                 if csharding == by {
                     // sharding by the same key, which is now unnecessary.
@@ -650,7 +675,7 @@ fn reshard(
             let n: NodeOperator =
                 ops::union::Union::new_deshard(src, graph[src].sharded_by()).into();
             let mut n = graph[src].mirror(n);
-            n.shard_by(to);
+            n.shard_by(to.clone());
             n
         }
         Sharding::ByColumn(c, _) => {
@@ -658,6 +683,11 @@ fn reshard(
             n.shard_by(graph[src].sharded_by());
             n
         }
+        Sharding::ByColumns(ref cs, _) => {
+            let mut n = graph[src].mirror(node::special::Sharder::new_composite(cs.clone()));
+            n.shard_by(graph[src].sharded_by());
+            n
+        }
         Sharding::Random(_) => unreachable!(),
     };
     let node = graph.add_node(node);
@@ -701,42 +731,54 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
             .filter(|ni| !graph[*ni].is_source())
             .collect();
 
-        let remap = |nd: &Node, pni: NodeIndex, ps: Sharding| -> Sharding {
-            if nd.is_internal() || nd.is_base() {
-                if let Sharding::ByColumn(c, shards) = ps {
-                    // remap c according to node's semantics
-                    let src = (0..nd.fields().len()).find(|&col| {
-                        for pc in nd.parent_columns(col) {
-                            if let (p, Some(src)) = pc {
-                                // found column c in parent pni
-                                if p == pni && src == c {
-                                    // extract *child* column ID that we found a match for
-                                    return true;
-                                } else if !graph[pni].is_internal() {
-                                    // need to look transitively for an indirect parent, since
-                                    // `parent_columns`'s return values does not take sharder
-                                    // and desharder nodes previously added into account (as
-                                    // the `src` in the operator is only rewritten to the
-                                    // sharder later, in `on_connected`).
-                                    // NOTE(malte): just checking connectivity here is perhaps a
-                                    // bit too lax (i.e., may miss some incorrect shardings)
-                                    if petgraph::algo::has_path_connecting(graph, p, pni, None)
-                                        && src == c
-                                    {
-                                        return true;
-                                    }
-                                }
+        let remap_col = |nd: &Node, pni: NodeIndex, c: usize| -> Option<usize> {
+            // remap c according to node's semantics
+            (0..nd.fields().len()).find(|&col| {
+                for pc in nd.parent_columns(col) {
+                    if let (p, Some(src)) = pc {
+                        // found column c in parent pni
+                        if p == pni && src == c {
+                            // extract *child* column ID that we found a match for
+                            return true;
+                        } else if !graph[pni].is_internal() {
+                            // need to look transitively for an indirect parent, since
+                            // `parent_columns`'s return values does not take sharder
+                            // and desharder nodes previously added into account (as
+                            // the `src` in the operator is only rewritten to the
+                            // sharder later, in `on_connected`).
+                            // NOTE(malte): just checking connectivity here is perhaps a
+                            // bit too lax (i.e., may miss some incorrect shardings)
+                            if petgraph::algo::has_path_connecting(graph, p, pni, None) && src == c
+                            {
+                                return true;
                             }
                         }
-                        false
-                    });
-
-                    if let Some(src) = src {
-                        return Sharding::ByColumn(src, shards);
-                    } else {
-                        return Sharding::Random(shards);
                     }
                 }
+                false
+            })
+        };
+        let remap = |nd: &Node, pni: NodeIndex, ps: Sharding| -> Sharding {
+            if nd.is_internal() || nd.is_base() {
+                match ps {
+                    Sharding::ByColumn(c, shards) => {
+                        return match remap_col(nd, pni, c) {
+                            Some(src) => Sharding::ByColumn(src, shards),
+                            None => Sharding::Random(shards),
+                        };
+                    }
+                    Sharding::ByColumns(ref cs, shards) => {
+                        return match cs
+                            .iter()
+                            .map(|&c| remap_col(nd, pni, c))
+                            .collect::<Option<Vec<_>>>()
+                        {
+                            Some(srcs) => Sharding::ByColumns(srcs, shards),
+                            None => Sharding::Random(shards),
+                        };
+                    }
+                    _ => {}
+                }
             }
             // in all other cases, the sharding matches the parent's
             ps
@@ -750,7 +792,7 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
                     let in_sharding = remap(
                         n,
                         in_ni,
-                        Sharding::ByColumn(s.sharded_by(), sharding_factor),
+                        Sharding::by_columns(s.sharded_by_columns(), sharding_factor),
                     );
                     if in_sharding != n.sharded_by() {
                         crit!(
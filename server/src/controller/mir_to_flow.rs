@@ -19,7 +19,7 @@ pub(super) fn mir_query_to_flow_parts(
     mir_query: &mut MirQuery,
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
-) -> QueryFlowParts {
+) -> Result<QueryFlowParts, String> {
     use std::collections::VecDeque;
 
     let mut new_nodes = Vec::new();
@@ -35,7 +35,7 @@ pub(super) fn mir_query_to_flow_parts(
     while !node_queue.is_empty() {
         let n = node_queue.pop_front().unwrap();
         assert_eq!(in_edge_counts[&n.borrow().versioned_name()], 0);
-        let flow_node = mir_node_to_flow_parts(&mut n.borrow_mut(), mig, table_mapping);
+        let flow_node = mir_node_to_flow_parts(&mut n.borrow_mut(), mig, table_mapping)?;
         match flow_node {
             FlowNode::New(na) => new_nodes.push(na),
             FlowNode::Existing(na) => reused_nodes.push(na),
@@ -63,19 +63,19 @@ pub(super) fn mir_query_to_flow_parts(
         .expect("Leaf must have FlowNode by now")
         .address();
 
-    QueryFlowParts {
+    Ok(QueryFlowParts {
         name: mir_query.name.clone(),
         new_nodes,
         reused_nodes,
         query_leaf: leaf_na,
-    }
+    })
 }
 
 fn mir_node_to_flow_parts(
     mir_node: &mut MirNode,
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
-) -> FlowNode {
+) -> Result<FlowNode, String> {
     let name = mir_node.name.clone();
     match mir_node.flow_node {
         None => {
@@ -99,7 +99,7 @@ fn mir_node_to_flow_parts(
                         mig,
                         table_mapping,
                         None,
-                    )
+                    )?
                 }
                 MirNodeType::Base {
                     ref mut column_specs,
@@ -133,7 +133,7 @@ fn mir_node_to_flow_parts(
                         mig,
                         table_mapping,
                         None,
-                    )
+                    )?
                 }
                 MirNodeType::FilterAggregation {
                     ref on,
@@ -155,12 +155,12 @@ fn mir_node_to_flow_parts(
                         mig,
                         table_mapping,
                         Some(conditions),
-                    )
+                    )?
                 }
                 MirNodeType::Filter { ref conditions } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
-                    make_filter_node(&name, parent, mir_node.columns.as_slice(), conditions, mig)
+                    make_filter_node(&name, parent, mir_node.columns.as_slice(), conditions, mig)?
                 }
                 MirNodeType::GroupConcat {
                     ref on,
@@ -180,7 +180,7 @@ fn mir_node_to_flow_parts(
                         mig,
                         table_mapping,
                         None,
-                    )
+                    )?
                 }
                 MirNodeType::Identity => {
                     assert_eq!(mir_node.ancestors.len(), 1);
@@ -285,7 +285,7 @@ fn mir_node_to_flow_parts(
                         mir_node.ancestors(),
                         mig,
                         table_mapping,
-                    )
+                    )?
                 }
                 MirNodeType::Distinct { ref group_by } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
@@ -339,9 +339,9 @@ fn mir_node_to_flow_parts(
                 FlowNode::New(na) => Some(FlowNode::Existing(na)),
                 ref n @ FlowNode::Existing(..) => Some(n.clone()),
             };
-            flow_node
+            Ok(flow_node)
         }
-        Some(ref flow_node) => flow_node.clone(),
+        Some(ref flow_node) => Ok(flow_node.clone()),
     }
 }
 
@@ -457,7 +457,7 @@ fn make_union_node(
     ancestors: &[MirNodeRef],
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
-) -> FlowNode {
+) -> Result<FlowNode, String> {
     let column_names = column_names(columns);
     let mut emit_column_id: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
 
@@ -473,13 +473,13 @@ fn make_union_node(
         let ni = n.borrow().flow_node_addr().unwrap();
         emit_column_id.insert(ni, emit_cols);
     }
-    let node = mig.add_ingredient(
+    let node = mig.try_add_ingredient(
         String::from(name),
         column_names.as_slice(),
-        ops::union::Union::new(emit_column_id),
-    );
+        ops::union::Union::new(emit_column_id).map_err(|e| e.to_string())?,
+    )?;
 
-    FlowNode::New(node)
+    Ok(FlowNode::New(node))
 }
 
 fn make_rewrite_node(
@@ -515,15 +515,15 @@ fn make_filter_node(
     columns: &[Column],
     conditions: &[(usize, FilterCondition)],
     mig: &mut Migration,
-) -> FlowNode {
+) -> Result<FlowNode, String> {
     let parent_na = parent.borrow().flow_node_addr().unwrap();
     let column_names = column_names(columns);
     let node = mig.add_ingredient(
         String::from(name),
         column_names.as_slice(),
-        ops::filter::Filter::new(parent_na, conditions),
+        ops::filter::Filter::new(parent_na, conditions)?,
     );
-    FlowNode::New(node)
+    Ok(FlowNode::New(node))
 }
 
 fn make_grouped_node(
@@ -537,7 +537,7 @@ fn make_grouped_node(
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
     conditions: Option<&[(usize, FilterCondition)]>,
-) -> FlowNode {
+) -> Result<FlowNode, String> {
     assert!(!group_by.is_empty());
     assert!(
         group_by.len() <= 6,
@@ -581,6 +581,7 @@ fn make_grouped_node(
         ),
         GroupedNodeType::FilterAggregation(agg) => {
             let cond = conditions.expect("FilterAggregation must have conditions!");
+            ops::filter::validate(cond)?;
             mig.add_ingredient(
                 String::from(name),
                 column_names.as_slice(),
@@ -599,7 +600,7 @@ fn make_grouped_node(
             mig.add_ingredient(String::from(name), column_names.as_slice(), gc)
         }
     };
-    FlowNode::New(na)
+    Ok(FlowNode::New(na))
 }
 
 fn make_identity_node(
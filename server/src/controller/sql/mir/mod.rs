@@ -231,9 +231,10 @@ impl SqlToMirConverter {
                     filter::Value::Constant(DataType::None),
                 )
             }
-            ConditionExpression::Base(ConditionBase::LiteralList(ref ll)) => {
-                FilterCondition::In(ll.iter().map(|l| DataType::from(l.clone())).collect())
-            }
+            ConditionExpression::Base(ConditionBase::LiteralList(ref ll)) => FilterCondition::In {
+                values: ll.iter().map(|l| DataType::from(l.clone())).collect(),
+                negated: false,
+            },
             ConditionExpression::Base(ConditionBase::Field(ref f)) => {
                 // NOTE(jon): the uwnrap here is almost certainly wrong given the business
                 // that goes on further down where it appens a column in magical circumstances.
@@ -387,7 +387,7 @@ impl SqlIncorporator {
         final_query_node: MirNodeRef,
         project_columns: Option<Vec<Column>>,
         mut mig: &mut Migration,
-    ) -> QueryFlowParts {
+    ) -> Result<QueryFlowParts, String> {
         trace!(self.log, "Adding a new leaf below: {:?}", final_query_node);
 
         let mut mir = self.mir_converter.add_leaf_below(
@@ -401,11 +401,11 @@ impl SqlIncorporator {
 
         // push it into the flow graph using the migration in `mig`, and obtain `QueryFlowParts`.
         // Note that we don't need to optimize the MIR here, because the query is trivial.
-        let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None);
+        let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None)?;
 
         self.register_query(query_name, None, &mir, mig.universe());
 
-        qfp
+        Ok(qfp)
     }
 
     fn add_base_via_mir(
@@ -413,7 +413,7 @@ impl SqlIncorporator {
         query_name: &str,
         query: &SqlQuery,
         mut mig: &mut Migration,
-    ) -> QueryFlowParts {
+    ) -> Result<QueryFlowParts, String> {
         // first, compute the MIR representation of the SQL query
         let mut mir = self.mir_converter.named_base_to_mir(query_name, query);
 
@@ -422,7 +422,7 @@ impl SqlIncorporator {
         // no optimization, because standalone base nodes can't be optimized
 
         // push it into the flow graph using the migration in `mig`, and obtain `QueryFlowParts`
-        let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None);
+        let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None)?;
 
         // remember the schema in case we need it later
         // on base table schema change, we will overwrite the existing schema here.
@@ -436,7 +436,7 @@ impl SqlIncorporator {
 
         self.register_query(query_name, None, &mir, mig.universe());
 
-        qfp
+        Ok(qfp)
     }
 
     fn add_compound_query(
@@ -467,7 +467,7 @@ impl SqlIncorporator {
             is_leaf,
         );
 
-        let qfp = mir_query_to_flow_parts(&mut combined_mir_query, &mut mig, None);
+        let qfp = mir_query_to_flow_parts(&mut combined_mir_query, &mut mig, None)?;
 
         self.register_query(query_name, None, &combined_mir_query, mig.universe());
 
@@ -500,8 +500,13 @@ impl SqlIncorporator {
                 (qfp, None)
             }
             QueryGraphReuse::ReaderOntoExisting(mn, project_columns, params) => {
-                let qfp =
-                    self.add_leaf_to_existing_query(&query_name, &params, mn, project_columns, mig);
+                let qfp = self.add_leaf_to_existing_query(
+                    &query_name,
+                    &params,
+                    mn,
+                    project_columns,
+                    mig,
+                )?;
                 (qfp, None)
             }
             QueryGraphReuse::None => {
@@ -558,7 +563,7 @@ impl SqlIncorporator {
         }
 
         // push it into the flow graph using the migration in `mig`, and obtain `QueryFlowParts`
-        let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None);
+        let qfp = mir_query_to_flow_parts(&mut mir, &mut mig, None)?;
 
         // register local state
         self.register_query(query_name, Some(qg), &mir, universe);
@@ -742,7 +747,7 @@ impl SqlIncorporator {
         );
 
         let qfp =
-            mir_query_to_flow_parts(&mut post_reuse_opt_mir, &mut mig, table_mapping.as_ref());
+            mir_query_to_flow_parts(&mut post_reuse_opt_mir, &mut mig, table_mapping.as_ref())?;
 
         info!(
             self.log,
@@ -899,11 +904,10 @@ impl SqlIncorporator {
                 // NOTE(malte): We can't currently reuse complete compound select queries, since
                 // our reuse logic operates on `SqlQuery` structures. Their subqueries do get
                 // reused, however.
-                self.add_compound_query(&query_name, &csq, is_leaf, mig)
-                    .unwrap()
+                self.add_compound_query(&query_name, &csq, is_leaf, mig)?
             }
             SqlQuery::Select(sq) => self.add_select_query(&query_name, &sq, is_leaf, mig)?.0,
-            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig),
+            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig)?,
             q => panic!("unhandled query type in recipe: {:?}", q),
         };
 
@@ -82,7 +82,7 @@ async fn it_works_basic() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             (a, b, c)
@@ -171,7 +171,7 @@ async fn it_completes() {
                 let mut emits = HashMap::new();
                 emits.insert(a, vec![0, 1]);
                 emits.insert(b, vec![0, 1]);
-                let u = Union::new(emits);
+                let u = Union::new_unchecked(emits);
                 let c = mig.add_ingredient("c", &["a", "b"], u);
                 mig.maintain_anonymous(c, &[0]);
                 (a, b, c)
@@ -245,6 +245,47 @@ async fn sharded_shuffle() {
     assert_eq!(rows.len(), 100);
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn sharded_compound_key_reader() {
+    let mut g = start_simple("sharded_compound_key_reader").await;
+
+    // the reader is keyed by two columns, neither of which is the base key, so it has to be
+    // sharded by the combination of both. every lookup must then go to the one shard that holds
+    // all the rows for that pair of values.
+    g.migrate(|mig| {
+        let a = mig.add_base(
+            "base",
+            &["id", "x", "y"],
+            Base::new(vec![]).with_key(vec![0]),
+        );
+        mig.maintain_anonymous(a, &[1, 2]);
+    })
+    .await;
+
+    let mut base = g.table("base").await.unwrap();
+    let mut view = g.view("base").await.unwrap();
+
+    base.perform_all((0..100).map(|i| vec![i.into(), (i % 5).into(), (i % 2).into()]))
+        .await
+        .unwrap();
+
+    sleep().await;
+
+    for x in 0..5 {
+        for y in 0..2 {
+            let rows = view
+                .lookup(&[DataType::Int(x), DataType::Int(y)], true)
+                .await
+                .unwrap();
+            assert_eq!(rows.len(), 10);
+            assert!(rows.iter().all(|row| {
+                let id = row.get::<i32>("id").unwrap();
+                id % 5 == x && id % 2 == y
+            }));
+        }
+    }
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn broad_recursing_upquery() {
     let nshards = 16;
@@ -328,6 +369,95 @@ async fn broad_recursing_upquery() {
     }
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_pushes_changes_to_subscribers() {
+    use noria::Modification;
+
+    let mut g = start_simple("it_pushes_changes_to_subscribers").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+    let mut subscription = read.subscribe(&[1.into()]).await.unwrap();
+
+    // only changes to the subscribed key are delivered
+    write.insert(vec![2.into(), 2.into()]).await.unwrap();
+    write.insert(vec![1.into(), 2.into()]).await.unwrap();
+    assert_eq!(
+        subscription.next().await.unwrap(),
+        vec![(vec![1.into(), 2.into()], true)]
+    );
+
+    // an update is delivered as a retraction followed by the new row
+    write
+        .update(vec![1.into()], vec![(1, Modification::Set(3.into()))])
+        .await
+        .unwrap();
+    let mut deltas = Vec::new();
+    while deltas.len() < 2 {
+        deltas.extend(subscription.next().await.unwrap());
+    }
+    assert_eq!(
+        deltas,
+        vec![
+            (vec![1.into(), 2.into()], false),
+            (vec![1.into(), 3.into()], true)
+        ]
+    );
+
+    subscription.unsubscribe().await.unwrap();
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_pages_through_a_key() {
+    use nom_sql::OrderType;
+    use noria::Pagination;
+
+    let mut g = start_simple("it_pages_through_a_key").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut read = g.view("a").await.unwrap();
+    let mut write = g.table("a").await.unwrap();
+    for i in 0..10 {
+        write.insert(vec![1.into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    for order in [None, Some(OrderType::OrderDescending)].iter().cloned() {
+        let mut seen = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = Pagination {
+                order: order.clone().map(|o| (1, o)),
+                offset,
+                limit: 3,
+            };
+            let (rows, total) = read.lookup_page(&[1.into()], page, true).await.unwrap();
+            assert_eq!(total, 10);
+            if rows.is_empty() {
+                break;
+            }
+            assert!(rows.len() <= 3);
+            seen.extend(rows.into_iter().map(|r| i32::from(&r[1])));
+            offset += 3;
+        }
+
+        let mut expected: Vec<i32> = (0..10).collect();
+        if order.is_some() {
+            expected.reverse();
+        }
+        assert_eq!(seen, expected);
+    }
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn base_mutation() {
     use noria::{Modification, Operation};
@@ -420,11 +550,11 @@ async fn shared_interdomain_ancestor() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
 
-            let u = Union::new(emits.clone());
+            let u = Union::new_unchecked(emits.clone());
             let b = mig.add_ingredient("b", &["a", "b"], u);
             mig.maintain_anonymous(b, &[0]);
 
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             (a, b, c)
@@ -474,7 +604,7 @@ async fn it_works_w_mat() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             (a, b, c)
@@ -549,7 +679,7 @@ async fn it_works_w_partial_mat() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             c
@@ -587,7 +717,7 @@ async fn it_works_w_partial_mat_below_empty() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             (a, b, c)
@@ -634,7 +764,7 @@ async fn it_works_deletion() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![1, 2]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["x", "y"], u);
             mig.maintain_anonymous(c, &[0]);
             (a, b, c)
@@ -702,6 +832,41 @@ async fn it_works_with_sql_recipe() {
     assert_eq!(result[0][0], 2.into());
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_is_null() {
+    let mut g = start_simple("it_works_with_is_null").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY Unbranded: SELECT id FROM Car WHERE brand IS NULL;
+        QUERY Branded: SELECT id FROM Car WHERE brand IS NOT NULL;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut unbranded = g.view("Unbranded").await.unwrap();
+    let mut branded = g.view("Branded").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![2.into(), DataType::None])
+        .await
+        .unwrap();
+
+    // Let writes propagate:
+    sleep().await;
+
+    let result = unbranded.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 2.into());
+
+    let result = branded.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 1.into());
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_vote() {
     let mut g = start_simple("it_works_with_vote").await;
@@ -1341,7 +1506,7 @@ async fn votes() {
             let mut emits = HashMap::new();
             emits.insert(article1, vec![0, 1]);
             emits.insert(article2, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let article = mig.add_ingredient("article", &["id", "title"], u);
             mig.maintain_anonymous(article, &[0]);
 
@@ -1445,7 +1610,7 @@ async fn empty_migration() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             (a, b, c)
@@ -2071,7 +2236,7 @@ async fn crossing_migration() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             c
@@ -2174,7 +2339,7 @@ async fn domain_amend_migration() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
             c
@@ -2723,7 +2888,7 @@ async fn node_removal() {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
+            let u = Union::new_unchecked(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0])
         })
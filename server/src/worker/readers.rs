@@ -9,11 +9,12 @@ use futures_util::{
     future::{FutureExt, TryFutureExt},
     stream::{StreamExt, TryStreamExt},
 };
-use noria::{ReadQuery, ReadReply, Tagged};
+use noria::{Pagination, ReadQuery, ReadReply, Tagged};
 use pin_project::pin_project;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::mem;
+use std::sync::{Arc, Mutex};
 use std::time;
 use std::{future::Future, task::Poll};
 use stream_cancel::Valve;
@@ -29,6 +30,13 @@ const RETRY_TIMEOUT: time::Duration = time::Duration::from_micros(100);
 /// while, waiting readers will use exponential backoff on this delay if they continue to miss.
 const TRIGGER_TIMEOUT_MS: u64 = 20;
 
+/// Subscription polls return empty-handed if no changes arrive within this long.
+const SUBSCRIPTION_POLL_TIMEOUT: time::Duration = time::Duration::from_millis(250);
+
+/// Subscriptions that aren't polled for this long are assumed to belong to clients that went away
+/// without unsubscribing, and are dropped.
+const SUBSCRIPTION_IDLE_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
 task_local! {
     static READERS: RefCell<HashMap<
         (NodeIndex, usize),
@@ -55,12 +63,35 @@ impl From<Vec<u8>> for SerializedReadReplyBatch {
 
 type Ack = tokio::sync::oneshot::Sender<Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>>;
 
+type Deltas = Arc<tokio::sync::Mutex<tokio::sync::mpsc::Receiver<Vec<Record>>>>;
+
+/// Subscriptions registered with any of this worker's readers.
+///
+/// These are shared among all client connections, since clients spread their requests across
+/// several connections. That also means that a subscription can't be dropped when a connection
+/// closes, so subscriptions that go unpolled for `SUBSCRIPTION_IDLE_TIMEOUT` are dropped instead.
+#[derive(Default)]
+struct Subscriptions {
+    next: u64,
+    // the changes for each subscription, and when it was last polled
+    deltas: HashMap<u64, (Deltas, time::Instant)>,
+}
+
+impl Subscriptions {
+    /// Drop the subscriptions that have been idle for too long.
+    fn expire(&mut self, now: time::Instant) {
+        self.deltas
+            .retain(|_, &mut (_, polled)| now.duration_since(polled) < SUBSCRIPTION_IDLE_TIMEOUT);
+    }
+}
+
 pub(super) async fn listen(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
     mut on: tokio::net::TcpListener,
     readers: Readers,
 ) {
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
     let mut stream = valve.wrap(on.incoming()).into_stream();
     while let Some(stream) = stream.next().await {
         if let Err(_) = stream {
@@ -70,6 +101,7 @@ pub(super) async fn listen(
 
         let stream = stream.unwrap();
         let readers = readers.clone();
+        let subscriptions = Arc::clone(&subscriptions);
         stream.set_nodelay(true).expect("could not set TCP_NODELAY");
         let alive = alive.clone();
 
@@ -119,7 +151,7 @@ pub(super) async fn listen(
             Default::default(),
             server::Server::new(
                 AsyncBincodeStream::from(stream).for_async(),
-                service_fn(move |req| handle_message(req, &readers, &mut tx)),
+                service_fn(move |req| handle_message(req, &readers, &subscriptions, &mut tx)),
             ),
         );
         tokio::spawn(
@@ -176,9 +208,43 @@ where
     SerializedReadReplyBatch(v)
 }
 
+/// Serialize the rows for a key, or just those on `page` if given, along with how many there were.
+fn serialize_page<'a, I>(rs: I, page: Option<&Pagination>) -> (SerializedReadReplyBatch, usize)
+where
+    I: IntoIterator<Item = &'a Vec<DataType>>,
+    I::IntoIter: ExactSizeIterator,
+{
+    match page {
+        Some(page) => {
+            let (rs, total) = page.slice(rs);
+            (serialize(rs), total)
+        }
+        None => {
+            let rs = rs.into_iter();
+            let total = rs.len();
+            (serialize(rs), total)
+        }
+    }
+}
+
+/// Reply to a read with the rows for each key, and also their totals if the read was paginated.
+fn read_reply(
+    tag: u32,
+    read: Result<Vec<(SerializedReadReplyBatch, usize)>, ()>,
+    paged: bool,
+) -> Tagged<ReadReply<SerializedReadReplyBatch>> {
+    let v = if paged {
+        ReadReply::Paged(read)
+    } else {
+        ReadReply::Normal(read.map(|read| read.into_iter().map(|(rs, _)| rs).collect()))
+    };
+    Tagged { tag, v }
+}
+
 fn handle_message(
     m: Tagged<ReadQuery>,
     s: &Readers,
+    subscriptions: &Mutex<Subscriptions>,
     wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
 ) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
     let tag = m.tag;
@@ -187,7 +253,9 @@ fn handle_message(
             target,
             mut keys,
             block,
+            page,
         } => {
+            let paged = page.is_some();
             let immediate = READERS.with(|readers_cache| {
                 let mut readers_cache = readers_cache.borrow_mut();
                 let reader = readers_cache.entry(target).or_insert_with(|| {
@@ -204,10 +272,12 @@ fn handle_message(
                 keys.retain(|key| {
                     i += 1;
                     if !ready {
-                        ret.push(SerializedReadReplyBatch::empty());
+                        ret.push((SerializedReadReplyBatch::empty(), 0));
                         return false;
                     }
-                    let rs = reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0);
+                    let rs = reader
+                        .try_find_and(key, |rs| serialize_page(rs, page.as_ref()))
+                        .map(|r| r.0);
                     match rs {
                         Ok(Some(rs)) => {
                             // immediate hit!
@@ -217,32 +287,26 @@ fn handle_message(
                         Err(()) => {
                             // map not yet ready
                             ready = false;
-                            ret.push(SerializedReadReplyBatch::empty());
+                            ret.push((SerializedReadReplyBatch::empty(), 0));
                             false
                         }
                         Ok(None) => {
                             // need to trigger partial replay for this key
                             pending.push(i as usize);
-                            ret.push(SerializedReadReplyBatch::empty());
+                            ret.push((SerializedReadReplyBatch::empty(), 0));
                             true
                         }
                     }
                 });
 
                 if !ready {
-                    return Ok(Tagged {
-                        tag,
-                        v: ReadReply::Normal(Err(())),
-                    });
+                    return Ok(read_reply(tag, Err(()), paged));
                 }
 
                 if keys.is_empty() {
                     // we hit on all the keys!
                     assert!(pending.is_empty());
-                    return Ok(Tagged {
-                        tag,
-                        v: ReadReply::Normal(Ok(ret)),
-                    });
+                    return Ok(read_reply(tag, Ok(ret), paged));
                 }
 
                 // trigger backfills for all the keys we missed on
@@ -255,10 +319,11 @@ fn handle_message(
                 Ok(reply) => Either::Left(Either::Left(future::ready(Ok(reply)))),
                 Err((keys, ret, pending)) => {
                     if !block {
-                        Either::Left(Either::Left(future::ready(Ok(Tagged {
+                        Either::Left(Either::Left(future::ready(Ok(read_reply(
                             tag,
-                            v: ReadReply::Normal(Ok(ret)),
-                        }))))
+                            Ok(ret),
+                            paged,
+                        )))))
                     } else {
                         let (tx, rx) = tokio::sync::oneshot::channel();
                         let trigger = time::Duration::from_millis(TRIGGER_TIMEOUT_MS);
@@ -270,6 +335,7 @@ fn handle_message(
                                 keys,
                                 pending,
                                 read: ret,
+                                page,
                                 truth: s.clone(),
                                 trigger_timeout: trigger,
                                 next_trigger: now,
@@ -300,10 +366,98 @@ fn handle_message(
                 reader.len()
             });
 
-            Either::Right(future::ready(Ok(Tagged {
+            Either::Right(Either::Left(future::ready(Ok(Tagged {
                 tag,
                 v: ReadReply::Size(size),
-            })))
+            }))))
+        }
+        ReadQuery::Subscribe { target, range } => {
+            let deltas = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.subscribe(range)
+            });
+
+            let now = time::Instant::now();
+            let mut subscriptions = subscriptions.lock().unwrap();
+            subscriptions.expire(now);
+            let subscription = subscriptions.next;
+            subscriptions.next += 1;
+            subscriptions.deltas.insert(
+                subscription,
+                (Arc::new(tokio::sync::Mutex::new(deltas)), now),
+            );
+
+            Either::Right(Either::Left(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Subscribed(subscription),
+            }))))
+        }
+        ReadQuery::Unsubscribe { subscription } => {
+            // an in-flight poll may still hold on to the receiver, but it is dropped (and the
+            // reader stops sending to it) once that poll finishes
+            subscriptions.lock().unwrap().deltas.remove(&subscription);
+
+            Either::Right(Either::Left(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Unsubscribed,
+            }))))
+        }
+        ReadQuery::Poll { subscription } => {
+            let now = time::Instant::now();
+            let deltas = {
+                let mut subscriptions = subscriptions.lock().unwrap();
+                subscriptions.expire(now);
+                subscriptions
+                    .deltas
+                    .get_mut(&subscription)
+                    .map(|(deltas, polled)| {
+                        *polled = now;
+                        Arc::clone(deltas)
+                    })
+            };
+
+            Either::Right(Either::Right(async move {
+                let deltas = match deltas {
+                    Some(deltas) => deltas,
+                    None => {
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::Deltas(Err(())),
+                        })
+                    }
+                };
+
+                let mut deltas = deltas.lock().await;
+                let mut rs = Vec::new();
+                match tokio::time::timeout(SUBSCRIPTION_POLL_TIMEOUT, deltas.recv()).await {
+                    Ok(Some(first)) => {
+                        rs.extend(first);
+                        // also pick up anything else that is already waiting
+                        while let Ok(more) = deltas.try_recv() {
+                            rs.extend(more);
+                        }
+                    }
+                    Ok(None) => {
+                        // the reader dropped the subscription, since it fell too far behind or went
+                        // away itself
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::Deltas(Err(())),
+                        });
+                    }
+                    Err(_) => {}
+                }
+
+                Ok(Tagged {
+                    tag,
+                    v: ReadReply::Deltas(Ok(rs.into_iter().map(Record::extract).collect())),
+                })
+            }))
         }
     }
 }
@@ -312,8 +466,10 @@ fn handle_message(
 struct BlockingRead {
     tag: u32,
     target: (NodeIndex, usize),
-    // serialized records for keys we have already read
-    read: Vec<SerializedReadReplyBatch>,
+    // serialized records for keys we have already read, and how many rows each key has
+    read: Vec<(SerializedReadReplyBatch, usize)>,
+    // the slice of each key's rows to return, if the read is paginated
+    page: Option<Pagination>,
     // keys we have yet to read
     keys: Vec<Vec<DataType>>,
     // index in self.read that each entyr in keys corresponds to
@@ -331,6 +487,7 @@ impl std::fmt::Debug for BlockingRead {
             .field("tag", &self.tag)
             .field("target", &self.target)
             .field("read", &self.read)
+            .field("page", &self.page)
             .field("keys", &self.keys)
             .field("pending", &self.pending)
             .field("trigger_timeout", &self.trigger_timeout)
@@ -353,6 +510,7 @@ impl BlockingRead {
 
             let now = time::Instant::now();
             let read = &mut self.read;
+            let page = self.page.as_ref();
             let next_trigger = self.next_trigger;
 
             // here's the trick we're going to play:
@@ -364,7 +522,10 @@ impl BlockingRead {
 
             while let Some(read_i) = self.pending.pop() {
                 let key = self.keys.pop().expect("pending.len() == keys.len()");
-                match reader.try_find_and(&key, |rs| serialize(rs)).map(|r| r.0) {
+                match reader
+                    .try_find_and(&key, |rs| serialize_page(rs, page))
+                    .map(|r| r.0)
+                {
                     Ok(Some(rs)) => {
                         read[read_i] = rs;
                     }
@@ -410,10 +571,11 @@ impl BlockingRead {
         })?;
 
         if self.keys.is_empty() {
-            Poll::Ready(Ok(Tagged {
-                tag: self.tag,
-                v: ReadReply::Normal(Ok(mem::take(&mut self.read))),
-            }))
+            Poll::Ready(Ok(read_reply(
+                self.tag,
+                Ok(mem::take(&mut self.read)),
+                self.page.is_some(),
+            )))
         } else {
             Poll::Pending
         }
@@ -553,6 +715,59 @@ mod readreply {
         ));
     }
 
+    #[test]
+    fn rtt_paged() {
+        let data = vec![vec![DataType::from(1)], vec![DataType::from(42)]];
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Paged::<SerializedReadReplyBatch>(Ok(vec![(
+                    super::serialize(&data),
+                    10,
+                )])),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Paged(Ok(got)),
+                tag: 32,
+            } => {
+                assert_eq!(got.len(), 1);
+                let (rows, total) = got.into_iter().next().unwrap();
+                assert_eq!(&*rows, &data);
+                assert_eq!(total, 10);
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn rtt_deltas() {
+        let deltas = vec![
+            (vec![DataType::from(1), DataType::from(42)], true),
+            (vec![DataType::from(1), DataType::from(43)], false),
+        ];
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Deltas::<SerializedReadReplyBatch>(Ok(deltas.clone())),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Deltas(Ok(got)),
+                tag: 32,
+            } => assert_eq!(got, deltas),
+            r => panic!("{:?}", r),
+        }
+    }
+
     async fn async_bincode_rtt_ok(data: Vec<Vec<Vec<DataType>>>) {
         use futures_util::{SinkExt, StreamExt};
 
@@ -305,11 +305,13 @@ async fn check_query(
                 row.into_iter()
                     .map(|v| match v {
                         DataType::None => "NULL".to_owned(),
+                        DataType::Bool(b) => (b as i32).to_string(),
                         DataType::Int(i) => i.to_string(),
                         DataType::UnsignedInt(i) => i.to_string(),
                         DataType::BigInt(i) => i.to_string(),
                         DataType::UnsignedBigInt(i) => i.to_string(),
                         DataType::Real(i, f) => ((i as f64) + (f as f64) * 1.0e-9).to_string(),
+                        DataType::Decimal(..) => v.to_string(),
                         DataType::Text(_) | DataType::TinyText(_) => {
                             let s: &str = (&v).into();
                             s.to_string()